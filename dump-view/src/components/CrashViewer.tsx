@@ -14,6 +14,14 @@ interface CrashSummary {
   message?: string;
 }
 
+interface CrashPage {
+  crashes: CrashSummary[];
+  total: number;
+  offset: number;
+  limit: number;
+  next_offset: number | null;
+}
+
 interface SentryStackFrame {
   function: string;
   filename?: string;
@@ -121,7 +129,7 @@ const CrashViewer: React.FC = () => {
   useEffect(() => {
     fetch('/crashes')
       .then((r) => r.json())
-      .then(setCrashes)
+      .then((page: CrashPage) => setCrashes(page.crashes))
       .catch((e) => setError(String(e)));
   }, []);
 
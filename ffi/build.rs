@@ -0,0 +1,15 @@
+// Writes include/crash.h from the exported items of src/lib.rs, with the
+// settings in cbindgen.toml. The header is checked in so C users need not
+// build this crate to get it; CI rebuilds and diffs it.
+
+use std::path::Path;
+
+fn main() {
+    let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let config = cbindgen::Config::from_file(Path::new(&dir).join("cbindgen.toml")).unwrap();
+    cbindgen::generate_with_config(&dir, config)
+        .expect("cannot generate crash.h")
+        .write_to_file(Path::new(&dir).join("include/crash.h"));
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+}
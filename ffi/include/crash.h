@@ -0,0 +1,153 @@
+/*
+ * C API of the crash handler, implemented by libcrash_ffi (crash_ffi crate).
+ * Generated by cbindgen from ffi/src/lib.rs; do not edit.
+ *
+ * Options take the keys of crash.toml and values written as in the CRASH_*
+ * variables: flags as "true"/"false", durations in seconds, lists
+ * comma-separated. Functions returning int return 0 on success and -1 on
+ * failure, with the reason printed to stderr. Strings are NUL-terminated
+ * UTF-8; arguments may be NULL only where noted.
+ *
+ * Linking the static library also needs the system libraries Rust code
+ * depends on, e.g. -lpthread -ldl -lm on Linux.
+ */
+
+#ifndef CRASH_H
+#define CRASH_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Levels for `crash_capture_message`.
+ */
+#define CRASH_LEVEL_DEBUG 0
+
+#define CRASH_LEVEL_INFO 1
+
+#define CRASH_LEVEL_WARNING 2
+
+#define CRASH_LEVEL_ERROR 3
+
+#define CRASH_LEVEL_FATAL 4
+
+/**
+ * Bytes an event id needs, with the terminating NUL.
+ */
+#define CRASH_EVENT_ID_LEN 37
+
+/**
+ * Options for `crash_init`, created with `crash_options_new`.
+ */
+typedef struct crash_options crash_options;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * New options with the defaults of `crash::Config`.
+ */
+crash_options *crash_options_new(void);
+
+/**
+ * Sets the option `key` to `value`. Returns 0, or -1 if the key is unknown
+ * or the value does not parse.
+ *
+ * # Safety
+ *
+ * `options` must come from `crash_options_new` and not be freed yet; `key`
+ * and `value` must be NUL-terminated strings.
+ */
+int crash_options_set(crash_options *options, const char *key, const char *value);
+
+/**
+ * Frees options that were not passed to `crash_init`.
+ *
+ * # Safety
+ *
+ * `options` must be NULL or come from `crash_options_new` and not be freed
+ * yet.
+ */
+void crash_options_free(crash_options *options);
+
+/**
+ * Installs the crash handler, see `crash::init`, and frees `options`; NULL
+ * installs it with the defaults. Returns 0, or -1 if it cannot be installed.
+ *
+ * # Safety
+ *
+ * `options` must be NULL or come from `crash_options_new` and not be freed
+ * yet.
+ */
+int crash_init(crash_options *options);
+
+/**
+ * Tags future reports with `key: value`.
+ *
+ * # Safety
+ *
+ * `key` and `value` must be NUL-terminated strings.
+ */
+void crash_set_tag(const char *key, const char *value);
+
+/**
+ * Attaches the string `value` to future reports as the extra `key`.
+ *
+ * # Safety
+ *
+ * `key` and `value` must be NUL-terminated strings.
+ */
+void crash_set_extra(const char *key, const char *value);
+
+/**
+ * Sets the user of future reports; all NULL clears it.
+ *
+ * # Safety
+ *
+ * Each argument must be NULL or a NUL-terminated string.
+ */
+void crash_set_user(const char *id, const char *username, const char *email);
+
+/**
+ * Records a breadcrumb for future reports.
+ *
+ * # Safety
+ *
+ * `category` and `message` must be NUL-terminated strings.
+ */
+void crash_add_breadcrumb(const char *category, const char *message);
+
+/**
+ * Attaches the file at `path` to future reports, read when they are made.
+ *
+ * # Safety
+ *
+ * `path` must be a NUL-terminated string.
+ */
+void crash_attach_file(const char *path);
+
+/**
+ * Reports `message` at `level` (a `CRASH_LEVEL_*` value). Returns 0 and
+ * copies the event id, NUL-terminated, into `event_id` if it is not NULL
+ * and `event_id_len` bytes are enough; returns -1 if the report was dropped
+ * or the handler is not installed.
+ *
+ * # Safety
+ *
+ * `message` must be a NUL-terminated string; `event_id` must be NULL or
+ * point to `event_id_len` writable bytes.
+ */
+int crash_capture_message(int level, const char *message, char *event_id, size_t event_id_len);
+
+/**
+ * Waits up to `timeout_ms` for reports still being delivered, see
+ * `crash::flush`. Returns 1 if all were, 0 otherwise.
+ */
+int crash_flush(uint64_t timeout_ms);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CRASH_H */
@@ -0,0 +1,229 @@
+// C API of the crash handler, for C and C++ applications and for native
+// hosts that embed Rust. It builds as `libcrash_ffi.so` (`.dylib`, `.dll`)
+// and `libcrash_ffi.a`; the declarations are in `include/crash.h`, which
+// build.rs generates from this file with cbindgen (see `cbindgen.toml`).
+//
+// Options are set by the names and in the text form of the CRASH_* variables
+// (see `crash::settings`), so C code configures everything `crash.toml` can:
+//
+//     crash_options *options = crash_options_new();
+//     crash_options_set(options, "output_dir", "/var/crash/my_app");
+//     crash_options_set(options, "upload_url", "https://crash.example.com/upload");
+//     if (crash_init(options) != 0) { ... }
+//
+// Native crashes are handled by the signal handlers as for Rust programs,
+// and on Linux the `crash-helper` executable is expected next to the
+// application unless `helper_path` says otherwise. Functions that take
+// strings accept NULL where the header says so; other strings must be
+// NUL-terminated and are read as UTF-8, invalid sequences replaced.
+
+use std::ffi::{c_char, c_int, CStr};
+use std::time::Duration;
+
+use crash::{Breadcrumb, Config, Level, User};
+
+/// Levels for `crash_capture_message`.
+pub const CRASH_LEVEL_DEBUG: c_int = 0;
+pub const CRASH_LEVEL_INFO: c_int = 1;
+pub const CRASH_LEVEL_WARNING: c_int = 2;
+pub const CRASH_LEVEL_ERROR: c_int = 3;
+pub const CRASH_LEVEL_FATAL: c_int = 4;
+
+/// Bytes an event id needs, with the terminating NUL.
+pub const CRASH_EVENT_ID_LEN: usize = 37;
+
+/// Options for `crash_init`, created with `crash_options_new`.
+pub struct CrashOptions {
+    config: Config,
+}
+
+// The string at `ptr`, or None for NULL.
+//
+// SAFETY: `ptr` must be NULL or point to a NUL-terminated string.
+unsafe fn text(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned())
+}
+
+fn to_level(level: c_int) -> Level {
+    match level {
+        CRASH_LEVEL_DEBUG => Level::Debug,
+        CRASH_LEVEL_INFO => Level::Info,
+        CRASH_LEVEL_WARNING => Level::Warning,
+        CRASH_LEVEL_FATAL => Level::Fatal,
+        _ => Level::Error,
+    }
+}
+
+/// New options with the defaults of `crash::Config`.
+#[no_mangle]
+pub extern "C" fn crash_options_new() -> *mut CrashOptions {
+    Box::into_raw(Box::new(CrashOptions {
+        config: Config::default(),
+    }))
+}
+
+/// Sets the option `key` to `value`. Returns 0, or -1 if the key is unknown
+/// or the value does not parse.
+///
+/// # Safety
+///
+/// `options` must come from `crash_options_new` and not be freed yet; `key`
+/// and `value` must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn crash_options_set(options: *mut CrashOptions, key: *const c_char, value: *const c_char) -> c_int {
+    let Some(options) = (unsafe { options.as_mut() }) else {
+        return -1;
+    };
+    let (Some(key), Some(value)) = (unsafe { text(key) }, unsafe { text(value) }) else {
+        return -1;
+    };
+    match crash::settings::set_text(&mut options.config, &key, &value) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("crash_options_set: {}", e);
+            -1
+        }
+    }
+}
+
+/// Frees options that were not passed to `crash_init`.
+///
+/// # Safety
+///
+/// `options` must be NULL or come from `crash_options_new` and not be freed
+/// yet.
+#[no_mangle]
+pub unsafe extern "C" fn crash_options_free(options: *mut CrashOptions) {
+    if !options.is_null() {
+        drop(unsafe { Box::from_raw(options) });
+    }
+}
+
+/// Installs the crash handler, see `crash::init`, and frees `options`; NULL
+/// installs it with the defaults. Returns 0, or -1 if it cannot be installed.
+///
+/// # Safety
+///
+/// `options` must be NULL or come from `crash_options_new` and not be freed
+/// yet.
+#[no_mangle]
+pub unsafe extern "C" fn crash_init(options: *mut CrashOptions) -> c_int {
+    let config = if options.is_null() {
+        Config::default()
+    } else {
+        unsafe { Box::from_raw(options) }.config
+    };
+    match crash::init(config) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("crash_init: {}", e);
+            -1
+        }
+    }
+}
+
+/// Tags future reports with `key: value`.
+///
+/// # Safety
+///
+/// `key` and `value` must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn crash_set_tag(key: *const c_char, value: *const c_char) {
+    if let (Some(key), Some(value)) = (unsafe { text(key) }, unsafe { text(value) }) {
+        crash::set_tag(key, value);
+    }
+}
+
+/// Attaches the string `value` to future reports as the extra `key`.
+///
+/// # Safety
+///
+/// `key` and `value` must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn crash_set_extra(key: *const c_char, value: *const c_char) {
+    if let (Some(key), Some(value)) = (unsafe { text(key) }, unsafe { text(value) }) {
+        crash::set_extra(key, value);
+    }
+}
+
+/// Sets the user of future reports; all NULL clears it.
+///
+/// # Safety
+///
+/// Each argument must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn crash_set_user(id: *const c_char, username: *const c_char, email: *const c_char) {
+    let user = User {
+        id: unsafe { text(id) },
+        username: unsafe { text(username) },
+        email: unsafe { text(email) },
+        ..Default::default()
+    };
+    let empty = user.id.is_none() && user.username.is_none() && user.email.is_none();
+    crash::set_user((!empty).then_some(user));
+}
+
+/// Records a breadcrumb for future reports.
+///
+/// # Safety
+///
+/// `category` and `message` must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn crash_add_breadcrumb(category: *const c_char, message: *const c_char) {
+    if let (Some(category), Some(message)) = (unsafe { text(category) }, unsafe { text(message) }) {
+        crash::add_breadcrumb(Breadcrumb::new(category, message));
+    }
+}
+
+/// Attaches the file at `path` to future reports, read when they are made.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn crash_attach_file(path: *const c_char) {
+    if let Some(path) = unsafe { text(path) } {
+        crash::attach_file(path);
+    }
+}
+
+/// Reports `message` at `level` (a `CRASH_LEVEL_*` value). Returns 0 and
+/// copies the event id, NUL-terminated, into `event_id` if it is not NULL
+/// and `event_id_len` bytes are enough; returns -1 if the report was dropped
+/// or the handler is not installed.
+///
+/// # Safety
+///
+/// `message` must be a NUL-terminated string; `event_id` must be NULL or
+/// point to `event_id_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn crash_capture_message(
+    level: c_int,
+    message: *const c_char,
+    event_id: *mut c_char,
+    event_id_len: usize,
+) -> c_int {
+    let Some(message) = (unsafe { text(message) }) else {
+        return -1;
+    };
+    let Some(id) = crash::capture_message(to_level(level), message) else {
+        return -1;
+    };
+    if !event_id.is_null() && id.len() < event_id_len {
+        unsafe {
+            std::ptr::copy_nonoverlapping(id.as_ptr(), event_id as *mut u8, id.len());
+            *event_id.add(id.len()) = 0;
+        }
+    }
+    0
+}
+
+/// Waits up to `timeout_ms` for reports still being delivered, see
+/// `crash::flush`. Returns 1 if all were, 0 otherwise.
+#[no_mangle]
+pub extern "C" fn crash_flush(timeout_ms: u64) -> c_int {
+    c_int::from(crash::flush(Duration::from_millis(timeout_ms)))
+}
@@ -0,0 +1,249 @@
+// Python bindings of the capture API, built with maturin as the `crash_py`
+// module, so the Python side of a mixed Rust/Python service reports into the
+// same directory and server as its Rust side:
+//
+//     import crash_py
+//     crash_py.init(output_dir="/var/crash/my_app", upload_url="https://crash.example.com/upload")
+//     crash_py.add_breadcrumb("startup", "configuration loaded")
+//     crash_py.capture_message("cache is cold", level="warning")
+//
+// `init` takes the settings of `crash.toml` as keyword arguments and installs
+// the crash handler, then wraps `sys.excepthook`, so an uncaught exception is
+// reported at level `fatal`, with its chain of causes and their tracebacks,
+// before the previous hook prints it. `excepthook=False` leaves the hook
+// alone. A process that embeds Python from Rust calls `crash::init` itself
+// and only needs the capture functions here; `init` then fails, as the
+// handler is installed already.
+
+use std::time::Duration;
+
+use crash::event::{ExceptionValue, MyFrame, MyStacktrace};
+use crash::{Breadcrumb, Config, Level, User};
+use pyo3::exceptions::{PyKeyboardInterrupt, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::sync::GILOnceCell;
+use pyo3::types::{PyBool, PyDict, PyList, PyTraceback, PyType};
+
+// Causes followed from an exception, against cycles.
+const MAX_CHAIN: usize = 16;
+
+// `sys.excepthook` before `init` replaced it.
+static PREVIOUS_HOOK: GILOnceCell<PyObject> = GILOnceCell::new();
+// Where the standard library is, whose frames are not in-app.
+static STDLIB: GILOnceCell<String> = GILOnceCell::new();
+
+fn level(name: &str) -> PyResult<Level> {
+    Level::from_name(name).ok_or_else(|| {
+        PyValueError::new_err(format!(
+            "unknown level '{}', expected debug, info, warning, error or fatal",
+            name
+        ))
+    })
+}
+
+// A keyword argument of `init` in the text form of the CRASH_* variables.
+fn setting_text(value: &Bound<'_, PyAny>) -> PyResult<String> {
+    if let Ok(flag) = value.downcast::<PyBool>() {
+        return Ok(flag.is_true().to_string());
+    }
+    if let Ok(list) = value.downcast::<PyList>() {
+        let items = list
+            .iter()
+            .map(|item| item.str().map(|s| s.to_string()))
+            .collect::<PyResult<Vec<_>>>()?;
+        return Ok(items.join(","));
+    }
+    Ok(value.str()?.to_string())
+}
+
+/// Installs the crash handler with the given settings, e.g.
+/// `output_dir="/var/crash"`, `sample_rate=0.5` or
+/// `in_app_include=["my_app"]`, and reports uncaught exceptions unless
+/// `excepthook` is false.
+#[pyfunction]
+#[pyo3(signature = (excepthook = true, **options))]
+fn init(py: Python<'_>, excepthook: bool, options: Option<&Bound<'_, PyDict>>) -> PyResult<()> {
+    let mut config = Config::default();
+    if let Some(options) = options {
+        for (key, value) in options.iter() {
+            let key: String = key.extract()?;
+            crash::settings::set_text(&mut config, &key, &setting_text(&value)?)
+                .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        }
+    }
+    py.allow_threads(|| crash::init(config))
+        .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+    if excepthook {
+        let sys = py.import("sys")?;
+        let previous = sys.getattr("excepthook")?;
+        PREVIOUS_HOOK.get_or_init(py, || previous.unbind());
+        sys.setattr("excepthook", wrap_pyfunction!(report_uncaught, py)?)?;
+    }
+    Ok(())
+}
+
+// Whether the file `filename` belongs to the application rather than to the
+// standard library or an installed package.
+fn is_in_app(py: Python<'_>, filename: &str) -> bool {
+    let stdlib = STDLIB.get_or_init(py, || {
+        py.import("sysconfig")
+            .and_then(|sysconfig| sysconfig.call_method1("get_path", ("stdlib",))?.extract())
+            .unwrap_or_default()
+    });
+    !(filename.starts_with('<')
+        || filename.contains("site-packages")
+        || filename.contains("dist-packages")
+        || (!stdlib.is_empty() && filename.starts_with(stdlib.as_str())))
+}
+
+// The frames of `traceback`, outermost first.
+fn stacktrace(py: Python<'_>, traceback: &Bound<'_, PyAny>) -> PyResult<Option<MyStacktrace>> {
+    let mut frames = Vec::new();
+    let mut current = traceback.clone();
+    while !current.is_none() {
+        let code = current.getattr("tb_frame")?.getattr("f_code")?;
+        let filename: String = code.getattr("co_filename")?.extract()?;
+        frames.push(MyFrame {
+            function: Some(code.getattr("co_name")?.extract()?),
+            lineno: current.getattr("tb_lineno")?.extract().ok(),
+            colno: None,
+            in_app: Some(is_in_app(py, &filename)),
+            filename: Some(filename),
+            instruction_addr: None,
+            pre_context: Vec::new(),
+            context_line: None,
+            post_context: Vec::new(),
+        });
+        current = current.getattr("tb_next")?;
+    }
+    Ok((!frames.is_empty()).then_some(MyStacktrace { frames }))
+}
+
+// `exc` and the exceptions it was raised from or while handling, root cause
+// first, as `traceback` prints them.
+fn exception_values(py: Python<'_>, exc: &Bound<'_, PyAny>) -> PyResult<Vec<ExceptionValue>> {
+    let mut values = Vec::new();
+    let mut current = exc.clone();
+    while !current.is_none() && values.len() < MAX_CHAIN {
+        let ty = current.get_type();
+        let name: String = ty.getattr("__qualname__")?.extract()?;
+        let module: String = ty.getattr("__module__")?.extract()?;
+        values.push(ExceptionValue {
+            exception_type: if module == "builtins" { name } else { format!("{}.{}", module, name) },
+            value: current.str()?.to_string(),
+            stacktrace: stacktrace(py, &current.getattr("__traceback__")?)?,
+            ..Default::default()
+        });
+        let cause = current.getattr("__cause__")?;
+        current = if !cause.is_none() || current.getattr("__suppress_context__")?.is_truthy()? {
+            cause
+        } else {
+            current.getattr("__context__")?
+        };
+    }
+    values.reverse();
+    Ok(values)
+}
+
+fn report(py: Python<'_>, exc: &Bound<'_, PyAny>, level: Level) -> PyResult<Option<String>> {
+    let values = exception_values(py, exc)?;
+    Ok(py.allow_threads(|| crash::capture_exception("python", level, values)))
+}
+
+// Replaces `sys.excepthook`: reports the exception, then hands it on.
+#[pyfunction]
+#[pyo3(signature = (exc_type, exc, traceback = None))]
+fn report_uncaught(
+    py: Python<'_>,
+    exc_type: &Bound<'_, PyType>,
+    exc: &Bound<'_, PyAny>,
+    traceback: Option<&Bound<'_, PyTraceback>>,
+) -> PyResult<()> {
+    // Ctrl-C is how the program was stopped, not a crash.
+    let reported = match exc_type.is_subclass_of::<PyKeyboardInterrupt>() {
+        Ok(true) => Ok(()),
+        Ok(false) => report(py, exc, Level::Fatal).map(|_| ()),
+        Err(e) => Err(e),
+    };
+    // The traceback is printed even if the report failed.
+    if let Some(previous) = PREVIOUS_HOOK.get(py) {
+        previous.call1(py, (exc_type, exc, traceback))?;
+    }
+    reported
+}
+
+/// Reports the exception `exc`, e.g. one that was caught, with its causes.
+/// Returns the event id, or None if it was dropped.
+#[pyfunction]
+#[pyo3(signature = (exc, level = "error"))]
+fn capture_exception(py: Python<'_>, exc: &Bound<'_, PyAny>, level: &str) -> PyResult<Option<String>> {
+    report(py, exc, self::level(level)?)
+}
+
+/// Reports `message` at `level`. Returns the event id, or None if it was
+/// dropped.
+#[pyfunction]
+#[pyo3(signature = (message, level = "info"))]
+fn capture_message(py: Python<'_>, message: String, level: &str) -> PyResult<Option<String>> {
+    let level = self::level(level)?;
+    Ok(py.allow_threads(|| crash::capture_message(level, message)))
+}
+
+/// Records a breadcrumb for future reports.
+#[pyfunction]
+#[pyo3(signature = (category, message, level = None))]
+fn add_breadcrumb(category: String, message: String, level: Option<String>) {
+    let mut breadcrumb = Breadcrumb::new(category, message);
+    if let Some(level) = level {
+        breadcrumb = breadcrumb.with_level(level);
+    }
+    crash::add_breadcrumb(breadcrumb);
+}
+
+/// Tags future reports with `key: value`.
+#[pyfunction]
+fn set_tag(key: String, value: String) {
+    crash::set_tag(key, value);
+}
+
+/// Attaches `value`, as text, to future reports as the extra `key`.
+#[pyfunction]
+fn set_extra(key: String, value: &Bound<'_, PyAny>) -> PyResult<()> {
+    crash::set_extra(key, value.str()?.to_string());
+    Ok(())
+}
+
+/// Sets the user of future reports; no arguments clears it.
+#[pyfunction]
+#[pyo3(signature = (id = None, username = None, email = None))]
+fn set_user(id: Option<String>, username: Option<String>, email: Option<String>) {
+    let user = (id.is_some() || username.is_some() || email.is_some()).then(|| User {
+        id,
+        username,
+        email,
+        ..Default::default()
+    });
+    crash::set_user(user);
+}
+
+/// Waits up to `timeout` seconds for reports still being delivered. Returns
+/// whether all were.
+#[pyfunction]
+#[pyo3(signature = (timeout = 2.0))]
+fn flush(py: Python<'_>, timeout: f64) -> PyResult<bool> {
+    let timeout = Duration::try_from_secs_f64(timeout).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    Ok(py.allow_threads(|| crash::flush(timeout)))
+}
+
+#[pymodule]
+fn crash_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(init, m)?)?;
+    m.add_function(wrap_pyfunction!(capture_exception, m)?)?;
+    m.add_function(wrap_pyfunction!(capture_message, m)?)?;
+    m.add_function(wrap_pyfunction!(add_breadcrumb, m)?)?;
+    m.add_function(wrap_pyfunction!(set_tag, m)?)?;
+    m.add_function(wrap_pyfunction!(set_extra, m)?)?;
+    m.add_function(wrap_pyfunction!(set_user, m)?)?;
+    m.add_function(wrap_pyfunction!(flush, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use serde::Serialize;
+
+// ----- Application streams -----
+//
+// Clients append data registered with `crash::add_minidump_stream` to their
+// minidumps as streams of type APP_STREAM_TYPE: the length of the name (u32,
+// little-endian), the name, then the data. Minidump readers skip unknown
+// streams, so they are read from the raw dump here.
+
+const APP_STREAM_TYPE: u32 = 0x4352_0001;
+const MINIDUMP_SIGNATURE: u32 = 0x504d_444d; // "MDMP"
+const HEADER_SIZE: usize = 32;
+const DIRECTORY_ENTRY_SIZE: usize = 12;
+
+// Text beyond this is cut from the summary; the dump keeps all of it.
+const MAX_TEXT: usize = 64 * 1024;
+const MAX_HEX: usize = 256;
+
+#[derive(Serialize)]
+pub struct AppStream {
+    pub name: String,
+    pub size: usize,
+    // UTF-8 data as text, anything else as hex.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hex: Option<String>,
+    pub truncated: bool,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn describe(payload: &[u8]) -> Option<AppStream> {
+    let name_len = read_u32(payload, 0)? as usize;
+    let name = std::str::from_utf8(payload.get(4..4 + name_len)?).ok()?;
+    let data = &payload[4 + name_len..];
+    let mut stream = AppStream {
+        name: name.to_string(),
+        size: data.len(),
+        text: None,
+        hex: None,
+        truncated: false,
+    };
+    match std::str::from_utf8(data) {
+        Ok(text) => {
+            let mut end = text.len().min(MAX_TEXT);
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            stream.text = Some(text[..end].to_string());
+            stream.truncated = end < text.len();
+        }
+        Err(_) => {
+            let shown = &data[..data.len().min(MAX_HEX)];
+            stream.hex = Some(shown.iter().map(|b| format!("{:02x}", b)).collect());
+            stream.truncated = shown.len() < data.len();
+        }
+    }
+    Some(stream)
+}
+
+/// The application streams of a raw minidump, in directory order. Malformed
+/// streams and dumps are skipped; `validate_minidump` reports those.
+pub fn app_streams(data: &[u8]) -> Vec<AppStream> {
+    if data.len() < HEADER_SIZE || read_u32(data, 0) != Some(MINIDUMP_SIGNATURE) {
+        return Vec::new();
+    }
+    let stream_count = read_u32(data, 8).unwrap_or(0) as usize;
+    let directory_rva = read_u32(data, 12).unwrap_or(0) as usize;
+
+    let mut streams = Vec::new();
+    for i in 0..stream_count {
+        let entry = directory_rva + i * DIRECTORY_ENTRY_SIZE;
+        let (Some(stream_type), Some(size), Some(rva)) =
+            (read_u32(data, entry), read_u32(data, entry + 4), read_u32(data, entry + 8))
+        else {
+            break;
+        };
+        if stream_type != APP_STREAM_TYPE {
+            continue;
+        }
+        let payload = data.get(rva as usize..rva as usize + size as usize);
+        if let Some(stream) = payload.and_then(describe) {
+            streams.push(stream);
+        }
+    }
+    streams
+}
@@ -0,0 +1,136 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::storage::TEMP_SUFFIX;
+
+// ----- Storage backends -----
+//
+// Artifacts (reports, minidumps, attachments, `.sum` files) and the records
+// the server keeps next to them (sessions, analyses, issue state, tombstones)
+// are stored through a `Storage`: files in the storage directory by default,
+// or objects in an S3-compatible bucket with CRASH_STORAGE=s3, see
+// `crate::s3`. With object storage the server keeps nothing of its own on
+// disk but the crash index, which is rebuilt from the bucket when missing, so
+// it can run in a container without a volume. `crate::storage` picks the
+// backend at startup; everything else goes through its helpers.
+
+/// A stored object and when it last changed.
+pub struct Object {
+    pub name: String,
+    pub modified: u64,        // Milliseconds since the UNIX epoch
+    pub created: Option<u64>, // Milliseconds since the UNIX epoch, if recorded
+}
+
+/// Where artifacts are kept. Names are flat file names such as
+/// `crash_report_<id>.json`.
+pub trait Storage: Send + Sync {
+    /// The content of `name`, failing with `NotFound` if there is none.
+    fn get(&self, name: &str) -> std::io::Result<Vec<u8>>;
+
+    /// Stores `data` as `name`, replacing what was there. Readers never see
+    /// it half written.
+    fn put(&self, name: &str, data: &[u8]) -> std::io::Result<()>;
+
+    /// Every stored object, except writes in progress.
+    fn list(&self) -> std::io::Result<Vec<Object>>;
+
+    /// Removes `name`, failing with `NotFound` if there is none, where the
+    /// backend can tell.
+    fn delete(&self, name: &str) -> std::io::Result<()>;
+
+    /// `name` and when it changed, or `None` if there is no such object.
+    fn stat(&self, name: &str) -> std::io::Result<Option<Object>>;
+
+    /// Adds `line` and a newline to the end of `name`, creating it if
+    /// missing. Object stores cannot append, so by default the object is
+    /// read and written back whole; fine for the small logs kept this way.
+    fn append(&self, name: &str, line: &str) -> std::io::Result<()> {
+        let mut data = match self.get(name) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        data.extend_from_slice(line.as_bytes());
+        data.push(b'\n');
+        self.put(name, &data)
+    }
+}
+
+pub fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// Files in a directory.
+pub struct FileStorage {
+    dir: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileStorage { dir: dir.into() }
+    }
+
+    fn object(name: String, metadata: &fs::Metadata) -> Object {
+        let modified = metadata.modified().map(to_millis).unwrap_or(0);
+        Object {
+            name,
+            modified,
+            // Not every filesystem records a birth time.
+            created: metadata.created().map(to_millis).ok(),
+        }
+    }
+}
+
+impl Storage for FileStorage {
+    fn get(&self, name: &str) -> std::io::Result<Vec<u8>> {
+        fs::read(self.dir.join(name))
+    }
+
+    // Written to `<name>.tmp` and renamed once complete.
+    fn put(&self, name: &str, data: &[u8]) -> std::io::Result<()> {
+        let temp = self.dir.join(format!("{}{}", name, TEMP_SUFFIX));
+        let written = fs::write(&temp, data).and_then(|()| fs::rename(&temp, self.dir.join(name)));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        written
+    }
+
+    fn list(&self) -> std::io::Result<Vec<Object>> {
+        let mut objects = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(TEMP_SUFFIX) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                objects.push(Self::object(name, &metadata));
+            }
+        }
+        Ok(objects)
+    }
+
+    fn delete(&self, name: &str) -> std::io::Result<()> {
+        fs::remove_file(self.dir.join(name))
+    }
+
+    fn stat(&self, name: &str) -> std::io::Result<Option<Object>> {
+        match fs::metadata(self.dir.join(name)) {
+            Ok(metadata) => Ok(Some(Self::object(name.to_string(), &metadata))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn append(&self, name: &str, line: &str) -> std::io::Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(name))?;
+        writeln!(file, "{}", line)
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use symbolic::debuginfo::{Archive, FileFormat, Function, Object};
+
+// ----- Native debug files -----
+//
+// The symbolizer only reads Breakpad symbol files. Debug files in other
+// formats (PDBs, ELF files with DWARF, the Mach-O DWARF files of dSYM
+// bundles) are converted with symbolic, whether uploaded to `POST /symbols`
+// or fetched from symbol servers (see `crate::symbol_servers`) and
+// debuginfod (see `crate::debuginfod`): a `MODULE` line, the source files, one `FUNC`
+// record per function with its line records, and `PUBLIC` records for the
+// symbols no function covers. Inlined code is attributed to the lines it was
+// inlined from, as Breakpad's own `dump_syms` does without inline records.
+// No CFI is written, so frames of these modules are unwound with frame
+// pointers and stack scanning.
+
+/// Where an address of a module is in its source.
+#[derive(Clone)]
+pub struct SourceLocation {
+    pub function: String,
+    pub file: Option<String>,
+    pub line: Option<u64>,
+}
+
+fn breakpad_os(format: FileFormat) -> &'static str {
+    match format {
+        FileFormat::Pdb | FileFormat::Pe => "windows",
+        FileFormat::MachO => "mac",
+        FileFormat::Elf => "Linux",
+        _ => "unknown",
+    }
+}
+
+// Line records of `function` and of the code inlined into it, as (address,
+// size, file, line) relative to the module.
+fn collect_lines(function: &Function, load_address: u64, lines: &mut Vec<(u64, Option<u64>, String, u64)>) {
+    for line in &function.lines {
+        lines.push((
+            line.address.saturating_sub(load_address),
+            line.size,
+            line.file.path_str(),
+            line.line,
+        ));
+    }
+    for inlinee in &function.inlinees {
+        collect_lines(inlinee, load_address, lines);
+    }
+}
+
+/// Converts the debug file `data`, named `debug_file`, to a Breakpad symbol
+/// file.
+pub fn to_breakpad(debug_file: &str, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let object = Object::parse(data).map_err(|e| anyhow::anyhow!("Cannot read {}: {}", debug_file, e))?;
+    object_to_breakpad(debug_file, &object)
+}
+
+/// Converts every object of the debug file `data`, named `debug_file`: one
+/// for most formats, one per architecture for universal Mach-O files.
+pub fn to_breakpad_all(debug_file: &str, data: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let archive = Archive::parse(data).map_err(|e| anyhow::anyhow!("Cannot read {}: {}", debug_file, e))?;
+    let mut converted = Vec::new();
+    for object in archive.objects() {
+        let object = object.map_err(|e| anyhow::anyhow!("Cannot read {}: {}", debug_file, e))?;
+        if !object.has_debug_info() && !object.has_symbols() {
+            anyhow::bail!("{} has neither debug info nor symbols", debug_file);
+        }
+        converted.push(object_to_breakpad(debug_file, &object)?);
+    }
+    if converted.is_empty() {
+        anyhow::bail!("{} contains no objects", debug_file);
+    }
+    Ok(converted)
+}
+
+fn object_to_breakpad(debug_file: &str, object: &Object) -> anyhow::Result<Vec<u8>> {
+    let load_address = object.load_address();
+    let mut files: HashMap<String, usize> = HashMap::new();
+    let mut records = String::new();
+    let mut function_starts = HashSet::new();
+
+    let session = object.debug_session()?;
+    for function in session.functions() {
+        let function = function?;
+        let address = function.address.saturating_sub(load_address);
+        if function.size == 0 || !function_starts.insert(address) {
+            continue;
+        }
+        writeln!(records, "FUNC {:x} {:x} 0 {}", address, function.size, function.name.as_str())?;
+
+        let mut lines = Vec::new();
+        collect_lines(&function, load_address, &mut lines);
+        lines.sort_by_key(|line| line.0);
+        let end = address + function.size;
+        for (i, (line_address, size, file, line)) in lines.iter().enumerate() {
+            // Without a size, a line runs to the next one or the function end.
+            let next = lines.get(i + 1).map_or(end, |next| next.0);
+            let size = size.unwrap_or_else(|| next.saturating_sub(*line_address));
+            if size == 0 {
+                continue;
+            }
+            let count = files.len();
+            let file_number = *files.entry(file.clone()).or_insert(count);
+            writeln!(records, "{:x} {:x} {} {}", line_address, size, line, file_number)?;
+        }
+    }
+
+    for symbol in object.symbols() {
+        let address = symbol.address.saturating_sub(load_address);
+        if let Some(name) = symbol.name.as_deref().filter(|_| !function_starts.contains(&address)) {
+            writeln!(records, "PUBLIC {:x} 0 {}", address, name)?;
+        }
+    }
+
+    let mut output = String::new();
+    writeln!(
+        output,
+        "MODULE {} {} {} {}",
+        breakpad_os(object.file_format()),
+        object.arch().name(),
+        object.debug_id().breakpad(),
+        debug_file
+    )?;
+    let mut files: Vec<(String, usize)> = files.into_iter().collect();
+    files.sort_by_key(|file| file.1);
+    for (name, number) in files {
+        writeln!(output, "FILE {} {}", number, name)?;
+    }
+    output.push_str(&records);
+    Ok(output.into_bytes())
+}
+
+/// Looks up `addresses`, relative to the module, in its Breakpad symbols.
+pub fn lookup(symbols: &[u8], addresses: &[u64]) -> anyhow::Result<Vec<Option<SourceLocation>>> {
+    let object = Object::parse(symbols)?;
+    let session = object.debug_session()?;
+    let mut found = vec![None; addresses.len()];
+    for function in session.functions() {
+        let function = function?;
+        let end = function.address.saturating_add(function.size);
+        for (address, location) in addresses.iter().zip(found.iter_mut()) {
+            if location.is_some() || !(function.address..end).contains(address) {
+                continue;
+            }
+            // The last line starting at or before the address.
+            let line = function.lines.iter().filter(|line| line.address <= *address).max_by_key(|line| line.address);
+            *location = Some(SourceLocation {
+                function: function.name.as_str().to_string(),
+                file: line.map(|line| line.file.path_str()),
+                line: line.map(|line| line.line),
+            });
+        }
+    }
+    Ok(found)
+}
@@ -0,0 +1,256 @@
+use minidump::system_info::Os;
+use minidump::{Minidump, MinidumpModuleList, MinidumpSystemInfo, Module};
+use std::collections::HashMap;
+use std::str::FromStr;
+use symbolic::common::DebugId;
+
+use crate::debug_files::{self, SourceLocation};
+use crate::symbol_servers::{self, WantedModule};
+use crate::symbols;
+
+// ----- debuginfod -----
+//
+// Linux distributions serve the debug info of their packages by GNU build-id
+// from debuginfod servers (`<server>/buildid/<build-id>/debuginfo`). The
+// servers in CRASH_DEBUGINFOD_URLS, or else DEBUGINFOD_URLS, separated by
+// spaces or commas, are asked for the ELF modules that no symbol directory
+// has symbols for:
+//
+// - those of Linux minidumps, before they are processed;
+// - those of JSON reports with frames that only carry an instruction
+//   address, which `/crash/{id}` resolves to function, file and line with
+//   the report's `debug_meta` images. Such frames are resolved from uploaded
+//   symbols as well, with or without debuginfod.
+//
+// The debug info is converted to Breakpad symbols and kept in the symbol
+// cache, like PDBs from symbol servers, see `crate::symbol_servers`.
+
+pub const DEBUGINFOD_URLS_ENV: &str = "CRASH_DEBUGINFOD_URLS";
+const MAX_DEBUGINFO_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+
+fn servers() -> Vec<String> {
+    std::env::var(DEBUGINFOD_URLS_ENV)
+        .ok()
+        .filter(|urls| !urls.trim().is_empty())
+        .or_else(|| std::env::var("DEBUGINFOD_URLS").ok())
+        .unwrap_or_default()
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .map(|url| url.trim_end_matches('/'))
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The ELF modules of a Linux `dump` that have no symbols yet and may be on
+/// a debuginfod server.
+pub fn wanted_modules<T: std::ops::Deref<Target = [u8]>>(dump: &Minidump<T>) -> Vec<WantedModule> {
+    if servers().is_empty() {
+        return Vec::new();
+    }
+    let is_linux = dump
+        .get_stream::<MinidumpSystemInfo>()
+        .is_ok_and(|info| matches!(info.os, Os::Linux | Os::Android));
+    let Ok(modules) = dump.get_stream::<MinidumpModuleList>() else {
+        return Vec::new();
+    };
+    if !is_linux {
+        return Vec::new();
+    }
+    modules
+        .iter()
+        .filter_map(|module| {
+            let debug_file = module.debug_file()?;
+            Some(WantedModule {
+                debug_file: debug_file.rsplit('/').next()?.to_string(),
+                debug_id: module.debug_identifier()?.breakpad().to_string(),
+                code_id: Some(module.code_identifier()?.as_str().to_ascii_lowercase()),
+            })
+        })
+        .filter(|module| symbols::wanted(&module.debug_file, &module.debug_id))
+        .collect()
+}
+
+/// Fetches the debug info of `modules` by build-id from the first server
+/// that has each. Blocks on the downloads.
+pub fn fetch(modules: &[WantedModule]) {
+    symbol_servers::fetch_with(modules, |agent, module| {
+        let Some(build_id) = module.code_id.as_deref().filter(|id| !id.is_empty()) else {
+            return Ok(None);
+        };
+        for server in servers() {
+            let url = format!("{}/buildid/{}/debuginfo", server, build_id);
+            if let Some(debug_info) = symbol_servers::download(agent, &url, MAX_DEBUGINFO_SIZE)? {
+                return Ok(Some(debug_info));
+            }
+        }
+        Ok(None)
+    });
+}
+
+// An ELF module of a JSON report, from `debug_meta`.
+struct Image {
+    module: WantedModule,
+    start: u64,
+    end: u64,
+}
+
+fn parse_hex(value: &serde_json::Value) -> Option<u64> {
+    u64::from_str_radix(value.as_str()?.trim_start_matches("0x"), 16).ok()
+}
+
+fn report_images(report: &serde_json::Value) -> Vec<Image> {
+    let Some(images) = report.pointer("/debug_meta/images").and_then(|v| v.as_array()) else {
+        return Vec::new();
+    };
+    images
+        .iter()
+        .filter(|image| image.get("type").and_then(|v| v.as_str()) == Some("elf"))
+        .filter_map(|image| {
+            let start = parse_hex(image.get("image_addr")?)?;
+            let code_file = image.get("code_file")?.as_str()?;
+            let debug_id = DebugId::from_str(image.get("debug_id")?.as_str()?).ok()?;
+            Some(Image {
+                module: WantedModule {
+                    debug_file: code_file.rsplit('/').next()?.to_string(),
+                    debug_id: debug_id.breakpad().to_string(),
+                    code_id: image.get("code_id").and_then(|v| v.as_str()).map(str::to_string),
+                },
+                start,
+                end: start.saturating_add(image.get("image_size")?.as_u64()?),
+            })
+        })
+        .collect()
+}
+
+// Every frame list of the report: the event's stack, those of its
+// exceptions and those of its threads.
+fn for_each_stack(report: &mut serde_json::Value, mut visit: impl FnMut(&mut Vec<serde_json::Value>)) {
+    let mut stacks: Vec<&mut serde_json::Value> = Vec::new();
+    let serde_json::Value::Object(report) = report else {
+        return;
+    };
+    for (key, value) in report.iter_mut() {
+        match key.as_str() {
+            "stacktrace" => stacks.push(value),
+            "exception" => stacks.extend(
+                value
+                    .get_mut("values")
+                    .and_then(|v| v.as_array_mut())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|value| value.get_mut("stacktrace")),
+            ),
+            "threads" => stacks.extend(
+                value
+                    .as_array_mut()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|thread| thread.get_mut("stacktrace")),
+            ),
+            _ => {}
+        }
+    }
+    for stack in stacks {
+        if let Some(frames) = stack.get_mut("frames").and_then(|v| v.as_array_mut()) {
+            visit(frames);
+        }
+    }
+}
+
+// The address to look up for a frame without a function. Frames are stored
+// outermost first; all but the innermost hold return addresses, which point
+// past the call.
+fn unresolved_address(frame: &serde_json::Value, innermost: bool) -> Option<u64> {
+    if frame.get("function").is_some_and(|f| !f.is_null()) {
+        return None;
+    }
+    let address = parse_hex(frame.get("instruction_addr")?)?;
+    Some(if innermost { address } else { address.saturating_sub(1) })
+}
+
+// Looks up `addresses` in the symbols of the images they fall in, fetching
+// missing symbols first. Blocks on the downloads.
+fn resolve(images: Vec<Image>, addresses: Vec<u64>) -> HashMap<u64, SourceLocation> {
+    let mut by_image: HashMap<usize, Vec<u64>> = HashMap::new();
+    for address in addresses {
+        if let Some(i) = images.iter().position(|image| (image.start..image.end).contains(&address)) {
+            by_image.entry(i).or_default().push(address);
+        }
+    }
+
+    let missing: Vec<WantedModule> = by_image
+        .keys()
+        .map(|&i| &images[i].module)
+        .filter(|module| symbols::wanted(&module.debug_file, &module.debug_id))
+        .cloned()
+        .collect();
+    if !servers().is_empty() {
+        fetch(&missing);
+    }
+
+    let mut resolved = HashMap::new();
+    for (i, addresses) in by_image {
+        let image = &images[i];
+        let Some(path) = symbols::find_symbols(&image.module.debug_file, &image.module.debug_id) else {
+            continue;
+        };
+        let relative: Vec<u64> = addresses.iter().map(|address| address - image.start).collect();
+        let found = std::fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| debug_files::lookup(&data, &relative));
+        match found {
+            Ok(found) => resolved.extend(
+                addresses
+                    .into_iter()
+                    .zip(found)
+                    .filter_map(|(address, location)| Some((address, location?))),
+            ),
+            Err(e) => eprintln!("Failed to read symbols of {}: {}", image.module.debug_file, e),
+        }
+    }
+    resolved
+}
+
+/// Fills in function, file and line of the frames of a JSON report that
+/// only carry an instruction address, see above.
+pub async fn symbolicate_report(report: &mut serde_json::Value) {
+    let images = report_images(report);
+    if images.is_empty() {
+        return;
+    }
+    let mut addresses = Vec::new();
+    for_each_stack(report, |frames| {
+        let innermost = frames.len().saturating_sub(1);
+        for (i, frame) in frames.iter().enumerate() {
+            addresses.extend(unresolved_address(frame, i == innermost));
+        }
+    });
+    if addresses.is_empty() {
+        return;
+    }
+
+    let resolved = match tokio::task::spawn_blocking(move || resolve(images, addresses)).await {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            eprintln!("Failed to symbolicate report: {}", e);
+            return;
+        }
+    };
+    for_each_stack(report, |frames| {
+        let innermost = frames.len().saturating_sub(1);
+        for (i, frame) in frames.iter_mut().enumerate() {
+            let Some(location) = unresolved_address(frame, i == innermost).and_then(|a| resolved.get(&a)) else {
+                continue;
+            };
+            if let Some(frame) = frame.as_object_mut() {
+                frame.insert("function".to_string(), location.function.clone().into());
+                if let Some(file) = &location.file {
+                    frame.insert("filename".to_string(), file.clone().into());
+                }
+                if let Some(line) = location.line {
+                    frame.insert("lineno".to_string(), line.into());
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,86 @@
+use actix_web::HttpRequest;
+use age::x25519::Identity;
+use std::fs;
+use std::sync::OnceLock;
+
+// ----- Encrypted artifacts -----
+//
+// Clients can encrypt reports, minidumps and attachments with age to an X25519
+// recipient (`Builder::encrypt_to`). The server decrypts them with the
+// identities in the file named by CRASH_AGE_IDENTITY: `AGE-SECRET-KEY-1...`
+// lines as written by `age-keygen`, several for key rotation, `#` comments
+// allowed. Uploads marked `X-Crash-Encryption: age` are decrypted on arrival,
+// then decompressed if they were compressed inside the ciphertext, and stored
+// like any other upload. Files ending in `.age` that reach the storage
+// directory otherwise are decrypted whenever they are read, and written back
+// encrypted to the first identity. Without identities both are rejected.
+
+pub const IDENTITY_FILE_ENV: &str = "CRASH_AGE_IDENTITY";
+pub const HEADER: &str = "X-Crash-Encryption";
+pub const EXTENSION: &str = ".age";
+
+static IDENTITIES: OnceLock<Vec<Identity>> = OnceLock::new();
+
+/// Loads the identities named by CRASH_AGE_IDENTITY, if set. Returns how many
+/// there are.
+pub fn load() -> anyhow::Result<usize> {
+    let mut identities = Vec::new();
+    if let Ok(path) = std::env::var(IDENTITY_FILE_ENV) {
+        let data = fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to read identity file '{}': {}", path, e))?;
+        for line in data.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let identity = line
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid identity in '{}': {}", path, e))?;
+            identities.push(identity);
+        }
+        if identities.is_empty() {
+            anyhow::bail!("No identities in '{}'", path);
+        }
+    }
+    let count = identities.len();
+    let _ = IDENTITIES.set(identities);
+    Ok(count)
+}
+
+fn identities() -> std::io::Result<&'static [Identity]> {
+    match IDENTITIES.get() {
+        Some(identities) if !identities.is_empty() => Ok(identities),
+        _ => Err(std::io::Error::other(format!(
+            "Encrypted artifact, but no identities are configured ({})",
+            IDENTITY_FILE_ENV
+        ))),
+    }
+}
+
+/// Decrypts an age file with the configured identities.
+pub fn decrypt(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let identities = identities()?;
+    let decryptor = age::Decryptor::new_buffered(data).map_err(std::io::Error::other)?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|identity| identity as &dyn age::Identity))
+        .map_err(std::io::Error::other)?;
+    let mut plaintext = Vec::new();
+    std::io::Read::read_to_end(&mut reader, &mut plaintext)?;
+    Ok(plaintext)
+}
+
+/// Encrypts `data` to the first configured identity.
+pub fn encrypt(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let recipient = identities()?[0].to_public();
+    age::encrypt(&recipient, data).map_err(std::io::Error::other)
+}
+
+/// The body of an upload, decrypted and decompressed when the request is
+/// marked as encrypted, or as it came otherwise.
+pub fn decode_body(req: &HttpRequest, body: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let encryption = req.headers().get(HEADER).and_then(|v| v.to_str().ok());
+    match encryption {
+        None => Ok(body.to_vec()),
+        Some("age") => Ok(crate::storage::decompress_sniffed(decrypt(body)?)?),
+        Some(other) => anyhow::bail!("Unsupported encryption '{}'", other),
+    }
+}
@@ -0,0 +1,711 @@
+use rusqlite::{params, params_from_iter, Connection};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{integrity, issues, load_sentry_json, postgres, stats, storage, ATTACHMENT_PREFIX};
+
+// ----- Metadata index -----
+//
+// Caches the per-crash metadata needed for listings so `/crashes` does not
+// re-parse every report. The index is an SQLite database, INDEX_FILE in the
+// storage directory, that listings, filters and statistics query directly.
+// Ingest endpoints index what they store right away; a refresh picks up
+// reports that reached the storage directory some other way: entries
+// remember the report's mtime, so only reports that changed are re-read, and
+// those that disappeared are dropped. The index can be rebuilt from the
+// artifacts at any time, and is when it was written with an older
+// INDEX_VERSION.
+//
+// With CRASH_DATABASE_URL the index lives in PostgreSQL instead, shared by
+// every server instance, see `crate::postgres`. Both keep the same table and
+// are queried with the same SQL, apart from what `Dialect` covers.
+//
+// `POST /admin/reindex` can first import the crashes of an older install from
+// CRASH_IMPORT_DIR. Only that directory, set when the server starts, is read:
+// the request merely asks for the import.
+
+pub const IMPORT_DIR_ENV: &str = "CRASH_IMPORT_DIR";
+pub const INDEX_FILE: &str = "crash_index.sqlite";
+pub const INDEX_VERSION: i64 = 5;
+// Where version 2 and older kept the index.
+const LEGACY_INDEX_FILE: &str = "crash_index.json";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS crashes (
+        id           TEXT PRIMARY KEY,
+        timestamp    TEXT,
+        seconds      REAL,
+        message      TEXT,
+        level        TEXT,
+        release      TEXT,
+        module       TEXT,
+        issue_id     TEXT NOT NULL,
+        has_minidump INTEGER NOT NULL,
+        flagged      INTEGER NOT NULL,
+        modified     INTEGER NOT NULL,
+        tags         TEXT NOT NULL,
+        project      TEXT
+    );
+    CREATE INDEX IF NOT EXISTS crashes_seconds ON crashes (seconds);
+    CREATE INDEX IF NOT EXISTS crashes_issue ON crashes (issue_id);
+";
+
+pub const COLUMNS: &str = "id, timestamp, message, level, release, module, issue_id, has_minidump, flagged, modified, tags, project";
+
+#[derive(Serialize, Clone)]
+pub struct IndexEntry {
+    pub id: String,
+    pub timestamp: Option<String>,
+    pub message: Option<String>,
+    pub level: Option<String>,
+    pub release: Option<String>,
+    pub module: Option<String>, // Crate or module the crash happened in
+    pub issue_id: String, // Hash of the fingerprint, see `crate::issues`
+    pub has_minidump: bool,
+    pub flagged: bool, // Failed its integrity checks, see `crate::integrity`
+    pub modified: u64, // Report mtime in milliseconds, used to detect changes
+    pub tags: BTreeMap<String, String>,
+    pub project: Option<String>, // Set by the ingest endpoints, see `crate::quotas`
+}
+
+impl IndexEntry {
+    /// The report timestamp, in seconds since the UNIX epoch.
+    pub fn seconds(&self) -> Option<f64> {
+        self.timestamp.as_deref().and_then(|t| t.parse().ok())
+    }
+
+    /// The values of COLUMNS and then `seconds`, as stored.
+    pub fn values(&self) -> Vec<SqlValue> {
+        vec![
+            SqlValue::Text(Some(self.id.clone())),
+            SqlValue::Text(self.timestamp.clone()),
+            SqlValue::Text(self.message.clone()),
+            SqlValue::Text(self.level.clone()),
+            SqlValue::Text(self.release.clone()),
+            SqlValue::Text(self.module.clone()),
+            SqlValue::Text(Some(self.issue_id.clone())),
+            SqlValue::Bool(self.has_minidump),
+            SqlValue::Bool(self.flagged),
+            SqlValue::Integer(self.modified as i64),
+            SqlValue::Text(Some(serde_json::to_string(&self.tags).unwrap_or_default())),
+            SqlValue::Text(self.project.clone()),
+            SqlValue::Real(self.seconds()),
+        ]
+    }
+
+    // Reads a row selected with COLUMNS.
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(IndexEntry {
+            id: row.get(0)?,
+            timestamp: row.get(1)?,
+            message: row.get(2)?,
+            level: row.get(3)?,
+            release: row.get(4)?,
+            module: row.get(5)?,
+            issue_id: row.get(6)?,
+            has_minidump: row.get(7)?,
+            flagged: row.get(8)?,
+            modified: row.get::<_, i64>(9)? as u64,
+            tags: decode_tags(&row.get::<_, String>(10)?),
+            project: row.get(11)?,
+        })
+    }
+}
+
+/// Tags as stored in the `tags` column, a JSON object.
+pub fn decode_tags(json: &str) -> BTreeMap<String, String> {
+    serde_json::from_str(json).unwrap_or_default()
+}
+
+/// A query parameter, bound the same way by both databases.
+#[derive(Clone)]
+pub enum SqlValue {
+    Text(Option<String>),
+    Real(Option<f64>),
+    Integer(i64),
+    Bool(bool),
+}
+
+impl rusqlite::ToSql for SqlValue {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        match self {
+            SqlValue::Text(value) => value.to_sql(),
+            SqlValue::Real(value) => value.to_sql(),
+            SqlValue::Integer(value) => value.to_sql(),
+            SqlValue::Bool(value) => value.to_sql(),
+        }
+    }
+}
+
+/// Where the SQL of the two databases differs.
+#[derive(Clone, Copy)]
+pub enum Dialect {
+    Sqlite,
+    Postgres,
+}
+
+impl Dialect {
+    // The placeholder of the `n`th parameter, counting from 1.
+    fn placeholder(self, n: usize) -> String {
+        match self {
+            Dialect::Sqlite => "?".to_string(),
+            Dialect::Postgres => format!("${}", n),
+        }
+    }
+
+    // Whether `column` contains the text parameter `placeholder`.
+    fn contains(self, column: &str, placeholder: &str) -> String {
+        match self {
+            Dialect::Sqlite => format!("instr({}, {}) > 0", column, placeholder),
+            Dialect::Postgres => format!("strpos({}, {}) > 0", column, placeholder),
+        }
+    }
+}
+
+/// Inserts an entry, or replaces the one with its id. Takes the values of
+/// COLUMNS and then `seconds`.
+pub fn upsert_sql(dialect: Dialect) -> String {
+    let columns: Vec<&str> = COLUMNS.split(", ").chain(["seconds"]).collect();
+    let placeholders: Vec<String> = (1..=columns.len()).map(|n| dialect.placeholder(n)).collect();
+    let updates: Vec<String> = columns[1..]
+        .iter()
+        .map(|column| format!("{0} = excluded.{0}", column))
+        .collect();
+    format!(
+        "INSERT INTO crashes ({}) VALUES ({}) ON CONFLICT (id) DO UPDATE SET {}",
+        columns.join(", "),
+        placeholders.join(", "),
+        updates.join(", ")
+    )
+}
+
+/// The crashes of one issue, as `GROUP BY issue_id` sums them up.
+pub struct IssueCounts {
+    pub issue_id: String,
+    pub count: u64,
+    pub first_seen: Option<f64>, // Seconds since the UNIX epoch
+    pub last_seen: Option<f64>,
+}
+
+/// Sums up every issue; both databases return the columns of `IssueCounts`.
+pub const ISSUE_COUNTS_SQL: &str =
+    "SELECT issue_id, COUNT(*), MIN(seconds), MAX(seconds) FROM crashes GROUP BY issue_id";
+
+// Values bound per `IN (...)` list, well within the parameter limits of both
+// databases.
+const IN_CHUNK: usize = 500;
+
+/// What a refresh changes, applied at once.
+#[derive(Default)]
+pub struct Changes {
+    pub upserted: Vec<IndexEntry>,
+    pub minidumps: Vec<(String, bool)>, // Entries whose dump arrived or went
+    pub removed: Vec<String>,
+}
+
+/// The database the index is kept in.
+pub trait IndexStore: Send {
+    fn dialect(&self) -> Dialect;
+
+    /// The report mtime and whether there is a dump, of every entry.
+    fn states(&self) -> anyhow::Result<HashMap<String, (u64, bool)>>;
+
+    /// Applies `changes` in one transaction.
+    fn apply(&mut self, changes: Changes) -> anyhow::Result<()>;
+
+    fn clear(&mut self) -> anyhow::Result<()>;
+
+    /// The entries `SELECT COLUMNS FROM crashes <clause>` returns.
+    fn select(&self, clause: &str, values: Vec<SqlValue>) -> anyhow::Result<Vec<IndexEntry>>;
+
+    /// What `SELECT COUNT(*) FROM crashes <clause>` returns.
+    fn count(&self, clause: &str, values: Vec<SqlValue>) -> anyhow::Result<usize>;
+
+    /// What ISSUE_COUNTS_SQL returns.
+    fn issue_counts(&self) -> anyhow::Result<Vec<IssueCounts>>;
+}
+
+/// Narrows a crash listing. Unset fields let every crash through.
+#[derive(Default)]
+pub struct CrashFilter {
+    pub since: Option<f64>, // Seconds since the UNIX epoch, inclusive
+    pub until: Option<f64>, // Seconds since the UNIX epoch, exclusive
+    pub levels: Vec<String>,
+    pub text: Option<String>, // Lowercase, matched against the message
+}
+
+impl CrashFilter {
+    // The WHERE clause and its parameters. Crashes without a timestamp fall
+    // outside every time range.
+    fn to_sql(&self, dialect: Dialect) -> (String, Vec<SqlValue>) {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        let next = |values: &mut Vec<SqlValue>, value: SqlValue| {
+            values.push(value);
+            dialect.placeholder(values.len())
+        };
+        if let Some(since) = self.since {
+            conditions.push(format!("seconds >= {}", next(&mut values, SqlValue::Real(Some(since)))));
+        }
+        if let Some(until) = self.until {
+            conditions.push(format!("seconds < {}", next(&mut values, SqlValue::Real(Some(until)))));
+        }
+        if !self.levels.is_empty() {
+            let placeholders: Vec<String> = self
+                .levels
+                .iter()
+                .map(|level| next(&mut values, SqlValue::Text(Some(level.clone()))))
+                .collect();
+            conditions.push(format!("level IN ({})", placeholders.join(", ")));
+        }
+        if let Some(text) = &self.text {
+            let placeholder = next(&mut values, SqlValue::Text(Some(text.clone())));
+            conditions.push(dialect.contains("lower(message)", &placeholder));
+        }
+        if conditions.is_empty() {
+            return (String::new(), values);
+        }
+        (format!("WHERE {}", conditions.join(" AND ")), values)
+    }
+}
+
+/// What a crash listing is ordered by.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Timestamp,
+    Message,
+    Module,
+}
+
+impl SortKey {
+    /// Parses `timestamp`, `message` or `module`.
+    pub fn from_name(name: &str) -> Option<SortKey> {
+        match name {
+            "timestamp" => Some(SortKey::Timestamp),
+            "message" => Some(SortKey::Message),
+            "module" => Some(SortKey::Module),
+            _ => None,
+        }
+    }
+
+    fn column(self) -> &'static str {
+        match self {
+            SortKey::Timestamp => "seconds",
+            SortKey::Message => "message",
+            SortKey::Module => "module",
+        }
+    }
+}
+
+pub struct CrashIndex {
+    store: Box<dyn IndexStore>,
+}
+
+#[derive(Serialize)]
+pub struct ReindexReport {
+    pub imported: usize,
+    pub indexed: usize,
+    pub failed: Vec<String>,
+}
+
+// Where the crash happened: the module of the exception (native crashes),
+// or else the crate of the innermost application frame, or of the innermost
+// frame when none is marked as the application's.
+fn crash_module(json: &serde_json::Value) -> Option<String> {
+    let exception_module = json
+        .pointer("/exception/values")
+        .and_then(|v| v.as_array())
+        .and_then(|values| values.last())
+        .and_then(|value| value.get("module"))
+        .and_then(|v| v.as_str());
+    if let Some(module) = exception_module {
+        return Some(module.to_string());
+    }
+    let frames = json.pointer("/stacktrace/frames")?.as_array()?;
+    // Frames are stored outermost first, so the crash site is last.
+    let module = |frame: &serde_json::Value| stats::module_of(frame.get("function")?.as_str()?);
+    frames
+        .iter()
+        .rev()
+        .filter(|frame| frame.get("in_app").and_then(|v| v.as_bool()) == Some(true))
+        .find_map(module)
+        .or_else(|| frames.iter().rev().find_map(module))
+}
+
+// Tags as an object, or as Sentry's list of `[key, value]` pairs.
+fn report_tags(json: &serde_json::Value) -> BTreeMap<String, String> {
+    let text = |value: &serde_json::Value| value.as_str().map(str::to_string).unwrap_or_else(|| value.to_string());
+    match json.get("tags") {
+        Some(serde_json::Value::Object(tags)) => tags.iter().map(|(key, value)| (key.clone(), text(value))).collect(),
+        Some(serde_json::Value::Array(pairs)) => pairs
+            .iter()
+            .filter_map(|pair| match pair.as_array()?.as_slice() {
+                [key, value] => Some((key.as_str()?.to_string(), text(value))),
+                _ => None,
+            })
+            .collect(),
+        _ => BTreeMap::new(),
+    }
+}
+
+fn build_entry(id: &str, modified: u64, has_minidump: bool) -> anyhow::Result<IndexEntry> {
+    let json = load_sentry_json(id)?;
+    let str_field = |key: &str| json.get(key).and_then(|v| v.as_str()).map(|s| s.to_string());
+    Ok(IndexEntry {
+        id: id.to_string(),
+        timestamp: str_field("timestamp"),
+        message: str_field("message"),
+        level: str_field("level"),
+        release: str_field("release"),
+        module: crash_module(&json),
+        issue_id: issues::issue_id_for(&json),
+        has_minidump,
+        flagged: integrity::report_integrity(id, &json).is_some_and(|i| i.flagged),
+        modified,
+        tags: report_tags(&json),
+        project: str_field("project"),
+    })
+}
+
+/// The index in the SQLite database INDEX_FILE.
+struct SqliteIndex {
+    db: Connection,
+}
+
+impl SqliteIndex {
+    fn open() -> anyhow::Result<Self> {
+        let _ = fs::remove_file(storage::path(LEGACY_INDEX_FILE));
+        let db = Connection::open(storage::path(INDEX_FILE))?;
+        let version: i64 = db.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version != INDEX_VERSION {
+            db.execute_batch("DROP TABLE IF EXISTS crashes;")?;
+            db.pragma_update(None, "user_version", INDEX_VERSION)?;
+        }
+        db.execute_batch(SCHEMA)?;
+        Ok(SqliteIndex { db })
+    }
+}
+
+impl IndexStore for SqliteIndex {
+    fn dialect(&self) -> Dialect {
+        Dialect::Sqlite
+    }
+
+    fn states(&self) -> anyhow::Result<HashMap<String, (u64, bool)>> {
+        let mut stmt = self.db.prepare("SELECT id, modified, has_minidump FROM crashes")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, (row.get::<_, i64>(1)? as u64, row.get::<_, bool>(2)?)))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn apply(&mut self, changes: Changes) -> anyhow::Result<()> {
+        let tx = self.db.transaction()?;
+        // Timestamps are stored as given and as seconds, for range queries.
+        let upsert = upsert_sql(Dialect::Sqlite);
+        for entry in &changes.upserted {
+            tx.execute(&upsert, params_from_iter(entry.values()))?;
+        }
+        for (id, has_minidump) in &changes.minidumps {
+            tx.execute(
+                "UPDATE crashes SET has_minidump = ?1 WHERE id = ?2",
+                params![has_minidump, id],
+            )?;
+        }
+        for id in &changes.removed {
+            tx.execute("DELETE FROM crashes WHERE id = ?1", params![id])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> anyhow::Result<()> {
+        self.db.execute("DELETE FROM crashes", [])?;
+        Ok(())
+    }
+
+    fn select(&self, clause: &str, values: Vec<SqlValue>) -> anyhow::Result<Vec<IndexEntry>> {
+        let mut stmt = self.db.prepare(&format!("SELECT {} FROM crashes {}", COLUMNS, clause))?;
+        let rows = stmt.query_map(params_from_iter(values), IndexEntry::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn count(&self, clause: &str, values: Vec<SqlValue>) -> anyhow::Result<usize> {
+        let count: i64 = self.db.query_row(
+            &format!("SELECT COUNT(*) FROM crashes {}", clause),
+            params_from_iter(values),
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    fn issue_counts(&self) -> anyhow::Result<Vec<IssueCounts>> {
+        let mut stmt = self.db.prepare(ISSUE_COUNTS_SQL)?;
+        let rows = stmt.query_map([], |row| {
+            Ok(IssueCounts {
+                issue_id: row.get(0)?,
+                count: row.get::<_, i64>(1)? as u64,
+                first_seen: row.get(2)?,
+                last_seen: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+impl CrashIndex {
+    /// Opens the index, in PostgreSQL when configured or else in the storage
+    /// directory, creating it if missing and starting over if it was
+    /// written by an older version.
+    pub fn load() -> anyhow::Result<Self> {
+        let store: Box<dyn IndexStore> = match postgres::database() {
+            Some(database) => Box::new(postgres::PgIndex::open(database)?),
+            None => Box::new(SqliteIndex::open()?),
+        };
+        Ok(CrashIndex { store })
+    }
+
+    /// Brings the index in line with the stored reports. Returns the IDs of
+    /// reports that could not be indexed.
+    pub fn refresh(&mut self) -> anyhow::Result<Vec<String>> {
+        let mut known = self.store.states()?;
+        let mut changes = Changes::default();
+        let mut failed = Vec::new();
+        let objects = storage::list()?;
+        // Minidumps are found in the same listing: looking each one up would
+        // take a request per file name it can have on object storage.
+        let minidumps: HashSet<&str> = objects
+            .iter()
+            .filter_map(|object| storage::minidump_id(&object.name))
+            .collect();
+        for object in &objects {
+            let Some(id) = storage::report_id(&object.name) else {
+                continue;
+            };
+
+            let modified = object.modified;
+            let has_minidump = minidumps.contains(id);
+            if let Some((known_modified, known_minidump)) = known.remove(id) {
+                if known_modified == modified {
+                    if known_minidump != has_minidump {
+                        changes.minidumps.push((id.to_string(), has_minidump));
+                    }
+                    continue;
+                }
+            }
+            match build_entry(id, modified, has_minidump) {
+                Ok(indexed) => changes.upserted.push(indexed),
+                Err(_) => failed.push(id.to_string()),
+            }
+        }
+
+        // What is left was not found in storage.
+        changes.removed = known.into_keys().collect();
+        self.store.apply(changes)?;
+        Ok(failed)
+    }
+
+    /// Discards the index and rebuilds it from the artifacts.
+    pub fn rebuild(&mut self) -> anyhow::Result<Vec<String>> {
+        self.store.clear()?;
+        self.refresh()
+    }
+
+    /// Drops the crashes `ids`, whose artifacts were deleted.
+    pub fn remove(&mut self, ids: Vec<String>) -> anyhow::Result<()> {
+        self.store.apply(Changes {
+            removed: ids,
+            ..Default::default()
+        })
+    }
+
+    /// Indexes crash `id` after its report or minidump was stored. Nothing
+    /// to do for a minidump whose report has not arrived yet.
+    pub fn index_crash(&mut self, id: &str) -> anyhow::Result<()> {
+        let Some((file, _)) = storage::find_report(id) else {
+            return Ok(());
+        };
+        let modified = storage::stat(&file)?.map_or(0, |object| object.modified);
+        self.store.apply(Changes {
+            upserted: vec![build_entry(id, modified, storage::minidump_file(id).is_some())?],
+            ..Default::default()
+        })
+    }
+
+    /// Every indexed crash.
+    pub fn entries(&self) -> anyhow::Result<Vec<IndexEntry>> {
+        self.store.select("", Vec::new())
+    }
+
+    /// Every issue with indexed crashes, with how many and when.
+    pub fn issue_counts(&self) -> anyhow::Result<Vec<IssueCounts>> {
+        self.store.issue_counts()
+    }
+
+    /// The indexed crashes of the issues `issue_ids`, in no particular order.
+    pub fn issue_crashes(&self, issue_ids: &[String]) -> anyhow::Result<Vec<IndexEntry>> {
+        self.select_in("issue_id", issue_ids)
+    }
+
+    /// The entries of those of the crashes `ids` that are indexed.
+    pub fn crashes(&self, ids: &[String]) -> anyhow::Result<Vec<IndexEntry>> {
+        self.select_in("id", ids)
+    }
+
+    // Entries whose `column` is one of `values`, a chunk of IN_CHUNK at a time.
+    fn select_in(&self, column: &str, values: &[String]) -> anyhow::Result<Vec<IndexEntry>> {
+        let dialect = self.store.dialect();
+        let mut entries = Vec::new();
+        for chunk in values.chunks(IN_CHUNK) {
+            let placeholders: Vec<String> = (1..=chunk.len()).map(|n| dialect.placeholder(n)).collect();
+            let clause = format!("WHERE {} IN ({})", column, placeholders.join(", "));
+            let values = chunk.iter().map(|value| SqlValue::Text(Some(value.clone()))).collect();
+            entries.extend(self.store.select(&clause, values)?);
+        }
+        Ok(entries)
+    }
+
+    /// Crashes that pass `filter`.
+    pub fn matching(&self, filter: &CrashFilter) -> anyhow::Result<Vec<IndexEntry>> {
+        let (condition, values) = filter.to_sql(self.store.dialect());
+        self.store.select(&condition, values)
+    }
+
+    /// One page of the crashes that pass `filter`, ordered by `sort`, and
+    /// how many pass it in all. Crashes without the value sorted by come
+    /// last either way; ties go newest first, then by id, so pages stay
+    /// stable. UUIDv7 ids order crashes within the same timestamp; the v4
+    /// ids of older clients only keep the order stable.
+    pub fn page(
+        &self,
+        filter: &CrashFilter,
+        sort: SortKey,
+        descending: bool,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<(Vec<IndexEntry>, usize)> {
+        let dialect = self.store.dialect();
+        let (condition, mut values) = filter.to_sql(dialect);
+        let total = self.store.count(&condition, values.clone())?;
+        values.push(SqlValue::Integer(limit as i64));
+        let limit = dialect.placeholder(values.len());
+        values.push(SqlValue::Integer(offset.min(i64::MAX as usize) as i64));
+        let offset = dialect.placeholder(values.len());
+        let clause = format!(
+            "{} ORDER BY {} {} NULLS LAST, seconds DESC NULLS LAST, id DESC LIMIT {} OFFSET {}",
+            condition,
+            sort.column(),
+            if descending { "DESC" } else { "ASC" },
+            limit,
+            offset
+        );
+        Ok((self.store.select(&clause, values)?, total))
+    }
+}
+
+/// The directory `POST /admin/reindex` may import from, if configured.
+pub fn import_dir() -> Option<PathBuf> {
+    std::env::var_os(IMPORT_DIR_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Copies legacy `crash_report_*.json` / `crash_dump_*.dmp` files, and the
+/// `crash_attachment_*` and `.sum` files written next to them, from `dir`
+/// into the storage directory, keeping existing files untouched.
+pub fn import_directory(dir: &Path) -> anyhow::Result<usize> {
+    let mut imported = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let file_name = name.to_string_lossy();
+        let artifact = file_name.strip_suffix(integrity::SUFFIX).unwrap_or(&file_name);
+        let is_artifact = storage::report_id(artifact).is_some()
+            || storage::is_minidump(artifact)
+            || (file_name.starts_with(ATTACHMENT_PREFIX) && !file_name.ends_with(storage::TEMP_SUFFIX));
+        if !is_artifact || storage::stat(&file_name)?.is_some() {
+            continue;
+        }
+        storage::put(&file_name, &fs::read(entry.path())?)?;
+        if storage::report_id(&file_name).is_some() {
+            imported += 1;
+        }
+    }
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_index() -> CrashIndex {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(SCHEMA).unwrap();
+        CrashIndex {
+            store: Box::new(SqliteIndex { db }),
+        }
+    }
+
+    fn entry(id: &str, issue_id: &str, timestamp: Option<&str>) -> IndexEntry {
+        IndexEntry {
+            id: id.to_string(),
+            timestamp: timestamp.map(str::to_string),
+            message: None,
+            level: None,
+            release: None,
+            module: None,
+            issue_id: issue_id.to_string(),
+            has_minidump: false,
+            flagged: false,
+            modified: 0,
+            tags: BTreeMap::new(),
+            project: None,
+        }
+    }
+
+    #[test]
+    fn issue_counts_group_by_issue() {
+        let mut index = memory_index();
+        index
+            .store
+            .apply(Changes {
+                upserted: vec![
+                    entry("a", "one", Some("100")),
+                    entry("b", "one", Some("300")),
+                    entry("c", "one", None),
+                    entry("d", "two", Some("200")),
+                ],
+                ..Default::default()
+            })
+            .unwrap();
+        let mut counts = index.issue_counts().unwrap();
+        counts.sort_by(|a, b| a.issue_id.cmp(&b.issue_id));
+        let summed: Vec<_> = counts
+            .iter()
+            .map(|c| (c.issue_id.as_str(), c.count, c.first_seen, c.last_seen))
+            .collect();
+        assert_eq!(
+            summed,
+            [("one", 3, Some(100.0), Some(300.0)), ("two", 1, Some(200.0), Some(200.0))]
+        );
+    }
+
+    #[test]
+    fn select_in_spans_chunks() {
+        let mut index = memory_index();
+        let ids: Vec<String> = (0..IN_CHUNK * 2 + 1).map(|n| format!("{:04}", n)).collect();
+        index
+            .store
+            .apply(Changes {
+                upserted: ids.iter().map(|id| entry(id, "issue", None)).collect(),
+                ..Default::default()
+            })
+            .unwrap();
+        let mut wanted = ids.clone();
+        wanted.push("missing".to_string());
+        assert_eq!(index.crashes(&wanted).unwrap().len(), ids.len());
+        assert_eq!(index.issue_crashes(&["issue".to_string()]).unwrap().len(), ids.len());
+    }
+}
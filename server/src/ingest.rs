@@ -0,0 +1,198 @@
+use actix_multipart::Multipart;
+use actix_web::error::PayloadError;
+use actix_web::http::header::HeaderMap;
+use actix_web::web::Bytes;
+use futures_util::StreamExt;
+use serde::Serialize;
+
+use crate::validate::{validate_minidump, ValidationReport};
+use crate::{privacy, schema, scrub, storage, ATTACHMENT_PREFIX, CRASH_REPORT_PREFIX, MINIDUMP_PREFIX};
+
+// ----- Ingestion -----
+//
+// Every ingestion format ends up here so events get the same treatment before
+// they are written: scrubbing, IP anonymization and dump validation.
+
+#[derive(Serialize)]
+pub struct IngestResult {
+    pub id: String,
+    pub minidump_validation: Option<ValidationReport>,
+}
+
+/// Returns whether `id` is safe to embed in artifact file names.
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Returns whether `name` is safe to use as an attachment file name suffix.
+pub fn is_valid_attachment_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 128
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Storage file of attachment `name` of crash `id`, if both are valid.
+pub fn attachment_path(id: &str, name: &str) -> anyhow::Result<String> {
+    if !is_valid_id(id) {
+        anyhow::bail!("Invalid event id '{}'", id);
+    }
+    if !is_valid_attachment_name(name) {
+        anyhow::bail!("Invalid attachment name '{}'", name);
+    }
+    Ok(format!("{}{}_{}", ATTACHMENT_PREFIX, id, name))
+}
+
+// Validates a dump, turning a failed validation into an error.
+fn checked_minidump(data: &[u8]) -> anyhow::Result<ValidationReport> {
+    let report = validate_minidump(data);
+    if !report.valid {
+        let reasons: Vec<&str> = report.warnings.iter().map(|w| w.message.as_str()).collect();
+        anyhow::bail!("Rejected invalid minidump: {}", reasons.join("; "));
+    }
+    Ok(report)
+}
+
+/// The id of an uploaded event. Events without one get a new, time-ordered
+/// id like those of current clients; ids that are there are checked by
+/// `persist_event`.
+pub fn event_id(event: &serde_json::Value) -> anyhow::Result<String> {
+    match event.get("event_id") {
+        Some(serde_json::Value::String(id)) => Ok(id.clone()),
+        None | Some(serde_json::Value::Null) => Ok(uuid::Uuid::now_v7().to_string()),
+        Some(_) => anyhow::bail!("'event_id' must be a string"),
+    }
+}
+
+/// Sanitizes and stores an event plus an optional minidump. Events that do not
+/// match the report schema and dumps that fail validation are rejected so
+/// broken capture setups surface immediately.
+pub fn persist_event(
+    id: &str,
+    project: &str,
+    mut event: serde_json::Value,
+    minidump: Option<&[u8]>,
+) -> anyhow::Result<IngestResult> {
+    if !is_valid_id(id) {
+        anyhow::bail!("Invalid event id '{}'", id);
+    }
+
+    let minidump_validation = minidump.map(checked_minidump).transpose()?;
+
+    schema::validate(&event)?;
+    let version = schema::upgrade(&mut event);
+    if version > schema::SCHEMA_VERSION {
+        eprintln!(
+            "Event {} has report schema version {}, newer than {}; storing it as is",
+            id,
+            version,
+            schema::SCHEMA_VERSION
+        );
+    }
+    scrub::scrub_event(&mut event);
+    privacy::anonymize_event(&mut event);
+    if let Some(map) = event.as_object_mut() {
+        map.insert("event_id".to_string(), serde_json::Value::String(id.to_string()));
+        map.insert("project".to_string(), serde_json::Value::String(project.to_string()));
+    }
+
+    if let Some(data) = minidump {
+        storage::put(&format!("{}{}.dmp", MINIDUMP_PREFIX, id), data)?;
+    }
+    storage::put(
+        &format!("{}{}.json", CRASH_REPORT_PREFIX, id),
+        serde_json::to_string_pretty(&event)?.as_bytes(),
+    )?;
+
+    Ok(IngestResult {
+        id: id.to_string(),
+        minidump_validation,
+    })
+}
+
+/// Stores a minidump uploaded on its own, e.g. streamed by a client that
+/// cannot write it to disk. The report for the same id may arrive separately.
+pub fn persist_minidump(id: &str, data: &[u8]) -> anyhow::Result<ValidationReport> {
+    if !is_valid_id(id) {
+        anyhow::bail!("Invalid event id '{}'", id);
+    }
+    let report = checked_minidump(data)?;
+    storage::put(&format!("{}{}.dmp", MINIDUMP_PREFIX, id), data)?;
+    Ok(report)
+}
+
+/// Stores an attachment (log file, configuration, ...) sent by a client next to
+/// the crash it belongs to.
+pub fn persist_attachment(id: &str, name: &str, data: &[u8]) -> anyhow::Result<()> {
+    storage::put(&attachment_path(id, name)?, data)?;
+    Ok(())
+}
+
+pub struct UploadedFile {
+    pub field: String,
+    pub filename: Option<String>,
+    pub data: Vec<u8>,
+}
+
+impl UploadedFile {
+    /// Whether the part was sent as field `name` or as a file with `extension`.
+    pub fn matches(&self, name: &str, extension: &str) -> bool {
+        self.field == name
+            || self
+                .filename
+                .as_deref()
+                .is_some_and(|f| f.to_ascii_lowercase().ends_with(extension))
+    }
+}
+
+/// Whether the request carries a `multipart/form-data` body.
+pub fn is_multipart(headers: &HeaderMap) -> bool {
+    headers
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim_start().to_ascii_lowercase().starts_with("multipart/form-data"))
+}
+
+/// The minidump part of a multipart upload: `upload_file_minidump` as sent
+/// by Breakpad and Crashpad, `minidump`, or any `.dmp` file.
+pub fn minidump_part(files: &[UploadedFile]) -> Option<&UploadedFile> {
+    files
+        .iter()
+        .find(|f| f.matches("upload_file_minidump", ".dmp") || f.field == "minidump")
+}
+
+/// Reads a multipart body that was already received, see `read_multipart`.
+pub async fn read_multipart_bytes(headers: &HeaderMap, body: Bytes, max_size: usize) -> anyhow::Result<Vec<UploadedFile>> {
+    let stream = futures_util::stream::once(async move { Ok::<_, PayloadError>(body) });
+    read_multipart(Multipart::new(headers, stream), max_size).await
+}
+
+/// Reads every part of a multipart upload, enforcing `max_size` per part.
+pub async fn read_multipart(mut payload: Multipart, max_size: usize) -> anyhow::Result<Vec<UploadedFile>> {
+    let mut files = Vec::new();
+    while let Some(item) = payload.next().await {
+        let mut field = item.map_err(|e| anyhow::anyhow!("Invalid multipart body: {}", e))?;
+        let name = field.name().unwrap_or_default().to_string();
+        let filename = field
+            .content_disposition()
+            .and_then(|cd| cd.get_filename())
+            .map(|f| f.to_string());
+
+        let mut data = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|e| anyhow::anyhow!("Failed to read part '{}': {}", name, e))?;
+            if data.len() + chunk.len() > max_size {
+                anyhow::bail!("Part '{}' exceeds the {} byte limit", name, max_size);
+            }
+            data.extend_from_slice(&chunk);
+        }
+        files.push(UploadedFile {
+            field: name,
+            filename,
+            data,
+        });
+    }
+    Ok(files)
+}
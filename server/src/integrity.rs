@@ -0,0 +1,210 @@
+use actix_web::HttpRequest;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::sync::OnceLock;
+
+use crate::storage;
+
+// ----- Integrity -----
+//
+// Clients send the SHA-256 of each artifact's content (as encoded, before
+// compression and encryption) in `X-Crash-Sha256` and sign reports with
+// HMAC-SHA256 in `X-Crash-Signature`; what they write locally gets a
+// `<file>.sum` file with the same. Signatures are checked against the keys in
+// the file named by CRASH_SIGNING_KEY_FILE, one per line, several for key
+// rotation, `#` comments allowed.
+//
+// Uploaded reports are stored with the outcome in `integrity`. Reports and
+// minidumps with a `.sum` file are checked whenever they are read, and a
+// report the server rewrites keeps the outcome of its last check. Minidumps
+// and attachments whose upload does not match its digest are rejected: a
+// truncated dump is of no use. A report is flagged when its digest or
+// signature does not match, or when keys are configured and it is unsigned.
+// Files that have no `.sum` file and were not uploaded are not checked.
+
+pub const KEY_FILE_ENV: &str = "CRASH_SIGNING_KEY_FILE";
+pub const SHA256_HEADER: &str = "X-Crash-Sha256";
+pub const SIGNATURE_HEADER: &str = "X-Crash-Signature";
+pub const SUFFIX: &str = ".sum";
+
+static KEYS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Loads the signing keys named by CRASH_SIGNING_KEY_FILE, if set. Returns
+/// how many there are.
+pub fn load() -> anyhow::Result<usize> {
+    let mut keys = Vec::new();
+    if let Ok(path) = std::env::var(KEY_FILE_ENV) {
+        let data = fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to read signing key file '{}': {}", path, e))?;
+        keys.extend(
+            data.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string),
+        );
+        if keys.is_empty() {
+            anyhow::bail!("No signing keys in '{}'", path);
+        }
+    }
+    let count = keys.len();
+    let _ = KEYS.set(keys);
+    Ok(count)
+}
+
+fn keys() -> &'static [String] {
+    KEYS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+/// Outcome of one check.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Check {
+    Valid,
+    Invalid,
+    Missing,
+    Unverified, // Signed, but there are no keys to check with
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Integrity {
+    pub sha256: String, // Of the content as checked
+    pub size: u64,
+    pub digest: Check,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<Check>, // Reports only; minidumps are not signed
+    pub flagged: bool,
+}
+
+// What clients write to `.sum` files.
+#[derive(Deserialize)]
+struct Sum {
+    sha256: String,
+    signature: Option<String>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn check_signature(content: &[u8], signature: Option<&str>) -> Check {
+    let Some(signature) = signature else {
+        return Check::Missing;
+    };
+    if keys().is_empty() {
+        return Check::Unverified;
+    }
+    let Some(signature) = unhex(signature.trim()) else {
+        return Check::Invalid;
+    };
+    let valid = keys().iter().any(|key| {
+        // HMAC takes keys of any length.
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts any key length");
+        mac.update(content);
+        mac.verify_slice(&signature).is_ok()
+    });
+    if valid {
+        Check::Valid
+    } else {
+        Check::Invalid
+    }
+}
+
+/// Checks `content` against the client's `sha256` and, for reports
+/// (`signed`), its `signature`.
+pub fn check(content: &[u8], sha256: Option<&str>, signature: Option<&str>, signed: bool) -> Integrity {
+    let actual = hex(&Sha256::digest(content));
+    let digest = match sha256 {
+        Some(expected) if expected.trim().eq_ignore_ascii_case(&actual) => Check::Valid,
+        Some(_) => Check::Invalid,
+        None => Check::Missing,
+    };
+    let signature = signed.then(|| check_signature(content, signature));
+    let flagged = digest == Check::Invalid
+        || match signature {
+            Some(Check::Invalid) => true,
+            Some(Check::Missing) => !keys().is_empty(),
+            _ => false,
+        };
+    Integrity {
+        sha256: actual,
+        size: content.len() as u64,
+        digest,
+        signature,
+        flagged,
+    }
+}
+
+fn header<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Checks the decoded body of an upload against its headers.
+pub fn check_upload(req: &HttpRequest, content: &[u8], signed: bool) -> Integrity {
+    check(content, header(req, SHA256_HEADER), header(req, SIGNATURE_HEADER), signed)
+}
+
+/// Checks an uploaded minidump or attachment, failing if it does not match
+/// the digest it was sent with.
+pub fn verify_upload(req: &HttpRequest, content: &[u8]) -> anyhow::Result<()> {
+    let integrity = check_upload(req, content, false);
+    if integrity.digest == Check::Invalid {
+        anyhow::bail!(
+            "Upload does not match its {} (got {} bytes with SHA-256 {})",
+            SHA256_HEADER,
+            integrity.size,
+            integrity.sha256
+        );
+    }
+    Ok(())
+}
+
+/// Name of the `.sum` file of `file`.
+pub fn sum_file(file: &str) -> String {
+    format!("{}{}", file, SUFFIX)
+}
+
+// Checks `file` against its `.sum` file, if it has one.
+fn check_file(file: &str, signed: bool) -> Option<Integrity> {
+    let sum: Sum = serde_json::from_slice(&storage::get(&sum_file(file)).ok()?).ok()?;
+    let content = storage::read_file(file).unwrap_or_default();
+    Some(check(&content, Some(&sum.sha256), sum.signature.as_deref(), signed))
+}
+
+/// Integrity of the report of crash `id`: checked against its `.sum` file,
+/// or as recorded when it was uploaded.
+pub fn report_integrity(id: &str, report: &serde_json::Value) -> Option<Integrity> {
+    storage::find_report(id)
+        .and_then(|(file, _)| check_file(&file, true))
+        .or_else(|| serde_json::from_value(report.get("integrity")?.clone()).ok())
+}
+
+/// Integrity of the minidump of crash `id`, if it has a `.sum` file.
+pub fn minidump_integrity(id: &str) -> Option<Integrity> {
+    check_file(&storage::minidump_file(id)?, false)
+}
+
+/// `report`, the new JSON of the report stored in `file`, with the outcome
+/// of checking `file` against its `.sum` file, which no longer applies once
+/// the report is rewritten. Unchanged if there is none.
+pub fn carry_over(file: &str, report: &[u8]) -> std::io::Result<Vec<u8>> {
+    let Some(integrity) = check_file(file, true) else {
+        return Ok(report.to_vec());
+    };
+    let mut report: serde_json::Value = serde_json::from_slice(report)?;
+    if let Some(map) = report.as_object_mut() {
+        map.insert("integrity".to_string(), serde_json::to_value(integrity)?);
+    }
+    Ok(serde_json::to_vec_pretty(&report)?)
+}
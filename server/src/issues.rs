@@ -0,0 +1,305 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::backend::to_millis;
+use crate::index::{CrashIndex, IssueCounts};
+use crate::{postgres, storage};
+
+// ----- Issues -----
+//
+// An issue groups crash reports that describe the same problem. Reports carrying
+// a client-side `fingerprint` are grouped by it, the rest by panic message.
+// Snoozes are kept in ISSUE_STATE_FILE, or in PostgreSQL when the server has a
+// database, see `crate::postgres`. Issues are grouped from the metadata index
+// (its `issue_id` column), so no report is read to list or snooze them.
+//
+// Changes of an issue's state are appended to ISSUE_EVENTS_FILE for
+// `crate::notifications`: snoozing, unsnoozing, and a snoozed issue coming
+// back once its snooze runs out. The last is noticed by `reactivate_expired`,
+// which also drops the spent snooze.
+
+pub const ISSUE_STATE_FILE: &str = "issue_state.json";
+pub const ISSUE_EVENTS_FILE: &str = "issue_events.jsonl";
+
+// Serializes read-modify-write cycles on the state file.
+static STATE_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Snooze {
+    // Reactivate once this UNIX timestamp (seconds) has passed
+    pub until: Option<u64>,
+    // Reactivate once this many new events arrived after snoozing
+    pub until_events: Option<u64>,
+    // Event count of the issue at the time it was snoozed
+    pub events_at_snooze: u64,
+    pub snoozed_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct IssueState {
+    #[serde(default)]
+    pub snoozed: HashMap<String, Snooze>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum IssueEventKind {
+    Snoozed,
+    Unsnoozed,
+    Reactivated,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IssueEvent {
+    pub issue_id: String,
+    pub kind: IssueEventKind,
+    pub at: u64, // Milliseconds since UNIX epoch
+}
+
+#[derive(Serialize)]
+pub struct Issue {
+    pub id: String,
+    pub title: Option<String>,
+    pub count: u64,
+    pub first_seen: Option<f64>,
+    pub last_seen: Option<f64>,
+    pub latest_crash_id: String,
+    pub crash_ids: Vec<String>,
+    pub snooze: Option<Snooze>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// FNV-1a, so issue IDs stay stable across server builds.
+fn stable_hash(input: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in input.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// Returns the issue a crash report belongs to.
+pub fn issue_id_for(report: &serde_json::Value) -> String {
+    let key = match report.get("fingerprint").and_then(|v| v.as_array()) {
+        Some(parts) if !parts.is_empty() => parts
+            .iter()
+            .map(|p| p.as_str().map(|s| s.to_string()).unwrap_or_else(|| p.to_string()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => format!(
+            "message:{}",
+            report.get("message").and_then(|v| v.as_str()).unwrap_or_default()
+        ),
+    };
+    format!("{:016x}", stable_hash(&key))
+}
+
+pub fn load_state() -> IssueState {
+    if let Some(database) = postgres::database() {
+        return match database.snoozes() {
+            Ok(snoozed) => IssueState { snoozed },
+            Err(e) => {
+                eprintln!("Failed to read issue snoozes: {}", e);
+                IssueState::default()
+            }
+        };
+    }
+    storage::get(ISSUE_STATE_FILE)
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default()
+}
+
+fn save_state(state: &IssueState) -> anyhow::Result<()> {
+    storage::put(ISSUE_STATE_FILE, &serde_json::to_vec_pretty(state)?)?;
+    Ok(())
+}
+
+/// Whether a snooze is still in effect for an issue with `count` events.
+pub fn is_active_snooze(snooze: &Snooze, count: u64) -> bool {
+    if let Some(until) = snooze.until {
+        if now_secs() >= until {
+            return false;
+        }
+    }
+    if let Some(until_events) = snooze.until_events {
+        if count.saturating_sub(snooze.events_at_snooze) >= until_events {
+            return false;
+        }
+    }
+    snooze.until.is_some() || snooze.until_events.is_some()
+}
+
+// Every issue with its counts from the index and its snooze if still in
+// effect, most recent first.
+fn issue_counts(index: &CrashIndex, state: &IssueState) -> anyhow::Result<Vec<(IssueCounts, Option<Snooze>)>> {
+    let mut issues: Vec<_> = index
+        .issue_counts()?
+        .into_iter()
+        .map(|counts| {
+            let snooze = state
+                .snoozed
+                .get(&counts.issue_id)
+                .filter(|s| is_active_snooze(s, counts.count))
+                .cloned();
+            (counts, snooze)
+        })
+        .collect();
+    issues.sort_by(|(a, _), (b, _)| b.last_seen.partial_cmp(&a.last_seen).unwrap_or(std::cmp::Ordering::Equal));
+    Ok(issues)
+}
+
+/// Groups the indexed crashes into issues, most recent first, skipping
+/// snoozed ones unless `include_snoozed`. Only the `limit` issues after
+/// `offset` have their crashes looked up.
+pub fn collect_issues(
+    index: &CrashIndex,
+    include_snoozed: bool,
+    offset: usize,
+    limit: Option<usize>,
+) -> anyhow::Result<Vec<Issue>> {
+    let page: Vec<_> = issue_counts(index, &load_state())?
+        .into_iter()
+        .filter(|(_, snooze)| include_snoozed || snooze.is_none())
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .collect();
+    let ids: Vec<String> = page.iter().map(|(counts, _)| counts.issue_id.clone()).collect();
+    let mut crashes = index.issue_crashes(&ids)?;
+    // Newest first, so the first crash of an issue is its latest.
+    crashes.sort_by(|a, b| {
+        b.seconds()
+            .partial_cmp(&a.seconds())
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| b.id.cmp(&a.id))
+    });
+    let mut by_issue: HashMap<String, Vec<_>> = HashMap::new();
+    for crash in crashes {
+        by_issue.entry(crash.issue_id.clone()).or_default().push(crash);
+    }
+    Ok(page
+        .into_iter()
+        .map(|(counts, snooze)| {
+            let crashes = by_issue.remove(&counts.issue_id).unwrap_or_default();
+            Issue {
+                title: crashes.first().and_then(|c| c.message.clone()),
+                latest_crash_id: crashes.first().map(|c| c.id.clone()).unwrap_or_default(),
+                crash_ids: crashes.into_iter().map(|c| c.id).collect(),
+                id: counts.issue_id,
+                count: counts.count,
+                first_seen: counts.first_seen,
+                last_seen: counts.last_seen,
+                snooze,
+            }
+        })
+        .collect())
+}
+
+fn record_event(issue_id: &str, kind: IssueEventKind) -> anyhow::Result<()> {
+    let event = IssueEvent {
+        issue_id: issue_id.to_string(),
+        kind,
+        at: to_millis(SystemTime::now()),
+    };
+    storage::append(ISSUE_EVENTS_FILE, &serde_json::to_string(&event)?)?;
+    Ok(())
+}
+
+/// Every recorded change of issue state, oldest first.
+pub fn read_events() -> Vec<IssueEvent> {
+    storage::get(ISSUE_EVENTS_FILE)
+        .map(|data| {
+            String::from_utf8_lossy(&data)
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Drops the snoozes that ran out and records that their issues are back.
+/// Returns the issues still snoozed.
+pub fn reactivate_expired(index: &CrashIndex) -> anyhow::Result<HashSet<String>> {
+    let state = load_state();
+    let mut snoozed = HashSet::new();
+    if state.snoozed.is_empty() {
+        return Ok(snoozed);
+    }
+    for (counts, snooze) in issue_counts(index, &state)? {
+        if snooze.is_some() {
+            snoozed.insert(counts.issue_id);
+            continue;
+        }
+        if !state.snoozed.contains_key(&counts.issue_id) {
+            continue;
+        }
+        // Another poller, or server, may have seen it first.
+        if remove_snooze(&counts.issue_id)? {
+            record_event(&counts.issue_id, IssueEventKind::Reactivated)?;
+        }
+    }
+    Ok(snoozed)
+}
+
+/// Snoozes an issue until a timestamp and/or a number of additional events.
+pub fn snooze_issue(
+    index: &CrashIndex,
+    issue_id: &str,
+    until: Option<u64>,
+    until_events: Option<u64>,
+) -> anyhow::Result<Option<Snooze>> {
+    if until.is_none() && until_events.is_none() {
+        anyhow::bail!("Either 'until' or 'until_events' must be set");
+    }
+    let Some(issue) = index.issue_counts()?.into_iter().find(|i| i.issue_id == issue_id) else {
+        return Ok(None);
+    };
+
+    let snooze = Snooze {
+        until,
+        until_events,
+        events_at_snooze: issue.count,
+        snoozed_at: now_secs(),
+    };
+    if let Some(database) = postgres::database() {
+        database.set_snooze(issue_id, &snooze)?;
+    } else {
+        let _guard = STATE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut state = load_state();
+        state.snoozed.insert(issue_id.to_string(), snooze.clone());
+        save_state(&state)?;
+    }
+    record_event(issue_id, IssueEventKind::Snoozed)?;
+    Ok(Some(snooze))
+}
+
+/// Removes a snooze. Returns whether the issue was snoozed.
+pub fn unsnooze_issue(issue_id: &str) -> anyhow::Result<bool> {
+    let removed = remove_snooze(issue_id)?;
+    if removed {
+        record_event(issue_id, IssueEventKind::Unsnoozed)?;
+    }
+    Ok(removed)
+}
+
+fn remove_snooze(issue_id: &str) -> anyhow::Result<bool> {
+    if let Some(database) = postgres::database() {
+        return database.remove_snooze(issue_id);
+    }
+    let _guard = STATE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut state = load_state();
+    let removed = state.snoozed.remove(issue_id).is_some();
+    if removed {
+        save_state(&state)?;
+    }
+    Ok(removed)
+}
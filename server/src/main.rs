@@ -1,17 +1,61 @@
-use actix_web::{get, web, App, HttpResponse, HttpServer, Responder};
-use serde::Serialize;
-use std::fs;
+use actix_web::{delete, get, post, put, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
 use anyhow::Context;
-use breakpad_symbols::{SimpleSymbolSupplier, Symbolizer};
 use minidump::Minidump;
 use minidump_processor::process_minidump;
 
+mod app_streams;
+mod backend;
+mod debug_files;
+mod debuginfod;
+mod encryption;
+mod index;
+mod integrity;
+mod ingest;
+mod issues;
+mod notifications;
+mod plugins;
+mod postgres;
+mod privacy;
+mod processing;
+mod quotas;
+mod s3;
+mod schema;
+mod scrub;
+mod sessions;
+mod stackwalk;
+mod stats;
+mod storage;
+mod symbol_servers;
+mod symbols;
+mod sync;
+mod validate;
+mod wer;
+
+use stackwalk::{StackwalkConfig, StackwalkOverrides};
+use validate::{validate_minidump, ValidationReport};
+
 // ----- Data structures returned by the API -----
 #[derive(Serialize)]
 struct CrashSummary {
     id: String,
     timestamp: Option<String>,
     message: Option<String>,
+    module: Option<String>, // Where the crash happened, see `index::IndexEntry`
+    flagged: bool, // Failed its integrity checks
+    tags: BTreeMap<String, String>,
+}
+
+// One page of `GET /crashes`.
+#[derive(Serialize)]
+struct CrashPage {
+    crashes: Vec<CrashSummary>,
+    total: usize, // Matching crashes across all pages
+    offset: usize,
+    limit: usize,
+    next_offset: Option<usize>, // Offset of the next page, null on the last one
 }
 
 #[derive(Serialize)]
@@ -21,22 +65,160 @@ struct CrashDetail {
     minidump_summary: Option<serde_json::Value>,
     // Full analysis for future use (not yet consumed by the frontend)
     minidump_analysis: Option<serde_json::Value>,
+    // Structural checks of the dump, present whenever a dump file exists
+    minidump_validation: Option<ValidationReport>,
+    // Digest and signature checks of the report and the dump
+    integrity: ArtifactIntegrity,
+}
+
+#[derive(Serialize)]
+struct ArtifactIntegrity {
+    report: Option<integrity::Integrity>,
+    minidump: Option<integrity::Integrity>,
+}
+
+// Shared state handed to every handler
+struct AppState {
+    index: Arc<Mutex<index::CrashIndex>>, // Shared with `notifications`
+    queue: Arc<processing::ProcessingQueue>,
+    quotas: quotas::QuotaTracker,
+}
+
+#[derive(Deserialize)]
+struct ReindexRequest {
+    // Copy the legacy crash_report_*.json / crash_dump_*.dmp files of
+    // CRASH_IMPORT_DIR into storage before rebuilding
+    #[serde(default)]
+    import: bool,
+}
+
+#[derive(Deserialize)]
+struct CrashListQuery {
+    // Page size, DEFAULT_PAGE_SIZE when absent, at most MAX_PAGE_SIZE
+    limit: Option<usize>,
+    #[serde(default)]
+    offset: usize,
+    // Seconds since the UNIX epoch: crashes at or after `since` and before `until`
+    since: Option<f64>,
+    until: Option<f64>,
+    // Comma separated levels, e.g. `fatal,error`
+    level: Option<String>,
+    // Case-insensitive substring of the message
+    q: Option<String>,
+    // `timestamp` (default), `message` or `module`
+    sort: Option<String>,
+    // `asc` or `desc`; newest first by default, otherwise A to Z
+    order: Option<String>,
+}
+
+impl CrashListQuery {
+    fn filter(&self) -> index::CrashFilter {
+        index::CrashFilter {
+            since: self.since,
+            until: self.until,
+            levels: self
+                .level
+                .iter()
+                .flat_map(|levels| levels.split(','))
+                .map(|level| level.trim().to_ascii_lowercase())
+                .filter(|level| !level.is_empty())
+                .collect(),
+            text: self.q.as_deref().filter(|q| !q.is_empty()).map(str::to_lowercase),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ChangesQuery {
+    since: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NotificationsQuery {
+    since: Option<String>,
+    // How long to hold the request open, e.g. `30s`
+    wait: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct IssuesQuery {
+    // Snoozed issues are hidden unless this is set
+    #[serde(default)]
+    include_snoozed: bool,
+    // Page of issues to return, all of them when `limit` is absent
+    limit: Option<usize>,
+    #[serde(default)]
+    offset: usize,
+}
+
+#[derive(Deserialize)]
+struct SnoozeRequest {
+    // UNIX timestamp (seconds) after which the issue reactivates
+    until: Option<u64>,
+    // Number of further events after which the issue reactivates
+    until_events: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ProcessedQuery {
+    // `minidump-processor` (default): the rust-minidump / minidump-stackwalk
+    // JSON schema. `summary`: the simplified summary used by the frontend.
+    format: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FrameStatsQuery {
+    // Time window such as `30d` or `12h`
+    window: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct SessionStatsQuery {
+    // Time window such as `30d` or `12h`, by session start
+    window: Option<String>,
+    project: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SymbolUploadQuery {
+    // File name of a native debug file sent as the body, e.g. `app.pdb`
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DetailQuery {
+    // Comma separated list of top-level sections to return, e.g.
+    // `sentry_report,modules,threads`. All sections are returned when absent.
+    fields: Option<String>,
 }
 
+// Sections that can be requested through `?fields=`. `modules`, `threads` and
+// `crashing_thread` are lifted out of the full minidump analysis.
+const DETAIL_FIELDS: &[&str] = &[
+    "sentry_report",
+    "minidump_summary",
+    "minidump_analysis",
+    "minidump_validation",
+    "integrity",
+    "modules",
+    "threads",
+    "crashing_thread",
+];
+const MINIDUMP_FIELDS: &[&str] = &["minidump_summary", "minidump_analysis", "modules", "threads", "crashing_thread"];
+
 const CRASH_REPORT_PREFIX: &str = "crash_report_"; // .json
 const MINIDUMP_PREFIX: &str = "crash_dump_"; // .dmp
+const ATTACHMENT_PREFIX: &str = "crash_attachment_"; // <id>_<name>
+const MAX_MINIDUMP_SIZE: usize = 512 * 1024 * 1024;
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
 
-// Utility to scan workspace directory for crash IDs
+// Utility to scan the storage directory for crash IDs
 fn collect_crash_ids() -> anyhow::Result<Vec<String>> {
     let mut ids = Vec::new();
-    for entry in fs::read_dir(".")? {
-        let entry = entry?;
-        let name = entry.file_name();
-        let file_name = name.to_string_lossy();
-        if let Some(id) = file_name
-            .strip_prefix(CRASH_REPORT_PREFIX)
-            .and_then(|s| s.strip_suffix(".json"))
-        {
+    for object in storage::list()? {
+        if let Some(id) = storage::report_id(&object.name) {
             ids.push(id.to_string());
         }
     }
@@ -44,26 +226,45 @@ fn collect_crash_ids() -> anyhow::Result<Vec<String>> {
 }
 
 fn load_sentry_json(id: &str) -> anyhow::Result<serde_json::Value> {
-    let path = format!("{}{}.json", CRASH_REPORT_PREFIX, id);
-    let data = fs::read_to_string(&path)
-        .with_context(|| format!("Failed to read sentry report {}", path))?;
-    let json: serde_json::Value = serde_json::from_str(&data)?;
+    let data = storage::read_report(id)
+        .with_context(|| format!("Failed to read sentry report {}", id))?;
+    let mut json: serde_json::Value = serde_json::from_slice(&data)?;
+    schema::upgrade(&mut json);
+    // Reports can reach the storage directory without passing through an
+    // ingest endpoint, so scrub on the way out as well.
+    scrub::scrub_event(&mut json);
+    privacy::anonymize_event(&mut json);
     Ok(json)
 }
 
 async fn analyze_minidump(id: &str) -> anyhow::Result<(serde_json::Value, serde_json::Value)> {
-    let path = format!("{}{}.dmp", MINIDUMP_PREFIX, id);
-    let dump = Minidump::read_path(&path)
-        .with_context(|| format!("Failed to read minidump {}", path))?;
+    let data = web::block({
+        let id = id.to_string();
+        move || storage::read_minidump(&id)
+    })
+    .await?
+    .with_context(|| format!("Failed to read minidump {}", id))?;
+    // Unknown to the minidump crate, so taken from the raw dump.
+    let app_streams = app_streams::app_streams(&data);
+    let dump = Minidump::read(data)
+        .with_context(|| format!("Failed to parse minidump {}", id))?;
 
-    // We can pass an empty list of symbol servers. This will prevent any network
-    // access, and limit symbolication to local files. For this example, we don't
-    // have any local symbol files, so this will be equivalent to no symbolication.
-    let provider = Symbolizer::new(SimpleSymbolSupplier::new(Vec::new()));
+    // Symbols of system modules, if symbol servers or debuginfod are configured.
+    let windows = symbol_servers::wanted_modules(&dump);
+    let linux = debuginfod::wanted_modules(&dump);
+    if !windows.is_empty() || !linux.is_empty() {
+        let _ = tokio::task::spawn_blocking(move || {
+            symbol_servers::fetch(&windows);
+            debuginfod::fetch(&linux);
+        })
+        .await;
+    }
 
-    let state = process_minidump(&dump, &provider)
+    // Walks every thread, symbolicated as far as symbols are found.
+    let symbolizer = symbols::symbolizer();
+    let state = process_minidump(&dump, symbolizer.as_ref())
         .await
-        .with_context(|| format!("Failed to process minidump {}", path))?;
+        .with_context(|| format!("Failed to process minidump {}", id))?;
 
     let mut json_output = Vec::new();
     state.print_json(&mut json_output, false)?;
@@ -123,69 +324,909 @@ async fn analyze_minidump(id: &str) -> anyhow::Result<(serde_json::Value, serde_
             "process_create_time": json.pointer("/crash_info/address").and_then(|v| v.as_u64()).unwrap_or(0),
             "processor_current_mhz": serde_json::Value::Null,
             "processor_max_mhz": serde_json::Value::Null,
-        }
+        },
+        "app_streams": app_streams,
     });
 
     Ok((json, summary))
 }
 
+// Resolves the stack walking policy for a crash: project defaults from the
+// config file, then the request's overrides.
+fn stackwalk_config(report: &serde_json::Value, overrides: &StackwalkOverrides) -> anyhow::Result<StackwalkConfig> {
+    let project = report.get("project").and_then(|v| v.as_str());
+    StackwalkConfig::for_project(project)?.merge(overrides)
+}
+
+async fn walked_analysis(
+    id: &str,
+    config: &StackwalkConfig,
+) -> anyhow::Result<(serde_json::Value, serde_json::Value)> {
+    let (mut analysis, summary) = processing::cached_or_analyze(id).await?;
+    config.apply(&mut analysis);
+    Ok((analysis, summary))
+}
+
+async fn load_minidump_validation(id: &str) -> Option<ValidationReport> {
+    let id = id.to_string();
+    web::block(move || storage::read_minidump(&id).ok().map(|data| validate_minidump(&data)))
+        .await
+        .ok()
+        .flatten()
+}
+
+async fn load_integrity(id: &str, report: &serde_json::Value) -> ArtifactIntegrity {
+    let (id, report) = (id.to_string(), report.clone());
+    web::block(move || ArtifactIntegrity {
+        report: integrity::report_integrity(&id, &report),
+        minidump: integrity::minidump_integrity(&id),
+    })
+    .await
+    .unwrap_or(ArtifactIntegrity {
+        report: None,
+        minidump: None,
+    })
+}
+
+// The report of crash `id` as `load_sentry_json` returns it, read on a
+// blocking thread; the response for the client if it cannot be.
+async fn load_report(id: &str) -> Result<serde_json::Value, HttpResponse> {
+    let id = id.to_string();
+    match web::block(move || load_sentry_json(&id)).await {
+        Ok(Ok(report)) => Ok(report),
+        Ok(Err(e)) => Err(HttpResponse::NotFound().body(e.to_string())),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+// Indexes what an ingest endpoint stored, so it is listed without waiting for
+// the next refresh. The index is only locked on blocking threads, as its
+// refresh may wait on the storage backend for long.
+async fn index_stored(state: &web::Data<AppState>, id: &str) {
+    let (state, id) = (state.clone(), id.to_string());
+    let indexed = web::block(move || {
+        let mut index = state.index.lock().unwrap_or_else(|e| e.into_inner());
+        index.index_crash(&id).map_err(|e| format!("Failed to index crash {}: {}", id, e))
+    })
+    .await;
+    match indexed {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => eprintln!("{}", e),
+        Err(e) => eprintln!("Failed to index crash: {}", e),
+    }
+}
+
 // --------------- HTTP Handlers ----------------
 
 #[get("/crashes")]
-async fn get_crashes() -> impl Responder {
-    match collect_crash_ids() {
-        Ok(ids) => {
-            let mut list = Vec::new();
-            for id in ids {
-                // Attempt to read basic metadata from json
-                if let Ok(json) = load_sentry_json(&id) {
-                    list.push(CrashSummary {
-                        id: id.clone(),
-                        timestamp: json
-                            .get("timestamp")
-                            .and_then(|v| v.as_str())
-                            .map(|s| s.to_string()),
-                        message: json
-                            .get("message")
-                            .and_then(|v| v.as_str())
-                            .map(|s| s.to_string()),
-                    });
-                }
+async fn get_crashes(state: web::Data<AppState>, query: web::Query<CrashListQuery>) -> impl Responder {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        return HttpResponse::BadRequest().body(format!("limit must be between 1 and {}", MAX_PAGE_SIZE));
+    }
+    let sort = match query.sort.as_deref() {
+        None => index::SortKey::Timestamp,
+        Some(name) => match index::SortKey::from_name(name) {
+            Some(sort) => sort,
+            None => {
+                return HttpResponse::BadRequest()
+                    .body(format!("Unknown sort '{}', expected timestamp, message or module", name))
             }
-            HttpResponse::Ok().json(list)
+        },
+    };
+    let descending = match query.order.as_deref() {
+        None => sort == index::SortKey::Timestamp,
+        Some("asc") => false,
+        Some("desc") => true,
+        Some(order) => {
+            return HttpResponse::BadRequest().body(format!("Unknown order '{}', expected asc or desc", order))
+        }
+    };
+    let (filter, offset) = (query.filter(), query.offset);
+    let listed = web::block({
+        let state = state.clone();
+        move || {
+            let mut index = state.index.lock().unwrap_or_else(|e| e.into_inner());
+            index.refresh()?;
+            state.queue.enqueue_unprocessed(index.entries()?.iter());
+            index.page(&filter, sort, descending, offset, limit)
         }
+    })
+    .await;
+    let (page, total) = match listed {
+        Ok(Ok(page)) => page,
+        Ok(Err(e)) => return HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let crashes: Vec<CrashSummary> = page
+        .into_iter()
+        .map(|entry| CrashSummary {
+            id: entry.id,
+            timestamp: entry.timestamp,
+            message: entry.message,
+            module: entry.module,
+            flagged: entry.flagged,
+            tags: entry.tags,
+        })
+        .collect();
+    let end = query.offset.saturating_add(crashes.len());
+    HttpResponse::Ok().json(CrashPage {
+        crashes,
+        total,
+        offset: query.offset,
+        limit,
+        next_offset: (end < total).then_some(end),
+    })
+}
+
+#[get("/crashes/changes")]
+async fn get_changes(query: web::Query<ChangesQuery>) -> impl Responder {
+    let since = match sync::parse_cursor(query.since.as_deref()) {
+        Ok(since) => since,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    match web::block(move || sync::collect_changes(&since)).await {
+        Ok(Ok(changes)) => HttpResponse::Ok().json(changes),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/notifications")]
+async fn get_notifications(state: web::Data<AppState>, query: web::Query<NotificationsQuery>) -> impl Responder {
+    let since = match sync::parse_cursor(query.since.as_deref()) {
+        Ok(since) => since,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let wait = match notifications::parse_wait(query.wait.as_deref()) {
+        Ok(wait) => wait,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    match notifications::wait_for_events(since, wait, state.index.clone()).await {
+        Ok(batch) => HttpResponse::Ok().json(batch),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/issues")]
+async fn get_issues(state: web::Data<AppState>, query: web::Query<IssuesQuery>) -> impl Responder {
+    let query = query.into_inner();
+    let listed = web::block(move || {
+        let mut index = state.index.lock().unwrap_or_else(|e| e.into_inner());
+        index.refresh()?;
+        issues::collect_issues(&index, query.include_snoozed, query.offset, query.limit)
+    })
+    .await;
+    match listed {
+        Ok(Ok(list)) => HttpResponse::Ok().json(list),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[put("/issue/{id}/snooze")]
+async fn snooze_issue(
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+    body: web::Json<SnoozeRequest>,
+) -> impl Responder {
+    let id = id.into_inner();
+    let snoozed = web::block({
+        let id = id.clone();
+        move || {
+            let index = state.index.lock().unwrap_or_else(|e| e.into_inner());
+            issues::snooze_issue(&index, &id, body.until, body.until_events)
+        }
+    })
+    .await;
+    match snoozed {
+        Ok(Ok(Some(snooze))) => HttpResponse::Ok().json(snooze),
+        Ok(Ok(None)) => HttpResponse::NotFound().body(format!("Unknown issue {}", id)),
+        Ok(Err(e)) => HttpResponse::BadRequest().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[delete("/issue/{id}/snooze")]
+async fn unsnooze_issue(id: web::Path<String>) -> impl Responder {
+    let id = id.into_inner();
+    let unsnoozed = web::block({
+        let id = id.clone();
+        move || issues::unsnooze_issue(&id)
+    })
+    .await;
+    match unsnoozed {
+        Ok(Ok(true)) => HttpResponse::NoContent().finish(),
+        Ok(Ok(false)) => HttpResponse::NotFound().body(format!("Issue {} is not snoozed", id)),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[delete("/users/{user_id}/data")]
+async fn delete_user_data(state: web::Data<AppState>, user_id: web::Path<String>) -> impl Responder {
+    let deleted = web::block(move || {
+        let report = privacy::delete_user_data(&user_id)?;
+        // Listings stop showing them now rather than after the next refresh.
+        let ids = report.deleted.iter().map(|crash| crash.crash_id.clone()).collect();
+        state.index.lock().unwrap_or_else(|e| e.into_inner()).remove(ids)?;
+        Ok::<_, anyhow::Error>(report)
+    })
+    .await;
+    match deleted {
+        Ok(Ok(report)) => HttpResponse::Ok().json(report),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
         Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
     }
 }
 
+#[post("/admin/reindex")]
+async fn reindex(state: web::Data<AppState>, body: Option<web::Json<ReindexRequest>>) -> impl Responder {
+    let imported = if body.as_ref().is_some_and(|b| b.import) {
+        let Some(dir) = index::import_dir() else {
+            return HttpResponse::BadRequest()
+                .body(format!("Imports are disabled, {} is not set", index::IMPORT_DIR_ENV));
+        };
+        let shown = dir.display().to_string();
+        match web::block(move || index::import_directory(&dir)).await {
+            Ok(Ok(count)) => count,
+            Ok(Err(e)) => {
+                return HttpResponse::InternalServerError().body(format!("Import from {} failed: {}", shown, e))
+            }
+            Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+        }
+    } else {
+        0
+    };
+
+    let rebuilt = web::block(move || {
+        let mut index = state.index.lock().unwrap_or_else(|e| e.into_inner());
+        let failed = index.rebuild()?;
+        let entries = index.entries()?;
+        state.queue.enqueue_unprocessed(entries.iter());
+        Ok::<_, anyhow::Error>((failed, entries.len()))
+    })
+    .await;
+    match rebuilt {
+        Ok(Ok((failed, indexed))) => HttpResponse::Ok().json(index::ReindexReport {
+            imported,
+            indexed,
+            failed,
+        }),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/admin/queue")]
+async fn get_queue(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({ "pending": state.queue.len() }))
+}
+
+#[get("/stats/frames")]
+async fn get_frame_stats(state: web::Data<AppState>, query: web::Query<FrameStatsQuery>) -> impl Responder {
+    let window = match stats::parse_window(query.window.as_deref()) {
+        Ok(window) => window,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let filter = index::CrashFilter {
+        since: Some(stats::window_start(window)),
+        ..Default::default()
+    };
+    let matching = web::block(move || {
+        let mut index = state.index.lock().unwrap_or_else(|e| e.into_inner());
+        index.refresh()?;
+        index.matching(&filter)
+    })
+    .await;
+    match matching {
+        Ok(Ok(entries)) => {
+            HttpResponse::Ok().json(stats::hot_frames(entries.iter(), window, query.limit.unwrap_or(50)))
+        }
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/stats/sessions")]
+async fn get_session_stats(query: web::Query<SessionStatsQuery>) -> impl Responder {
+    let window = match stats::parse_window(query.window.as_deref()) {
+        Ok(window) => window,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let project = query.into_inner().project;
+    match web::block(move || sessions::release_health(window, project.as_deref())).await {
+        Ok(Ok(stats)) => HttpResponse::Ok().json(stats),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/stats/quotas")]
+async fn get_quota_stats(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(state.quotas.stats())
+}
+
 #[get("/crash/{id}")]
-async fn get_crash(id: web::Path<String>) -> impl Responder {
+async fn get_crash(
+    id: web::Path<String>,
+    query: web::Query<DetailQuery>,
+    overrides: web::Query<StackwalkOverrides>,
+) -> impl Responder {
     let id = id.into_inner();
-    let sentry = match load_sentry_json(&id) {
+    let mut sentry = match load_report(&id).await {
         Ok(v) => v,
-        Err(e) => return HttpResponse::NotFound().body(e.to_string()),
+        Err(response) => return response,
+    };
+    debuginfod::symbolicate_report(&mut sentry).await;
+    let stackwalk = match stackwalk_config(&sentry, &overrides) {
+        Ok(config) => config,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
     };
 
-    let (minidump_analysis, minidump_summary) = match analyze_minidump(&id).await {
-        Ok((analysis, summary)) => (Some(analysis), Some(summary)),
-        Err(_) => (None, None),
+    let fields = match &query.fields {
+        Some(fields) => fields
+            .split(',')
+            .map(|f| f.trim())
+            .filter(|f| !f.is_empty())
+            .collect::<Vec<_>>(),
+        None => {
+            let (minidump_analysis, minidump_summary) = match walked_analysis(&id, &stackwalk).await {
+                Ok((analysis, summary)) => (Some(analysis), Some(summary)),
+                Err(_) => (None, None),
+            };
+            let detail = CrashDetail {
+                integrity: load_integrity(&id, &sentry).await,
+                minidump_validation: load_minidump_validation(&id).await,
+                sentry_report: sentry,
+                minidump_summary,
+                minidump_analysis,
+            };
+            return HttpResponse::Ok().json(detail);
+        }
     };
 
-    let detail = CrashDetail {
-        sentry_report: sentry,
-        minidump_summary,
-        minidump_analysis,
+    if let Some(unknown) = fields.iter().find(|f| !DETAIL_FIELDS.contains(f)) {
+        return HttpResponse::BadRequest().body(format!(
+            "Unknown field '{}', expected one of: {}",
+            unknown,
+            DETAIL_FIELDS.join(", ")
+        ));
+    }
+
+    // Only pay for minidump processing when a section derived from it was requested.
+    let (analysis, summary) = if fields.iter().any(|f| MINIDUMP_FIELDS.contains(f)) {
+        match walked_analysis(&id, &stackwalk).await {
+            Ok((analysis, summary)) => (Some(analysis), Some(summary)),
+            Err(_) => (None, None),
+        }
+    } else {
+        (None, None)
     };
+
+    let mut detail = serde_json::Map::new();
+    for field in fields {
+        let value = match field {
+            "sentry_report" => sentry.clone(),
+            "minidump_summary" => summary.clone().unwrap_or_default(),
+            "minidump_analysis" => analysis.clone().unwrap_or_default(),
+            "minidump_validation" => serde_json::to_value(load_minidump_validation(&id).await)
+                .unwrap_or_default(),
+            "integrity" => serde_json::to_value(load_integrity(&id, &sentry).await).unwrap_or_default(),
+            section => analysis
+                .as_ref()
+                .and_then(|a| a.get(section))
+                .cloned()
+                .unwrap_or_default(),
+        };
+        detail.insert(field.to_string(), value);
+    }
     HttpResponse::Ok().json(detail)
 }
 
+#[get("/crash/{id}/processed")]
+async fn get_processed(
+    id: web::Path<String>,
+    query: web::Query<ProcessedQuery>,
+    overrides: web::Query<StackwalkOverrides>,
+) -> impl Responder {
+    let id = id.into_inner();
+    let sentry = match load_report(&id).await {
+        Ok(v) => v,
+        Err(response) => return response,
+    };
+    let stackwalk = match stackwalk_config(&sentry, &overrides) {
+        Ok(config) => config,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let format = query.format.as_deref().unwrap_or("minidump-processor");
+    if format != "minidump-processor" && format != "summary" {
+        return HttpResponse::BadRequest().body(format!(
+            "Unknown format '{}', expected minidump-processor or summary",
+            format
+        ));
+    }
+    match walked_analysis(&id, &stackwalk).await {
+        Ok((analysis, summary)) => {
+            if format == "summary" {
+                HttpResponse::Ok().json(summary)
+            } else {
+                HttpResponse::Ok().json(analysis)
+            }
+        }
+        Err(e) => HttpResponse::NotFound().body(e.to_string()),
+    }
+}
+
+#[post("/validate")]
+async fn validate_dump(body: web::Bytes) -> impl Responder {
+    HttpResponse::Ok().json(validate_minidump(&body))
+}
+
+#[post("/crashes")]
+async fn ingest_report(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    body: web::Bytes,
+) -> impl Responder {
+    let project = quotas::project_of(&req);
+    if let Err(exceeded) = state.quotas.check_and_record(&project, body.len() as u64) {
+        return exceeded.into_response();
+    }
+    let body = match encryption::decode_body(&req, &body) {
+        Ok(body) => body,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid crash report: {}", e)),
+    };
+    let content_type = req
+        .headers()
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/json");
+    let integrity = integrity::check_upload(&req, &body, true);
+    let mut event = match storage::decode_upload(content_type, &body) {
+        Ok(event) => event,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid crash report: {}", e)),
+    };
+    // Replaces whatever the client put there.
+    if let (Some(map), Ok(integrity)) = (event.as_object_mut(), serde_json::to_value(integrity)) {
+        map.insert("integrity".to_string(), integrity);
+    }
+    let id = match ingest::event_id(&event) {
+        Ok(id) => id,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid crash report: {}", e)),
+    };
+    let persisted = web::block({
+        let id = id.clone();
+        move || ingest::persist_event(&id, &project, event, None)
+    })
+    .await;
+    match persisted {
+        Ok(Ok(result)) => {
+            index_stored(&state, &id).await;
+            HttpResponse::Created().json(result)
+        }
+        Ok(Err(e)) => HttpResponse::BadRequest().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Session records are small and sent once per session, so they are not
+// counted against the event quotas.
+#[post("/sessions")]
+async fn ingest_session(req: HttpRequest, body: web::Bytes) -> impl Responder {
+    let record = match serde_json::from_slice(&body) {
+        Ok(record) => record,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid session: {}", e)),
+    };
+    let project = quotas::project_of(&req);
+    match web::block(move || sessions::persist_session(&project, record)).await {
+        Ok(Ok(sid)) => HttpResponse::Created().json(serde_json::json!({ "sid": sid })),
+        Ok(Err(e)) => HttpResponse::BadRequest().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Takes the dump as the raw body, or as a multipart form with the dump in
+// `upload_file_minidump` the way Breakpad and Crashpad send it. Encryption and
+// digest headers only apply to raw bodies.
+#[post("/crashes/{id}/minidump")]
+async fn upload_minidump(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Bytes,
+) -> impl Responder {
+    let id = path.into_inner();
+    let project = quotas::project_of(&req);
+    if let Err(exceeded) = state.quotas.check_and_record(&project, body.len() as u64) {
+        return exceeded.into_response();
+    }
+    if ingest::is_multipart(req.headers()) {
+        let files = match ingest::read_multipart_bytes(req.headers(), body, MAX_MINIDUMP_SIZE).await {
+            Ok(files) => files,
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        };
+        if ingest::minidump_part(&files).is_none() {
+            return HttpResponse::BadRequest().body("Missing 'upload_file_minidump' part with the .dmp file");
+        }
+        let persisted = web::block({
+            let id = id.clone();
+            move || {
+                let minidump = ingest::minidump_part(&files).map(|m| m.data.as_slice()).unwrap_or_default();
+                ingest::persist_minidump(&id, minidump)
+            }
+        })
+        .await;
+        return minidump_stored(&state, &id, persisted).await;
+    }
+    let body = match encryption::decode_body(&req, &body) {
+        Ok(body) => body,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(e) = integrity::verify_upload(&req, &body) {
+        return HttpResponse::BadRequest().body(e.to_string());
+    }
+    let persisted = web::block({
+        let id = id.clone();
+        move || ingest::persist_minidump(&id, &body)
+    })
+    .await;
+    minidump_stored(&state, &id, persisted).await
+}
+
+// The response to a minidump upload that `persisted` on a blocking thread.
+async fn minidump_stored(
+    state: &web::Data<AppState>,
+    id: &str,
+    persisted: Result<anyhow::Result<ValidationReport>, actix_web::error::BlockingError>,
+) -> HttpResponse {
+    match persisted {
+        Ok(Ok(report)) => {
+            index_stored(state, id).await;
+            HttpResponse::Created().json(report)
+        }
+        Ok(Err(e)) => HttpResponse::BadRequest().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Breakpad symbol files or native debug files (PDB, ELF, dSYM DWARF), as the
+// body or as the parts of a multipart form. Crashes analysed without them are
+// processed again.
+#[post("/symbols")]
+async fn upload_symbols(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    query: web::Query<SymbolUploadQuery>,
+    body: web::Bytes,
+) -> impl Responder {
+    let files: Vec<(Option<String>, Vec<u8>)> = if ingest::is_multipart(req.headers()) {
+        match ingest::read_multipart_bytes(req.headers(), body, MAX_MINIDUMP_SIZE).await {
+            Ok(files) => files.into_iter().map(|file| (file.filename, file.data)).collect(),
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        }
+    } else {
+        vec![(query.into_inner().name, body.to_vec())]
+    };
+    if files.is_empty() {
+        return HttpResponse::BadRequest().body("No symbol files in the upload");
+    }
+
+    // Converting large debug files takes a while.
+    let stored = web::block(move || {
+        let mut modules = Vec::new();
+        for (name, data) in &files {
+            modules.extend(symbols::store_debug_file(name.as_deref(), data)?);
+        }
+        Ok::<_, anyhow::Error>(modules)
+    })
+    .await;
+    let modules = match stored {
+        Ok(Ok(modules)) => modules,
+        Ok(Err(e)) => return HttpResponse::BadRequest().body(e.to_string()),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let forgotten = web::block({
+        let ids: Vec<_> = modules.iter().map(|m| (m.debug_file.clone(), m.debug_id.clone())).collect();
+        move || {
+            let mut reprocessed = 0;
+            for (debug_file, debug_id) in &ids {
+                match processing::forget_unsymbolicated(debug_file, debug_id) {
+                    Ok(count) => reprocessed += count,
+                    Err(e) => eprintln!("Failed to find crashes lacking symbols of {}: {}", debug_file, e),
+                }
+            }
+            reprocessed
+        }
+    })
+    .await;
+    let reprocessed = match forgotten {
+        Ok(reprocessed) => reprocessed,
+        Err(e) => {
+            eprintln!("Failed to find crashes lacking symbols: {}", e);
+            0
+        }
+    };
+    if reprocessed > 0 {
+        let enqueued = web::block(move || {
+            let index = state.index.lock().unwrap_or_else(|e| e.into_inner());
+            index.entries().map(|entries| state.queue.enqueue_unprocessed(entries.iter()))
+        })
+        .await;
+        match enqueued {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => eprintln!("Failed to read crash index: {}", e),
+            Err(e) => eprintln!("Failed to read crash index: {}", e),
+        }
+    }
+    HttpResponse::Created().json(symbols::UploadReport { modules, reprocessed })
+}
+
+// The body size the client declared in `Content-Length`, if any.
+fn content_length(req: &HttpRequest) -> Option<u64> {
+    req.headers()
+        .get("Content-Length")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+}
+
+// A report and its minidump in one multipart form: the JSON event in `sentry`
+// or `report`, the dump in `upload_file_minidump` (or `minidump`). Stored
+// together, so the report is never listed without the dump it came with.
+// The integrity headers cover the report part.
+#[post("/crashes/upload")]
+async fn upload_crash(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    payload: actix_multipart::Multipart,
+) -> impl Responder {
+    let project = quotas::project_of(&req);
+    // Counted before the parts are read where the client says how large the
+    // body is, so uploads over quota are not buffered first.
+    let declared = content_length(&req);
+    if let Some(length) = declared {
+        if let Err(exceeded) = state.quotas.check_and_record(&project, length) {
+            return exceeded.into_response();
+        }
+    }
+    let files = match ingest::read_multipart(payload, MAX_MINIDUMP_SIZE).await {
+        Ok(files) => files,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if declared.is_none() {
+        let size: usize = files.iter().map(|f| f.data.len()).sum();
+        if let Err(exceeded) = state.quotas.check_and_record(&project, size as u64) {
+            return exceeded.into_response();
+        }
+    }
+    let Some(report) = files.iter().find(|f| f.field == "sentry" || f.matches("report", ".json")) else {
+        return HttpResponse::BadRequest().body("Missing 'report' part with the JSON event");
+    };
+    if ingest::minidump_part(&files).is_none() {
+        return HttpResponse::BadRequest().body("Missing 'upload_file_minidump' part with the .dmp file");
+    }
+    let integrity = integrity::check_upload(&req, &report.data, true);
+    let mut event: serde_json::Value = match serde_json::from_slice(&report.data) {
+        Ok(event) => event,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid crash report: {}", e)),
+    };
+    // Replaces whatever the client put there.
+    if let (Some(map), Ok(integrity)) = (event.as_object_mut(), serde_json::to_value(integrity)) {
+        map.insert("integrity".to_string(), integrity);
+    }
+    let id = match ingest::event_id(&event) {
+        Ok(id) => id,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid crash report: {}", e)),
+    };
+    let persisted = web::block({
+        let id = id.clone();
+        move || {
+            let minidump = ingest::minidump_part(&files).map(|m| m.data.as_slice());
+            ingest::persist_event(&id, &project, event, minidump)
+        }
+    })
+    .await;
+    match persisted {
+        Ok(Ok(result)) => {
+            index_stored(&state, &id).await;
+            HttpResponse::Created().json(result)
+        }
+        Ok(Err(e)) => HttpResponse::BadRequest().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[post("/crashes/{id}/attachments/{name}")]
+async fn upload_attachment(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+    body: web::Bytes,
+) -> impl Responder {
+    let project = quotas::project_of(&req);
+    if let Err(exceeded) = state.quotas.check_and_record(&project, body.len() as u64) {
+        return exceeded.into_response();
+    }
+    let body = match encryption::decode_body(&req, &body) {
+        Ok(body) => body,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(e) = integrity::verify_upload(&req, &body) {
+        return HttpResponse::BadRequest().body(e.to_string());
+    }
+    let (id, name) = path.into_inner();
+    match web::block(move || ingest::persist_attachment(&id, &name, &body)).await {
+        Ok(Ok(())) => HttpResponse::Created().finish(),
+        Ok(Err(e)) => HttpResponse::BadRequest().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/crash/{id}/attachments/{name}")]
+async fn get_attachment(path: web::Path<(String, String)>) -> impl Responder {
+    let (id, name) = path.into_inner();
+    let file = match ingest::attachment_path(&id, &name) {
+        Ok(file) => file,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    // Attachments a client wrote encrypted to its output directory.
+    let data = web::block(move || {
+        storage::get(&file).or_else(|_| {
+            let encrypted = storage::get(&format!("{}{}", file, encryption::EXTENSION))?;
+            encryption::decrypt(&encrypted)
+        })
+    })
+    .await;
+    match data {
+        Ok(Ok(data)) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", name)))
+            .body(data),
+        Ok(Err(_)) => HttpResponse::NotFound().body(format!("No attachment '{}' for crash {}", name, id)),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[post("/ingest/wer")]
+async fn ingest_wer(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    payload: actix_multipart::Multipart,
+) -> impl Responder {
+    let project = quotas::project_of(&req);
+    let files = match ingest::read_multipart(payload, MAX_MINIDUMP_SIZE).await {
+        Ok(files) => files,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let size: usize = files.iter().map(|f| f.data.len()).sum();
+    if let Err(exceeded) = state.quotas.check_and_record(&project, size as u64) {
+        return exceeded.into_response();
+    }
+    let Some(report) = files.iter().find(|f| f.matches("report", ".wer")) else {
+        return HttpResponse::BadRequest().body("Missing 'report' part with the .wer file");
+    };
+
+    let wer = match wer::parse_wer(&report.data) {
+        Ok(wer) => wer,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let id = wer.report_id().unwrap_or_else(|| uuid::Uuid::now_v7().to_string());
+    let event = wer.to_event();
+    let persisted = web::block({
+        let id = id.clone();
+        move || {
+            let minidump = ingest::minidump_part(&files).map(|m| m.data.as_slice());
+            ingest::persist_event(&id, &project, event, minidump)
+        }
+    })
+    .await;
+    match persisted {
+        Ok(Ok(result)) => {
+            index_stored(&state, &id).await;
+            HttpResponse::Created().json(result)
+        }
+        Ok(Err(e)) => HttpResponse::UnprocessableEntity().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// `--data-dir <dir>` or `--data-dir=<dir>`, see `storage::init`.
+fn data_dir_arg() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--data-dir" {
+            return args.next();
+        }
+        if let Some(dir) = arg.strip_prefix("--data-dir=") {
+            return Some(dir.to_string());
+        }
+    }
+    None
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     // Find a free port or default 8080
     let port = std::env::var("PORT").unwrap_or_else(|_| "8080".to_string());
     println!("Starting crash viewer backend on 0.0.0.0:{}", port);
 
-    HttpServer::new(|| App::new().service(get_crashes).service(get_crash))
+    let location = storage::init(data_dir_arg())
+        .map_err(|e| std::io::Error::other(format!("Invalid storage configuration: {}", e)))?;
+    println!("Storing crash artifacts in {}", location);
+
+    scrub::init().map_err(|e| std::io::Error::other(format!("Invalid scrub rules: {}", e)))?;
+    // Before the index is refreshed, which reads the reports.
+    let identities = encryption::load()
+        .map_err(|e| std::io::Error::other(format!("Invalid encryption configuration: {}", e)))?;
+    if identities > 0 {
+        println!("Decrypting encrypted crash artifacts with {} identit(y/ies)", identities);
+    }
+    let signing_keys = integrity::load()
+        .map_err(|e| std::io::Error::other(format!("Invalid signing key configuration: {}", e)))?;
+    if signing_keys > 0 {
+        println!("Checking report signatures with {} key(s)", signing_keys);
+    }
+
+    println!("Looking up minidump symbols in {}", symbols::describe());
+    if let Some(dir) = index::import_dir() {
+        println!("Importing crashes from {} on POST /admin/reindex", dir.display());
+    }
+
+    let has_database = postgres::init()
+        .map_err(|e| std::io::Error::other(format!("Cannot connect to the database: {}", e)))?;
+    if has_database {
+        println!("Keeping crash metadata and issue state in PostgreSQL");
+    }
+
+    let mut crash_index = index::CrashIndex::load()
+        .map_err(|e| std::io::Error::other(format!("Cannot open the crash index: {}", e)))?;
+    if let Err(e) = crash_index.refresh() {
+        eprintln!("Failed to refresh crash index: {}", e);
+    }
+    let queue = processing::ProcessingQueue::new();
+    match crash_index.entries() {
+        Ok(entries) => queue.enqueue_unprocessed(entries.iter()),
+        Err(e) => eprintln!("Failed to read crash index: {}", e),
+    }
+    actix_web::rt::spawn(queue.clone().run());
+
+    let quotas = quotas::QuotaTracker::load()
+        .map_err(|e| std::io::Error::other(format!("Invalid quota configuration: {}", e)))?;
+
+    let state = web::Data::new(AppState {
+        index: Arc::new(Mutex::new(crash_index)),
+        queue,
+        quotas,
+    });
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .app_data(web::PayloadConfig::new(MAX_MINIDUMP_SIZE))
+            .service(get_crashes)
+            .service(get_changes)
+            .service(get_notifications)
+            .service(get_issues)
+            .service(snooze_issue)
+            .service(unsnooze_issue)
+            .service(delete_user_data)
+            .service(reindex)
+            .service(get_queue)
+            .service(get_frame_stats)
+            .service(get_session_stats)
+            .service(get_quota_stats)
+            .service(get_crash)
+            .service(get_processed)
+            .service(validate_dump)
+            .service(ingest_wer)
+            .service(ingest_report)
+            .service(ingest_session)
+            .service(upload_minidump)
+            .service(upload_crash)
+            .service(upload_attachment)
+            .service(upload_symbols)
+            .service(get_attachment)
+    })
         .bind(("0.0.0.0", port.parse::<u16>().unwrap_or(8080)))?
         .run()
         .await
@@ -0,0 +1,257 @@
+use actix_web::web;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::index::CrashIndex;
+use crate::issues::{self, IssueEventKind};
+use crate::sync::{self, Change, ChangeKind, Cursor};
+
+// ----- Long-poll notification delivery -----
+//
+// For clients behind proxies that block streaming connections. A request
+// parks until at least one event newer than its cursor exists or the wait
+// deadline passes, then returns the events plus a cursor for the next poll.
+// Events are changes of crashes, see `crate::sync`, and of issue state, see
+// `crate::issues`. Crashes belonging to snoozed issues are not announced.
+//
+// Which issues are snoozed, and which snoozes ran out, is worked out from the
+// metadata index at most once per POLL_INTERVAL and shared by every parked
+// request, however many clients wait.
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+pub const DEFAULT_WAIT: Duration = Duration::from_secs(30);
+pub const MAX_WAIT: Duration = Duration::from_secs(120);
+// Issue events sort among crash changes of the same time by this prefix and
+// the issue id, see `sync::Cursor`.
+const ISSUE_KEY_PREFIX: &str = "issue/";
+
+// The issues snoozed as of the last tick, and when that was.
+static SNOOZED: Mutex<Option<(Instant, Arc<HashSet<String>>)>> = Mutex::new(None);
+
+#[derive(Serialize)]
+pub struct Notification {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crash_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issue_id: Option<String>,
+    pub at: u64, // Milliseconds since UNIX epoch
+}
+
+#[derive(Serialize)]
+pub struct NotificationBatch {
+    pub events: Vec<Notification>,
+    pub cursor: String,
+}
+
+/// Parses durations such as `30s`, `500ms`, `2m`, `1h` or a bare number of
+/// seconds.
+pub fn parse_wait(wait: Option<&str>) -> anyhow::Result<Duration> {
+    let Some(wait) = wait.map(str::trim).filter(|w| !w.is_empty()) else {
+        return Ok(DEFAULT_WAIT);
+    };
+    let (number, unit) = match wait.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => wait.split_at(i),
+        None => (wait, "s"),
+    };
+    let value: u64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid wait '{}'", wait))?;
+    let seconds = |factor: u64| {
+        value
+            .checked_mul(factor)
+            .map(Duration::from_secs)
+            .ok_or_else(|| anyhow::anyhow!("Wait '{}' is too long", wait))
+    };
+    let duration = match unit {
+        "ms" => Duration::from_millis(value),
+        "s" => Duration::from_secs(value),
+        "m" => seconds(60)?,
+        "h" => seconds(3600)?,
+        _ => anyhow::bail!("Invalid wait unit in '{}', expected ms, s, m or h", wait),
+    };
+    Ok(duration.min(MAX_WAIT))
+}
+
+fn event_type(kind: ChangeKind) -> &'static str {
+    match kind {
+        ChangeKind::Created => "crash.created",
+        ChangeKind::Updated => "crash.updated",
+        ChangeKind::Deleted => "crash.deleted",
+    }
+}
+
+// The issues snoozed now, reactivating those whose snooze ran out, shared by
+// the polls of one tick. Later pollers wait for the first one's answer.
+fn snoozed_issues(index: &Mutex<CrashIndex>) -> anyhow::Result<Arc<HashSet<String>>> {
+    let mut snoozed = SNOOZED.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((at, issues)) = snoozed.as_ref() {
+        if at.elapsed() < POLL_INTERVAL {
+            return Ok(issues.clone());
+        }
+    }
+    let issues = {
+        let index = index.lock().unwrap_or_else(|e| e.into_inner());
+        Arc::new(issues::reactivate_expired(&index)?)
+    };
+    *snoozed = Some((Instant::now(), issues.clone()));
+    Ok(issues)
+}
+
+// Drops changes to crashes whose issue is currently snoozed. Crashes not
+// indexed yet are kept.
+fn without_snoozed(
+    changes: Vec<Change>,
+    snoozed: &HashSet<String>,
+    index: &Mutex<CrashIndex>,
+) -> anyhow::Result<Vec<Change>> {
+    if snoozed.is_empty() || changes.iter().all(|c| c.change == ChangeKind::Deleted) {
+        return Ok(changes);
+    }
+    let ids: Vec<String> = changes
+        .iter()
+        .filter(|c| c.change != ChangeKind::Deleted)
+        .map(|c| c.id.clone())
+        .collect();
+    let hidden: HashSet<String> = index
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .crashes(&ids)?
+        .into_iter()
+        .filter(|entry| snoozed.contains(&entry.issue_id))
+        .map(|entry| entry.id)
+        .collect();
+    Ok(changes
+        .into_iter()
+        .filter(|c| c.change == ChangeKind::Deleted || !hidden.contains(&c.id))
+        .collect())
+}
+
+fn issue_event_type(kind: IssueEventKind) -> &'static str {
+    match kind {
+        IssueEventKind::Snoozed => "issue.snoozed",
+        IssueEventKind::Unsnoozed => "issue.unsnoozed",
+        IssueEventKind::Reactivated => "issue.reactivated",
+    }
+}
+
+// The events after `since`, in order, and the cursor past them. Reads the
+// storage backend, so it runs on a blocking thread.
+fn poll(since: &Cursor, index: &Mutex<CrashIndex>) -> anyhow::Result<(Vec<Notification>, Cursor)> {
+    let snoozed = snoozed_issues(index)?;
+    let changes = sync::collect_changes(since)?;
+    let mut cursor = sync::parse_cursor(Some(&changes.cursor))?;
+    // Changes that are filtered out are consumed all the same.
+    let mut events: Vec<(Cursor, Notification)> = without_snoozed(changes.changes, &snoozed, index)?
+        .into_iter()
+        .map(|c| {
+            let key = Cursor {
+                at: c.changed_at,
+                id: c.id.clone(),
+            };
+            let notification = Notification {
+                kind: event_type(c.change),
+                crash_id: Some(c.id),
+                issue_id: None,
+                at: c.changed_at,
+            };
+            (key, notification)
+        })
+        .collect();
+    for event in issues::read_events() {
+        let key = Cursor {
+            at: event.at,
+            id: format!("{}{}", ISSUE_KEY_PREFIX, event.issue_id),
+        };
+        if !since.is_before(key.at, &key.id) {
+            continue;
+        }
+        if cursor.is_before(key.at, &key.id) {
+            cursor = key.clone();
+        }
+        let notification = Notification {
+            kind: issue_event_type(event.kind),
+            crash_id: None,
+            issue_id: Some(event.issue_id),
+            at: event.at,
+        };
+        events.push((key, notification));
+    }
+    events.sort_by(|(a, _), (b, _)| (a.at, &a.id).cmp(&(b.at, &b.id)));
+    Ok((events.into_iter().map(|(_, event)| event).collect(), cursor))
+}
+
+/// Waits up to `wait` for events newer than `since`.
+pub async fn wait_for_events(
+    since: Cursor,
+    wait: Duration,
+    index: Arc<Mutex<CrashIndex>>,
+) -> anyhow::Result<NotificationBatch> {
+    let deadline = Instant::now() + wait;
+    let mut since = since;
+    loop {
+        let polled = web::block({
+            let (since, index) = (since.clone(), index.clone());
+            move || poll(&since, &index)
+        })
+        .await;
+        let (events, cursor) = polled.map_err(|e| anyhow::anyhow!("{}", e))??;
+        if !events.is_empty() || Instant::now() >= deadline {
+            return Ok(NotificationBatch {
+                events,
+                cursor: cursor.to_string(),
+            });
+        }
+        since = cursor;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        actix_web::rt::time::sleep(POLL_INTERVAL.min(remaining)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_wait() {
+        assert_eq!(parse_wait(None).unwrap(), DEFAULT_WAIT);
+        assert_eq!(parse_wait(Some("")).unwrap(), DEFAULT_WAIT);
+        assert_eq!(parse_wait(Some("  ")).unwrap(), DEFAULT_WAIT);
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!(parse_wait(Some("500ms")).unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_wait(Some("45s")).unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_wait(Some("45")).unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_wait(Some("1m")).unwrap(), Duration::from_secs(60));
+        assert_eq!(parse_wait(Some("0")).unwrap(), Duration::ZERO);
+    }
+
+    #[test]
+    fn capped_at_max_wait() {
+        assert_eq!(parse_wait(Some("121s")).unwrap(), MAX_WAIT);
+        assert_eq!(parse_wait(Some("3m")).unwrap(), MAX_WAIT);
+        assert_eq!(parse_wait(Some("1h")).unwrap(), MAX_WAIT);
+        assert_eq!(parse_wait(Some(&u64::MAX.to_string())).unwrap(), MAX_WAIT);
+        assert_eq!(parse_wait(Some(&format!("{}ms", u64::MAX))).unwrap(), MAX_WAIT);
+    }
+
+    #[test]
+    fn overflowing_waits_are_rejected() {
+        assert!(parse_wait(Some(&format!("{}m", u64::MAX / 60 + 1))).is_err());
+        assert!(parse_wait(Some(&format!("{}h", u64::MAX / 3600 + 1))).is_err());
+        assert!(parse_wait(Some(&format!("{}s", u64::MAX as u128 + 1))).is_err());
+        assert_eq!(parse_wait(Some(&format!("{}m", u64::MAX / 60))).unwrap(), MAX_WAIT);
+    }
+
+    #[test]
+    fn invalid_waits() {
+        for wait in ["s", "-1s", "1.5s", "10d", "1 m", "ms10"] {
+            assert!(parse_wait(Some(wait)).is_err(), "{}", wait);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::storage;
+
+// ----- Processor plugins -----
+//
+// Processors run on every crash once its minidump has been analyzed and may
+// rewrite the stored report: add classification tags, enrich it with data
+// from other systems, or set a `route` for downstream notification. Which
+// processors run, and with which options, is configured per project in
+// CRASH_PROCESSORS (default `processors.json`):
+//
+// {
+//   "default": [
+//     { "name": "classify", "options": { "rules": [
+//       { "pattern": "out of memory", "tag": "category", "value": "oom" }
+//     ] } }
+//   ],
+//   "projects": {
+//     "backend": [
+//       { "name": "command", "options": { "program": "./route-crash", "args": [], "timeout": 30 } }
+//     ]
+//   }
+// }
+//
+// Processors are trait objects created by name. `classify` and `command` are
+// built in, and `command` is the extension point: it runs an external
+// program, so custom logic can be added without rebuilding the server. It is
+// killed if it runs longer than its `timeout` in seconds (default 30).
+//
+// No processor may change the report's PROTECTED_KEYS, which tie it to its
+// crash and project; what one writes there is put back and logged.
+
+pub const DEFAULT_PROCESSORS_FILE: &str = "processors.json";
+const DEFAULT_COMMAND_TIMEOUT_SECS: u64 = 30;
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(20);
+const PROTECTED_KEYS: &[&str] = &["event_id", "project"];
+
+/// A user-configurable step of the processing pipeline.
+pub trait Processor: Send + Sync {
+    /// Processes one crash. `report` is the stored event and may be modified
+    /// in place; `analysis` is the processed minidump, if there is one.
+    fn process(&self, report: &mut serde_json::Value, analysis: Option<&serde_json::Value>) -> anyhow::Result<()>;
+}
+
+/// Creates a processor from its `options` block.
+pub type ProcessorFactory = fn(&serde_json::Value) -> anyhow::Result<Box<dyn Processor>>;
+
+#[derive(Deserialize, Clone)]
+struct ProcessorSpec {
+    name: String,
+    #[serde(default)]
+    options: serde_json::Value,
+}
+
+#[derive(Deserialize, Default)]
+struct ProcessorsFile {
+    #[serde(default)]
+    default: Vec<ProcessorSpec>,
+    #[serde(default)]
+    projects: HashMap<String, Vec<ProcessorSpec>>,
+}
+
+// Processors available by name in the configuration file.
+fn factory(name: &str) -> Option<ProcessorFactory> {
+    match name {
+        "classify" => Some(ClassifyProcessor::create),
+        "command" => Some(CommandProcessor::create),
+        _ => None,
+    }
+}
+
+fn load_config() -> anyhow::Result<ProcessorsFile> {
+    let path = std::env::var("CRASH_PROCESSORS").unwrap_or_else(|_| DEFAULT_PROCESSORS_FILE.to_string());
+    match fs::read_to_string(&path) {
+        Ok(data) => Ok(serde_json::from_str(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ProcessorsFile::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Builds the processors configured for `project`, falling back to `default`.
+fn processors_for(project: Option<&str>) -> anyhow::Result<Vec<(String, Box<dyn Processor>)>> {
+    let config = load_config()?;
+    let specs = project
+        .and_then(|p| config.projects.get(p))
+        .unwrap_or(&config.default);
+    specs
+        .iter()
+        .map(|spec| {
+            let factory = factory(&spec.name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown processor '{}'", spec.name))?;
+            let processor = factory(&spec.options)
+                .map_err(|e| anyhow::anyhow!("Invalid options for processor '{}': {}", spec.name, e))?;
+            Ok((spec.name.clone(), processor))
+        })
+        .collect()
+}
+
+/// Runs the configured processors on the stored report of crash `id` and
+/// writes it back if any are configured. A failing processor is logged and
+/// skipped so it cannot hold up the rest of the pipeline.
+pub fn run_processors(id: &str, analysis: Option<&serde_json::Value>) -> anyhow::Result<()> {
+    let data = match storage::read_report(id) {
+        Ok(data) => data,
+        // Streamed dumps can arrive before their report.
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut report: serde_json::Value = serde_json::from_slice(&data)?;
+
+    let project = report.get("project").and_then(|p| p.as_str()).map(|p| p.to_string());
+    let processors = processors_for(project.as_deref())?;
+    if processors.is_empty() {
+        return Ok(());
+    }
+
+    let applied = apply(id, processors, &mut report, analysis);
+    if let Some(map) = report.as_object_mut() {
+        map.insert("processed_by".to_string(), serde_json::Value::Array(applied));
+    }
+    storage::write_report(id, serde_json::to_string_pretty(&report)?.as_bytes())?;
+    Ok(())
+}
+
+// Runs `processors` on `report` of crash `id` in order and returns the names
+// of those that succeeded.
+fn apply(
+    id: &str,
+    processors: Vec<(String, Box<dyn Processor>)>,
+    report: &mut serde_json::Value,
+    analysis: Option<&serde_json::Value>,
+) -> Vec<serde_json::Value> {
+    let protected: Vec<_> = PROTECTED_KEYS.iter().map(|key| report.get(*key).cloned()).collect();
+    let mut applied = Vec::new();
+    for (name, processor) in processors {
+        match processor.process(report, analysis) {
+            Ok(()) => applied.push(serde_json::Value::String(name.clone())),
+            Err(e) => eprintln!("Processor '{}' failed on crash {}: {}", name, id, e),
+        }
+        let Some(map) = report.as_object_mut() else {
+            continue;
+        };
+        for (key, original) in PROTECTED_KEYS.iter().zip(&protected) {
+            if map.get(*key) == original.as_ref() {
+                continue;
+            }
+            eprintln!("Processor '{}' may not change '{}' of crash {}", name, key, id);
+            match original {
+                Some(value) => map.insert(key.to_string(), value.clone()),
+                None => map.remove(*key),
+            };
+        }
+    }
+    applied
+}
+
+// Recursively merges `patch` into `target`; objects are merged key by key,
+// anything else is replaced.
+fn merge(target: &mut serde_json::Value, patch: serde_json::Value) {
+    match (target, patch) {
+        (serde_json::Value::Object(target), serde_json::Value::Object(patch)) => {
+            for (key, value) in patch {
+                merge(target.entry(key).or_insert(serde_json::Value::Null), value);
+            }
+        }
+        (target, patch) => *target = patch,
+    }
+}
+
+// ---------- Built-in: classify ----------
+
+#[derive(Deserialize)]
+struct ClassifyRule {
+    pattern: String,
+    tag: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct ClassifyOptions {
+    rules: Vec<ClassifyRule>,
+}
+
+/// Sets tags on reports whose message or crash reason matches a regex.
+struct ClassifyProcessor {
+    rules: Vec<(Regex, String, String)>,
+}
+
+impl ClassifyProcessor {
+    fn create(options: &serde_json::Value) -> anyhow::Result<Box<dyn Processor>> {
+        let options: ClassifyOptions = serde_json::from_value(options.clone())?;
+        let rules = options
+            .rules
+            .into_iter()
+            .map(|rule| {
+                let regex = Regex::new(&rule.pattern)
+                    .map_err(|e| anyhow::anyhow!("Invalid pattern '{}': {}", rule.pattern, e))?;
+                Ok((regex, rule.tag, rule.value))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Box::new(ClassifyProcessor { rules }))
+    }
+}
+
+impl Processor for ClassifyProcessor {
+    fn process(&self, report: &mut serde_json::Value, analysis: Option<&serde_json::Value>) -> anyhow::Result<()> {
+        let message = report.get("message").and_then(|m| m.as_str()).unwrap_or_default().to_string();
+        let reason = analysis
+            .and_then(|a| a.pointer("/crash_info/type"))
+            .and_then(|r| r.as_str())
+            .unwrap_or_default();
+
+        let mut tags = serde_json::Map::new();
+        for (regex, tag, value) in &self.rules {
+            if regex.is_match(&message) || regex.is_match(reason) {
+                tags.insert(tag.clone(), serde_json::Value::String(value.clone()));
+            }
+        }
+        if !tags.is_empty() {
+            merge(report, serde_json::json!({ "tags": tags }));
+        }
+        Ok(())
+    }
+}
+
+// ---------- Built-in: command ----------
+
+#[derive(Deserialize)]
+struct CommandProcessor {
+    program: String,
+    #[serde(default)]
+    args: Vec<String>,
+    // Seconds the program may run before it is killed
+    #[serde(default = "default_command_timeout")]
+    timeout: u64,
+}
+
+fn default_command_timeout() -> u64 {
+    DEFAULT_COMMAND_TIMEOUT_SECS
+}
+
+impl CommandProcessor {
+    fn create(options: &serde_json::Value) -> anyhow::Result<Box<dyn Processor>> {
+        let processor = serde_json::from_value::<CommandProcessor>(options.clone())?;
+        if processor.timeout == 0 {
+            anyhow::bail!("timeout must be at least one second");
+        }
+        Ok(Box::new(processor))
+    }
+}
+
+/// Runs an external program with `{"report": ..., "analysis": ...}` on stdin.
+/// A JSON object printed on stdout is merged into the report.
+impl Processor for CommandProcessor {
+    fn process(&self, report: &mut serde_json::Value, analysis: Option<&serde_json::Value>) -> anyhow::Result<()> {
+        let input = serde_json::to_vec(&serde_json::json!({ "report": report, "analysis": analysis }))?;
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to start '{}': {}", self.program, e))?;
+        // Input and output go through their own threads: a program that
+        // prints before it has read everything would otherwise block on a
+        // full pipe while the server blocks writing to it.
+        if let Some(mut stdin) = child.stdin.take() {
+            std::thread::spawn(move || {
+                // A program may stop reading early; what it printed still counts.
+                let _ = stdin.write_all(&input);
+            });
+        }
+        let mut stdout = child.stdout.take();
+        let reader = std::thread::spawn(move || {
+            let mut output = Vec::new();
+            stdout.as_mut().map_or(Ok(0), |stdout| stdout.read_to_end(&mut output)).map(|_| output)
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(self.timeout);
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                anyhow::bail!("'{}' did not finish within {} s", self.program, self.timeout);
+            }
+            std::thread::sleep(COMMAND_POLL_INTERVAL);
+        };
+        if !status.success() {
+            anyhow::bail!("'{}' exited with {}", self.program, status);
+        }
+        let stdout = reader
+            .join()
+            .map_err(|_| anyhow::anyhow!("Reading the output of '{}' failed", self.program))??;
+        if stdout.iter().all(|b| b.is_ascii_whitespace()) {
+            return Ok(());
+        }
+        let patch: serde_json::Value = serde_json::from_slice(&stdout)?;
+        if !patch.is_object() {
+            anyhow::bail!("'{}' must print a JSON object", self.program);
+        }
+        merge(report, patch);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Rewrite(serde_json::Value);
+
+    impl Processor for Rewrite {
+        fn process(&self, report: &mut serde_json::Value, _: Option<&serde_json::Value>) -> anyhow::Result<()> {
+            merge(report, self.0.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn protected_keys_are_restored() {
+        let mut report = serde_json::json!({ "event_id": "abc", "message": "boom" });
+        let processors: Vec<(String, Box<dyn Processor>)> = vec![(
+            "rewrite".to_string(),
+            Box::new(Rewrite(serde_json::json!({ "event_id": "other", "project": "p", "route": "ops" }))),
+        )];
+        let applied = apply("abc", processors, &mut report, None);
+        assert_eq!(applied, [serde_json::json!("rewrite")]);
+        assert_eq!(report, serde_json::json!({ "event_id": "abc", "message": "boom", "route": "ops" }));
+    }
+
+    #[test]
+    fn merge_replaces_values_and_merges_objects() {
+        let mut report = serde_json::json!({ "tags": { "a": "1" }, "level": "error" });
+        merge(&mut report, serde_json::json!({ "tags": { "b": "2" }, "level": "fatal" }));
+        assert_eq!(report, serde_json::json!({ "tags": { "a": "1", "b": "2" }, "level": "fatal" }));
+    }
+}
@@ -0,0 +1,160 @@
+use serde::Serialize;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{collect_crash_ids, integrity, processing, storage, sync, ATTACHMENT_PREFIX, MINIDUMP_PREFIX};
+
+// ----- GDPR tooling -----
+//
+// IP anonymization is controlled by CRASH_IP_ANONYMIZATION:
+//   "truncate" (default) - zero the host part (last octet / last 80 bits)
+//   "remove"             - drop the address entirely
+//   "off"                - keep addresses as sent
+
+pub const DELETION_LOG_FILE: &str = "gdpr_deletions.jsonl";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum IpMode {
+    Off,
+    Truncate,
+    Remove,
+}
+
+pub fn ip_mode() -> IpMode {
+    match std::env::var("CRASH_IP_ANONYMIZATION").as_deref() {
+        Ok("off") => IpMode::Off,
+        Ok("remove") => IpMode::Remove,
+        _ => IpMode::Truncate,
+    }
+}
+
+/// Zeroes the host part of an address. Unparseable input is dropped.
+pub fn anonymize_ip(ip: &str) -> Option<String> {
+    match ip.trim().parse::<IpAddr>().ok()? {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            Some(std::net::Ipv4Addr::new(a, b, c, 0).to_string())
+        }
+        IpAddr::V6(v6) => {
+            let mut segments = v6.segments();
+            for segment in &mut segments[3..] {
+                *segment = 0;
+            }
+            Some(std::net::Ipv6Addr::from(segments).to_string())
+        }
+    }
+}
+
+/// Applies the configured IP anonymization to `user.ip_address`.
+pub fn anonymize_event(event: &mut serde_json::Value) {
+    let mode = ip_mode();
+    if mode == IpMode::Off {
+        return;
+    }
+    let Some(user) = event.get_mut("user").and_then(|u| u.as_object_mut()) else {
+        return;
+    };
+    let anonymized = match (mode, user.get("ip_address").and_then(|v| v.as_str())) {
+        (IpMode::Truncate, Some(ip)) => anonymize_ip(ip),
+        _ => None,
+    };
+    match anonymized {
+        Some(ip) => {
+            user.insert("ip_address".to_string(), serde_json::Value::String(ip));
+        }
+        None => {
+            user.remove("ip_address");
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DeletedCrash {
+    pub crash_id: String,
+    pub files: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct DeletionReport {
+    pub user_id: String,
+    pub requested_at: u64,
+    pub deleted: Vec<DeletedCrash>,
+    pub errors: Vec<String>,
+}
+
+// Matches on the raw report so scrubbed fields still identify the user.
+fn belongs_to_user(id: &str, user_id: &str) -> bool {
+    let Some(report) = storage::read_report(id)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<serde_json::Value>(&data).ok())
+    else {
+        return false;
+    };
+    let Some(user) = report.get("user") else {
+        return false;
+    };
+    ["id", "email", "username", "ip_address"]
+        .iter()
+        .any(|key| user.get(*key).and_then(|v| v.as_str()) == Some(user_id))
+}
+
+// The stored files of crash `id`, `.sum` files, the cached analysis, which
+// holds the dump's stacks and modules, and any failed analysis record
+// included. Taken from a listing, since object stores do not tell whether a
+// deletion removed anything.
+fn artifacts_for(id: &str) -> anyhow::Result<Vec<String>> {
+    let mut wanted: HashSet<String> = storage::report_variants(id).into_iter().collect();
+    wanted.extend(storage::variants(MINIDUMP_PREFIX, id, ".dmp"));
+    wanted.insert(processing::analysis_file(id));
+    wanted.insert(processing::failure_file(id));
+    let attachment_prefix = format!("{}{}_", ATTACHMENT_PREFIX, id);
+    Ok(storage::list()?
+        .into_iter()
+        .map(|object| object.name)
+        .filter(|name| {
+            let artifact = name.strip_suffix(integrity::SUFFIX).unwrap_or(name);
+            wanted.contains(artifact) || artifact.starts_with(&attachment_prefix)
+        })
+        .collect())
+}
+
+/// Deletes every crash and attachment tied to `user_id` and records the
+/// outcome in the deletion log. The caller drops the deleted crashes from the
+/// index.
+pub fn delete_user_data(user_id: &str) -> anyhow::Result<DeletionReport> {
+    let mut report = DeletionReport {
+        user_id: user_id.to_string(),
+        requested_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        deleted: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    for id in collect_crash_ids()? {
+        if !belongs_to_user(&id, user_id) {
+            continue;
+        }
+        let mut removed = Vec::new();
+        for file in artifacts_for(&id)? {
+            match storage::delete(&file) {
+                Ok(()) => removed.push(file),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => report.errors.push(format!("{}: {}", file, e)),
+            }
+        }
+        if let Err(e) = sync::record_deletion(&id) {
+            report.errors.push(format!("tombstone for {}: {}", id, e));
+        }
+        report.deleted.push(DeletedCrash {
+            crash_id: id,
+            files: removed,
+        });
+    }
+
+    storage::append(DELETION_LOG_FILE, &serde_json::to_string(&report)?)?;
+
+    Ok(report)
+}
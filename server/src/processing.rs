@@ -0,0 +1,485 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+use crate::index::IndexEntry;
+use crate::plugins;
+use crate::{analyze_minidump, storage};
+
+// ----- Background minidump processing -----
+//
+// Minidump analysis is expensive, so dumps are processed ahead of time by a
+// background worker and the results cached next to the dump. Jobs are ordered
+// so triage stays fast during incident storms: crashes from spiking issues and
+// from their project's newest release first, then everything else
+// newest-first, with the backfill of old dumps last. The server has no
+// alerting, so a spike (SPIKE_THRESHOLD events within SPIKE_WINDOW_SECS)
+// stands in for the issues someone is being alerted about.
+//
+// A dump that cannot be analyzed gets a failure record instead of an
+// analysis. Each enqueue of unprocessed crashes tries it once more, until it
+// has failed MAX_ANALYSIS_ATTEMPTS times; then it is left alone.
+
+pub const ANALYSIS_PREFIX: &str = "crash_analysis_"; // .json
+pub const FAILURE_PREFIX: &str = "failed_analysis_"; // .json
+const MAX_ANALYSIS_ATTEMPTS: u64 = 3;
+// Bumped when analyses change shape or content, so cached ones are redone.
+// Version 2 is symbolicated, see `crate::symbols`.
+const ANALYSIS_VERSION: u64 = 2;
+
+// Issues with at least this many events in the last hour are treated as
+// spiking, the closest the server has to an alerting issue.
+const SPIKE_THRESHOLD: usize = 10;
+const SPIKE_WINDOW_SECS: f64 = 3600.0;
+// Crashes older than this are considered backfill.
+const BACKFILL_AGE_SECS: f64 = 7.0 * 24.0 * 3600.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Priority {
+    Backfill = 0,
+    Normal = 1,
+    NewestRelease = 2,
+    Spiking = 3,
+}
+
+#[derive(PartialEq, Eq)]
+struct Job {
+    priority: Priority,
+    timestamp: u64, // Crash time in seconds, newer first within a priority
+    seq: u64,       // Insertion order, FIFO as the final tie breaker
+    id: String,
+}
+
+impl Ord for Job {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then(self.timestamp.cmp(&other.timestamp))
+            .then(other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Default)]
+pub struct ProcessingQueue {
+    jobs: Mutex<BinaryHeap<Job>>,
+    queued: Mutex<HashSet<String>>,
+    seq: AtomicU64,
+    notify: Notify,
+}
+
+// One dot-separated identifier of a pre-release; numeric ones sort first.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum PreRelease {
+    Number(u64),
+    Text(String),
+}
+
+// A release such as `my-app@1.10.2-rc.1`, ordered as semver orders versions:
+// by the numeric parts, then a pre-release below the release itself.
+// Build metadata after `+` is ignored.
+#[derive(PartialEq, Eq, Debug)]
+struct ReleaseKey {
+    numbers: Vec<u64>,
+    pre: Vec<PreRelease>,
+}
+
+impl Ord for ReleaseKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.numbers.cmp(&other.numbers).then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => self.pre.cmp(&other.pre),
+        })
+    }
+}
+
+impl PartialOrd for ReleaseKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn release_key(release: &str) -> ReleaseKey {
+    let version = release.rsplit('@').next().unwrap_or(release);
+    // A prefix such as `v` is not part of the version.
+    let version = &version[version.find(|c: char| c.is_ascii_digit()).unwrap_or(version.len())..];
+    let version = version.split('+').next().unwrap_or_default();
+    let (core, pre) = version.split_once('-').unwrap_or((version, ""));
+    ReleaseKey {
+        numbers: core
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|part| !part.is_empty())
+            .filter_map(|part| part.parse().ok())
+            .collect(),
+        pre: pre
+            .split('.')
+            .filter(|part| !part.is_empty())
+            .map(|part| match part.parse() {
+                Ok(number) if part.bytes().all(|b| b.is_ascii_digit()) => PreRelease::Number(number),
+                _ => PreRelease::Text(part.to_string()),
+            })
+            .collect(),
+    }
+}
+
+fn now_secs() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// Facts about the whole crash set that individual priorities depend on.
+pub struct PriorityContext {
+    // Per project, as releases of different projects do not compare.
+    newest_releases: HashMap<Option<String>, String>,
+    spiking_issues: HashSet<String>,
+}
+
+impl PriorityContext {
+    pub fn from_entries<'a>(entries: impl Iterator<Item = &'a IndexEntry>) -> Self {
+        let now = now_secs();
+        let mut newest_releases: HashMap<Option<&str>, &str> = HashMap::new();
+        let mut recent_per_issue: HashMap<&str, usize> = HashMap::new();
+
+        for entry in entries {
+            if let Some(release) = entry.release.as_deref() {
+                let newest = newest_releases.entry(entry.project.as_deref()).or_insert(release);
+                if release_key(release) > release_key(newest) {
+                    *newest = release;
+                }
+            }
+            let timestamp = entry.timestamp.as_deref().and_then(|t| t.parse::<f64>().ok());
+            if timestamp.is_some_and(|t| now - t <= SPIKE_WINDOW_SECS) {
+                *recent_per_issue.entry(entry.issue_id.as_str()).or_default() += 1;
+            }
+        }
+
+        PriorityContext {
+            newest_releases: newest_releases
+                .into_iter()
+                .map(|(project, release)| (project.map(str::to_string), release.to_string()))
+                .collect(),
+            spiking_issues: recent_per_issue
+                .into_iter()
+                .filter(|(_, count)| *count >= SPIKE_THRESHOLD)
+                .map(|(issue, _)| issue.to_string())
+                .collect(),
+        }
+    }
+
+    pub fn priority_for(&self, entry: &IndexEntry) -> Priority {
+        if self.spiking_issues.contains(&entry.issue_id) {
+            return Priority::Spiking;
+        }
+        if entry.release.is_some() && entry.release.as_ref() == self.newest_releases.get(&entry.project) {
+            return Priority::NewestRelease;
+        }
+        let age = entry
+            .timestamp
+            .as_deref()
+            .and_then(|t| t.parse::<f64>().ok())
+            .map(|t| now_secs() - t);
+        match age {
+            Some(age) if age <= BACKFILL_AGE_SECS => Priority::Normal,
+            _ => Priority::Backfill,
+        }
+    }
+}
+
+/// The file the analysis of crash `id` is cached in.
+pub fn analysis_file(id: &str) -> String {
+    format!("{}{}.json", ANALYSIS_PREFIX, id)
+}
+
+/// Returns the cached `(analysis, summary)` pair for a crash, if processed.
+pub fn load_cached_analysis(id: &str) -> Option<(serde_json::Value, serde_json::Value)> {
+    let data = storage::get(&analysis_file(id)).ok()?;
+    let mut cached: serde_json::Value = serde_json::from_slice(&data).ok()?;
+    if cached.get("version").and_then(|v| v.as_u64()) != Some(ANALYSIS_VERSION) {
+        return None;
+    }
+    Some((cached.get_mut("analysis")?.take(), cached.get_mut("summary")?.take()))
+}
+
+fn store_analysis(id: &str, analysis: &serde_json::Value, summary: &serde_json::Value) -> anyhow::Result<()> {
+    let cached = serde_json::json!({ "version": ANALYSIS_VERSION, "analysis": analysis, "summary": summary });
+    storage::put(&analysis_file(id), &serde_json::to_vec(&cached)?)?;
+    Ok(())
+}
+
+/// The file the failed analyses of crash `id` are recorded in.
+pub fn failure_file(id: &str) -> String {
+    format!("{}{}.json", FAILURE_PREFIX, id)
+}
+
+// How many times the analysis of crash `id` has failed.
+fn failed_attempts(id: &str) -> u64 {
+    storage::get(&failure_file(id))
+        .ok()
+        .and_then(|data| serde_json::from_slice::<serde_json::Value>(&data).ok())
+        .and_then(|failure| failure.get("attempts").and_then(|v| v.as_u64()))
+        .unwrap_or(0)
+}
+
+fn record_failure(id: &str, error: &anyhow::Error) -> anyhow::Result<()> {
+    let failure = serde_json::json!({ "attempts": failed_attempts(id) + 1, "error": error.to_string() });
+    storage::put(&failure_file(id), &serde_json::to_vec(&failure)?)?;
+    Ok(())
+}
+
+fn clear_failure(id: &str) -> std::io::Result<()> {
+    match storage::delete(&failure_file(id)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Drops the cached analyses that lacked the symbols of module `debug_file`
+/// with `debug_id`, so they are done again with them. Returns how many.
+pub fn forget_unsymbolicated(debug_file: &str, debug_id: &str) -> anyhow::Result<usize> {
+    let mut forgotten = 0;
+    for object in storage::list()? {
+        let Some(id) = object
+            .name
+            .strip_prefix(ANALYSIS_PREFIX)
+            .and_then(|rest| rest.strip_suffix(".json"))
+        else {
+            continue;
+        };
+        let Some((analysis, _)) = load_cached_analysis(id) else {
+            continue;
+        };
+        let lacked = analysis
+            .get("modules")
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+            .any(|module| {
+                let field = |key: &str| module.get(key).and_then(|v| v.as_str()).unwrap_or_default();
+                field("debug_file") == debug_file
+                    && field("debug_id").eq_ignore_ascii_case(debug_id)
+                    && module.get("loaded_symbols").and_then(|v| v.as_bool()) != Some(true)
+            });
+        if lacked {
+            storage::delete(&object.name)?;
+            forgotten += 1;
+        }
+    }
+    Ok(forgotten)
+}
+
+/// Returns the analysis of a crash's minidump, from the cache when possible.
+/// The cache is read and written on blocking threads, as the storage backend
+/// may be remote.
+pub async fn cached_or_analyze(id: &str) -> anyhow::Result<(serde_json::Value, serde_json::Value)> {
+    let owned = id.to_string();
+    if let Some(cached) = tokio::task::spawn_blocking(move || load_cached_analysis(&owned)).await? {
+        return Ok(cached);
+    }
+    let (analysis, summary) = match analyze_minidump(id).await {
+        Ok(analyzed) => analyzed,
+        Err(e) => {
+            let (owned, error) = (id.to_string(), anyhow::anyhow!("{}", e));
+            let recorded = tokio::task::spawn_blocking(move || record_failure(&owned, &error)).await;
+            match recorded {
+                Ok(Ok(())) => {}
+                Ok(Err(record_error)) => eprintln!("Failed to record failed analysis of {}: {}", id, record_error),
+                Err(record_error) => eprintln!("Failed to record failed analysis of {}: {}", id, record_error),
+            }
+            return Err(e);
+        }
+    };
+    // Stores the analysis, then runs the processors, which may run external
+    // programs.
+    let processed = tokio::task::spawn_blocking({
+        let (id, analysis, summary) = (id.to_string(), analysis.clone(), summary.clone());
+        move || {
+            if let Err(e) = store_analysis(&id, &analysis, &summary) {
+                eprintln!("Failed to cache analysis of {}: {}", id, e);
+            }
+            if let Err(e) = clear_failure(&id) {
+                eprintln!("Failed to clear failed analysis of {}: {}", id, e);
+            }
+            plugins::run_processors(&id, Some(&analysis))
+        }
+    })
+    .await;
+    match processed {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => eprintln!("Failed to run processors on {}: {}", id, e),
+        Err(e) => eprintln!("Failed to run processors on {}: {}", id, e),
+    }
+    Ok((analysis, summary))
+}
+
+impl ProcessingQueue {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Queues every crash with an unprocessed minidump, except those whose
+    /// analysis failed too often.
+    pub fn enqueue_unprocessed<'a>(&self, entries: impl Iterator<Item = &'a IndexEntry> + Clone) {
+        let context = PriorityContext::from_entries(entries.clone());
+        // One listing rather than a lookup per crash, which object stores
+        // charge for. Jobs find the analysis cached if the listing failed.
+        let (analyzed, failed): (HashSet<String>, HashSet<String>) = storage::list()
+            .map(|objects| {
+                objects
+                    .into_iter()
+                    .map(|object| object.name)
+                    .filter(|name| name.starts_with(ANALYSIS_PREFIX) || name.starts_with(FAILURE_PREFIX))
+                    .partition(|name| name.starts_with(ANALYSIS_PREFIX))
+            })
+            .unwrap_or_default();
+        for entry in entries {
+            if !entry.has_minidump || analyzed.contains(&analysis_file(&entry.id)) {
+                continue;
+            }
+            // Failures are few, so they are read one by one.
+            if failed.contains(&failure_file(&entry.id)) && failed_attempts(&entry.id) >= MAX_ANALYSIS_ATTEMPTS {
+                continue;
+            }
+            self.enqueue(entry, context.priority_for(entry));
+        }
+    }
+
+    pub fn enqueue(&self, entry: &IndexEntry, priority: Priority) {
+        let mut queued = self.queued.lock().unwrap_or_else(|e| e.into_inner());
+        if !queued.insert(entry.id.clone()) {
+            return;
+        }
+        let job = Job {
+            priority,
+            timestamp: entry
+                .timestamp
+                .as_deref()
+                .and_then(|t| t.parse::<f64>().ok())
+                .map(|t| t as u64)
+                .unwrap_or(0),
+            seq: self.seq.fetch_add(1, AtomicOrdering::Relaxed),
+            id: entry.id.clone(),
+        };
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).push(job);
+        self.notify.notify_one();
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    fn pop(&self) -> Option<Job> {
+        let job = self.jobs.lock().unwrap_or_else(|e| e.into_inner()).pop()?;
+        self.queued
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&job.id);
+        Some(job)
+    }
+
+    /// Worker loop: processes jobs highest priority first, forever.
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            let Some(job) = self.pop() else {
+                self.notify.notified().await;
+                continue;
+            };
+            if storage::minidump_file(&job.id).is_none() {
+                continue;
+            }
+            if let Err(e) = cached_or_analyze(&job.id).await {
+                eprintln!("Failed to process minidump {} ({:?}): {}", job.id, job.priority, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_keys() {
+        assert_eq!(release_key("my-app@1.10.2").numbers, [1, 10, 2]);
+        assert_eq!(release_key("1.10.2").numbers, [1, 10, 2]);
+        let key = release_key("app@2.0.0-rc.1+build.7");
+        assert_eq!(key.numbers, [2, 0, 0]);
+        assert_eq!(key.pre, [PreRelease::Text("rc".to_string()), PreRelease::Number(1)]);
+        assert_eq!(release_key("scope@pkg@3.1").numbers, [3, 1]);
+        assert!(release_key("").numbers.is_empty());
+        assert!(release_key("app@").numbers.is_empty());
+        // Parts too large for a number are skipped rather than failing.
+        assert_eq!(release_key("1.99999999999999999999999.3").numbers, [1, 3]);
+    }
+
+    #[test]
+    fn releases_compare_numerically() {
+        assert!(release_key("app@1.10.0") > release_key("app@1.9.9"));
+        assert!(release_key("app@1.0.1") > release_key("app@1.0"));
+        assert_eq!(release_key("app@v1.2"), release_key("1.2"));
+    }
+
+    #[test]
+    fn pre_releases_compare_as_semver() {
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "2.0.0-rc.1",
+            "2.0.0+build.5",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(release_key(pair[0]) < release_key(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+        assert_eq!(release_key("2.0.0+build.5"), release_key("2.0.0"));
+    }
+
+    fn entry(id: &str, project: &str, release: &str) -> IndexEntry {
+        IndexEntry {
+            id: id.to_string(),
+            timestamp: None,
+            message: None,
+            level: None,
+            release: Some(release.to_string()),
+            module: None,
+            issue_id: id.to_string(),
+            has_minidump: true,
+            flagged: false,
+            modified: 0,
+            tags: Default::default(),
+            project: Some(project.to_string()),
+        }
+    }
+
+    #[test]
+    fn newest_release_is_per_project() {
+        let entries = [
+            entry("a", "web", "web@3.0.0"),
+            entry("b", "web", "web@3.1.0-rc.1"),
+            entry("c", "api", "api@1.2.0"),
+            entry("d", "api", "api@1.1.0"),
+        ];
+        let context = PriorityContext::from_entries(entries.iter());
+        let priorities: Vec<_> = entries.iter().map(|e| context.priority_for(e)).collect();
+        assert_eq!(
+            priorities,
+            [Priority::Backfill, Priority::NewestRelease, Priority::NewestRelease, Priority::Backfill]
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ----- Per-project ingestion quotas -----
+//
+// Limits are read from CRASH_QUOTAS (default `quotas.json`); missing limits
+// mean unlimited:
+//
+// {
+//   "default": { "events_per_hour": 1000, "bytes_per_day": 1073741824 },
+//   "projects": { "noisy": { "events_per_hour": 100 } }
+// }
+//
+// Usage is counted in fixed hourly and daily windows, in memory.
+
+pub const DEFAULT_QUOTA_FILE: &str = "quotas.json";
+pub const DEFAULT_PROJECT: &str = "default";
+const HOUR: u64 = 3600;
+const DAY: u64 = 24 * HOUR;
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct QuotaLimits {
+    pub events_per_hour: Option<u64>,
+    pub events_per_day: Option<u64>,
+    pub bytes_per_hour: Option<u64>,
+    pub bytes_per_day: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+struct QuotaFile {
+    #[serde(default)]
+    default: QuotaLimits,
+    #[serde(default)]
+    projects: HashMap<String, QuotaLimits>,
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct WindowUsage {
+    pub window_start: u64,
+    pub events: u64,
+    pub bytes: u64,
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct ProjectUsage {
+    pub hour: WindowUsage,
+    pub day: WindowUsage,
+    pub accepted_events: u64,
+    pub dropped_events: u64,
+    pub dropped_bytes: u64,
+}
+
+#[derive(Serialize)]
+pub struct ProjectQuotaStats {
+    pub project: String,
+    pub limits: QuotaLimits,
+    pub usage: ProjectUsage,
+}
+
+/// Details of a rejected ingestion, turned into a 429 response.
+pub struct QuotaExceeded {
+    pub quota: &'static str,
+    pub limit: u64,
+    pub reset_at: u64,
+    pub retry_after: u64,
+}
+
+pub struct QuotaTracker {
+    default: QuotaLimits,
+    projects: HashMap<String, QuotaLimits>,
+    usage: Mutex<HashMap<String, ProjectUsage>>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// The project an ingestion request belongs to: the `X-Crash-Project` header
+/// or `?project=` query parameter, falling back to `default`.
+pub fn project_of(req: &HttpRequest) -> String {
+    let from_header = req
+        .headers()
+        .get("X-Crash-Project")
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+    let from_query = || {
+        web::Query::<HashMap<String, String>>::from_query(req.query_string())
+            .ok()
+            .and_then(|q| q.get("project").cloned())
+    };
+    from_header
+        .or_else(from_query)
+        .filter(|p| !p.is_empty())
+        .unwrap_or_else(|| DEFAULT_PROJECT.to_string())
+}
+
+impl QuotaExceeded {
+    pub fn into_response(self) -> HttpResponse {
+        HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", self.retry_after.to_string()))
+            .insert_header(("X-Quota-Exceeded", self.quota))
+            .insert_header(("X-Quota-Limit", self.limit.to_string()))
+            .insert_header(("X-Quota-Remaining", "0"))
+            .insert_header(("X-Quota-Reset", self.reset_at.to_string()))
+            .body(format!("Quota {} of {} exceeded", self.quota, self.limit))
+    }
+}
+
+impl WindowUsage {
+    fn roll(&mut self, now: u64, length: u64) {
+        let start = now - now % length;
+        if self.window_start != start {
+            *self = WindowUsage {
+                window_start: start,
+                events: 0,
+                bytes: 0,
+            };
+        }
+    }
+}
+
+impl QuotaTracker {
+    pub fn load() -> anyhow::Result<Self> {
+        let path = std::env::var("CRASH_QUOTAS").unwrap_or_else(|_| DEFAULT_QUOTA_FILE.to_string());
+        let file: QuotaFile = match fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => QuotaFile::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(QuotaTracker {
+            default: file.default,
+            projects: file.projects,
+            usage: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn limits_for(&self, project: &str) -> &QuotaLimits {
+        self.projects.get(project).unwrap_or(&self.default)
+    }
+
+    /// Accounts one event of `bytes` to `project`, or rejects it if any quota
+    /// would be exceeded. Rejections are counted as dropped events.
+    pub fn check_and_record(&self, project: &str, bytes: u64) -> Result<(), QuotaExceeded> {
+        let limits = self.limits_for(project).clone();
+        let now = now_secs();
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        let usage = usage.entry(project.to_string()).or_default();
+        usage.hour.roll(now, HOUR);
+        usage.day.roll(now, DAY);
+
+        let checks = [
+            ("events_per_hour", limits.events_per_hour, usage.hour.events + 1, usage.hour.window_start + HOUR),
+            ("bytes_per_hour", limits.bytes_per_hour, usage.hour.bytes.saturating_add(bytes), usage.hour.window_start + HOUR),
+            ("events_per_day", limits.events_per_day, usage.day.events + 1, usage.day.window_start + DAY),
+            ("bytes_per_day", limits.bytes_per_day, usage.day.bytes.saturating_add(bytes), usage.day.window_start + DAY),
+        ];
+        for (quota, limit, would_be, reset_at) in checks {
+            if let Some(limit) = limit {
+                if would_be > limit {
+                    usage.dropped_events += 1;
+                    usage.dropped_bytes = usage.dropped_bytes.saturating_add(bytes);
+                    return Err(QuotaExceeded {
+                        quota,
+                        limit,
+                        reset_at,
+                        retry_after: reset_at.saturating_sub(now),
+                    });
+                }
+            }
+        }
+
+        usage.hour.events += 1;
+        usage.hour.bytes = usage.hour.bytes.saturating_add(bytes);
+        usage.day.events += 1;
+        usage.day.bytes = usage.day.bytes.saturating_add(bytes);
+        usage.accepted_events += 1;
+        Ok(())
+    }
+
+    /// Current limits and usage of every project seen since startup.
+    pub fn stats(&self) -> Vec<ProjectQuotaStats> {
+        let usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        let mut stats: Vec<ProjectQuotaStats> = usage
+            .iter()
+            .map(|(project, usage)| ProjectQuotaStats {
+                project: project.clone(),
+                limits: self.limits_for(project).clone(),
+                usage: usage.clone(),
+            })
+            .collect();
+        stats.sort_by(|a, b| a.project.cmp(&b.project));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(file: &str) -> QuotaTracker {
+        let file: QuotaFile = serde_json::from_str(file).unwrap();
+        QuotaTracker {
+            default: file.default,
+            projects: file.projects,
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn missing_limits_are_unlimited() {
+        let quotas = tracker("{}");
+        for _ in 0..1000 {
+            assert!(quotas.check_and_record(DEFAULT_PROJECT, u64::MAX).is_ok());
+        }
+        let stats = quotas.stats();
+        assert_eq!(stats[0].usage.accepted_events, 1000);
+        assert_eq!(stats[0].usage.day.bytes, u64::MAX);
+    }
+
+    #[test]
+    fn project_limits_replace_the_default() {
+        let quotas = tracker(r#"{ "default": { "events_per_hour": 1 }, "projects": { "big": { "bytes_per_day": 10 } } }"#);
+        assert_eq!(quotas.limits_for("other").events_per_hour, Some(1));
+        assert_eq!(quotas.limits_for("big").events_per_hour, None);
+        assert_eq!(quotas.limits_for("big").bytes_per_day, Some(10));
+    }
+
+    #[test]
+    fn invalid_files() {
+        for file in [r#"{ "default": { "events_per_hour": -1 } }"#, r#"{ "projects": [] }"#, "{"] {
+            assert!(serde_json::from_str::<QuotaFile>(file).is_err(), "{}", file);
+        }
+    }
+
+    #[test]
+    fn limits_are_inclusive() {
+        let quotas = tracker(r#"{ "default": { "events_per_hour": 2, "bytes_per_hour": 100 } }"#);
+        assert!(quotas.check_and_record("p", 60).is_ok());
+        let exceeded = quotas.check_and_record("p", 41).unwrap_err();
+        assert_eq!((exceeded.quota, exceeded.limit), ("bytes_per_hour", 100));
+        assert!(exceeded.retry_after <= HOUR);
+        assert!(quotas.check_and_record("p", 40).is_ok());
+        let exceeded = quotas.check_and_record("p", 0).unwrap_err();
+        assert_eq!(exceeded.quota, "events_per_hour");
+
+        let usage = &quotas.stats()[0].usage;
+        assert_eq!((usage.accepted_events, usage.dropped_events, usage.dropped_bytes), (2, 2, 41));
+    }
+
+    #[test]
+    fn huge_events_do_not_overflow() {
+        let quotas = tracker(r#"{ "default": { "bytes_per_day": 100 } }"#);
+        assert!(quotas.check_and_record("p", 60).is_ok());
+        assert_eq!(quotas.check_and_record("p", u64::MAX).unwrap_err().quota, "bytes_per_day");
+        assert_eq!(quotas.check_and_record("p", u64::MAX).unwrap_err().quota, "bytes_per_day");
+        assert_eq!(quotas.stats()[0].usage.dropped_bytes, u64::MAX);
+    }
+}
@@ -0,0 +1,341 @@
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::time::{Duration, SystemTime};
+
+use crate::backend::{to_millis, Object, Storage};
+use crate::storage::TEMP_SUFFIX;
+
+// ----- S3 storage -----
+//
+// Keeps artifacts as objects in an S3 bucket or an S3-compatible store
+// (MinIO, R2, ...), named like the files would be, under an optional prefix.
+// Selected with CRASH_STORAGE=s3 and configured with
+//
+//   CRASH_S3_BUCKET    the bucket, required
+//   CRASH_S3_REGION    AWS region, `us-east-1` by default
+//   CRASH_S3_ENDPOINT  another store, e.g. `http://minio:9000`
+//   CRASH_S3_PREFIX    put in front of every object name, e.g. `crashes/`
+//
+// with the credentials in AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and
+// AWS_SESSION_TOKEN. Requests are signed with AWS Signature Version 4 and use
+// path-style URLs (`{endpoint}/{bucket}/{key}`), which every compatible
+// store accepts. Objects have no creation time, so `/crashes/changes` reports
+// every change to a crash as its creation.
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct S3Storage {
+    endpoint: String,
+    host: String,
+    bucket: String,
+    region: String,
+    prefix: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl S3Storage {
+    /// The store configured by the CRASH_S3_* and AWS_* variables.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let Some(bucket) = env("CRASH_S3_BUCKET") else {
+            anyhow::bail!("CRASH_STORAGE=s3 needs CRASH_S3_BUCKET");
+        };
+        let region = env("CRASH_S3_REGION").unwrap_or_else(|| "us-east-1".to_string());
+        let endpoint = env("CRASH_S3_ENDPOINT")
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region))
+            .trim_end_matches('/')
+            .to_string();
+        let host = endpoint
+            .split_once("://")
+            .map_or(endpoint.as_str(), |(_, rest)| rest)
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        Ok(S3Storage {
+            endpoint,
+            host,
+            bucket,
+            region,
+            prefix: env("CRASH_S3_PREFIX").unwrap_or_default(),
+            access_key: env("AWS_ACCESS_KEY_ID").unwrap_or_default(),
+            secret_key: env("AWS_SECRET_ACCESS_KEY").unwrap_or_default(),
+            session_token: env("AWS_SESSION_TOKEN"),
+            agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build(),
+        })
+    }
+
+    /// Where objects go, for the startup message.
+    pub fn location(&self) -> String {
+        format!("{}/{}/{}", self.endpoint, self.bucket, self.prefix)
+    }
+
+    // Sends a signed request. `query` pairs are unencoded; the request fails
+    // with the store's error for any status but 2xx.
+    fn request(
+        &self,
+        method: &str,
+        key: Option<&str>,
+        query: &[(&str, &str)],
+        body: &[u8],
+    ) -> std::io::Result<ureq::Response> {
+        let path = match key {
+            Some(key) => format!("/{}/{}{}", self.bucket, self.prefix, key),
+            None => format!("/{}", self.bucket),
+        };
+        let uri = encode(&path, false);
+        let mut query: Vec<(String, String)> = query.iter().map(|(k, v)| (encode(k, true), encode(v, true))).collect();
+        query.sort();
+        let query = query.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&");
+
+        let payload_hash = hex(&Sha256::digest(body));
+        let timestamp = amz_date(to_millis(SystemTime::now()) / 1000);
+        let mut headers = vec![
+            ("host".to_string(), self.host.clone()),
+            ("x-amz-content-sha256".to_string(), payload_hash.clone()),
+            ("x-amz-date".to_string(), timestamp.clone()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+        headers.sort();
+        let authorization = self.authorization(method, &uri, &query, &headers, &payload_hash, &timestamp);
+
+        let url = if query.is_empty() {
+            format!("{}{}", self.endpoint, uri)
+        } else {
+            format!("{}{}?{}", self.endpoint, uri, query)
+        };
+        let mut request = self.agent.request(method, &url).set("Authorization", &authorization);
+        for (name, value) in headers.iter().filter(|(name, _)| name != "host") {
+            request = request.set(name, value);
+        }
+        match request.send_bytes(body) {
+            Ok(response) => Ok(response),
+            Err(ureq::Error::Status(404, _)) => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No object {}", path),
+            )),
+            Err(e) => Err(std::io::Error::other(format!("{} {}: {}", method, path, e))),
+        }
+    }
+
+    // `Authorization` header of a request with the given canonical URI,
+    // query and headers (lowercase names, sorted), at `timestamp`
+    // (`YYYYMMDD'T'HHMMSS'Z'`).
+    fn authorization(
+        &self,
+        method: &str,
+        uri: &str,
+        query: &str,
+        headers: &[(String, String)],
+        payload_hash: &str,
+        timestamp: &str,
+    ) -> String {
+        let date = &timestamp[..8];
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+        let signed_headers = headers.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(";");
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method, uri, query, canonical_headers, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = [date, &self.region, "s3", "aws4_request"]
+            .iter()
+            .fold(format!("AWS4{}", self.secret_key).into_bytes(), |key, part| hmac(&key, part.as_bytes()));
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key,
+            scope,
+            signed_headers,
+            hex(&hmac(&key, string_to_sign.as_bytes()))
+        )
+    }
+}
+
+impl Storage for S3Storage {
+    fn get(&self, name: &str) -> std::io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.request("GET", Some(name), &[], &[])?
+            .into_reader()
+            .read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    // Objects only become visible once fully uploaded.
+    fn put(&self, name: &str, data: &[u8]) -> std::io::Result<()> {
+        self.request("PUT", Some(name), &[], data)?;
+        Ok(())
+    }
+
+    fn list(&self) -> std::io::Result<Vec<Object>> {
+        let mut objects = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", self.prefix.as_str())];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let body = self.request("GET", None, &query, &[])?.into_string()?;
+            for contents in elements(&body, "Contents") {
+                let Some(name) = element(contents, "Key").and_then(|key| key.strip_prefix(self.prefix.as_str())) else {
+                    continue;
+                };
+                let name = unescape(name);
+                // Only the flat namespace of the prefix, as in a directory.
+                if name.is_empty() || name.contains('/') || name.ends_with(TEMP_SUFFIX) {
+                    continue;
+                }
+                objects.push(Object {
+                    name,
+                    modified: element(contents, "LastModified").and_then(parse_iso8601).unwrap_or(0),
+                    created: None,
+                });
+            }
+            token = match element(&body, "IsTruncated") {
+                Some("true") => element(&body, "NextContinuationToken").map(unescape),
+                _ => None,
+            };
+            if token.is_none() {
+                return Ok(objects);
+            }
+        }
+    }
+
+    // S3 does not tell whether there was anything to delete.
+    fn delete(&self, name: &str) -> std::io::Result<()> {
+        self.request("DELETE", Some(name), &[], &[])?;
+        Ok(())
+    }
+
+    fn stat(&self, name: &str) -> std::io::Result<Option<Object>> {
+        match self.request("HEAD", Some(name), &[], &[]) {
+            Ok(response) => Ok(Some(Object {
+                name: name.to_string(),
+                modified: response.header("Last-Modified").and_then(parse_http_date).unwrap_or(0),
+                created: None,
+            })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Percent-encodes `text` as SigV4 wants it; slashes are kept in paths.
+fn encode(text: &str, encode_slash: bool) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            b'/' if !encode_slash => "/".to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// Contents of every `<tag>` element of `xml`. ListObjectsV2 responses are
+// flat enough to not need a parser.
+fn elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        let Some(end) = after.find(&close) else {
+            break;
+        };
+        found.push(&after[..end]);
+        rest = &after[end + close.len()..];
+    }
+    found
+}
+
+fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    elements(xml, tag).into_iter().next()
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// Days since the UNIX epoch of a civil date (Howard Hinnant's algorithm).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn to_epoch_millis(year: i64, month: i64, day: i64, hour: i64, minute: i64, second: i64) -> Option<u64> {
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    u64::try_from(secs).ok().map(|secs| secs * 1000)
+}
+
+// `2009-10-12T17:50:30.000Z`, as in listings.
+fn parse_iso8601(text: &str) -> Option<u64> {
+    let field = |range: std::ops::Range<usize>| text.get(range)?.parse::<i64>().ok();
+    to_epoch_millis(field(0..4)?, field(5..7)?, field(8..10)?, field(11..13)?, field(14..16)?, field(17..19)?)
+}
+
+// `Mon, 12 Oct 2009 17:50:30 GMT`, as in `Last-Modified`.
+fn parse_http_date(text: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let mut parts = text.split_whitespace().skip(1);
+    let day = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let year = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|t| t.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    to_epoch_millis(year, month, day, hour, minute, second)
+}
+
+// `YYYYMMDD'T'HHMMSS'Z'` for `secs` since the UNIX epoch.
+fn amz_date(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rest = secs % 86400;
+    // Days to a civil date (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
@@ -0,0 +1,106 @@
+// ----- Report schema versions -----
+//
+// Reports carry the version of their format in `schema_version`. Version 1
+// reports, written before the field existed, have none; version 2 added it
+// along with time-ordered (UUIDv7) event ids. Reports are brought to the
+// current version when they are stored and when they are read, so the rest
+// of the server only sees one format. Reports from newer clients are kept as
+// they are: unknown fields pass through untouched.
+//
+// Uploaded reports are checked with `validate` before they are stored, so
+// the listing and issue grouping can rely on the fields they read having the
+// expected types.
+
+/// Newest report format the server understands.
+pub const SCHEMA_VERSION: u64 = 2;
+
+/// Format version of `report`, 1 for reports without one.
+pub fn schema_version(report: &serde_json::Value) -> u64 {
+    report
+        .get("schema_version")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(1)
+}
+
+/// Upgrades `report` to SCHEMA_VERSION in place. Returns its version before.
+pub fn upgrade(report: &mut serde_json::Value) -> u64 {
+    let version = schema_version(report);
+    let Some(map) = report.as_object_mut() else {
+        return version;
+    };
+    // 1 -> 2: only the version field itself is new.
+    if version < SCHEMA_VERSION {
+        map.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+    }
+    version
+}
+
+const LEVELS: &[&str] = &["fatal", "error", "warning", "info", "debug"];
+
+// Top-level fields that hold a string when present.
+const STRING_FIELDS: &[&str] = &[
+    "event_id",
+    "platform",
+    "release",
+    "environment",
+    "dist",
+    "server_name",
+    "installation_id",
+];
+
+// Top-level fields that hold an object, or a list, when present.
+const OBJECT_FIELDS: &[&str] = &["tags", "extra", "contexts", "user", "debug_meta", "stacktrace"];
+const LIST_FIELDS: &[&str] = &["threads", "fingerprint", "attachments", "async_tasks"];
+
+/// Checks that `report` is an event: an object whose known fields have the
+/// types clients write. Unknown fields are not looked at.
+pub fn validate(report: &serde_json::Value) -> anyhow::Result<()> {
+    let Some(map) = report.as_object() else {
+        anyhow::bail!("The report is not a JSON object");
+    };
+    let present = |key: &str| map.get(key).filter(|v| !v.is_null());
+    for key in STRING_FIELDS {
+        if present(key).is_some_and(|v| !v.is_string()) {
+            anyhow::bail!("'{}' must be a string", key);
+        }
+    }
+    for key in OBJECT_FIELDS {
+        if present(key).is_some_and(|v| !v.is_object()) {
+            anyhow::bail!("'{}' must be an object", key);
+        }
+    }
+    for key in LIST_FIELDS {
+        if present(key).is_some_and(|v| !v.is_array()) {
+            anyhow::bail!("'{}' must be a list", key);
+        }
+    }
+    if present("schema_version").is_some_and(|v| !v.is_u64()) {
+        anyhow::bail!("'schema_version' must be a positive integer");
+    }
+    match present("timestamp") {
+        None => {}
+        Some(serde_json::Value::String(s)) if s.parse::<f64>().is_ok() => {}
+        Some(v) if v.is_number() => {}
+        Some(_) => anyhow::bail!("'timestamp' must be seconds since the UNIX epoch"),
+    }
+    if let Some(level) = present("level") {
+        if !level.as_str().is_some_and(|l| LEVELS.contains(&l)) {
+            anyhow::bail!("'level' must be one of {}", LEVELS.join(", "));
+        }
+    }
+    // A string, or Sentry's `{"formatted": ...}`.
+    if present("message").is_some_and(|v| !v.is_string() && !v.is_object()) {
+        anyhow::bail!("'message' must be a string");
+    }
+    // A list, or Sentry's `{"values": [...]}`.
+    if present("breadcrumbs").is_some_and(|v| !v.is_array() && !v.get("values").is_some_and(|v| v.is_array())) {
+        anyhow::bail!("'breadcrumbs' must be a list");
+    }
+    if let Some(exception) = present("exception") {
+        let values = exception.get("values").and_then(|v| v.as_array());
+        if !values.is_some_and(|values| values.iter().all(|v| v.is_object())) {
+            anyhow::bail!("'exception.values' must be a list of objects");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::fs;
+use std::sync::OnceLock;
+
+// ----- Server-side data scrubbing -----
+//
+// Safety net for clients that did not enable client-side scrubbing. Rules are
+// read from the file named by CRASH_SCRUB_RULES (default `scrub_rules.json`):
+//
+// {
+//   "key_patterns": ["password", "token"],   // case-insensitive substring of object keys
+//   "regexes": ["\\b\\d{16}\\b"],            // applied to every string value
+//   "field_paths": ["/user/email"]           // JSON pointers that are always redacted
+// }
+
+pub const DEFAULT_RULES_FILE: &str = "scrub_rules.json";
+pub const FILTERED: &str = "[Filtered]";
+
+const DEFAULT_KEY_PATTERNS: &[&str] = &[
+    "password",
+    "passwd",
+    "secret",
+    "token",
+    "api_key",
+    "apikey",
+    "authorization",
+    "cookie",
+    "session",
+    "private_key",
+];
+
+#[derive(Deserialize, Default)]
+struct ScrubRulesFile {
+    key_patterns: Option<Vec<String>>,
+    #[serde(default)]
+    regexes: Vec<String>,
+    #[serde(default)]
+    field_paths: Vec<String>,
+}
+
+pub struct Scrubber {
+    key_patterns: Vec<String>,
+    regexes: Vec<Regex>,
+    field_paths: Vec<String>,
+}
+
+static SCRUBBER: OnceLock<Scrubber> = OnceLock::new();
+
+impl Scrubber {
+    fn from_file(path: &str) -> anyhow::Result<Self> {
+        let rules: ScrubRulesFile = match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ScrubRulesFile::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let key_patterns = rules
+            .key_patterns
+            .unwrap_or_else(|| DEFAULT_KEY_PATTERNS.iter().map(|p| p.to_string()).collect())
+            .into_iter()
+            .map(|p| p.to_lowercase())
+            .collect();
+        let regexes = rules
+            .regexes
+            .iter()
+            .map(|r| Regex::new(r).map_err(|e| anyhow::anyhow!("Invalid scrub regex '{}': {}", r, e)))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Scrubber {
+            key_patterns,
+            regexes,
+            field_paths: rules.field_paths,
+        })
+    }
+
+    fn key_matches(&self, key: &str) -> bool {
+        let key = key.to_lowercase();
+        self.key_patterns.iter().any(|p| key.contains(p.as_str()))
+    }
+
+    fn scrub_value(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, child) in map.iter_mut() {
+                    if self.key_matches(key) && !child.is_object() && !child.is_array() {
+                        *child = serde_json::Value::String(FILTERED.to_string());
+                    } else {
+                        self.scrub_value(child);
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => {
+                for item in items {
+                    self.scrub_value(item);
+                }
+            }
+            serde_json::Value::String(s) => {
+                for regex in &self.regexes {
+                    if regex.is_match(s) {
+                        *s = regex.replace_all(s, FILTERED).into_owned();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Redacts everything matched by the configured rules, in place.
+    pub fn scrub(&self, event: &mut serde_json::Value) {
+        for path in &self.field_paths {
+            if let Some(target) = event.pointer_mut(path) {
+                *target = serde_json::Value::String(FILTERED.to_string());
+            }
+        }
+        self.scrub_value(event);
+    }
+}
+
+/// Loads the scrubbing rules. Must be called once at startup; invalid rules
+/// are reported instead of silently disabling scrubbing.
+pub fn init() -> anyhow::Result<()> {
+    let path = std::env::var("CRASH_SCRUB_RULES").unwrap_or_else(|_| DEFAULT_RULES_FILE.to_string());
+    let scrubber = Scrubber::from_file(&path)?;
+    let _ = SCRUBBER.set(scrubber);
+    Ok(())
+}
+
+/// Applies the configured rules to an event.
+pub fn scrub_event(event: &mut serde_json::Value) {
+    if let Some(scrubber) = SCRUBBER.get() {
+        scrubber.scrub(event);
+    }
+}
@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::ingest::is_valid_id;
+use crate::storage;
+
+// ----- Sessions -----
+//
+// Clients send one record per ended session (`POST /sessions`), stored as
+// `crash_session_<sid>.json`; a later record with the same id replaces the
+// earlier one. Clients without an upload URL leave their records in their
+// output directory, which the server reads the same way when it is run there.
+// `GET /stats/sessions` aggregates the records of sessions started in a
+// window into crash-free rates per release, as Sentry's release health does.
+// Sessions that ended `abnormal` (killed, aborted or crashed natively, which
+// the client cannot tell apart) count as crashed; records still `ok` belong
+// to running sessions and are only counted as open.
+
+pub const SESSION_PREFIX: &str = "crash_session_";
+
+const STATUSES: &[&str] = &["ok", "exited", "crashed", "abnormal"];
+
+#[derive(Deserialize)]
+struct SessionRecord {
+    did: Option<String>,
+    started: f64,
+    status: String,
+    #[serde(default)]
+    errors: u64,
+    release: Option<String>,
+    project: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+pub struct ReleaseHealth {
+    pub release: Option<String>,
+    pub sessions: u64,
+    pub exited: u64,
+    pub crashed: u64,
+    pub abnormal: u64,
+    pub errored: u64, // Ended cleanly but reported errors
+    pub open: u64,
+    pub users: usize,
+    pub crashed_users: usize,
+    pub crash_free_sessions: Option<f64>,
+    pub crash_free_users: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct SessionStats {
+    pub window_secs: u64,
+    pub total: ReleaseHealth,
+    pub releases: Vec<ReleaseHealth>,
+}
+
+#[derive(Default)]
+struct Accumulator {
+    health: ReleaseHealth,
+    users: HashSet<String>,
+    crashed_users: HashSet<String>,
+}
+
+impl Accumulator {
+    fn add(&mut self, record: &SessionRecord) {
+        let health = &mut self.health;
+        let crashed = match record.status.as_str() {
+            "ok" => {
+                health.open += 1;
+                return;
+            }
+            "exited" => {
+                health.exited += 1;
+                if record.errors > 0 {
+                    health.errored += 1;
+                }
+                false
+            }
+            "crashed" => {
+                health.crashed += 1;
+                true
+            }
+            _ => {
+                health.abnormal += 1;
+                true
+            }
+        };
+        health.sessions += 1;
+        if let Some(did) = &record.did {
+            self.users.insert(did.clone());
+            if crashed {
+                self.crashed_users.insert(did.clone());
+            }
+        }
+    }
+
+    fn finish(mut self) -> ReleaseHealth {
+        let rate = |bad: usize, total: usize| (total > 0).then(|| 1.0 - bad as f64 / total as f64);
+        let health = &mut self.health;
+        health.users = self.users.len();
+        health.crashed_users = self.crashed_users.len();
+        health.crash_free_sessions = rate((health.crashed + health.abnormal) as usize, health.sessions as usize);
+        health.crash_free_users = rate(health.crashed_users, health.users);
+        self.health
+    }
+}
+
+/// Stores a session record sent by a client for `project`.
+pub fn persist_session(project: &str, mut record: serde_json::Value) -> anyhow::Result<String> {
+    let sid = match record.get("sid").and_then(|v| v.as_str()) {
+        Some(sid) if is_valid_id(sid) => sid.to_string(),
+        Some(sid) => anyhow::bail!("Invalid session id '{}'", sid),
+        None => anyhow::bail!("Missing sid"),
+    };
+    let status = record.get("status").and_then(|v| v.as_str()).unwrap_or_default();
+    if !STATUSES.contains(&status) {
+        anyhow::bail!("Invalid session status '{}'", status);
+    }
+    if !record.get("started").is_some_and(|v| v.is_number()) {
+        anyhow::bail!("Missing session start time");
+    }
+    if let Some(map) = record.as_object_mut() {
+        map.insert("project".to_string(), serde_json::Value::String(project.to_string()));
+    }
+    storage::put(
+        &format!("{}{}.json", SESSION_PREFIX, sid),
+        &serde_json::to_vec_pretty(&record)?,
+    )?;
+    Ok(sid)
+}
+
+/// Crash-free rates over sessions started within `window_secs`, overall and
+/// per release, most sessions first. With `project`, only its sessions.
+pub fn release_health(window_secs: u64, project: Option<&str>) -> anyhow::Result<SessionStats> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0);
+    let cutoff = now - window_secs as f64;
+
+    let mut total = Accumulator::default();
+    let mut releases: BTreeMap<Option<String>, Accumulator> = BTreeMap::new();
+    for object in storage::list()? {
+        if !object.name.starts_with(SESSION_PREFIX) || !object.name.ends_with(".json") {
+            continue;
+        }
+        let Some(record) = storage::get(&object.name)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<SessionRecord>(&data).ok())
+        else {
+            continue;
+        };
+        if record.started < cutoff {
+            continue;
+        }
+        // Records read from a client's output directory carry no project.
+        if let Some(project) = project {
+            if record.project.as_deref().unwrap_or(crate::quotas::DEFAULT_PROJECT) != project {
+                continue;
+            }
+        }
+        total.add(&record);
+        releases.entry(record.release.clone()).or_default().add(&record);
+    }
+
+    let mut releases: Vec<ReleaseHealth> = releases
+        .into_iter()
+        .map(|(release, acc)| {
+            let mut health = acc.finish();
+            health.release = release;
+            health
+        })
+        .collect();
+    releases.sort_by_key(|health| std::cmp::Reverse(health.sessions));
+    Ok(SessionStats {
+        window_secs,
+        total: total.finish(),
+        releases,
+    })
+}
@@ -0,0 +1,146 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+
+// ----- Stack walking policy -----
+//
+// The walker falls back to stack scanning when CFI and frame pointers run out,
+// which recovers frames from frame-pointer-omitted release builds but can also
+// produce garbage. The policy below is applied to every walked thread using the
+// per-frame `trust` value: scanned frames are marked low-trust, and stacks are
+// cut at the first frame that the policy rejects (everything past it was
+// derived from that frame).
+//
+// Defaults and per-project overrides come from CRASH_STACKWALK_CONFIG
+// (default `stackwalk.json`):
+//
+// {
+//   "default": { "scan": true, "max_scan_frames": 20, "min_trust": "scan" },
+//   "projects": { "backend": { "scan": false } }
+// }
+//
+// Individual requests can override them with `?scan=`, `?max_scan_frames=`
+// and `?min_trust=`.
+
+pub const DEFAULT_CONFIG_FILE: &str = "stackwalk.json";
+
+// Trust levels reported by rust-minidump, weakest first.
+const TRUST_ORDER: &[&str] = &["none", "scan", "cfi_scan", "frame_pointer", "cfi", "prewalked", "context"];
+
+#[derive(Deserialize, Clone, Default)]
+pub struct StackwalkOverrides {
+    pub scan: Option<bool>,
+    pub max_scan_frames: Option<usize>,
+    pub min_trust: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct StackwalkFile {
+    #[serde(default)]
+    default: StackwalkOverrides,
+    #[serde(default)]
+    projects: HashMap<String, StackwalkOverrides>,
+}
+
+#[derive(Clone, Debug)]
+pub struct StackwalkConfig {
+    pub scan: bool,
+    pub max_scan_frames: Option<usize>,
+    pub min_trust: usize, // Index into TRUST_ORDER
+}
+
+impl Default for StackwalkConfig {
+    fn default() -> Self {
+        StackwalkConfig {
+            scan: true,
+            max_scan_frames: None,
+            min_trust: 0,
+        }
+    }
+}
+
+fn trust_rank(trust: &str) -> Option<usize> {
+    TRUST_ORDER.iter().position(|t| *t == trust)
+}
+
+fn is_scanned(trust: &str) -> bool {
+    trust == "scan" || trust == "cfi_scan"
+}
+
+impl StackwalkConfig {
+    /// Layers `overrides` on top of this configuration.
+    pub fn merge(mut self, overrides: &StackwalkOverrides) -> anyhow::Result<Self> {
+        if let Some(scan) = overrides.scan {
+            self.scan = scan;
+        }
+        if let Some(max) = overrides.max_scan_frames {
+            self.max_scan_frames = Some(max);
+        }
+        if let Some(trust) = &overrides.min_trust {
+            self.min_trust = trust_rank(trust).ok_or_else(|| {
+                anyhow::anyhow!("Unknown trust '{}', expected one of: {}", trust, TRUST_ORDER.join(", "))
+            })?;
+        }
+        Ok(self)
+    }
+
+    /// Loads the configured defaults for `project`.
+    pub fn for_project(project: Option<&str>) -> anyhow::Result<Self> {
+        let path = std::env::var("CRASH_STACKWALK_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_FILE.to_string());
+        let file: StackwalkFile = match fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StackwalkFile::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let config = StackwalkConfig::default().merge(&file.default)?;
+        match project.and_then(|p| file.projects.get(p)) {
+            Some(overrides) => config.merge(overrides),
+            None => Ok(config),
+        }
+    }
+
+    fn filter_frames(&self, frames: &mut Vec<serde_json::Value>) {
+        let mut scanned = 0;
+        let mut keep = frames.len();
+        for (i, frame) in frames.iter_mut().enumerate() {
+            let trust = frame.get("trust").and_then(|t| t.as_str()).unwrap_or("none").to_string();
+            // The context frame comes from the register state and is always kept.
+            if i > 0 {
+                let too_weak = trust_rank(&trust).unwrap_or(0) < self.min_trust;
+                let scan_rejected = is_scanned(&trust)
+                    && (!self.scan || self.max_scan_frames.is_some_and(|max| scanned >= max));
+                if too_weak || scan_rejected {
+                    keep = i;
+                    break;
+                }
+            }
+            if is_scanned(&trust) {
+                scanned += 1;
+                if let Some(map) = frame.as_object_mut() {
+                    map.insert("low_trust".to_string(), serde_json::Value::Bool(true));
+                }
+            }
+        }
+        frames.truncate(keep);
+    }
+
+    /// Applies the policy to every thread of a processed minidump, in place.
+    pub fn apply(&self, analysis: &mut serde_json::Value) {
+        let mut apply_to_thread = |thread: &mut serde_json::Value| {
+            if let Some(frames) = thread.get_mut("frames").and_then(|f| f.as_array_mut()) {
+                self.filter_frames(frames);
+                let count = frames.len();
+                if let Some(map) = thread.as_object_mut() {
+                    map.insert("frame_count".to_string(), count.into());
+                }
+            }
+        };
+        if let Some(threads) = analysis.get_mut("threads").and_then(|t| t.as_array_mut()) {
+            threads.iter_mut().for_each(&mut apply_to_thread);
+        }
+        if let Some(thread) = analysis.get_mut("crashing_thread") {
+            apply_to_thread(thread);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::index::IndexEntry;
+use crate::load_sentry_json;
+
+// ----- Hot-frame statistics -----
+//
+// Aggregates how often functions and modules appear across all crash stacks in
+// a time window. Frames are weighted by their distance from the crash site
+// (1 for the crashing frame, 1/2 for its caller, ...), so a dependency that
+// keeps showing up near the top of many distinct issues stands out.
+
+pub const DEFAULT_WINDOW_SECS: u64 = 30 * 24 * 3600;
+
+#[derive(Serialize)]
+pub struct FrameStat {
+    pub name: String,
+    pub weight: f64,
+    pub occurrences: u64,
+    pub crashes: usize,
+    pub issues: usize,
+}
+
+#[derive(Serialize)]
+pub struct FrameStats {
+    pub window_secs: u64,
+    pub crash_count: usize,
+    pub functions: Vec<FrameStat>,
+    pub modules: Vec<FrameStat>,
+}
+
+#[derive(Default)]
+struct Accumulator {
+    weight: f64,
+    occurrences: u64,
+    crashes: HashSet<String>,
+    issues: HashSet<String>,
+}
+
+/// Parses windows such as `30d`, `12h`, `90m` or `3600s`.
+pub fn parse_window(window: Option<&str>) -> anyhow::Result<u64> {
+    let Some(window) = window.map(str::trim).filter(|w| !w.is_empty()) else {
+        return Ok(DEFAULT_WINDOW_SECS);
+    };
+    let unit_start = window.char_indices().last().map(|(i, _)| i).unwrap_or(0);
+    let (number, unit) = window.split_at(unit_start);
+    let value: u64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid window '{}'", window))?;
+    match unit {
+        "d" => Ok(value * 24 * 3600),
+        "h" => Ok(value * 3600),
+        "m" => Ok(value * 60),
+        "s" => Ok(value),
+        _ => anyhow::bail!("Invalid window unit in '{}', expected d, h, m or s", window),
+    }
+}
+
+// Strips the trailing `::h0123456789abcdef` hash of legacy-mangled Rust symbols.
+fn clean_function(name: &str) -> &str {
+    match name.rsplit_once("::h") {
+        Some((head, hash)) if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) => head,
+        _ => name,
+    }
+}
+
+/// The crate a Rust symbol belongs to, e.g. `tokio` for
+/// `<tokio::runtime::Runtime as Drop>::drop`.
+pub fn module_of(function: &str) -> Option<String> {
+    let trimmed = function.trim_start_matches(['<', '&', '*']).trim_start_matches("mut ");
+    let (head, _) = trimmed.split_once("::")?;
+    if head.is_empty() || head.contains(' ') {
+        return None;
+    }
+    Some(head.to_string())
+}
+
+fn finish(map: HashMap<String, Accumulator>, limit: usize) -> Vec<FrameStat> {
+    let mut stats: Vec<FrameStat> = map
+        .into_iter()
+        .map(|(name, acc)| FrameStat {
+            name,
+            weight: acc.weight,
+            occurrences: acc.occurrences,
+            crashes: acc.crashes.len(),
+            issues: acc.issues.len(),
+        })
+        .collect();
+    stats.sort_by(|a, b| b.weight.partial_cmp(&a.weight).unwrap_or(std::cmp::Ordering::Equal));
+    stats.truncate(limit);
+    stats
+}
+
+/// Start of a window of `window_secs` ending now, in seconds since the UNIX
+/// epoch.
+pub fn window_start(window_secs: u64) -> f64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0);
+    now - window_secs as f64
+}
+
+/// Computes hot frames over `entries`, the crashes of the last `window_secs`
+/// as selected from the index.
+pub fn hot_frames<'a>(
+    entries: impl Iterator<Item = &'a IndexEntry>,
+    window_secs: u64,
+    limit: usize,
+) -> FrameStats {
+    let mut functions: HashMap<String, Accumulator> = HashMap::new();
+    let mut modules: HashMap<String, Accumulator> = HashMap::new();
+    let mut crash_count = 0;
+
+    for entry in entries {
+        let Ok(report) = load_sentry_json(&entry.id) else {
+            continue;
+        };
+        let Some(frames) = report.pointer("/stacktrace/frames").and_then(|f| f.as_array()) else {
+            continue;
+        };
+        crash_count += 1;
+
+        // Frames are stored outermost first, so the crash site is last.
+        for (distance, frame) in frames.iter().rev().enumerate() {
+            let Some(function) = frame.get("function").and_then(|f| f.as_str()) else {
+                continue;
+            };
+            let function = clean_function(function);
+            let weight = 1.0 / (distance as f64 + 1.0);
+
+            let record = |acc: &mut Accumulator| {
+                acc.weight += weight;
+                acc.occurrences += 1;
+                acc.crashes.insert(entry.id.clone());
+                acc.issues.insert(entry.issue_id.clone());
+            };
+            record(functions.entry(function.to_string()).or_default());
+            if let Some(module) = module_of(function) {
+                record(modules.entry(module).or_default());
+            }
+        }
+    }
+
+    FrameStats {
+        window_secs,
+        crash_count,
+        functions: finish(functions, limit),
+        modules: finish(modules, limit),
+    }
+}
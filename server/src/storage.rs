@@ -0,0 +1,299 @@
+use flate2::read::GzDecoder;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::backend::{FileStorage, Object, Storage};
+use crate::s3::S3Storage;
+use crate::{encryption, integrity, CRASH_REPORT_PREFIX, MINIDUMP_PREFIX};
+
+// ----- Storage directory -----
+//
+// Reports, minidumps, attachments and everything the server derives from
+// them (index, analyses, sessions, issue state, tombstones) live in a single
+// directory, given by `--data-dir` or CRASH_DATA_DIR and created on startup.
+// Without either it is the working directory, as before. With CRASH_STORAGE=s3
+// everything but the crash index goes to object storage instead, see
+// `crate::backend`. The rest of the server deals in file names and reads and
+// writes them with `get`, `put`, `list`, `stat` and `delete`; configuration
+// files (`quotas.json`, `scrub_rules.json`, ...) are not data and stay
+// relative to the working directory.
+
+pub const DATA_DIR_ENV: &str = "CRASH_DATA_DIR";
+pub const BACKEND_ENV: &str = "CRASH_STORAGE";
+
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+static BACKEND: OnceLock<Box<dyn Storage>> = OnceLock::new();
+
+/// Sets up the storage directory: `dir` from the command line, or else
+/// CRASH_DATA_DIR, or else the working directory. Creates it if missing.
+/// Then picks the backend from CRASH_STORAGE (`file`, the default, or `s3`)
+/// and returns where artifacts go.
+pub fn init(dir: Option<String>) -> anyhow::Result<String> {
+    let dir = dir
+        .or_else(|| std::env::var(DATA_DIR_ENV).ok().filter(|d| !d.is_empty()))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    fs::create_dir_all(&dir)?;
+    let dir = DATA_DIR.get_or_init(|| dir);
+    let (backend, location): (Box<dyn Storage>, String) = match std::env::var(BACKEND_ENV).as_deref() {
+        Err(_) | Ok("") | Ok("file") => (Box::new(FileStorage::new(dir)), dir.display().to_string()),
+        Ok("s3") => {
+            let s3 = S3Storage::from_env()?;
+            let location = s3.location();
+            (Box::new(s3), location)
+        }
+        Ok(other) => anyhow::bail!("Unknown {} '{}', expected file or s3", BACKEND_ENV, other),
+    };
+    let _ = BACKEND.set(backend);
+    Ok(location)
+}
+
+/// The storage directory, see `init`. With object storage it only holds the
+/// crash index.
+pub fn data_dir() -> &'static Path {
+    DATA_DIR.get().map(PathBuf::as_path).unwrap_or(Path::new("."))
+}
+
+/// Path of `file` in the storage directory, for what stays on local disk.
+pub fn path(file: &str) -> PathBuf {
+    data_dir().join(file)
+}
+
+fn backend() -> &'static dyn Storage {
+    BACKEND
+        .get_or_init(|| Box::new(FileStorage::new(data_dir())))
+        .as_ref()
+}
+
+/// The stored file `file`, as it is, see `Storage::get`.
+pub fn get(file: &str) -> std::io::Result<Vec<u8>> {
+    backend().get(file)
+}
+
+/// Stores `data` as `file`, see `Storage::put`.
+pub fn put(file: &str, data: &[u8]) -> std::io::Result<()> {
+    backend().put(file, data)
+}
+
+pub fn list() -> std::io::Result<Vec<Object>> {
+    backend().list()
+}
+
+pub fn stat(file: &str) -> std::io::Result<Option<Object>> {
+    backend().stat(file)
+}
+
+pub fn delete(file: &str) -> std::io::Result<()> {
+    backend().delete(file)
+}
+
+pub fn append(file: &str, line: &str) -> std::io::Result<()> {
+    backend().append(file, line)
+}
+
+// ----- Compressed and binary artifacts -----
+//
+// Clients can compress what they write (`crash_report_<id>.json.zst`,
+// `crash_dump_<id>.dmp.gz`) and encode reports as MessagePack or CBOR
+// (`crash_report_<id>.msgpack`). Anything that lists or reads reports and
+// dumps goes through these helpers so every form is accepted, and reports are
+// always handed out as JSON; when several exist, uncompressed JSON wins.
+// Artifacts stored by the ingest endpoints are always uncompressed JSON.
+// Files encrypted by the client get an `.age` suffix on top and are
+// decrypted on read, see `crate::encryption`. The `.sum` files next to them
+// are handled by `crate::integrity`.
+//
+// Clients write reports to `<name>.tmp` and rename them once complete, and
+// so does the server's `FileStorage`; `.tmp` files are writes in progress or
+// cut short by a crash, never listed or read.
+
+// File name suffixes, uncompressed and unencrypted first.
+const COMPRESSION_SUFFIXES: &[&str] = &["", ".gz", ".zst", ".age", ".gz.age", ".zst.age"];
+
+/// Suffix of an artifact while it is being written.
+pub const TEMP_SUFFIX: &str = ".tmp";
+
+// Report file name extensions, JSON first.
+const REPORT_EXTENSIONS: &[&str] = &[".json", ".msgpack", ".cbor"];
+
+/// Report encodings accepted by `POST /crashes`, by `Content-Type`.
+pub fn decode_upload(content_type: &str, body: &[u8]) -> anyhow::Result<serde_json::Value> {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    Ok(match mime {
+        "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => rmp_serde::from_slice(body)?,
+        "application/cbor" => serde_cbor::from_slice(body)?,
+        _ => serde_json::from_slice(body)?,
+    })
+}
+
+// Converts a report stored with `extension` to JSON.
+fn decode_report(extension: &str, data: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    let value: serde_json::Value = match extension {
+        ".msgpack" => rmp_serde::from_slice(&data).map_err(std::io::Error::other)?,
+        ".cbor" => serde_cbor::from_slice(&data).map_err(std::io::Error::other)?,
+        _ => return Ok(data),
+    };
+    serde_json::to_vec_pretty(&value).map_err(std::io::Error::other)
+}
+
+// Converts a JSON report to the encoding of `extension`.
+fn encode_report(extension: &str, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let value = || serde_json::from_slice::<serde_json::Value>(data);
+    match extension {
+        ".msgpack" => rmp_serde::to_vec_named(&value()?).map_err(std::io::Error::other),
+        ".cbor" => serde_cbor::to_vec(&value()?).map_err(std::io::Error::other),
+        _ => Ok(data.to_vec()),
+    }
+}
+
+fn decompress(file: &str, data: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    if let Some(inner) = file.strip_suffix(encryption::EXTENSION) {
+        decompress(inner, encryption::decrypt(&data)?)
+    } else if file.ends_with(".gz") {
+        let mut out = Vec::new();
+        GzDecoder::new(data.as_slice()).read_to_end(&mut out)?;
+        Ok(out)
+    } else if file.ends_with(".zst") {
+        zstd::decode_all(data.as_slice())
+    } else {
+        Ok(data)
+    }
+}
+
+/// Decompresses `data` if it starts like a gzip or zstd stream, for uploads
+/// that were compressed inside their encryption.
+pub fn decompress_sniffed(data: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    if data.starts_with(&[0x1f, 0x8b]) {
+        decompress(".gz", data)
+    } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        decompress(".zst", data)
+    } else {
+        Ok(data)
+    }
+}
+
+fn compress(file: &str, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    if let Some(inner) = file.strip_suffix(encryption::EXTENSION) {
+        encryption::encrypt(&compress(inner, data)?)
+    } else if file.ends_with(".gz") {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data)?;
+        encoder.finish()
+    } else if file.ends_with(".zst") {
+        zstd::encode_all(data, 0)
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+/// Every file name an artifact can be stored under.
+pub fn variants(prefix: &str, id: &str, extension: &str) -> Vec<String> {
+    COMPRESSION_SUFFIXES
+        .iter()
+        .map(|suffix| format!("{}{}{}{}", prefix, id, extension, suffix))
+        .collect()
+}
+
+/// The file an artifact is stored in, if any.
+pub fn find(prefix: &str, id: &str, extension: &str) -> Option<String> {
+    variants(prefix, id, extension)
+        .into_iter()
+        .find(|file| stat(file).is_ok_and(|object| object.is_some()))
+}
+
+/// Every file name the report of crash `id` can be stored under.
+pub fn report_variants(id: &str) -> Vec<String> {
+    REPORT_EXTENSIONS
+        .iter()
+        .flat_map(|extension| variants(CRASH_REPORT_PREFIX, id, extension))
+        .collect()
+}
+
+/// The report file of crash `id` and its encoding extension, if any.
+pub fn find_report(id: &str) -> Option<(String, &'static str)> {
+    REPORT_EXTENSIONS
+        .iter()
+        .find_map(|extension| Some((find(CRASH_REPORT_PREFIX, id, extension)?, *extension)))
+}
+
+/// Returns the crash id of a report file name, in any encoding, compressed
+/// or not.
+pub fn report_id(file_name: &str) -> Option<&str> {
+    if file_name.ends_with(TEMP_SUFFIX) {
+        return None;
+    }
+    let rest = file_name.strip_prefix(CRASH_REPORT_PREFIX)?;
+    COMPRESSION_SUFFIXES.iter().find_map(|suffix| {
+        let rest = rest.strip_suffix(suffix)?;
+        REPORT_EXTENSIONS
+            .iter()
+            .find_map(|extension| rest.strip_suffix(extension))
+    })
+}
+
+/// Returns the crash id of a minidump file name, compressed or not.
+pub fn minidump_id(file_name: &str) -> Option<&str> {
+    if file_name.ends_with(TEMP_SUFFIX) {
+        return None;
+    }
+    let rest = file_name.strip_prefix(MINIDUMP_PREFIX)?;
+    COMPRESSION_SUFFIXES
+        .iter()
+        .find_map(|suffix| rest.strip_suffix(suffix)?.strip_suffix(".dmp"))
+}
+
+/// Whether `file_name` is a minidump, compressed or not.
+pub fn is_minidump(file_name: &str) -> bool {
+    minidump_id(file_name).is_some()
+}
+
+/// The content of `file`, decrypted and decompressed but still in its
+/// encoding.
+pub fn read_file(file: &str) -> std::io::Result<Vec<u8>> {
+    decompress(file, get(file)?)
+}
+
+fn read(prefix: &str, id: &str, extension: &str) -> std::io::Result<Vec<u8>> {
+    let Some(file) = find(prefix, id, extension) else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("No {}{}{} file", prefix, id, extension),
+        ));
+    };
+    read_file(&file)
+}
+
+/// The report of crash `id`, as JSON.
+pub fn read_report(id: &str) -> std::io::Result<Vec<u8>> {
+    let Some((file, extension)) = find_report(id) else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("No {}{}.json file", CRASH_REPORT_PREFIX, id),
+        ));
+    };
+    decode_report(extension, read_file(&file)?)
+}
+
+pub fn read_minidump(id: &str) -> std::io::Result<Vec<u8>> {
+    read(MINIDUMP_PREFIX, id, ".dmp")
+}
+
+pub fn minidump_file(id: &str) -> Option<String> {
+    find(MINIDUMP_PREFIX, id, ".dmp")
+}
+
+/// Replaces the stored report of crash `id` with the JSON `data`, keeping
+/// its encoding and compression.
+pub fn write_report(id: &str, data: &[u8]) -> std::io::Result<()> {
+    let (file, extension) =
+        find_report(id).unwrap_or_else(|| (format!("{}{}.json", CRASH_REPORT_PREFIX, id), ".json"));
+    let data = integrity::carry_over(&file, data)?;
+    put(&file, &compress(&file, &encode_report(extension, &data)?)?)?;
+    // It described the report as the client wrote it.
+    match delete(&integrity::sum_file(&file)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
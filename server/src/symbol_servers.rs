@@ -0,0 +1,127 @@
+use minidump::{Minidump, MinidumpModuleList, Module};
+use std::io::Read;
+use std::time::Duration;
+
+use crate::{debug_files, symbols};
+
+// ----- Microsoft symbol servers -----
+//
+// Frames in Windows system modules (ntdll.dll, kernel32.dll, ...) need the
+// PDBs Microsoft publishes on its symbol server, which nobody uploads. With
+// CRASH_SYMSRV_URLS, comma separated symbol servers in symsrv layout
+// (`<pdb>/<GUID><age>/<pdb>`, e.g. `https://msdl.microsoft.com/download/symbols`),
+// the PDBs of Windows modules that no symbol directory has symbols for are
+// fetched before a minidump is processed, converted to Breakpad symbols (see
+// `crate::debug_files`) and kept in the symbol cache, where the symbolizer
+// finds them from then on. The PDBs themselves are not kept.
+//
+// Breakpad stores such as Mozilla's are configured with CRASH_SYMBOL_URLS
+// instead, see `crate::symbols`. Modules a server does not have are not asked
+// for again until the server restarts.
+
+pub const SYMSRV_URLS_ENV: &str = "CRASH_SYMSRV_URLS";
+const TIMEOUT: Duration = Duration::from_secs(120);
+const MAX_PDB_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+
+/// A module whose symbols are to be fetched.
+#[derive(Clone)]
+pub struct WantedModule {
+    pub debug_file: String,
+    pub debug_id: String, // Breakpad form
+    pub code_id: Option<String>,
+}
+
+fn servers() -> Vec<String> {
+    std::env::var(SYMSRV_URLS_ENV)
+        .unwrap_or_default()
+        .split(',')
+        .map(|url| url.trim().trim_end_matches('/'))
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The Windows modules of `dump` that have no symbols yet and may be on a
+/// symbol server.
+pub fn wanted_modules<T: std::ops::Deref<Target = [u8]>>(dump: &Minidump<T>) -> Vec<WantedModule> {
+    if servers().is_empty() {
+        return Vec::new();
+    }
+    let Ok(modules) = dump.get_stream::<MinidumpModuleList>() else {
+        return Vec::new();
+    };
+    modules
+        .iter()
+        .filter_map(|module| {
+            let debug_file = module.debug_file()?;
+            Some(WantedModule {
+                debug_file: debug_file.rsplit(['\\', '/']).next()?.to_string(),
+                debug_id: module.debug_identifier()?.breakpad().to_string(),
+                code_id: None,
+            })
+        })
+        .filter(|module| {
+            module.debug_file.to_ascii_lowercase().ends_with(".pdb")
+                && symbols::wanted(&module.debug_file, &module.debug_id)
+        })
+        .collect()
+}
+
+/// The body of `url`, or `None` if the server does not have it.
+pub fn download(agent: &ureq::Agent, url: &str, max_size: u64) -> anyhow::Result<Option<Vec<u8>>> {
+    match agent.get(url).call() {
+        Ok(response) => {
+            let mut data = Vec::new();
+            response.into_reader().take(max_size).read_to_end(&mut data)?;
+            Ok(Some(data))
+        }
+        Err(ureq::Error::Status(404, _)) => Ok(None),
+        Err(e) => anyhow::bail!("{}: {}", url, e),
+    }
+}
+
+/// Downloads the debug file of each module with `download`, converts it and
+/// keeps the symbols in the symbol cache, then makes the symbolizer see
+/// them. Modules no server has are remembered.
+pub fn fetch_with(
+    modules: &[WantedModule],
+    download: impl Fn(&ureq::Agent, &WantedModule) -> anyhow::Result<Option<Vec<u8>>>,
+) {
+    if modules.is_empty() {
+        return;
+    }
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    let mut fetched = false;
+    for module in modules {
+        let (debug_file, debug_id) = (&module.debug_file, &module.debug_id);
+        let converted = download(&agent, module).and_then(|debug_data| {
+            debug_data.map(|debug_data| debug_files::to_breakpad(debug_file, &debug_data)).transpose()
+        });
+        match converted {
+            Ok(Some(data)) => match symbols::cache(debug_file, debug_id, &data) {
+                Ok(()) => fetched = true,
+                Err(e) => eprintln!("Failed to store symbols of {}: {}", debug_file, e),
+            },
+            Ok(None) => symbols::mark_unavailable(debug_file, debug_id),
+            // Asked for again next time.
+            Err(e) => eprintln!("Failed to fetch symbols of {} {}: {}", debug_file, debug_id, e),
+        }
+    }
+    if fetched {
+        symbols::reset();
+    }
+}
+
+/// Fetches the PDBs of `modules`, see `wanted_modules`, from the first
+/// server that has each. Blocks on the downloads.
+pub fn fetch(modules: &[WantedModule]) {
+    fetch_with(modules, |agent, module| {
+        for server in servers() {
+            let url = format!("{}/{}/{}/{}", server, module.debug_file, module.debug_id, module.debug_file);
+            if let Some(pdb) = download(agent, &url, MAX_PDB_SIZE)? {
+                return Ok(Some(pdb));
+            }
+        }
+        Ok(None)
+    });
+}
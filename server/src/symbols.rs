@@ -0,0 +1,228 @@
+use breakpad_symbols::{HttpSymbolSupplier, SimpleSymbolSupplier, Symbolizer};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{debug_files, storage};
+
+// ----- Symbols -----
+//
+// A minidump only holds register state, raw stack memory and the list of
+// loaded modules. To walk the stack of every thread through CFI and to name
+// each frame's function, source file and line, the processor needs the
+// Breakpad symbol file (`.sym`) of each module, looked up by debug file and
+// debug id in the usual layout `<debug_file>/<debug_id>/<debug_file>.sym`
+// (`foo.pdb` has `foo.sym`). They are looked up in
+//
+//   `symbols` in the storage directory, where `POST /symbols` puts them;
+//                      native debug files uploaded there are converted
+//                      first, see `crate::debug_files`
+//   CRASH_SYMBOL_PATH  more local directories, separated like PATH
+//   CRASH_SYMBOL_URLS  comma separated symbol servers with the same layout;
+//                      downloads are cached in `symbol_cache` in the storage
+//                      directory
+//
+// and Microsoft symbol servers, which only have PDBs, see
+// `crate::symbol_servers`.
+//
+// Modules without symbols are still walked, with frame pointers and stack
+// scanning (see `crate::stackwalk`), and their frames only carry the module
+// and offset. The symbolizer keeps parsed symbol files in memory, and
+// remembers which it could not find, so it is shared by every analysis and
+// replaced when symbols are uploaded.
+
+pub const SYMBOL_PATH_ENV: &str = "CRASH_SYMBOL_PATH";
+pub const SYMBOL_URLS_ENV: &str = "CRASH_SYMBOL_URLS";
+const UPLOAD_DIR: &str = "symbols";
+const CACHE_DIR: &str = "symbol_cache";
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+static SYMBOLIZER: Mutex<Option<Arc<Symbolizer>>> = Mutex::new(None);
+// Modules no symbol server had, as `<debug file>/<debug id>`.
+static UNAVAILABLE: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+/// The module a symbol file describes, from its `MODULE` line.
+#[derive(Serialize)]
+pub struct SymbolModule {
+    pub os: String,
+    pub arch: String,
+    pub debug_id: String,
+    pub debug_file: String,
+}
+
+#[derive(Serialize)]
+pub struct UploadReport {
+    pub modules: Vec<SymbolModule>,
+    pub reprocessed: usize, // Crashes queued for another analysis
+}
+
+/// Where downloaded symbols are kept.
+pub fn cache_dir() -> PathBuf {
+    storage::path(CACHE_DIR)
+}
+
+fn symbol_paths() -> Vec<PathBuf> {
+    let mut paths = vec![storage::path(UPLOAD_DIR), cache_dir()];
+    if let Some(extra) = std::env::var_os(SYMBOL_PATH_ENV) {
+        paths.extend(std::env::split_paths(&extra).filter(|path| !path.as_os_str().is_empty()));
+    }
+    paths
+}
+
+fn symbol_urls() -> Vec<String> {
+    std::env::var(SYMBOL_URLS_ENV)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn new_symbolizer() -> Symbolizer {
+    let paths = symbol_paths();
+    let urls = symbol_urls();
+    if urls.is_empty() {
+        return Symbolizer::new(SimpleSymbolSupplier::new(paths));
+    }
+    Symbolizer::new(HttpSymbolSupplier::new(
+        urls,
+        cache_dir(),
+        std::env::temp_dir(),
+        paths,
+        DOWNLOAD_TIMEOUT,
+    ))
+}
+
+/// The symbolizer minidumps are processed with.
+pub fn symbolizer() -> Arc<Symbolizer> {
+    let mut symbolizer = SYMBOLIZER.lock().unwrap_or_else(|e| e.into_inner());
+    symbolizer.get_or_insert_with(|| Arc::new(new_symbolizer())).clone()
+}
+
+/// Starts over with a new symbolizer, which looks again for the symbols the
+/// old one could not find. For when symbols were added.
+pub fn reset() {
+    *SYMBOLIZER.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Where the symbols of `debug_file` with `debug_id` are in a symbol
+/// directory.
+pub fn relative_path(debug_file: &str, debug_id: &str) -> PathBuf {
+    let name = format!("{}.sym", debug_file.strip_suffix(".pdb").unwrap_or(debug_file));
+    PathBuf::from(debug_file).join(debug_id).join(name)
+}
+
+/// The symbols of the module in a local symbol directory, if any.
+pub fn find_symbols(debug_file: &str, debug_id: &str) -> Option<PathBuf> {
+    let relative = relative_path(debug_file, debug_id);
+    symbol_paths().into_iter().map(|dir| dir.join(&relative)).find(|path| path.is_file())
+}
+
+/// Whether the module's symbols are worth asking a server for: not found
+/// locally, and not missing from every server already.
+pub fn wanted(debug_file: &str, debug_id: &str) -> bool {
+    let key = format!("{}/{}", debug_file, debug_id);
+    find_symbols(debug_file, debug_id).is_none()
+        && !UNAVAILABLE
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .is_some_and(|keys| keys.contains(&key))
+}
+
+/// Remembers that no server has the module's symbols, until restart.
+pub fn mark_unavailable(debug_file: &str, debug_id: &str) {
+    UNAVAILABLE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_with(HashSet::new)
+        .insert(format!("{}/{}", debug_file, debug_id));
+}
+
+/// Keeps symbols downloaded from a server in the symbol cache. The next
+/// `reset` makes the symbolizer see them.
+pub fn cache(debug_file: &str, debug_id: &str, data: &[u8]) -> std::io::Result<()> {
+    write_symbols(&cache_dir().join(relative_path(debug_file, debug_id)), data)
+}
+
+// Writes `data` to `path`, through a temporary file so the symbolizer never
+// reads it half written.
+fn write_symbols(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(storage::TEMP_SUFFIX);
+    fs::write(&temp, data).and_then(|()| fs::rename(&temp, path))
+}
+
+// Debug files name directories, so they must be plain file names.
+fn is_valid_debug_file(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', '\0'])
+}
+
+/// Reads the `MODULE <os> <arch> <debug id> <debug file>` line a Breakpad
+/// symbol file starts with.
+pub fn parse_module(data: &[u8]) -> anyhow::Result<SymbolModule> {
+    let first_line = data.split(|&b| b == b'\n').next().unwrap_or_default();
+    let line = std::str::from_utf8(first_line)
+        .map_err(|_| anyhow::anyhow!("Not a Breakpad symbol file"))?
+        .trim_end_matches('\r');
+    let mut fields = line.splitn(5, ' ');
+    let (Some("MODULE"), Some(os), Some(arch), Some(debug_id), Some(debug_file)) =
+        (fields.next(), fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        anyhow::bail!("Not a Breakpad symbol file, expected a MODULE line");
+    };
+    if debug_id.is_empty() || !debug_id.chars().all(|c| c.is_ascii_hexdigit()) {
+        anyhow::bail!("Invalid debug id '{}'", debug_id);
+    }
+    if !is_valid_debug_file(debug_file) {
+        anyhow::bail!("Invalid debug file '{}'", debug_file);
+    }
+    Ok(SymbolModule {
+        os: os.to_string(),
+        arch: arch.to_string(),
+        debug_id: debug_id.to_ascii_uppercase(),
+        debug_file: debug_file.to_string(),
+    })
+}
+
+/// Stores an uploaded symbol file where the symbolizer finds it, replacing
+/// an earlier upload for the same module, and returns the module.
+pub fn store(data: &[u8]) -> anyhow::Result<SymbolModule> {
+    let module = parse_module(data)?;
+    let path = storage::path(UPLOAD_DIR).join(relative_path(&module.debug_file, &module.debug_id));
+    write_symbols(&path, data)?;
+    reset();
+    Ok(module)
+}
+
+/// Stores an uploaded debug file named `name`: a Breakpad symbol file as it
+/// is, anything else converted, see `crate::debug_files`. Returns the
+/// modules it has symbols for.
+pub fn store_debug_file(name: Option<&str>, data: &[u8]) -> anyhow::Result<Vec<SymbolModule>> {
+    if data.starts_with(b"MODULE ") {
+        return Ok(vec![store(data)?]);
+    }
+    // Named like the module the symbolizer looks the symbols up for.
+    let name = name.and_then(|name| name.rsplit(['/', '\\']).next());
+    let Some(name) = name.filter(|name| is_valid_debug_file(name)) else {
+        anyhow::bail!("Native debug files need a file name, e.g. ?name=libapp.so");
+    };
+    debug_files::to_breakpad_all(name, data)?
+        .iter()
+        .map(|symbols| store(symbols))
+        .collect()
+}
+
+/// Where symbols are looked up, for the startup log.
+pub fn describe() -> String {
+    let mut sources: Vec<String> = symbol_paths().iter().map(|path| path.display().to_string()).collect();
+    sources.extend(symbol_urls());
+    sources.join(", ")
+}
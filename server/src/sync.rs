@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use crate::backend::to_millis;
+use crate::storage;
+
+// ----- Incremental sync -----
+//
+// Changes are derived from the artifacts themselves: a report whose file was
+// created after the cursor is "created", one whose report or minidump was
+// modified after it is "updated". Deletions cannot be observed on disk, so
+// anything removing crashes appends a line to the tombstone journal.
+//
+// Changes are ordered by time, then crash id, and the cursor is the time and
+// id of the last one returned (`<millis>:<id>`). Times are file mtimes, which
+// many filesystems and S3 only keep to the second, so several changes can
+// share one; the id tells which of them a poller has already seen.
+
+pub const TOMBSTONE_FILE: &str = "crash_tombstones.jsonl";
+
+#[derive(Serialize, Deserialize)]
+pub struct Tombstone {
+    pub id: String,
+    pub deleted_at: u64, // Milliseconds since UNIX epoch
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+#[derive(Serialize)]
+pub struct Change {
+    pub id: String,
+    pub change: ChangeKind,
+    pub changed_at: u64,
+}
+
+#[derive(Serialize)]
+pub struct ChangeSet {
+    pub changes: Vec<Change>,
+    // Pass back as `since` to receive only newer changes.
+    pub cursor: String,
+}
+
+/// Position of a poller in the change feed: the time and crash id of the
+/// last change it received. Changes sort after it if they are later, or as
+/// late with a greater id.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub at: u64, // Milliseconds since UNIX epoch
+    pub id: String,
+}
+
+impl Cursor {
+    /// Whether the change at `at` to `id` comes after this position.
+    pub fn is_before(&self, at: u64, id: &str) -> bool {
+        (self.at, self.id.as_str()) < (at, id)
+    }
+}
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.at, self.id)
+    }
+}
+
+/// Parses a cursor previously returned in a `ChangeSet`. An empty cursor
+/// means "from the beginning"; a bare time, as older servers returned,
+/// starts with the changes at that time.
+pub fn parse_cursor(cursor: Option<&str>) -> anyhow::Result<Cursor> {
+    let invalid = || anyhow::anyhow!("Invalid cursor '{}'", cursor.unwrap_or_default());
+    match cursor {
+        None | Some("") => Ok(Cursor::default()),
+        Some(c) => {
+            let (at, id) = c.split_once(':').unwrap_or((c, ""));
+            Ok(Cursor {
+                at: at.parse().map_err(|_| invalid())?,
+                id: id.to_string(),
+            })
+        }
+    }
+}
+
+/// Appends a tombstone so pollers learn about a deleted crash.
+pub fn record_deletion(id: &str) -> anyhow::Result<()> {
+    let tombstone = Tombstone {
+        id: id.to_string(),
+        deleted_at: to_millis(SystemTime::now()),
+    };
+    storage::append(TOMBSTONE_FILE, &serde_json::to_string(&tombstone)?)?;
+    Ok(())
+}
+
+fn read_tombstones() -> Vec<Tombstone> {
+    storage::get(TOMBSTONE_FILE)
+        .map(|data| {
+            String::from_utf8_lossy(&data)
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Collects every change after `since`, see `Cursor`.
+pub fn collect_changes(since: &Cursor) -> anyhow::Result<ChangeSet> {
+    let mut changes = Vec::new();
+
+    let objects = storage::list()?;
+    // From the same listing rather than a lookup per report.
+    let dumps_modified: HashMap<&str, u64> = objects
+        .iter()
+        .filter_map(|object| Some((storage::minidump_id(&object.name)?, object.modified)))
+        .collect();
+    for object in &objects {
+        let Some(id) = storage::report_id(&object.name) else {
+            continue;
+        };
+
+        let modified = object.modified;
+        // Not every filesystem records a birth time; fall back to mtime.
+        let created = object.created.unwrap_or(modified);
+        let dump_modified = dumps_modified.get(id).copied().unwrap_or(0);
+        let last_change = modified.max(dump_modified);
+
+        if since.is_before(created, id) {
+            changes.push(Change {
+                id: id.to_string(),
+                change: ChangeKind::Created,
+                changed_at: last_change.max(created),
+            });
+        } else if since.is_before(last_change, id) {
+            changes.push(Change {
+                id: id.to_string(),
+                change: ChangeKind::Updated,
+                changed_at: last_change,
+            });
+        }
+    }
+
+    for tombstone in read_tombstones() {
+        if since.is_before(tombstone.deleted_at, &tombstone.id) {
+            changes.push(Change {
+                id: tombstone.id,
+                change: ChangeKind::Deleted,
+                changed_at: tombstone.deleted_at,
+            });
+        }
+    }
+
+    changes.sort_by(|a, b| (a.changed_at, &a.id).cmp(&(b.changed_at, &b.id)));
+    let cursor = changes.last().map_or_else(
+        || since.clone(),
+        |c| Cursor {
+            at: c.changed_at,
+            id: c.id.clone(),
+        },
+    );
+
+    Ok(ChangeSet {
+        changes,
+        cursor: cursor.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = Cursor {
+            at: 1_700_000_000_123,
+            id: "a:b".to_string(),
+        };
+        assert_eq!(parse_cursor(Some(&cursor.to_string())).unwrap(), cursor);
+    }
+
+    #[test]
+    fn empty_and_bare_cursors() {
+        assert_eq!(parse_cursor(None).unwrap(), Cursor::default());
+        assert_eq!(parse_cursor(Some("")).unwrap(), Cursor::default());
+        let bare = parse_cursor(Some("42")).unwrap();
+        assert_eq!(bare, Cursor { at: 42, id: String::new() });
+        // Older cursors start with the changes at their time.
+        assert!(bare.is_before(42, "a"));
+        assert!(!bare.is_before(41, "z"));
+    }
+
+    #[test]
+    fn invalid_cursors() {
+        for cursor in ["abc", ":id", "-1:id", "18446744073709551616:id"] {
+            assert!(parse_cursor(Some(cursor)).is_err(), "{}", cursor);
+        }
+    }
+
+    #[test]
+    fn changes_at_the_same_time_are_ordered_by_id() {
+        let cursor = Cursor {
+            at: 100,
+            id: "b".to_string(),
+        };
+        assert!(!cursor.is_before(100, "a"));
+        assert!(!cursor.is_before(100, "b"));
+        assert!(cursor.is_before(100, "c"));
+        assert!(cursor.is_before(101, "a"));
+        assert!(!cursor.is_before(99, "z"));
+    }
+}
@@ -0,0 +1,299 @@
+use minidump::{Minidump, MinidumpModuleList, Module};
+use serde::Serialize;
+
+// ----- Minidump validation -----
+//
+// Structural checks run before a dump is processed so that broken client
+// capture setups show up as explicit warnings instead of empty summaries.
+
+const MINIDUMP_SIGNATURE: u32 = 0x504d_444d; // "MDMP"
+const HEADER_SIZE: usize = 32;
+const DIRECTORY_ENTRY_SIZE: usize = 12;
+
+const THREAD_LIST_STREAM: u32 = 3;
+const MODULE_LIST_STREAM: u32 = 4;
+const MEMORY_LIST_STREAM: u32 = 5;
+const EXCEPTION_STREAM: u32 = 6;
+const SYSTEM_INFO_STREAM: u32 = 7;
+const MEMORY64_LIST_STREAM: u32 = 9;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Serialize)]
+pub struct ValidationWarning {
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct ValidationReport {
+    pub valid: bool,
+    pub size: usize,
+    pub stream_count: u32,
+    pub warnings: Vec<ValidationWarning>,
+}
+
+impl ValidationReport {
+    fn push(&mut self, code: &'static str, severity: Severity, message: String) {
+        if severity == Severity::Error {
+            self.valid = false;
+        }
+        self.warnings.push(ValidationWarning {
+            code,
+            severity,
+            message,
+        });
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Checks a raw minidump for truncation, missing required streams and
+/// overlapping module ranges.
+pub fn validate_minidump(data: &[u8]) -> ValidationReport {
+    let mut report = ValidationReport {
+        valid: true,
+        size: data.len(),
+        stream_count: 0,
+        warnings: Vec::new(),
+    };
+
+    if data.len() < HEADER_SIZE {
+        report.push(
+            "truncated_header",
+            Severity::Error,
+            format!("file is {} bytes, smaller than the minidump header", data.len()),
+        );
+        return report;
+    }
+    if read_u32(data, 0) != Some(MINIDUMP_SIGNATURE) {
+        report.push(
+            "bad_signature",
+            Severity::Error,
+            "missing MDMP signature".to_string(),
+        );
+        return report;
+    }
+
+    let stream_count = read_u32(data, 8).unwrap_or(0);
+    let directory_rva = read_u32(data, 12).unwrap_or(0) as usize;
+    report.stream_count = stream_count;
+
+    let directory_end = directory_rva + stream_count as usize * DIRECTORY_ENTRY_SIZE;
+    if directory_end > data.len() {
+        report.push(
+            "truncated_directory",
+            Severity::Error,
+            format!(
+                "stream directory ends at {} but file is only {} bytes",
+                directory_end,
+                data.len()
+            ),
+        );
+        return report;
+    }
+
+    let mut stream_types = Vec::with_capacity(stream_count as usize);
+    for i in 0..stream_count as usize {
+        let entry = directory_rva + i * DIRECTORY_ENTRY_SIZE;
+        let stream_type = read_u32(data, entry).unwrap_or(0);
+        let data_size = read_u32(data, entry + 4).unwrap_or(0) as usize;
+        let rva = read_u32(data, entry + 8).unwrap_or(0) as usize;
+        stream_types.push(stream_type);
+
+        if rva + data_size > data.len() {
+            report.push(
+                "truncated_stream",
+                Severity::Error,
+                format!(
+                    "stream type {} at {:#x} (+{} bytes) extends past end of file",
+                    stream_type, rva, data_size
+                ),
+            );
+        }
+    }
+
+    for (stream_type, name) in [
+        (THREAD_LIST_STREAM, "ThreadListStream"),
+        (MODULE_LIST_STREAM, "ModuleListStream"),
+        (SYSTEM_INFO_STREAM, "SystemInfoStream"),
+    ] {
+        if !stream_types.contains(&stream_type) {
+            report.push(
+                "missing_stream",
+                Severity::Error,
+                format!("required stream {} is missing", name),
+            );
+        }
+    }
+    if !stream_types.contains(&MEMORY_LIST_STREAM) && !stream_types.contains(&MEMORY64_LIST_STREAM) {
+        report.push(
+            "missing_stream",
+            Severity::Warning,
+            "no memory list stream, stack walking will not be possible".to_string(),
+        );
+    }
+    if !stream_types.contains(&EXCEPTION_STREAM) {
+        report.push(
+            "missing_stream",
+            Severity::Warning,
+            "no exception stream, the crashing thread cannot be identified".to_string(),
+        );
+    }
+
+    // Module overlap checks need the parsed module list; only attempt it if
+    // the structure above looked sane.
+    if report.valid {
+        check_module_ranges(data, &mut report);
+    }
+
+    report
+}
+
+fn check_module_ranges(data: &[u8], report: &mut ValidationReport) {
+    let dump = match Minidump::read(data) {
+        Ok(dump) => dump,
+        Err(e) => {
+            report.push("unreadable", Severity::Error, e.to_string());
+            return;
+        }
+    };
+    let modules = match dump.get_stream::<MinidumpModuleList>() {
+        Ok(modules) => modules,
+        Err(e) => {
+            report.push("unreadable_modules", Severity::Error, e.to_string());
+            return;
+        }
+    };
+
+    let mut ranges: Vec<(u64, u64, String)> = modules
+        .iter()
+        .map(|m| {
+            let base = m.base_address();
+            (base, base.saturating_add(m.size()), m.code_file().into_owned())
+        })
+        .collect();
+    ranges.sort_by_key(|r| r.0);
+
+    for pair in ranges.windows(2) {
+        let (_, prev_end, prev_name) = &pair[0];
+        let (next_start, _, next_name) = &pair[1];
+        if next_start < prev_end {
+            report.push(
+                "overlapping_modules",
+                Severity::Warning,
+                format!(
+                    "{} overlaps {} at {:#x}",
+                    next_name, prev_name, next_start
+                ),
+            );
+        }
+    }
+    for (start, end, name) in &ranges {
+        if start == end {
+            report.push(
+                "empty_module",
+                Severity::Warning,
+                format!("{} has a zero-sized mapping at {:#x}", name, start),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A minidump header with its stream directory right after it, listing
+    // `streams` as (type, size, rva), padded with zeros to `len` bytes.
+    fn dump(stream_count: u32, streams: &[(u32, u32, u32)], len: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&MINIDUMP_SIGNATURE.to_le_bytes());
+        data.extend_from_slice(&0xa793u32.to_le_bytes());
+        data.extend_from_slice(&stream_count.to_le_bytes());
+        data.extend_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+        data.resize(HEADER_SIZE, 0);
+        for (stream_type, size, rva) in streams {
+            for field in [stream_type, size, rva] {
+                data.extend_from_slice(&field.to_le_bytes());
+            }
+        }
+        data.resize(len.max(data.len()), 0);
+        data
+    }
+
+    fn codes(report: &ValidationReport) -> Vec<&'static str> {
+        report.warnings.iter().map(|w| w.code).collect()
+    }
+
+    #[test]
+    fn empty_and_short_files_are_truncated() {
+        for len in [0, 4, HEADER_SIZE - 1] {
+            let report = validate_minidump(&dump(0, &[], 0)[..len]);
+            assert!(!report.valid);
+            assert_eq!(codes(&report), ["truncated_header"]);
+        }
+    }
+
+    #[test]
+    fn wrong_signature() {
+        let mut data = dump(0, &[], 0);
+        data[..4].copy_from_slice(b"MDMQ");
+        let report = validate_minidump(&data);
+        assert!(!report.valid);
+        assert_eq!(codes(&report), ["bad_signature"]);
+    }
+
+    #[test]
+    fn directory_past_the_end() {
+        // Claims more streams than the file has room for.
+        let report = validate_minidump(&dump(u32::MAX, &[], 64));
+        assert!(!report.valid);
+        assert_eq!(report.stream_count, u32::MAX);
+        assert_eq!(codes(&report), ["truncated_directory"]);
+
+        let mut data = dump(1, &[(THREAD_LIST_STREAM, 0, 0)], 0);
+        data[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(codes(&validate_minidump(&data)), ["truncated_directory"]);
+    }
+
+    #[test]
+    fn stream_past_the_end() {
+        let streams = [
+            (THREAD_LIST_STREAM, 16, 100),
+            (MODULE_LIST_STREAM, u32::MAX, u32::MAX),
+            (SYSTEM_INFO_STREAM, 0, 64),
+        ];
+        let report = validate_minidump(&dump(3, &streams, 112));
+        assert!(!report.valid);
+        assert_eq!(
+            codes(&report),
+            ["truncated_stream", "truncated_stream", "missing_stream", "missing_stream"]
+        );
+    }
+
+    #[test]
+    fn missing_streams() {
+        let report = validate_minidump(&dump(1, &[(EXCEPTION_STREAM, 0, 44)], 44));
+        assert!(!report.valid);
+        let severities: Vec<_> = report.warnings.iter().map(|w| (w.code, w.severity)).collect();
+        assert_eq!(
+            severities,
+            [
+                ("missing_stream", Severity::Error),
+                ("missing_stream", Severity::Error),
+                ("missing_stream", Severity::Error),
+                ("missing_stream", Severity::Warning),
+            ]
+        );
+    }
+}
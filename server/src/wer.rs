@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+
+// ----- Windows Error Reporting ingestion -----
+//
+// `.wer` files are UTF-16LE (sometimes UTF-8) `Key=Value` lines. The crash
+// signature is spread over indexed `Sig[n].Name` / `Sig[n].Value` pairs
+// ("Application Name", "Exception Code", ...), which are mapped onto the
+// event schema here.
+
+// Seconds between the FILETIME epoch (1601) and the UNIX epoch.
+const FILETIME_UNIX_OFFSET: u64 = 11_644_473_600;
+
+pub struct WerReport {
+    pub fields: BTreeMap<String, String>,
+    // Sig / DynamicSig pairs keyed by their human readable name
+    pub signature: BTreeMap<String, String>,
+}
+
+fn decode(data: &[u8]) -> String {
+    let utf16 = data.starts_with(&[0xff, 0xfe])
+        || (data.len() >= 2 && data[1] == 0 && data[0] != 0);
+    if !utf16 {
+        return String::from_utf8_lossy(data.strip_prefix(&[0xef, 0xbb, 0xbf]).unwrap_or(data)).into_owned();
+    }
+    let body = data.strip_prefix(&[0xff, 0xfe]).unwrap_or(data);
+    let units: Vec<u16> = body
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Parses a `.wer` file.
+pub fn parse_wer(data: &[u8]) -> anyhow::Result<WerReport> {
+    let text = decode(data);
+    let mut fields = BTreeMap::new();
+    for line in text.lines() {
+        if let Some((key, value)) = line.split_once('=') {
+            fields.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    if !fields.contains_key("EventType") {
+        anyhow::bail!("Not a WER report: missing EventType");
+    }
+
+    let mut signature = BTreeMap::new();
+    for (key, name) in &fields {
+        let Some(prefix) = key.strip_suffix(".Name") else {
+            continue;
+        };
+        if !(prefix.starts_with("Sig[") || prefix.starts_with("DynamicSig[")) {
+            continue;
+        }
+        if let Some(value) = fields.get(&format!("{}.Value", prefix)) {
+            signature.insert(name.clone(), value.clone());
+        }
+    }
+
+    Ok(WerReport { fields, signature })
+}
+
+impl WerReport {
+    fn field(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(|s| s.as_str())
+    }
+
+    fn sig(&self, name: &str) -> Option<&str> {
+        self.signature.get(name).map(|s| s.as_str())
+    }
+
+    /// `ReportIdentifier` is a GUID, reused as event ID so re-uploads dedupe.
+    pub fn report_id(&self) -> Option<String> {
+        self.field("ReportIdentifier")
+            .and_then(|id| uuid::Uuid::parse_str(id.trim_matches(|c| c == '{' || c == '}')).ok())
+            .map(|id| id.to_string())
+    }
+
+    fn timestamp(&self) -> Option<String> {
+        let filetime: u64 = self.field("EventTime")?.parse().ok()?;
+        let secs = (filetime / 10_000_000).checked_sub(FILETIME_UNIX_OFFSET)?;
+        Some(secs.to_string())
+    }
+
+    /// Maps the report onto the Sentry-like event schema.
+    pub fn to_event(&self) -> serde_json::Value {
+        let app = self
+            .sig("Application Name")
+            .or_else(|| self.field("AppName"))
+            .unwrap_or("unknown application");
+        let exception_code = self.sig("Exception Code");
+        let fault_module = self.sig("Fault Module Name");
+
+        let message = match (exception_code, fault_module) {
+            (Some(code), Some(module)) => format!("{} crashed with exception {} in {}", app, code, module),
+            (Some(code), None) => format!("{} crashed with exception {}", app, code),
+            _ => format!(
+                "{}: {}",
+                app,
+                self.field("FriendlyEventName").unwrap_or("application crash")
+            ),
+        };
+
+        let loaded_modules: Vec<&str> = self
+            .fields
+            .iter()
+            .filter(|(k, _)| k.starts_with("LoadedModule["))
+            .map(|(_, v)| v.as_str())
+            .collect();
+
+        serde_json::json!({
+            "schema_version": crate::schema::SCHEMA_VERSION,
+            "timestamp": self.timestamp(),
+            "message": message,
+            "level": "fatal",
+            "platform": "native",
+            "exception": {
+                "values": [{
+                    "type": exception_code.unwrap_or("unknown"),
+                    "value": message,
+                    "module": fault_module,
+                    "fault_offset": self.sig("Exception Offset"),
+                }]
+            },
+            "tags": {
+                "wer.event_type": self.field("EventType"),
+                "wer.report_type": self.field("ReportType"),
+            },
+            "contexts": {
+                "os": {
+                    "name": "Windows",
+                    "version": self.sig("OS Version"),
+                },
+                "app": {
+                    "app_name": app,
+                    "app_version": self.sig("Application Version"),
+                    "app_path": self.field("AppPath"),
+                },
+            },
+            "extra": {
+                "wer_signature": self.signature,
+                "wer_loaded_modules": loaded_modules,
+                "wer_fields": self.fields,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &str = "Version=1\r\n\
+        EventType=APPCRASH\r\n\
+        EventTime=133500000000000000\r\n\
+        ReportIdentifier={6f1c2a3b-4d5e-4f60-8a7b-9c0d1e2f3a4b}\r\n\
+        Sig[0].Name=Application Name\r\n\
+        Sig[0].Value=app.exe\r\n\
+        Sig[1].Name=Exception Code\r\n\
+        Sig[1].Value=c0000005\r\n\
+        DynamicSig[1].Name=OS Version\r\n\
+        DynamicSig[1].Value=10.0.19045.2.0.0.256.48\r\n\
+        Sig[2].Name=Orphaned Name\r\n\
+        LoadedModule[0]=C:\\app\\app.exe\r\n";
+
+    fn utf16(text: &str, bom: bool) -> Vec<u8> {
+        let mut data = if bom { vec![0xff, 0xfe] } else { Vec::new() };
+        data.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        data
+    }
+
+    #[test]
+    fn encodings() {
+        let utf8_bom = [&[0xef, 0xbb, 0xbf][..], REPORT.as_bytes()].concat();
+        for data in [REPORT.as_bytes().to_vec(), utf8_bom, utf16(REPORT, true), utf16(REPORT, false)] {
+            let report = parse_wer(&data).unwrap();
+            assert_eq!(report.field("Version"), Some("1"));
+            assert_eq!(report.sig("Application Name"), Some("app.exe"));
+        }
+    }
+
+    #[test]
+    fn signature_pairs() {
+        let report = parse_wer(REPORT.as_bytes()).unwrap();
+        assert_eq!(report.sig("Exception Code"), Some("c0000005"));
+        assert_eq!(report.sig("OS Version"), Some("10.0.19045.2.0.0.256.48"));
+        // A name without a value is left out.
+        assert_eq!(report.signature.len(), 3);
+    }
+
+    #[test]
+    fn not_a_report() {
+        assert!(parse_wer(b"").is_err());
+        assert!(parse_wer(b"Version=1\nSig[0].Name=Application Name\n").is_err());
+        assert!(parse_wer(&[0xff, 0xfe, 0x00]).is_err());
+        // A truncated UTF-16 file keeps the lines it has.
+        let data = utf16(REPORT, true);
+        assert!(parse_wer(&data[..data.len() - 1]).is_ok());
+    }
+
+    #[test]
+    fn report_id_and_timestamp() {
+        let report = parse_wer(REPORT.as_bytes()).unwrap();
+        assert_eq!(report.report_id().as_deref(), Some("6f1c2a3b-4d5e-4f60-8a7b-9c0d1e2f3a4b"));
+        assert_eq!(report.timestamp().as_deref(), Some("1705526400"));
+
+        let report = parse_wer(b"EventType=APPCRASH\nReportIdentifier=nope\nEventTime=1\n").unwrap();
+        assert_eq!(report.report_id(), None);
+        // Before the UNIX epoch.
+        assert_eq!(report.timestamp(), None);
+        let report = parse_wer(format!("EventType=APPCRASH\nEventTime={}0\n", u64::MAX).as_bytes()).unwrap();
+        assert_eq!(report.timestamp(), None);
+    }
+}
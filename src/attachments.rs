@@ -0,0 +1,159 @@
+// Files and buffers attached to crash reports.
+//
+// The application registers attachments up front with `crash::attach_file`
+// (read when the crash happens, so a log file is as fresh as possible) or
+// `crash::attach_bytes`, or as a function producing the contents at crash time
+// (`crash::attach_with`). When the panic hook captures an event it copies each
+// one next to the report as `crash_attachment_<id>_<name>`, uploads it to the
+// crash server when one is configured, and lists it with its SHA-256 in the
+// event's `attachments`. Native crash reports written from signal handlers carry no
+// attachments.
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::compression::Compression;
+use crate::config::Config;
+use crate::event::AttachmentRef;
+use crate::integrity::Digest;
+
+pub const ATTACHMENT_PREFIX: &str = "crash_attachment_";
+
+#[derive(Clone, Debug)]
+enum Source {
+    File(PathBuf),
+    Bytes(Vec<u8>),
+    Provider(fn() -> Vec<u8>),
+}
+
+#[derive(Clone, Debug)]
+struct Attachment {
+    name: String,
+    source: Source,
+}
+
+static ATTACHMENTS: Mutex<Vec<Attachment>> = Mutex::new(Vec::new());
+
+fn register(attachment: Attachment) {
+    let mut attachments = ATTACHMENTS.lock().unwrap_or_else(|e| e.into_inner());
+    // Registering the same name again replaces the earlier attachment.
+    attachments.retain(|a| a.name != attachment.name);
+    attachments.push(attachment);
+}
+
+/// Attaches the file at `path` to future crash reports, under its file name.
+pub fn attach_file(path: impl Into<PathBuf>) {
+    let path = path.into();
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "attachment".to_string());
+    register(Attachment {
+        name,
+        source: Source::File(path),
+    });
+}
+
+/// Attaches an in-memory buffer to future crash reports under `name`.
+pub fn attach_bytes(name: impl Into<String>, data: impl Into<Vec<u8>>) {
+    register(Attachment {
+        name: name.into(),
+        source: Source::Bytes(data.into()),
+    });
+}
+
+/// Attaches the output of `provider`, called when a crash is captured, to
+/// future crash reports under `name`. The provider runs inside the panic hook
+/// and must not block.
+pub fn attach_with(name: impl Into<String>, provider: fn() -> Vec<u8>) {
+    register(Attachment {
+        name: name.into(),
+        source: Source::Provider(provider),
+    });
+}
+
+/// Removes every registered attachment.
+pub fn clear_attachments() {
+    ATTACHMENTS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// File name of attachment `name` of event `event_id`.
+pub fn file_name(event_id: &str, name: &str) -> String {
+    format!("{}{}_{}", ATTACHMENT_PREFIX, event_id, crate::output::sanitize(name))
+}
+
+/// Endpoint attachment `name` of event `event_id` is uploaded to on `server`.
+pub fn upload_endpoint(server: &str, event_id: &str, name: &str) -> String {
+    format!(
+        "{}/crashes/{}/attachments/{}",
+        server.trim_end_matches('/'),
+        event_id,
+        crate::output::sanitize(name)
+    )
+}
+
+/// Stores the registered attachments for `event_id` as configured and
+/// returns references to those that were delivered. Used by the panic hook,
+/// so a registry locked by the panicking code is skipped.
+pub fn save(config: &Config, event_id: &str) -> Vec<AttachmentRef> {
+    let attachments = match ATTACHMENTS.try_lock() {
+        Ok(attachments) => attachments.clone(),
+        Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner().clone(),
+        Err(std::sync::TryLockError::WouldBlock) => return Vec::new(),
+    };
+
+    let mut saved = Vec::new();
+    for attachment in attachments {
+        let data = match attachment.source {
+            Source::File(path) => match std::fs::read(&path) {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Failed to read attachment '{}': {}", path.display(), e);
+                    continue;
+                }
+            },
+            Source::Bytes(data) => data,
+            Source::Provider(provider) => provider(),
+        };
+        saved.extend(deliver(config, event_id, &attachment.name, &data));
+    }
+    saved
+}
+
+/// Uploads and/or writes `data` as attachment `name` of `event_id`, like the
+/// registered attachments. Returns its reference if it was delivered.
+pub fn deliver(config: &Config, event_id: &str, name: &str, data: &[u8]) -> Option<AttachmentRef> {
+    let file_name = file_name(event_id, name);
+    // Listed in the report, whose signature covers it.
+    let digest = Digest::new(data, None);
+    let mut delivered = false;
+    if let Some(server) = &config.upload_url {
+        let url = upload_endpoint(server, event_id, name);
+        let sent = crate::transport::post_artifact(
+            config,
+            &url,
+            "application/octet-stream",
+            Compression::None,
+            &digest,
+            data.to_vec(),
+        );
+        match sent {
+            Ok(()) => delivered = true,
+            Err(e) => eprintln!("Failed to upload attachment '{}': {}", name, e),
+        }
+    }
+    if config.write_local || !delivered {
+        let stored = format!("{}{}", file_name, crate::encryption::extension(config));
+        let written = crate::encryption::seal(config, data.to_vec()).and_then(|data| config.storage().write(&stored, &data));
+        match written {
+            Ok(_) => delivered = true,
+            Err(e) => eprintln!("Failed to write attachment '{}': {}", file_name, e),
+        }
+    }
+    delivered.then(|| AttachmentRef {
+        name: name.to_string(),
+        filename: file_name,
+        size: data.len() as u64,
+        sha256: digest.sha256,
+    })
+}
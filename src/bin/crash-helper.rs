@@ -0,0 +1,13 @@
+/// Helper executable launched from the crash signal handler. It receives the
+/// crashing process id over the handshake pipe and writes its minidump.
+#[cfg(target_os = "linux")]
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    std::process::exit(crash::helper::run_helper(&args));
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    eprintln!("crash-helper: out-of-process dumping is only supported on Linux");
+    std::process::exit(1);
+}
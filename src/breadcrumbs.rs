@@ -0,0 +1,83 @@
+// Client-side breadcrumb trail.
+//
+// Applications record what happened before a crash with `add_breadcrumb`. The
+// most recent breadcrumbs are kept in a bounded ring buffer and copied into
+// the event when the panic hook fires.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::event::Breadcrumb;
+
+pub const DEFAULT_MAX_BREADCRUMBS: usize = 100;
+
+struct Trail {
+    capacity: usize,
+    entries: VecDeque<Breadcrumb>,
+}
+
+static TRAIL: Mutex<Trail> = Mutex::new(Trail {
+    capacity: DEFAULT_MAX_BREADCRUMBS,
+    entries: VecDeque::new(),
+});
+
+impl Breadcrumb {
+    /// Creates an `info` breadcrumb timestamped now.
+    pub fn new(category: impl Into<String>, message: impl Into<String>) -> Self {
+        let timestamp = crate::clock::since_epoch().as_secs_f64();
+        Breadcrumb {
+            timestamp: timestamp.to_string(),
+            category: Some(category.into()),
+            message: Some(message.into()),
+            level: Some("info".to_string()),
+            data: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_level(mut self, level: impl Into<String>) -> Self {
+        self.level = Some(level.into());
+        self
+    }
+
+    pub fn with_data(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.data.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// Records a breadcrumb, evicting the oldest one when the buffer is full.
+pub fn add_breadcrumb(breadcrumb: Breadcrumb) {
+    let mut trail = TRAIL.lock().unwrap_or_else(|e| e.into_inner());
+    if trail.capacity == 0 {
+        return;
+    }
+    while trail.entries.len() >= trail.capacity {
+        trail.entries.pop_front();
+    }
+    trail.entries.push_back(breadcrumb);
+}
+
+/// Changes how many breadcrumbs are kept.
+pub fn set_max_breadcrumbs(capacity: usize) {
+    let mut trail = TRAIL.lock().unwrap_or_else(|e| e.into_inner());
+    trail.capacity = capacity;
+    while trail.entries.len() > capacity {
+        trail.entries.pop_front();
+    }
+}
+
+/// Removes all recorded breadcrumbs.
+pub fn clear_breadcrumbs() {
+    TRAIL.lock().unwrap_or_else(|e| e.into_inner()).entries.clear();
+}
+
+/// The recorded breadcrumbs, oldest first. Used by the panic hook, so it does
+/// not block: if the panic happened while the buffer was locked, the trail is
+/// skipped rather than deadlocking.
+pub fn snapshot() -> Vec<Breadcrumb> {
+    match TRAIL.try_lock() {
+        Ok(trail) => trail.entries.iter().cloned().collect(),
+        Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner().entries.iter().cloned().collect(),
+        Err(std::sync::TryLockError::WouldBlock) => Vec::new(),
+    }
+}
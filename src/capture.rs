@@ -0,0 +1,211 @@
+// Reports for handled errors, plain messages and caught panics.
+//
+// Not every problem is a panic. `crash::capture_error` files a report for an
+// error the application handled, with its `source()` chain as Sentry
+// exception values, `crash::capture_message` one for a message and
+// `crash::capture_exception` one for an exception of another language, such
+// as those the Python bindings catch in `sys.excepthook`. All go through the
+// same pipeline as panic reports (sampling, scopes, integrations,
+// `before_send`, scrubbing, upload and local storage) but carry their own
+// level and never a minidump.
+//
+// `crash::catch_and_report` runs a closure under `catch_unwind`. A panic in it
+// is reported by the panic hook as usual, at `Builder::caught_panic_level`
+// (`error`) and without a minidump, and handed back to the caller, which
+// keeps running.
+
+use std::cell::Cell;
+use std::error::Error;
+use std::panic::UnwindSafe;
+
+use uuid::Uuid;
+
+use crate::event::{Exception, ExceptionValue, SentryEvent};
+
+/// Severity of a captured event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Debug,
+    Info,
+    Warning,
+    Error,
+    Fatal,
+}
+
+impl Level {
+    /// The level named `name`, as in reports: `debug`, `info`, `warning`,
+    /// `error` or `fatal`.
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warning" => Some(Level::Warning),
+            "error" => Some(Level::Error),
+            "fatal" => Some(Level::Fatal),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warning => "warning",
+            Level::Error => "error",
+            Level::Fatal => "fatal",
+        }
+    }
+}
+
+// Name of the type `E` without module paths (`ParseIntError`,
+// `Wrapped<ParseIntError>`), or None for trait objects, whose type is only
+// known at run time.
+fn type_name<E: ?Sized>() -> Option<String> {
+    let full = std::any::type_name::<E>().trim_start_matches('&');
+    if full.contains("dyn ") {
+        return None;
+    }
+    let mut name = String::new();
+    let mut path = String::new();
+    for c in full.chars().chain(std::iter::once(' ')) {
+        if c.is_alphanumeric() || c == '_' || c == ':' {
+            path.push(c);
+        } else {
+            name.push_str(path.rsplit("::").next().unwrap_or_default());
+            path.clear();
+            name.push(c);
+        }
+    }
+    name.pop();
+    Some(name)
+}
+
+// Name of the type behind an error only known as `dyn Error`, such as the
+// sources of another, from its `Debug` output, which starts with it for
+// derived implementations (`ParseIntError { kind: InvalidDigit }`).
+fn error_type(error: &dyn Error) -> String {
+    let debug = format!("{:?}", error);
+    let name: String = debug
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == ':')
+        .collect();
+    if name.is_empty() {
+        "Error".to_string()
+    } else {
+        name
+    }
+}
+
+// Builds an event with `build`, then completes and delivers it. Returns its
+// id if it was delivered.
+pub(crate) fn capture(build: impl FnOnce(&Uuid, f64) -> SentryEvent) -> Option<String> {
+    let config = crate::hook::config()?;
+    let event_id = config.new_event_id();
+    if crate::sampling::decide(config, &event_id) != crate::sampling::Decision::Capture {
+        return None;
+    }
+    let timestamp = crate::clock::since_epoch().as_secs_f64();
+    let event = build(&event_id, timestamp);
+    #[cfg(not(target_arch = "wasm32"))]
+    if matches!(event.level.as_deref(), Some("error" | "fatal")) {
+        crate::session::record_error();
+    }
+    let delivered = match crate::hook::process_and_deliver(config, event) {
+        Ok(deliveries) if !deliveries.is_empty() => Some(event_id.to_string()),
+        Ok(_) => None,
+        Err(dropped_by) => {
+            config.log_debug(format_args!("Event {} dropped by {}.", event_id, dropped_by));
+            None
+        }
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    crate::metrics::push(config);
+    delivered
+}
+
+/// Reports `error`, with the errors it was caused by, at level `error`.
+/// Returns the event id, or `None` if the crash handler is not installed or
+/// the event was dropped or could not be stored.
+pub fn capture_error<E: Error + ?Sized>(error: &E) -> Option<String> {
+    let mut values = vec![ExceptionValue {
+        exception_type: type_name::<E>().unwrap_or_else(|| error_type(&error)),
+        value: error.to_string(),
+        ..Default::default()
+    }];
+    let mut current = error.source();
+    while let Some(error) = current {
+        values.push(ExceptionValue {
+            exception_type: error_type(error),
+            value: error.to_string(),
+            ..Default::default()
+        });
+        current = error.source();
+    }
+    values.reverse();
+
+    capture(|event_id, timestamp| {
+        let mut event = crate::hook::base_event(event_id, timestamp, Level::Error.as_str(), error.to_string());
+        event.exception = Some(Exception { values });
+        event
+    })
+}
+
+/// Reports an exception raised outside Rust, e.g. by an embedded interpreter,
+/// for the language `platform` (`python`). `values` are the exception and
+/// those it was caused by, root cause first, each with the stack it was
+/// raised on; the stack of the last one becomes the stack of the event,
+/// which carries no Rust stack. Returns the event id, or `None` if the crash
+/// handler is not installed or the event was dropped or could not be stored.
+pub fn capture_exception(platform: &str, level: Level, mut values: Vec<ExceptionValue>) -> Option<String> {
+    let message = values
+        .last()
+        .map(|value| format!("{}: {}", value.exception_type, value.value))
+        .unwrap_or_default();
+    capture(|event_id, timestamp| {
+        let mut event = crate::hook::base_event(event_id, timestamp, level.as_str(), message);
+        event.platform = Some(platform.to_string());
+        event.stacktrace = values.last_mut().and_then(|value| value.stacktrace.take());
+        event.exception = Some(Exception { values });
+        event
+    })
+}
+
+/// Reports `message` at `level`. Returns the event id, or `None` if the crash
+/// handler is not installed or the event was dropped or could not be stored.
+pub fn capture_message(level: Level, message: impl Into<String>) -> Option<String> {
+    let message = message.into();
+    capture(|event_id, timestamp| {
+        crate::hook::base_event(event_id, timestamp, level.as_str(), message)
+    })
+}
+
+thread_local! {
+    // Number of `catch_and_report` calls active on this thread.
+    static CATCHING: Cell<usize> = const { Cell::new(0) };
+}
+
+// Leaves a `catch_and_report` scope however the closure ends.
+struct CatchGuard;
+
+impl Drop for CatchGuard {
+    fn drop(&mut self) {
+        CATCHING.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// Whether a panic on this thread will be caught by `catch_and_report`.
+pub(crate) fn is_catching() -> bool {
+    CATCHING.try_with(|depth| depth.get() > 0).unwrap_or(false)
+}
+
+/// Runs `f`, catching a panic in it. The panic is reported like any other,
+/// but at the level of caught panics and without a minidump, and its payload
+/// is returned to the caller.
+pub fn catch_and_report<F, R>(f: F) -> std::thread::Result<R>
+where
+    F: FnOnce() -> R + UnwindSafe,
+{
+    CATCHING.with(|depth| depth.set(depth.get() + 1));
+    let _guard = CatchGuard;
+    std::panic::catch_unwind(f)
+}
@@ -0,0 +1,260 @@
+// Crash reports for child processes.
+//
+// A subprocess that crashes takes its panic hook and signal handlers with it,
+// if it had any. `crash::monitor_child` hands a spawned `Child` to a thread
+// that waits for it; when it is killed by a signal or exits with a nonzero
+// status, that thread files an `error` report through the installed crash
+// handler. The report says how the child ended, carries the `child` extra
+// (pid, command line, exit code or signal, core files) and attaches:
+//
+// - the last `STDERR_TAIL` bytes of its stderr, when it was spawned with
+//   `Stdio::piped()`; the output is still passed through to our stderr,
+// - minidumps that appeared in the output directory while it ran, such as
+//   one written by a child that uses this crate with the same `output_dir`.
+//
+// Core files are only listed (path and size), they are too large to attach.
+// They are looked for as `core` and `core.<pid>` in the current directory,
+// which is where the kernel puts them unless `core_pattern` says otherwise.
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitStatus};
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+
+use serde_json::json;
+
+use crate::config::Config;
+use crate::event::AttachmentRef;
+
+/// Bytes of the child's stderr kept for the report.
+pub const STDERR_TAIL: usize = 64 * 1024;
+
+/// A child process being watched by `monitor_child`.
+#[derive(Debug)]
+pub struct ChildMonitor {
+    pid: u32,
+    thread: JoinHandle<std::io::Result<ExitStatus>>,
+}
+
+impl ChildMonitor {
+    /// Process id of the child.
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    /// Waits for the child to exit and for its report, if any, to be filed.
+    pub fn wait(self) -> std::io::Result<ExitStatus> {
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err(std::io::Error::other("child monitor panicked")))
+    }
+}
+
+/// Watches `child` and reports it when it crashes or fails, see
+/// `crate::child`. Takes its stderr if it was piped. The child is waited for
+/// by the monitor; use `ChildMonitor::wait` for its exit status.
+pub fn monitor_child(mut child: Child) -> std::io::Result<ChildMonitor> {
+    let pid = child.id();
+    let started = SystemTime::now();
+    let command = command_line(pid);
+    let stderr = match child.stderr.take() {
+        Some(stderr) => Some(
+            std::thread::Builder::new()
+                .name(format!("crash-child-stderr-{}", pid))
+                .spawn(move || tail_stderr(stderr))?,
+        ),
+        None => None,
+    };
+
+    let thread = std::thread::Builder::new()
+        .name(format!("crash-child-{}", pid))
+        .spawn(move || {
+            let status = child.wait()?;
+            // The pipe closes when the child is gone, unless it passed it on.
+            let stderr = stderr.and_then(|reader| reader.join().ok());
+            if let Some(reason) = exit_reason(&status) {
+                let exit = ChildExit {
+                    pid,
+                    command,
+                    status,
+                    reason,
+                    stderr,
+                    started,
+                };
+                if let (Some(event_id), Some(config)) = (report(&exit), crate::hook::config()) {
+                    config.log_debug(format_args!("Child process {} failed, reported as {}", pid, event_id));
+                }
+            }
+            Ok(status)
+        })?;
+    Ok(ChildMonitor { pid, thread })
+}
+
+// Passes the child's stderr through and keeps its tail.
+fn tail_stderr(mut stderr: impl Read) -> Vec<u8> {
+    let mut tail = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let read = match stderr.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        let _ = std::io::stderr().write_all(&buf[..read]);
+        tail.extend_from_slice(&buf[..read]);
+        if tail.len() > 2 * STDERR_TAIL {
+            tail.drain(..tail.len() - STDERR_TAIL);
+        }
+    }
+    if tail.len() > STDERR_TAIL {
+        tail.drain(..tail.len() - STDERR_TAIL);
+    }
+    tail
+}
+
+// Everything known about a failed child.
+struct ChildExit {
+    pid: u32,
+    command: Option<String>,
+    status: ExitStatus,
+    reason: String,
+    stderr: Option<Vec<u8>>,
+    started: SystemTime,
+}
+
+// How the child ended, or `None` if it succeeded.
+fn exit_reason(status: &ExitStatus) -> Option<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            let core = if status.core_dumped() { ", core dumped" } else { "" };
+            return Some(format!("killed by {}{}", signal_label(signal), core));
+        }
+    }
+    match status.code() {
+        Some(0) => None,
+        // NTSTATUS codes of unhandled exceptions, e.g. 0xC0000005 for an
+        // access violation.
+        #[cfg(windows)]
+        Some(code) if code as u32 >= 0xC000_0000 => Some(format!("terminated by exception {:#x}", code as u32)),
+        Some(code) => Some(format!("exited with status {}", code)),
+        None => Some("terminated".to_string()),
+    }
+}
+
+#[cfg(unix)]
+fn signal_label(signal: i32) -> String {
+    let (name, description) = match signal {
+        libc::SIGKILL => ("SIGKILL", "killed"),
+        libc::SIGTERM => ("SIGTERM", "terminated"),
+        libc::SIGINT => ("SIGINT", "interrupted"),
+        _ => crate::signals::signal_name(signal),
+    };
+    if name == "UNKNOWN" {
+        format!("signal {}", signal)
+    } else {
+        format!("signal {} ({})", name, description)
+    }
+}
+
+// Command line of process `pid`, read while it runs.
+#[cfg(target_os = "linux")]
+fn command_line(pid: u32) -> Option<String> {
+    let raw = std::fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let args: Vec<_> = raw
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect();
+    (!args.is_empty()).then(|| args.join(" "))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn command_line(_pid: u32) -> Option<String> {
+    None
+}
+
+// Files matching `matches` in `dir` modified at or after `since`.
+fn new_files(dir: &Path, since: SystemTime, matches: impl Fn(&str) -> bool) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| matches(&entry.file_name().to_string_lossy()))
+        .filter(|entry| {
+            entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| modified >= since)
+        })
+        .map(|entry| entry.path())
+        .collect()
+}
+
+// Files the delivery of `exit` attached, for `event_id`.
+fn attach(config: &Config, event_id: &str, exit: &ChildExit) -> Vec<AttachmentRef> {
+    let mut attached = Vec::new();
+    if let Some(stderr) = &exit.stderr {
+        attached.extend(crate::attachments::deliver(config, event_id, "stderr.txt", stderr));
+    }
+    let dumps = new_files(&config.output_dir, exit.started, |name| {
+        name.starts_with("crash_dump_") && name.contains(".dmp") && !name.ends_with(crate::integrity::SUFFIX)
+    });
+    for dump in dumps {
+        let name = dump.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        match std::fs::read(&dump) {
+            Ok(data) => attached.extend(crate::attachments::deliver(config, event_id, &name, &data)),
+            Err(e) => eprintln!("Failed to read minidump '{}': {}", dump.display(), e),
+        }
+    }
+    attached
+}
+
+fn report(exit: &ChildExit) -> Option<String> {
+    let config = crate::hook::config()?;
+    crate::capture::capture(|event_id, timestamp| {
+        let program = exit
+            .command
+            .as_deref()
+            .and_then(|command| command.split(' ').next())
+            .and_then(|program| Path::new(program).file_name())
+            .map(|name| name.to_string_lossy().into_owned());
+        let message = match &program {
+            Some(program) => format!("Child process {} {}", program, exit.reason),
+            None => format!("Child process {}", exit.reason),
+        };
+        let mut event = crate::hook::base_event(event_id, timestamp, crate::Level::Error.as_str(), message);
+        // The stack is the monitor's, not the child's.
+        event.stacktrace = None;
+
+        let mut child = json!({
+            "pid": exit.pid,
+            "command": exit.command,
+            "exit_code": exit.status.code(),
+        });
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            child["signal"] = json!(exit.status.signal());
+            if exit.status.core_dumped() {
+                let cwd = std::env::current_dir().unwrap_or_default();
+                let pid_core = format!("core.{}", exit.pid);
+                let cores: Vec<_> = new_files(&cwd, exit.started, |name| name == "core" || name == pid_core)
+                    .into_iter()
+                    .map(|path| {
+                        let size = std::fs::metadata(&path).map(|m| m.len()).ok();
+                        json!({ "path": path, "size": size })
+                    })
+                    .collect();
+                child["core_files"] = json!(cores);
+            }
+        }
+        event.extra.insert("child".to_string(), child);
+        event.attachments = attach(config, &event.event_id, exit);
+        event
+    })
+}
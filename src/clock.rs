@@ -0,0 +1,19 @@
+// Wall-clock time for event and breadcrumb timestamps. `SystemTime::now`
+// panics on wasm32-unknown-unknown, so there the time comes from
+// JavaScript's `Date.now()` instead.
+
+use std::time::Duration;
+
+/// Time since the UNIX epoch, zero if the clock is set before it.
+pub(crate) fn since_epoch() -> Duration {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        Duration::from_secs_f64(js_sys::Date::now().max(0.0) / 1000.0)
+    }
+}
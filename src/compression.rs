@@ -0,0 +1,83 @@
+// Optional compression of crash artifacts.
+//
+// With compression enabled, reports and minidumps written locally get a
+// `.gz` or `.zst` suffix (`crash_report_<id>.json.zst`) and reports sent to
+// the crash server carry a matching `Content-Encoding`. The server reads
+// either form. Reports written from signal handlers are never compressed, and
+// reports sent to Sentry are sent as is.
+
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Parses `none`, `gzip` or `zstd`.
+    pub fn from_name(name: &str) -> Option<Compression> {
+        match name {
+            "none" => Some(Compression::None),
+            "gzip" => Some(Compression::Gzip),
+            "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// The compression implied by the suffix of `path`.
+    pub fn from_path(path: &Path) -> Compression {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Suffix appended to file names, including the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    /// Value of the `Content-Encoding` header for compressed uploads.
+    pub fn content_encoding(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Compression::Zstd => zstd::encode_all(data, 0),
+            #[cfg(target_arch = "wasm32")]
+            Compression::Zstd => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "zstd is not available on wasm32",
+            )),
+        }
+    }
+}
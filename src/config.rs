@@ -0,0 +1,770 @@
+// Configuration of the crash handler, passed to `crash::init`.
+
+use std::panic::PanicHookInfo;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use uuid::{NoContext, Timestamp, Uuid};
+
+use crate::capture::Level;
+use crate::compression::Compression;
+use crate::encoding::Encoding;
+use crate::event::SentryEvent;
+use crate::integrity::SigningKey;
+use crate::storage::Storage;
+use crate::transport::{FileTransport, HttpTransport, SentryTransport, Transport};
+use crate::unwind::Unwinder;
+use crate::upload::{upload_endpoint, DumpDestination};
+use crate::windows_dump::MinidumpType;
+
+/// Application callback that can modify or drop an event right before it is
+/// written or uploaded, see `Builder::before_send`.
+#[derive(Clone)]
+pub struct BeforeSend(Arc<dyn Fn(SentryEvent) -> Option<SentryEvent> + Send + Sync>);
+
+impl std::fmt::Debug for BeforeSend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BeforeSend(..)")
+    }
+}
+
+type PanicLevelFn = dyn Fn(&PanicHookInfo<'_>, Level) -> Level + Send + Sync;
+
+/// Application callback that picks the level of a panic report, see
+/// `Builder::panic_level_with`.
+#[derive(Clone)]
+pub struct PanicLevel(Arc<PanicLevelFn>);
+
+impl std::fmt::Debug for PanicLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PanicLevel(..)")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub output_dir: PathBuf,          // Where reports and minidumps are written, created if missing
+    pub filename_template: String,    // Report file name, see `crate::output`
+    pub app_name: Option<String>,     // `{app_name}` placeholder, defaults to the executable name
+    pub app_version: Option<String>,  // Reported in the `app` context
+    pub release: Option<String>,      // Build the crash came from, defaults to `<app_name>@<app_version>`
+    pub environment: Option<String>,  // Deployment environment, e.g. `production`
+    pub dist: Option<String>,         // Distribution of the release, e.g. a build number
+    pub server_name: Option<String>,  // Host the crash happened on, defaults to the hostname
+    pub minidump: bool,               // Write a minidump next to each panic report
+    pub minidump_type: MinidumpType,  // What Windows minidumps contain, see `crate::windows_dump`
+    pub compression: Compression,     // Compress reports and minidumps, see `crate::compression`
+    pub encoding: Encoding,           // Report format, see `crate::encoding`
+    pub encrypt_to: Option<String>,   // age recipient artifacts are encrypted to, see `crate::encryption`
+    pub signing_key: Option<SigningKey>, // Signs reports, see `crate::integrity`
+    pub upload_url: Option<String>,   // Crash server to send reports to, see `crate::transport`
+    pub upload_minidump: bool,        // Send minidumps to the server instead of writing them
+    pub sentry_dsn: Option<String>,   // Send reports straight to Sentry, see `crate::sentry`
+    pub upload_timeout: Duration,     // Upper bound for sending a report
+    pub shutdown_timeout: Duration,   // How long a `FlushGuard` waits for deliveries, see `crate::flush`
+    pub write_local: bool,            // Also write reports locally when uploading
+    pub max_queued_reports: usize,    // Failed uploads kept for a retry, see `crate::queue`; 0 disables
+    pub deferred: bool,               // Use the low-overhead deferred hook
+    pub preallocate: bool,            // Deferred hook that never allocates, see `crate::deferred`
+    pub chain_previous_hook: bool,    // Call the panic hook installed before ours after it
+    pub native_crashes: bool,         // Report native crashes from signal handlers (Unix) and Mach exceptions (macOS)
+    pub watchdog: bool,               // Capture from a long-lived helper process (Linux)
+    pub capture_threads: bool,        // List every thread with its stack in panic reports, see `crate::threads`
+    pub panic_level: Level,           // Level of panic reports
+    pub caught_panic_level: Level,    // Level of panics the process survives, see `crash::catch_and_report`
+    pub signal_level: Level,          // Level of native crash reports
+    pub panic_level_with: Option<PanicLevel>, // Picks the level of each panic instead
+    pub unwinder: Unwinder,           // How stacks are walked, see `crate::unwind`
+    pub in_app_include: Vec<String>,  // Crates or modules whose frames are in-app, see `crate::frames`
+    pub in_app_exclude: Vec<String>,  // Crates or modules whose frames are never in-app
+    pub trim_frames: bool,            // Cut panic machinery and runtime startup frames from stacks
+    pub max_frames: Option<usize>,    // Frames kept per stack, dropped from the middle
+    pub source_context: usize,        // Source lines around in-app frames, see `crate::source_context`
+    pub source_root: Option<PathBuf>, // Where relative source paths are looked up, default the current dir
+    pub max_breadcrumbs: usize,       // Size of the breadcrumb ring buffer
+    pub max_reports_per_minute: Option<u32>, // Cap on panic reports, see `crate::sampling`
+    pub sample_rate: f64,             // Fraction of panic reports kept, 0.0 to 1.0
+    pub max_reports: Option<usize>,   // Crashes kept in `output_dir`, see `crate::retention`
+    pub max_age: Option<Duration>,    // Remove crashes older than this at startup
+    pub max_total_bytes: Option<u64>, // Upper bound for crash files in `output_dir`
+    pub capture_env: bool,            // Attach environment variables, see `crate::environment`
+    pub env_allowlist: Vec<String>,   // Only capture variables matching one of these, if any
+    pub env_denylist: Vec<String>,    // Never capture variables matching one of these
+    pub capture_args: bool,           // Attach the command line, see `crate::arguments`
+    pub secret_args: Vec<String>,     // Options whose values are masked, on top of the built-in ones
+    pub scrub_pii: bool,              // Scrub reports before delivery, see `crate::scrub`
+    pub scrub_rules: Vec<String>,     // Extra regexes to scrub, on top of the built-in ones
+    pub capture_output: usize,        // Bytes of stdout/stderr attached to reports, 0 for none (Unix)
+    pub memory_limit: Option<u64>,    // Report the resident set size exceeding this, see `crate::oom`
+    pub deadlock_timeout: Duration,   // Report waits for a tracked lock longer than this, see `crate::deadlock`
+    pub hang_timeout: Option<Duration>, // Report heartbeats further apart than this, see `crate::hang`
+    pub auto_session: bool,           // Start a session in `init`, see `crate::session`
+    pub sortable_event_ids: bool,     // Time-ordered UUIDv7 event ids rather than random v4 ones
+    pub crash_loop_threshold: Option<u32>, // Early crashes in a row that make a crash loop, see `crate::crash_loop`
+    pub crash_loop_window: Duration,  // Crashes this soon after starting count as early
+    pub crash_loop_throttle: bool,    // Drop the reports of runs in a crash loop that was reported
+    pub metrics_addr: Option<String>, // Serve counters for Prometheus here, see `crate::metrics`
+    pub metrics_push_url: Option<String>, // Prometheus Pushgateway the counters are pushed to
+    pub helper_path: Option<PathBuf>, // Linux dump helper, default `crash-helper` beside the exe
+    pub before_send: Option<BeforeSend>, // Last chance to modify or drop an event
+    pub storage: Option<Arc<dyn Storage>>, // Where reports are written, see `crate::storage`
+    pub transports: Vec<Arc<dyn Transport>>, // Further destinations for reports, see `crate::transport`
+    pub external_config: bool,        // Apply `crash.toml` and CRASH_* variables in `init`, see `crate::settings`
+    pub config_file: Option<PathBuf>, // Read instead of `crash.toml` when CRASH_CONFIG is not set
+    pub debug: bool,                  // Print where reports went and what dropped them to stderr
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            output_dir: PathBuf::from("."),
+            filename_template: crate::output::DEFAULT_FILENAME_TEMPLATE.to_string(),
+            app_name: None,
+            app_version: None,
+            release: std::env::var("SENTRY_RELEASE").ok().filter(|r| !r.is_empty()),
+            environment: std::env::var("SENTRY_ENVIRONMENT").ok().filter(|e| !e.is_empty()),
+            dist: None,
+            server_name: None,
+            minidump: true,
+            minidump_type: MinidumpType::Normal,
+            compression: Compression::None,
+            encoding: Encoding::Json,
+            encrypt_to: None,
+            signing_key: SigningKey::from_env(),
+            upload_url: std::env::var(crate::upload::UPLOAD_URL_ENV).ok().filter(|u| !u.is_empty()),
+            upload_minidump: true,
+            sentry_dsn: std::env::var("SENTRY_DSN").ok().filter(|d| !d.is_empty()),
+            upload_timeout: Duration::from_secs(5),
+            shutdown_timeout: Duration::from_secs(2),
+            write_local: true,
+            max_queued_reports: 100,
+            deferred: false,
+            preallocate: false,
+            chain_previous_hook: true,
+            native_crashes: true,
+            watchdog: false,
+            capture_threads: true,
+            panic_level: Level::Fatal,
+            caught_panic_level: Level::Error,
+            signal_level: Level::Fatal,
+            panic_level_with: None,
+            unwinder: Unwinder::Backtrace,
+            in_app_include: Vec::new(),
+            in_app_exclude: Vec::new(),
+            trim_frames: true,
+            max_frames: None,
+            source_context: crate::source_context::DEFAULT_SOURCE_CONTEXT,
+            source_root: None,
+            max_breadcrumbs: crate::breadcrumbs::DEFAULT_MAX_BREADCRUMBS,
+            max_reports_per_minute: None,
+            sample_rate: 1.0,
+            max_reports: None,
+            max_age: None,
+            max_total_bytes: None,
+            capture_env: false,
+            env_allowlist: Vec::new(),
+            env_denylist: Vec::new(),
+            capture_args: true,
+            secret_args: Vec::new(),
+            scrub_pii: false,
+            scrub_rules: Vec::new(),
+            capture_output: 0,
+            memory_limit: None,
+            deadlock_timeout: Duration::from_secs(30),
+            hang_timeout: None,
+            auto_session: false,
+            sortable_event_ids: true,
+            crash_loop_threshold: None,
+            crash_loop_window: Duration::from_secs(30),
+            crash_loop_throttle: false,
+            metrics_addr: None,
+            metrics_push_url: None,
+            helper_path: None,
+            before_send: None,
+            storage: None,
+            transports: Vec::new(),
+            external_config: true,
+            config_file: None,
+            debug: false,
+        }
+    }
+}
+
+impl Config {
+    /// Prints `message` to stderr with `debug` on. Where reports went is of
+    /// no concern to the application's own output.
+    pub(crate) fn log_debug(&self, message: std::fmt::Arguments) {
+        if self.debug {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// Where the minidump of `event_id` goes.
+    pub fn dump_destination(&self, event_id: &str) -> DumpDestination {
+        match &self.upload_url {
+            Some(url) if self.upload_minidump => DumpDestination::Upload(upload_endpoint(url, event_id)),
+            _ => DumpDestination::File(self.output_dir.join(format!(
+                "crash_dump_{}.dmp{}{}",
+                event_id,
+                self.compression.extension(),
+                crate::encryption::extension(self)
+            ))),
+        }
+    }
+
+    /// Sets the release, environment, dist and server name of `event`.
+    pub fn apply_release(&self, event: &mut SentryEvent) {
+        event.release = self.release.clone();
+        event.environment = self.environment.clone();
+        event.dist = self.dist.clone();
+        event.server_name = self.server_name.clone();
+    }
+
+    /// Level of the report of the panic `info`, which the process survives if
+    /// `caught`: what the `panic_level_with` callback picks, if there is one,
+    /// from `panic_level` or `caught_panic_level`.
+    pub fn level_of_panic(&self, info: &PanicHookInfo<'_>, caught: bool) -> Level {
+        let level = if caught { self.caught_panic_level } else { self.panic_level };
+        match &self.panic_level_with {
+            Some(PanicLevel(callback)) => callback(info, level),
+            None => level,
+        }
+    }
+
+    /// Runs the `before_send` callback, if any, on `event`.
+    pub fn apply_before_send(&self, event: SentryEvent) -> Option<SentryEvent> {
+        match &self.before_send {
+            Some(BeforeSend(callback)) => callback(event),
+            None => Some(event),
+        }
+    }
+
+    /// Where reports and attachments are written: the configured storage, or
+    /// files in `output_dir` (`localStorage` on wasm32).
+    pub fn storage(&self) -> Arc<dyn Storage> {
+        self.storage
+            .clone()
+            .unwrap_or_else(|| crate::storage::default_storage(&self.output_dir))
+    }
+
+    /// Where reports are delivered, in order: the crash server, Sentry, the
+    /// added transports and the local storage, as configured.
+    pub fn transports(&self) -> Vec<Arc<dyn Transport>> {
+        let mut transports: Vec<Arc<dyn Transport>> = Vec::new();
+        if let Some(server) = &self.upload_url {
+            transports.push(Arc::new(HttpTransport { server: server.clone() }));
+        }
+        if let Some(dsn) = &self.sentry_dsn {
+            transports.push(Arc::new(SentryTransport { dsn: dsn.clone() }));
+        }
+        transports.extend(self.transports.iter().cloned());
+        if self.write_local {
+            transports.push(Arc::new(FileTransport));
+        }
+        transports
+    }
+
+    /// A new event id: a UUIDv7, which sorts by the time it was made, or a
+    /// random v4 with `sortable_event_ids` off.
+    pub fn new_event_id(&self) -> Uuid {
+        if self.sortable_event_ids {
+            let now = crate::clock::since_epoch();
+            Uuid::new_v7(Timestamp::from_unix(NoContext, now.as_secs(), now.subsec_nanos()))
+        } else {
+            Uuid::new_v4()
+        }
+    }
+
+    /// File name of the report for `event_id`, captured at `timestamp`.
+    pub fn report_file_name(&self, event_id: &str, timestamp: u64) -> String {
+        let app_name = self.app_name.clone().unwrap_or_else(crate::output::default_app_name);
+        crate::output::render(&self.filename_template, event_id, timestamp, &app_name)
+    }
+}
+
+/// Builder for `Config`, e.g.
+/// `crash::Builder::new().output_dir("crashes").init()`.
+#[derive(Default)]
+pub struct Builder {
+    config: Config,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn output_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.output_dir = dir.into();
+        self
+    }
+
+    /// Report file name template, e.g. `{app_name}_{timestamp}_{event_id}.json`.
+    pub fn filename_template(mut self, template: impl Into<String>) -> Self {
+        self.config.filename_template = template.into();
+        self
+    }
+
+    pub fn app_name(mut self, name: impl Into<String>) -> Self {
+        self.config.app_name = Some(name.into());
+        self
+    }
+
+    /// Version of the application, e.g. `env!("CARGO_PKG_VERSION")`.
+    pub fn app_version(mut self, version: impl Into<String>) -> Self {
+        self.config.app_version = Some(version.into());
+        self
+    }
+
+    /// Release the crashes are attributed to, e.g. `crash::release_name!()`.
+    /// Defaults to `SENTRY_RELEASE`, or `<app_name>@<app_version>` when an
+    /// app version is set.
+    pub fn release(mut self, release: impl Into<String>) -> Self {
+        self.config.release = Some(release.into());
+        self
+    }
+
+    /// Deployment environment, e.g. `production` or `staging`. Defaults to
+    /// `SENTRY_ENVIRONMENT`.
+    pub fn environment(mut self, environment: impl Into<String>) -> Self {
+        self.config.environment = Some(environment.into());
+        self
+    }
+
+    /// Distribution of the release, e.g. a build number or target.
+    pub fn dist(mut self, dist: impl Into<String>) -> Self {
+        self.config.dist = Some(dist.into());
+        self
+    }
+
+    /// Name of the host, defaults to the hostname.
+    pub fn server_name(mut self, name: impl Into<String>) -> Self {
+        self.config.server_name = Some(name.into());
+        self
+    }
+
+    pub fn minidump(mut self, enabled: bool) -> Self {
+        self.config.minidump = enabled;
+        self
+    }
+
+    /// How much of the process goes into minidumps on Windows; ignored
+    /// elsewhere.
+    pub fn minidump_type(mut self, minidump_type: MinidumpType) -> Self {
+        self.config.minidump_type = minidump_type;
+        self
+    }
+
+    /// Compresses reports and minidumps with gzip or zstd.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.config.compression = compression;
+        self
+    }
+
+    /// Writes and uploads reports as JSON, MessagePack or CBOR.
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.config.encoding = encoding;
+        self
+    }
+
+    /// Encrypts reports, minidumps and attachments to the age X25519
+    /// `recipient` (`age1...`), see `crate::encryption`. Needs the
+    /// `encryption` feature; `init` fails without it.
+    pub fn encrypt_to(mut self, recipient: impl Into<String>) -> Self {
+        self.config.encrypt_to = Some(recipient.into());
+        self
+    }
+
+    /// Signs reports with HMAC-SHA256 under `key`, shared with the crash
+    /// server, see `crate::integrity`. Defaults to CRASH_SIGNING_KEY.
+    pub fn signing_key(mut self, key: impl Into<String>) -> Self {
+        self.config.signing_key = Some(SigningKey::new(key));
+        self
+    }
+
+    /// Sends reports (and minidumps) to the crash server at `url`.
+    pub fn upload_url(mut self, url: impl Into<String>) -> Self {
+        self.config.upload_url = Some(url.into());
+        self
+    }
+
+    pub fn upload_minidump(mut self, enabled: bool) -> Self {
+        self.config.upload_minidump = enabled;
+        self
+    }
+
+    pub fn sentry_dsn(mut self, dsn: impl Into<String>) -> Self {
+        self.config.sentry_dsn = Some(dsn.into());
+        self
+    }
+
+    pub fn upload_timeout(mut self, timeout: Duration) -> Self {
+        self.config.upload_timeout = timeout;
+        self
+    }
+
+    /// How long the guard returned by `init_guarded` waits for background
+    /// deliveries when it is dropped.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.config.shutdown_timeout = timeout;
+        self
+    }
+
+    pub fn write_local(mut self, enabled: bool) -> Self {
+        self.config.write_local = enabled;
+        self
+    }
+
+    /// Keeps at most `count` reports whose upload failed, to send them again
+    /// on the next run. 0 disables the queue.
+    pub fn max_queued_reports(mut self, count: usize) -> Self {
+        self.config.max_queued_reports = count;
+        self
+    }
+
+    pub fn deferred(mut self, enabled: bool) -> Self {
+        self.config.deferred = enabled;
+        self
+    }
+
+    /// Captures panics without allocating, for crashes caused by allocator
+    /// corruption or exhaustion: implies `deferred`, with every buffer of the
+    /// hook allocated by `init`, see `crate::deferred`. The previous hook
+    /// allocates, so `chain_previous_hook(false)` goes with it.
+    pub fn preallocate(mut self, enabled: bool) -> Self {
+        self.config.preallocate = enabled;
+        self
+    }
+
+    /// Whether the panic hook that was installed before `init` (by default
+    /// the one printing the panic message) still runs, after ours.
+    pub fn chain_previous_hook(mut self, enabled: bool) -> Self {
+        self.config.chain_previous_hook = enabled;
+        self
+    }
+
+    pub fn native_crashes(mut self, enabled: bool) -> Self {
+        self.config.native_crashes = enabled;
+        self
+    }
+
+    pub fn watchdog(mut self, enabled: bool) -> Self {
+        self.config.watchdog = enabled;
+        self
+    }
+
+    /// Whether panic reports list the other threads too, with their stacks
+    /// on Linux. Without it only the panicking thread is listed.
+    pub fn capture_threads(mut self, enabled: bool) -> Self {
+        self.config.capture_threads = enabled;
+        self
+    }
+
+    /// Level of panic reports (default `fatal`).
+    pub fn panic_level(mut self, level: Level) -> Self {
+        self.config.panic_level = level;
+        self
+    }
+
+    /// Level of the reports of panics the process survives, caught by
+    /// `crash::catch_and_report` or an async runtime (default `error`).
+    pub fn caught_panic_level(mut self, level: Level) -> Self {
+        self.config.caught_panic_level = level;
+        self
+    }
+
+    /// Level of native crash reports, from the signal handlers or the
+    /// watchdog (default `fatal`).
+    pub fn signal_level(mut self, level: Level) -> Self {
+        self.config.signal_level = level;
+        self
+    }
+
+    /// Calls `callback` with every panic and the level it would be reported
+    /// at, and reports it at the level returned instead, e.g. to lower the
+    /// panics of a known flaky dependency by their payload or location. Runs
+    /// inside the panic hook, so it should not panic itself.
+    pub fn panic_level_with<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&PanicHookInfo<'_>, Level) -> Level + Send + Sync + 'static,
+    {
+        self.config.panic_level_with = Some(PanicLevel(Arc::new(callback)));
+        self
+    }
+
+    /// How stacks are walked, see `crate::unwind`. `Unwinder::Framehop`
+    /// needs the `framehop` feature and Linux on x86_64 or aarch64.
+    pub fn unwinder(mut self, unwinder: Unwinder) -> Self {
+        self.config.unwinder = unwinder;
+        self
+    }
+
+    /// Marks frames of the crate or module `prefix` (`my_app`,
+    /// `my_app::net`) as in-app; once given, no other frames are. May be given
+    /// several times.
+    pub fn in_app_include(mut self, prefix: impl Into<String>) -> Self {
+        self.config.in_app_include.push(prefix.into());
+        self
+    }
+
+    /// Never marks frames of the crate or module `prefix` as in-app, e.g. an
+    /// async runtime. May be given several times.
+    pub fn in_app_exclude(mut self, prefix: impl Into<String>) -> Self {
+        self.config.in_app_exclude.push(prefix.into());
+        self
+    }
+
+    pub fn trim_frames(mut self, enabled: bool) -> Self {
+        self.config.trim_frames = enabled;
+        self
+    }
+
+    /// Keeps at most `count` frames per stack, from both ends.
+    pub fn max_frames(mut self, count: usize) -> Self {
+        self.config.max_frames = Some(count);
+        self
+    }
+
+    /// Adds `lines` lines of source before and after the line of each in-app
+    /// frame when the sources are readable; 0 turns it off.
+    pub fn source_context(mut self, lines: usize) -> Self {
+        self.config.source_context = lines;
+        self
+    }
+
+    /// Directory relative source paths from the debug info are resolved
+    /// against, e.g. the workspace root.
+    pub fn source_root(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.source_root = Some(dir.into());
+        self
+    }
+
+    pub fn max_breadcrumbs(mut self, capacity: usize) -> Self {
+        self.config.max_breadcrumbs = capacity;
+        self
+    }
+
+    /// Writes at most `count` panic reports per minute; further panics are
+    /// only printed.
+    pub fn max_reports_per_minute(mut self, count: u32) -> Self {
+        self.config.max_reports_per_minute = Some(count);
+        self
+    }
+
+    /// Keeps only a `rate` fraction of panic reports, e.g. 0.25.
+    pub fn sample_rate(mut self, rate: f64) -> Self {
+        self.config.sample_rate = rate;
+        self
+    }
+
+    /// Keeps at most `count` crashes in the output directory.
+    pub fn max_reports(mut self, count: usize) -> Self {
+        self.config.max_reports = Some(count);
+        self
+    }
+
+    /// Removes crashes older than `age` from the output directory.
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.config.max_age = Some(age);
+        self
+    }
+
+    /// Keeps the crash files in the output directory under `bytes` in total.
+    pub fn max_total_bytes(mut self, bytes: u64) -> Self {
+        self.config.max_total_bytes = Some(bytes);
+        self
+    }
+
+    pub fn capture_env(mut self, enabled: bool) -> Self {
+        self.config.capture_env = enabled;
+        self
+    }
+
+    /// Only capture environment variables matching `pattern`, e.g. `RUST_*`.
+    /// May be given several times.
+    pub fn env_allow(mut self, pattern: impl Into<String>) -> Self {
+        self.config.env_allowlist.push(pattern.into());
+        self
+    }
+
+    /// Never capture environment variables matching `pattern`, e.g. `AWS_*`.
+    /// May be given several times.
+    pub fn env_deny(mut self, pattern: impl Into<String>) -> Self {
+        self.config.env_denylist.push(pattern.into());
+        self
+    }
+
+    /// Attaches the command-line arguments, with secrets masked, as the
+    /// `process` context. On by default.
+    pub fn capture_args(mut self, enabled: bool) -> Self {
+        self.config.capture_args = enabled;
+        self
+    }
+
+    /// Masks the value of options matching `pattern`, e.g. `--db-*`, in the
+    /// captured arguments. May be given several times.
+    pub fn secret_arg(mut self, pattern: impl Into<String>) -> Self {
+        self.config.secret_args.push(pattern.into());
+        self
+    }
+
+    /// Replaces email addresses, IP addresses and credit card numbers in
+    /// reports by `[Filtered]`.
+    pub fn scrub_pii(mut self, enabled: bool) -> Self {
+        self.config.scrub_pii = enabled;
+        self
+    }
+
+    /// Also scrubs matches of the regex `pattern`; enables scrubbing. An
+    /// invalid pattern makes `init` fail. May be given several times.
+    pub fn scrub_rule(mut self, pattern: impl Into<String>) -> Self {
+        self.config.scrub_pii = true;
+        self.config.scrub_rules.push(pattern.into());
+        self
+    }
+
+    /// Attaches the last `bytes` written to stdout and stderr to panic
+    /// reports, see `crate::output_capture`. Unix only; ignored elsewhere.
+    pub fn capture_output(mut self, bytes: usize) -> Self {
+        self.config.capture_output = bytes;
+        self
+    }
+
+    /// Files a report when the resident set size goes above `bytes`, see
+    /// `crate::oom`. Linux and macOS; `init` fails elsewhere.
+    pub fn memory_limit(mut self, bytes: u64) -> Self {
+        self.config.memory_limit = Some(bytes);
+        self
+    }
+
+    /// How long a thread may wait for a lock of `crate::deadlock` before the
+    /// wait is reported (default 30 s). Only with the `deadlock` feature.
+    pub fn deadlock_timeout(mut self, timeout: Duration) -> Self {
+        self.config.deadlock_timeout = timeout;
+        self
+    }
+
+    /// Files an `app_hang` report when `crash::heartbeat` is not called for
+    /// longer than `timeout`, see `crate::hang`.
+    pub fn hang_timeout(mut self, timeout: Duration) -> Self {
+        self.config.hang_timeout = Some(timeout);
+        self
+    }
+
+    /// Starts a session when the crash handler is installed, ended when the
+    /// process exits, see `crate::session`. Not on wasm32.
+    pub fn auto_session(mut self, enabled: bool) -> Self {
+        self.config.auto_session = enabled;
+        self
+    }
+
+    /// Event ids are UUIDv7 by default, so reports sort by id in the order
+    /// they were captured; `false` makes them random v4 ids as before.
+    pub fn sortable_event_ids(mut self, enabled: bool) -> Self {
+        self.config.sortable_event_ids = enabled;
+        self
+    }
+
+    /// Detects crash loops, see `crate::crash_loop`: `threshold` runs in a row
+    /// that crashed within `window` of starting. Not on wasm32.
+    pub fn crash_loop(mut self, threshold: u32, window: Duration) -> Self {
+        self.config.crash_loop_threshold = Some(threshold);
+        self.config.crash_loop_window = window;
+        self
+    }
+
+    /// In a crash loop, only reports from the run that detected it.
+    pub fn crash_loop_throttle(mut self, enabled: bool) -> Self {
+        self.config.crash_loop_throttle = enabled;
+        self
+    }
+
+    /// Serves the crash counters in the Prometheus text format at
+    /// `GET /metrics` on `addr`, e.g. `127.0.0.1:9464`, see `crate::metrics`.
+    /// `init` fails if it cannot listen there. Not on wasm32.
+    pub fn metrics_addr(mut self, addr: impl Into<String>) -> Self {
+        self.config.metrics_addr = Some(addr.into());
+        self
+    }
+
+    /// Pushes the crash counters to the Prometheus Pushgateway at `url` at
+    /// `init` and after each report. Not on wasm32.
+    pub fn metrics_push_url(mut self, url: impl Into<String>) -> Self {
+        self.config.metrics_push_url = Some(url.into());
+        self
+    }
+
+    pub fn helper_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.helper_path = Some(path.into());
+        self
+    }
+
+    /// Calls `callback` with every panic event right before it is written or
+    /// uploaded. It can enrich or redact the event, or veto it by returning
+    /// `None`. Runs inside the panic hook, so it should not panic itself;
+    /// native crash reports do not pass through it.
+    pub fn before_send<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(SentryEvent) -> Option<SentryEvent> + Send + Sync + 'static,
+    {
+        self.config.before_send = Some(BeforeSend(Arc::new(callback)));
+        self
+    }
+
+    /// Writes reports and attachments to `storage` instead of files in the
+    /// output directory. Minidumps and native crash reports are still files.
+    pub fn storage(mut self, storage: impl Storage + 'static) -> Self {
+        self.config.storage = Some(Arc::new(storage));
+        self
+    }
+
+    /// Also delivers reports through `transport`, after the crash server and
+    /// Sentry and before the local storage. Can be called several times.
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.config.transports.push(Arc::new(transport));
+        self
+    }
+
+    /// Whether `init` applies `crash.toml` and the CRASH_* environment
+    /// variables over this configuration, see `crate::settings`. On by
+    /// default; not on wasm32.
+    pub fn external_config(mut self, enabled: bool) -> Self {
+        self.config.external_config = enabled;
+        self
+    }
+
+    /// Reads the settings from `path` instead of `crash.toml`; `init` fails
+    /// if it cannot. CRASH_CONFIG still takes precedence.
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.config_file = Some(path.into());
+        self
+    }
+
+    /// Prints where each report went, and what dropped it, to stderr. Off by
+    /// default, so the library prints nothing but failures.
+    pub fn debug(mut self, enabled: bool) -> Self {
+        self.config.debug = enabled;
+        self
+    }
+
+    pub fn build(self) -> Config {
+        self.config
+    }
+
+    /// Builds the configuration and installs the crash handler.
+    pub fn init(self) -> std::io::Result<()> {
+        crate::init(self.config)
+    }
+
+    /// Like `init`, and returns a guard that waits up to `shutdown_timeout`
+    /// for background deliveries when dropped, see `crate::flush`. Not on
+    /// wasm32.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn init_guarded(self) -> std::io::Result<crate::flush::FlushGuard> {
+        crate::init(self.config)?;
+        // Settings from outside the code may have changed the timeout.
+        let timeout = crate::hook::config().map_or(Duration::from_secs(2), |config| config.shutdown_timeout);
+        Ok(crate::flush::FlushGuard::new(timeout))
+    }
+}
@@ -0,0 +1,137 @@
+// Device, OS, runtime and app context attached to every event.
+//
+// Collected once by `crash::init`, so capturing a crash never has to read
+// files or call into the OS for it. The layout follows Sentry's `contexts`
+// (`os`, `device`, `runtime`, `app`), which the server displays as is, plus
+// `process` with the command line (see `crate::arguments`), `environment`
+// when environment capture is enabled and `browser` on wasm32.
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use serde_json::{json, Value};
+
+use crate::config::Config;
+
+// `rustc --version` of the compiler that built this crate, set by build.rs.
+const RUSTC_VERSION: &str = env!("CRASH_RUSTC_VERSION");
+
+static CONTEXTS: OnceLock<BTreeMap<String, Value>> = OnceLock::new();
+
+// NAME and VERSION_ID from os-release(5), e.g. ("Ubuntu", "22.04").
+#[cfg(target_os = "linux")]
+fn os_release() -> (Option<String>, Option<String>) {
+    let Ok(data) = std::fs::read_to_string("/etc/os-release")
+        .or_else(|_| std::fs::read_to_string("/usr/lib/os-release"))
+    else {
+        return (None, None);
+    };
+    let field = |key: &str| {
+        data.lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .map(|value| value.trim_matches('"').to_string())
+    };
+    (field("NAME"), field("VERSION_ID"))
+}
+
+// Kernel release and version as reported by uname(2).
+#[cfg(unix)]
+fn uname() -> Option<(String, String)> {
+    // SAFETY: `utsname` is plain data filled in by the call.
+    let mut name: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut name) } != 0 {
+        return None;
+    }
+    let field = |chars: &[libc::c_char]| {
+        // SAFETY: uname NUL-terminates every field.
+        unsafe { std::ffi::CStr::from_ptr(chars.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    Some((field(&name.release), field(&name.version)))
+}
+
+pub(crate) fn hostname() -> Option<String> {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+        // SAFETY: the length passed matches the buffer.
+        if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+            return None;
+        }
+        let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+        Some(String::from_utf8_lossy(&buf[..len]).into_owned()).filter(|h| !h.is_empty())
+    }
+    #[cfg(not(unix))]
+    {
+        std::env::var("COMPUTERNAME").ok()
+    }
+}
+
+fn os_context() -> Value {
+    #[cfg_attr(not(unix), allow(unused_mut))]
+    let mut os = json!({ "name": std::env::consts::OS });
+    #[cfg(target_os = "linux")]
+    {
+        let (name, version) = os_release();
+        if let Some(name) = name {
+            os["name"] = name.into();
+        }
+        if let Some(version) = version {
+            os["version"] = version.into();
+        }
+    }
+    #[cfg(unix)]
+    if let Some((release, version)) = uname() {
+        os["kernel_version"] = release.clone().into();
+        os["build"] = version.into();
+        // Without a distribution version, the kernel release is the best we have.
+        if os.get("version").is_none() {
+            os["version"] = release.into();
+        }
+    }
+    os
+}
+
+/// Collects the contexts for the application described by `config`.
+pub fn collect(config: &Config) -> BTreeMap<String, Value> {
+    let mut contexts = BTreeMap::new();
+    contexts.insert("os".to_string(), os_context());
+    contexts.insert(
+        "device".to_string(),
+        json!({ "arch": std::env::consts::ARCH, "name": hostname() }),
+    );
+    contexts.insert(
+        "runtime".to_string(),
+        json!({
+            "name": "rustc",
+            "version": RUSTC_VERSION.split_whitespace().nth(1),
+            "raw_description": RUSTC_VERSION,
+        }),
+    );
+    contexts.insert(
+        "app".to_string(),
+        json!({ "app_name": config.app_name, "app_version": config.app_version }),
+    );
+    #[cfg(target_arch = "wasm32")]
+    if let Some(user_agent) = crate::web::user_agent() {
+        contexts.insert("browser".to_string(), json!({ "user_agent": user_agent }));
+    }
+    if let Some(environment) = crate::environment::capture(config) {
+        contexts.insert("environment".to_string(), json!(environment));
+    }
+    if let Some(argv) = crate::arguments::capture(config) {
+        contexts.insert("process".to_string(), json!({ "argv": argv }));
+    }
+    contexts
+}
+
+/// Collects the contexts once; later calls keep the first result.
+pub fn init(config: &Config) {
+    CONTEXTS.get_or_init(|| collect(config));
+}
+
+/// The contexts collected by `init`, empty if it did not run.
+pub fn get() -> BTreeMap<String, Value> {
+    CONTEXTS.get().cloned().unwrap_or_default()
+}
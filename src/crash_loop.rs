@@ -0,0 +1,165 @@
+// Crash-loop detection.
+//
+// An application that crashes right after starting, restarted by a service
+// manager each time, files the same report over and over and never gets to
+// do anything else. With `Builder::crash_loop(threshold, window)`,
+// `crash::init` notes the start of the process in `crash_loop.json` in the
+// output directory and takes the note back once the process has run for
+// `window`, or when it exits normally before that. The notes earlier runs
+// left behind are runs that ended within `window` of starting in a panic, a
+// native crash, an abort or a kill. When `init` finds `threshold` of them or
+// more, the process is in a crash loop: `crash::in_crash_loop` returns true,
+// so the application can start in a safe mode, and the reports of the run are
+// tagged `crash_loop: true` (native crash reports are not). With
+// `Builder::crash_loop_throttle` only the run that detects the loop reports;
+// the runs after it in the same loop drop their reports. A run that gets past
+// `window` ends the loop and clears the notes of the runs before it.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Once, OnceLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+pub const STATE_FILE: &str = "crash_loop.json";
+
+/// The start of a run that has not yet got past the window.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Start {
+    pid: u32,
+    started: f64, // Seconds since the UNIX epoch
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    starts: Vec<Start>,
+}
+
+// What `start` found, for the rest of the run.
+struct Detection {
+    path: PathBuf,
+    own: Start,
+    crashes: u32,
+    threshold: u32,
+}
+
+static DETECTION: OnceLock<Detection> = OnceLock::new();
+// Set by a fatal panic, so the note stays for the next run.
+static CRASHED: AtomicBool = AtomicBool::new(false);
+static SETTLED: AtomicBool = AtomicBool::new(false);
+
+fn read(path: &Path) -> State {
+    std::fs::read(path)
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default()
+}
+
+fn write(path: &Path, state: &State) -> std::io::Result<()> {
+    if state.starts.is_empty() {
+        return match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    let data = serde_json::to_vec(state).map_err(std::io::Error::other)?;
+    std::fs::write(path, data)
+}
+
+/// Counts the early crashes of the runs before this one and notes the start
+/// of this one. Called by `crash::init` when crash-loop detection is on.
+pub(crate) fn start(config: &Config) -> std::io::Result<()> {
+    let Some(threshold) = config.crash_loop_threshold else {
+        return Ok(());
+    };
+    let path = config.output_dir.join(STATE_FILE);
+    let mut state = read(&path);
+    // Instances running next to us have not crashed, or not yet.
+    let crashes = state
+        .starts
+        .iter()
+        .filter(|start| !crate::session::is_running(start.pid))
+        .count() as u32;
+    let own = Start {
+        pid: std::process::id(),
+        started: crate::clock::since_epoch().as_secs_f64(),
+    };
+    state.starts.push(own.clone());
+    write(&path, &state)?;
+
+    let detection = Detection {
+        path,
+        own,
+        crashes,
+        threshold,
+    };
+    if DETECTION.set(detection).is_err() {
+        return Ok(());
+    }
+    if in_crash_loop() {
+        crate::scope::set_tag("crash_loop", "true");
+    }
+
+    static AT_EXIT: Once = Once::new();
+    AT_EXIT.call_once(|| unsafe {
+        libc::atexit(settle_at_exit);
+    });
+    let window = config.crash_loop_window;
+    std::thread::Builder::new()
+        .name("crash-loop".to_string())
+        .spawn(move || {
+            std::thread::sleep(window);
+            settle();
+        })?;
+    Ok(())
+}
+
+// The run got past the window or exits normally: takes back its note and
+// those of the runs before it, which ends a loop.
+fn settle() {
+    let Some(detection) = DETECTION.get() else {
+        return;
+    };
+    if CRASHED.load(Ordering::Acquire) || SETTLED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let mut state = read(&detection.path);
+    state
+        .starts
+        .retain(|start| start.started > detection.own.started);
+    if let Err(e) = write(&detection.path, &state) {
+        eprintln!("Failed to update '{}': {}", detection.path.display(), e);
+    }
+}
+
+extern "C" fn settle_at_exit() {
+    settle();
+}
+
+/// Keeps the note of this run: a fatal panic ends it.
+pub(crate) fn mark_crashed() {
+    CRASHED.store(true, Ordering::Release);
+}
+
+/// Whether the runs before this one crashed soon after starting at least
+/// the configured number of times in a row. False when detection is off.
+pub fn in_crash_loop() -> bool {
+    DETECTION
+        .get()
+        .is_some_and(|detection| detection.crashes >= detection.threshold)
+}
+
+/// How many runs in a row before this one crashed soon after starting.
+pub fn crash_count() -> u32 {
+    DETECTION.get().map_or(0, |detection| detection.crashes)
+}
+
+/// Whether this run is in a crash loop another run has reported already.
+pub(crate) fn throttled(config: &Config) -> bool {
+    config.crash_loop_throttle
+        && DETECTION
+            .get()
+            .is_some_and(|detection| detection.crashes > detection.threshold)
+}
@@ -0,0 +1,698 @@
+// Deadlock detection for mutexes, behind the `deadlock` feature.
+//
+// `crash::deadlock::Mutex` stands in for `std::sync::Mutex`, and with the
+// `parking_lot` feature `crash::deadlock::parking_lot::Mutex` for
+// `parking_lot::Mutex`. They keep track of which thread holds each lock and
+// where it was taken, which lock each thread waits for, and in which order
+// locks are taken. Three problems are reported, each once, as events tagged
+// `deadlock`:
+//
+// - `cycle`: threads waiting for each other's locks, none of which can go
+//   on. The thread whose wait closes the cycle reports it at level `fatal`
+//   before it blocks for good, with the stacks of the others.
+// - `timeout`: a thread waiting longer than `Builder::deadlock_timeout`,
+//   found by a thread that checks every second. Reported at level `error`
+//   with the stacks of the waiting thread and of the one holding the lock.
+// - `lock_order`: locks taken in orders that contradict each other, e.g. B
+//   while holding A in one place and A while holding B in another. Two
+//   threads doing so at the same time deadlock; reported at level `warning`
+//   as soon as the second order is seen, whether it came to that or not.
+//
+// Stacks of other threads are sampled as for panic reports, so only on
+// Linux. Every lock and unlock updates shared bookkeeping under a global
+// lock, which makes the wrappers meant for tests and staging more than for
+// hot paths in production.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
+use std::time::{Duration, Instant};
+
+use crate::capture::Level;
+use crate::event::{SentryEvent, Thread};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Where a lock was taken or waited for.
+type Site = &'static Location<'static>;
+// Where a lock was taken, and where another was taken while holding it.
+type Order = (Site, Site);
+
+static NEXT_LOCK: AtomicUsize = AtomicUsize::new(1);
+static NEXT_THREAD: AtomicU64 = AtomicU64::new(1);
+static STATE: std::sync::Mutex<State> = std::sync::Mutex::new(State {
+    threads: BTreeMap::new(),
+    holders: BTreeMap::new(),
+    waits: BTreeMap::new(),
+    order: BTreeMap::new(),
+    cycles: BTreeSet::new(),
+    inversions: BTreeSet::new(),
+});
+
+// A thread that took a tracked lock.
+#[derive(Clone)]
+struct ThreadInfo {
+    os_id: Option<u64>,
+    name: Option<String>,
+}
+
+impl ThreadInfo {
+    fn label(&self) -> String {
+        match (&self.name, self.os_id) {
+            (Some(name), _) => name.clone(),
+            (None, Some(id)) => format!("thread {}", id),
+            (None, None) => "unknown thread".to_string(),
+        }
+    }
+}
+
+struct Holder {
+    thread: u64,
+    at: Site,
+}
+
+struct Wait {
+    lock: usize,
+    at: Site,
+    since: Instant,
+    reported: bool,
+}
+
+struct State {
+    threads: BTreeMap<u64, ThreadInfo>,
+    holders: BTreeMap<usize, Holder>, // By lock.
+    waits: BTreeMap<u64, Wait>,       // By thread.
+    // (held, taken): a lock taken while holding another, and where each of
+    // the two was taken the first time.
+    order: BTreeMap<(usize, usize), Order>,
+    // Locks of the cycles and of the inversions reported already.
+    cycles: BTreeSet<Vec<usize>>,
+    inversions: BTreeSet<Vec<usize>>,
+}
+
+// One thread of a cycle or a long wait: it waits at `waits_at` for a lock
+// that `holder` took at `held_at`.
+struct Link {
+    thread: ThreadInfo,
+    waits_at: Site,
+    holder: ThreadInfo,
+    held_at: Site,
+}
+
+fn state() -> std::sync::MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl State {
+    fn thread(&self, key: u64) -> ThreadInfo {
+        self.threads.get(&key).cloned().unwrap_or(ThreadInfo { os_id: None, name: None })
+    }
+
+    // The waits from `thread` on, if they lead back to it.
+    fn wait_cycle(&self, thread: u64) -> Option<(Vec<usize>, Vec<Link>)> {
+        let mut locks = Vec::new();
+        let mut links = Vec::new();
+        let mut current = thread;
+        loop {
+            let wait = self.waits.get(&current)?;
+            let holder = self.holders.get(&wait.lock)?;
+            locks.push(wait.lock);
+            links.push(Link {
+                thread: self.thread(current),
+                waits_at: wait.at,
+                holder: self.thread(holder.thread),
+                held_at: holder.at,
+            });
+            if holder.thread == thread {
+                return Some((locks, links));
+            }
+            // A cycle without this thread is someone else's to report.
+            if links.len() > self.waits.len() {
+                return None;
+            }
+            current = holder.thread;
+        }
+    }
+
+    // The orders leading from lock `from` to lock `to`, if any, each with
+    // the lock it leads to.
+    fn order_path(&self, from: usize, to: usize) -> Option<Vec<(usize, Order)>> {
+        let mut stack = vec![(from, Vec::new())];
+        let mut seen = BTreeSet::new();
+        while let Some((lock, path)) = stack.pop() {
+            if lock == to {
+                return Some(path);
+            }
+            if !seen.insert(lock) {
+                continue;
+            }
+            for (&(_, next), &order) in self.order.range((lock, 0)..=(lock, usize::MAX)) {
+                let mut path = path.clone();
+                path.push((next, order));
+                stack.push((next, path));
+            }
+        }
+        None
+    }
+}
+
+// Registers a thread on its first lock and forgets it when it exits.
+struct Registration(u64);
+
+impl Registration {
+    fn new() -> Self {
+        let key = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+        let current = crate::threads::current_thread(false);
+        let info = ThreadInfo {
+            os_id: current.id,
+            name: current.name,
+        };
+        state().threads.insert(key, info);
+        Registration(key)
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        state().threads.remove(&self.0);
+    }
+}
+
+thread_local! {
+    static THREAD: Registration = Registration::new();
+    // Tracked locks this thread holds, in the order it took them.
+    static HELD: RefCell<Vec<(usize, Site)>> = const { RefCell::new(Vec::new()) };
+    // Set while this thread files a report, so the locks taken on the way do
+    // not file another.
+    static REPORTING: Cell<bool> = const { Cell::new(false) };
+}
+
+// Key of the calling thread, 0 while it exits.
+fn this_thread() -> u64 {
+    THREAD.try_with(|registration| registration.0).unwrap_or(0)
+}
+
+// Number of a lock, assigned the first time it is taken so `new` can be const.
+struct LockId(AtomicUsize);
+
+impl LockId {
+    const fn new() -> Self {
+        LockId(AtomicUsize::new(0))
+    }
+
+    fn get(&self) -> usize {
+        let id = self.0.load(Ordering::Relaxed);
+        if id != 0 {
+            return id;
+        }
+        let new = NEXT_LOCK.fetch_add(1, Ordering::Relaxed);
+        match self.0.compare_exchange(0, new, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => new,
+            Err(id) => id,
+        }
+    }
+}
+
+impl Default for LockId {
+    fn default() -> Self {
+        LockId::new()
+    }
+}
+
+// Before a blocking lock of `lock` at `at`: records the order it is taken in
+// after the locks this thread holds, and reports contradicting orders.
+fn before_lock(lock: usize, at: Site) {
+    let held = HELD.try_with(|held| held.borrow().clone()).unwrap_or_default();
+    if held.is_empty() {
+        return;
+    }
+    let mut inversions = Vec::new();
+    {
+        let mut state = state();
+        for (first, first_at) in held {
+            if first == lock || state.order.contains_key(&(first, lock)) {
+                continue;
+            }
+            state.order.insert((first, lock), (first_at, at));
+            if let Some(path) = state.order_path(lock, first) {
+                let mut locks: Vec<usize> = path.iter().map(|&(lock, _)| lock).collect();
+                locks.push(lock);
+                locks.sort_unstable();
+                if state.inversions.insert(locks) {
+                    let mut orders = vec![(first_at, at)];
+                    orders.extend(path.into_iter().map(|(_, order)| order));
+                    inversions.push(orders);
+                }
+            }
+        }
+    }
+    for orders in inversions {
+        report_inversion(&orders);
+    }
+}
+
+// Marks the calling thread as blocked on a lock until dropped.
+struct Waiting;
+
+impl Waiting {
+    // Reports the cycle of waits this one closes, if any, before the caller
+    // blocks.
+    fn start(lock: usize, at: Site) -> Self {
+        let thread = this_thread();
+        let cycle = {
+            let mut state = state();
+            let wait = Wait {
+                lock,
+                at,
+                since: Instant::now(),
+                reported: false,
+            };
+            state.waits.insert(thread, wait);
+            state.wait_cycle(thread).and_then(|(mut locks, links)| {
+                locks.sort_unstable();
+                state.cycles.insert(locks).then_some(links)
+            })
+        };
+        if let Some(links) = cycle {
+            report_cycle(&links);
+        }
+        Waiting
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        let thread = this_thread();
+        state().waits.remove(&thread);
+    }
+}
+
+// Marks a lock as held by the calling thread until dropped.
+struct Held(usize);
+
+impl Held {
+    fn acquired(lock: usize, at: Site) -> Self {
+        let _ = HELD.try_with(|held| held.borrow_mut().push((lock, at)));
+        let thread = this_thread();
+        state().holders.insert(lock, Holder { thread, at });
+        Held(lock)
+    }
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        let lock = self.0;
+        let _ = HELD.try_with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(index) = held.iter().rposition(|&(id, _)| id == lock) {
+                held.remove(index);
+            }
+        });
+        let thread = this_thread();
+        let mut state = state();
+        if state.holders.get(&lock).is_some_and(|holder| holder.thread == thread) {
+            state.holders.remove(&lock);
+        }
+    }
+}
+
+fn site(at: Site) -> String {
+    format!("{}:{}:{}", at.file(), at.line(), at.column())
+}
+
+// `threads` other than the calling one, with their stacks.
+fn other_threads(threads: &[&ThreadInfo]) -> Vec<Thread> {
+    let current = crate::threads::current_thread(false).id;
+    let mut others: Vec<&ThreadInfo> = Vec::new();
+    for &thread in threads {
+        if thread.os_id != current && !others.iter().any(|other| other.os_id == thread.os_id) {
+            others.push(thread);
+        }
+    }
+    let ids: Vec<u64> = others.iter().filter_map(|thread| thread.os_id).collect();
+    let mut stacks = crate::threads::sample_stacks(&ids).into_iter();
+    others
+        .into_iter()
+        .map(|thread| Thread {
+            id: thread.os_id,
+            name: thread.name.clone(),
+            crashed: false,
+            current: false,
+            stacktrace: thread.os_id.and_then(|_| stacks.next().flatten()),
+        })
+        .collect()
+}
+
+fn links_json(links: &[Link]) -> serde_json::Value {
+    links
+        .iter()
+        .map(|link| {
+            serde_json::json!({
+                "thread": link.thread.label(),
+                "waits_at": site(link.waits_at),
+                "held_by": link.holder.label(),
+                "held_at": site(link.held_at),
+            })
+        })
+        .collect()
+}
+
+// Files a `deadlock` report of `kind`, unless the calling thread is filing
+// one already.
+fn report(level: Level, kind: &str, message: String, complete: impl FnOnce(&mut SentryEvent)) {
+    if REPORTING.try_with(|reporting| reporting.replace(true)).unwrap_or(true) {
+        return;
+    }
+    crate::capture::capture(|event_id, timestamp| {
+        let mut event = crate::hook::base_event(event_id, timestamp, level.as_str(), message);
+        event.tags.insert("deadlock".to_string(), kind.to_string());
+        complete(&mut event);
+        event
+    });
+    let _ = REPORTING.try_with(|reporting| reporting.set(false));
+}
+
+fn report_cycle(links: &[Link]) {
+    let message = if links.len() == 1 {
+        "Deadlock: a thread waits for a lock it holds".to_string()
+    } else {
+        format!("Deadlock: {} threads wait for each other's locks", links.len())
+    };
+    report(Level::Fatal, "cycle", message, |event| {
+        for thread in &mut event.threads {
+            thread.crashed = true;
+        }
+        let threads: Vec<&ThreadInfo> = links.iter().map(|link| &link.thread).collect();
+        event.threads.extend(other_threads(&threads));
+        event.extra.insert("deadlock".to_string(), links_json(links));
+    });
+}
+
+fn report_inversion(orders: &[Order]) {
+    let message = format!("Lock order inversion: {} locks taken in contradicting orders", orders.len());
+    report(Level::Warning, "lock_order", message, |event| {
+        let orders = orders
+            .iter()
+            .map(|&(held_at, taken_at)| serde_json::json!({ "held_at": site(held_at), "then_taken_at": site(taken_at) }))
+            .collect();
+        event.extra.insert("lock_order".to_string(), serde_json::Value::Array(orders));
+    });
+}
+
+// Reported from the monitor thread, so the stacks of interest are the
+// sampled ones; the waiting thread's is the event's.
+fn report_timeout(link: &Link, timeout: Duration) {
+    let message = format!("Deadlock: a thread waited more than {} s for a lock", timeout.as_secs());
+    report(Level::Error, "timeout", message, |event| {
+        let mut threads = other_threads(&[&link.thread, &link.holder]);
+        if let Some(waiting) = threads.first_mut().filter(|thread| thread.id == link.thread.os_id) {
+            waiting.crashed = true;
+        }
+        event.stacktrace = threads.first().and_then(|thread| thread.stacktrace.clone());
+        event.threads = threads;
+        event.extra.insert("deadlock".to_string(), links_json(std::slice::from_ref(link)));
+    });
+}
+
+/// Starts the thread that reports waits for a tracked lock longer than
+/// `timeout`. Reports go through the installed crash handler.
+pub fn spawn_monitor(timeout: Duration) -> std::io::Result<()> {
+    std::thread::Builder::new()
+        .name("crash-deadlock-monitor".to_string())
+        .spawn(move || monitor(timeout))?;
+    Ok(())
+}
+
+fn monitor(timeout: Duration) {
+    loop {
+        std::thread::sleep(CHECK_INTERVAL);
+        let overdue: Vec<Link> = {
+            let mut state = state();
+            let mut overdue = Vec::new();
+            let threads: Vec<u64> = state.waits.keys().copied().collect();
+            for thread in threads {
+                let Some(wait) = state.waits.get(&thread).filter(|w| !w.reported && w.since.elapsed() > timeout) else {
+                    continue;
+                };
+                let (lock, waits_at) = (wait.lock, wait.at);
+                let Some(holder) = state.holders.get(&lock) else {
+                    continue;
+                };
+                // Part of a cycle, which was reported as such.
+                let in_cycle = state.wait_cycle(thread).is_some_and(|(mut locks, _)| {
+                    locks.sort_unstable();
+                    state.cycles.contains(&locks)
+                });
+                if !in_cycle {
+                    overdue.push(Link {
+                        thread: state.thread(thread),
+                        waits_at,
+                        holder: state.thread(holder.thread),
+                        held_at: holder.at,
+                    });
+                }
+                if let Some(wait) = state.waits.get_mut(&thread) {
+                    wait.reported = true;
+                }
+            }
+            overdue
+        };
+        for link in &overdue {
+            report_timeout(link, timeout);
+        }
+    }
+}
+
+fn map_guard<G, U>(result: LockResult<G>, wrap: impl FnOnce(G) -> U) -> LockResult<U> {
+    match result {
+        Ok(guard) => Ok(wrap(guard)),
+        Err(poisoned) => Err(PoisonError::new(wrap(poisoned.into_inner()))),
+    }
+}
+
+/// `std::sync::Mutex` with deadlock detection, see `crate::deadlock`.
+#[derive(Default)]
+pub struct Mutex<T: ?Sized> {
+    id: LockId,
+    inner: std::sync::Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Mutex {
+            id: LockId::new(),
+            inner: std::sync::Mutex::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> LockResult<T> {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Like `std::sync::Mutex::lock`; reports the deadlock waiting here would
+    /// complete before it blocks.
+    #[track_caller]
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        let (id, at) = (self.id.get(), Location::caller());
+        before_lock(id, at);
+        let result = match self.inner.try_lock() {
+            Ok(guard) => Ok(guard),
+            Err(TryLockError::Poisoned(poisoned)) => Err(poisoned),
+            Err(TryLockError::WouldBlock) => {
+                let _waiting = Waiting::start(id, at);
+                self.inner.lock()
+            }
+        };
+        map_guard(result, |inner| MutexGuard {
+            _held: Held::acquired(id, at),
+            inner,
+        })
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        let (id, at) = (self.id.get(), Location::caller());
+        let wrap = |inner| MutexGuard {
+            _held: Held::acquired(id, at),
+            inner,
+        };
+        match self.inner.try_lock() {
+            Ok(inner) => Ok(wrap(inner)),
+            Err(TryLockError::Poisoned(poisoned)) => Err(TryLockError::Poisoned(PoisonError::new(wrap(poisoned.into_inner())))),
+            Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.is_poisoned()
+    }
+
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.inner.get_mut()
+    }
+}
+
+impl<T> From<T> for Mutex<T> {
+    fn from(value: T) -> Self {
+        Mutex::new(value)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+/// Guard of a `Mutex`, see `std::sync::MutexGuard`.
+pub struct MutexGuard<'a, T: ?Sized> {
+    // Dropped first, so the lock is no longer marked as held once it is free.
+    _held: Held,
+    inner: std::sync::MutexGuard<'a, T>,
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+/// `parking_lot::Mutex` with deadlock detection.
+#[cfg(feature = "parking_lot")]
+pub mod parking_lot {
+    use std::fmt;
+    use std::ops::{Deref, DerefMut};
+    use std::panic::Location;
+
+    use super::{before_lock, Held, LockId, Waiting};
+
+    /// `parking_lot::Mutex` with deadlock detection, see `crate::deadlock`.
+    #[derive(Default)]
+    pub struct Mutex<T: ?Sized> {
+        id: LockId,
+        inner: ::parking_lot::Mutex<T>,
+    }
+
+    impl<T> Mutex<T> {
+        pub const fn new(value: T) -> Self {
+            Mutex {
+                id: LockId::new(),
+                inner: ::parking_lot::Mutex::new(value),
+            }
+        }
+
+        pub fn into_inner(self) -> T {
+            self.inner.into_inner()
+        }
+    }
+
+    impl<T: ?Sized> Mutex<T> {
+        /// Like `parking_lot::Mutex::lock`; reports the deadlock waiting here
+        /// would complete before it blocks.
+        #[track_caller]
+        pub fn lock(&self) -> MutexGuard<'_, T> {
+            let (id, at) = (self.id.get(), Location::caller());
+            before_lock(id, at);
+            let inner = match self.inner.try_lock() {
+                Some(guard) => guard,
+                None => {
+                    let _waiting = Waiting::start(id, at);
+                    self.inner.lock()
+                }
+            };
+            MutexGuard {
+                _held: Held::acquired(id, at),
+                inner,
+            }
+        }
+
+        #[track_caller]
+        pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+            let (id, at) = (self.id.get(), Location::caller());
+            let inner = self.inner.try_lock()?;
+            Some(MutexGuard {
+                _held: Held::acquired(id, at),
+                inner,
+            })
+        }
+
+        pub fn is_locked(&self) -> bool {
+            self.inner.is_locked()
+        }
+
+        pub fn get_mut(&mut self) -> &mut T {
+            self.inner.get_mut()
+        }
+    }
+
+    impl<T> From<T> for Mutex<T> {
+        fn from(value: T) -> Self {
+            Mutex::new(value)
+        }
+    }
+
+    impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.inner.fmt(f)
+        }
+    }
+
+    /// Guard of a `Mutex`, see `parking_lot::MutexGuard`.
+    pub struct MutexGuard<'a, T: ?Sized> {
+        // Dropped first, so the lock is no longer marked as held once it is free.
+        _held: Held,
+        inner: ::parking_lot::MutexGuard<'a, T>,
+    }
+
+    impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.inner
+        }
+    }
+
+    impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            &mut self.inner
+        }
+    }
+
+    impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.inner.fmt(f)
+        }
+    }
+
+    impl<T: ?Sized + fmt::Display> fmt::Display for MutexGuard<'_, T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.inner.fmt(f)
+        }
+    }
+}
@@ -0,0 +1,425 @@
+// Debug identifiers of the loaded modules.
+//
+// A report only carries raw addresses for frames that could not be resolved
+// in-process (and native crash reports carry nothing else). To symbolicate
+// them later against uploaded symbols, the server needs to know which build
+// of which module was mapped where. `collect` lists the loaded modules with
+// their load address, size and identifiers in Sentry's `debug_meta` format:
+//
+// - Linux: every ELF file mapped into the process, from `/proc/self/maps`,
+//   with its GNU build-id; the modules from `dl_iterate_phdr` when /proc is
+//   not mounted.
+// - macOS: every dyld image, with its `LC_UUID`.
+// - Windows: the main executable, with the GUID and age of its PDB.
+
+use crate::event::DebugMeta;
+
+// Sentry's debug id for an ELF build-id: its first 16 bytes as a GUID, whose
+// first three fields are little-endian.
+#[cfg(target_os = "linux")]
+fn elf_debug_id(build_id: &[u8]) -> String {
+    let mut bytes = [0u8; 16];
+    let len = build_id.len().min(16);
+    bytes[..len].copy_from_slice(&build_id[..len]);
+    bytes[..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+    uuid::Uuid::from_bytes(bytes).to_string()
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::{elf_debug_id, hex};
+    use crate::event::DebugImage;
+
+    const NT_GNU_BUILD_ID: u32 = 3;
+
+    // The GNU build-id in the note segment at `data`.
+    fn build_id(data: &[u8], align: usize) -> Option<Vec<u8>> {
+        let align_up = |n: usize| (n + align - 1) & !(align - 1);
+        let word = |at: usize| Some(u32::from_ne_bytes(data.get(at..at + 4)?.try_into().ok()?));
+        let mut at = 0;
+        while at + 12 <= data.len() {
+            let name_size = word(at)? as usize;
+            let desc_size = word(at + 4)? as usize;
+            let note_type = word(at + 8)?;
+            let name_start = at + 12;
+            let desc_start = name_start + align_up(name_size);
+            let desc = data.get(desc_start..desc_start + desc_size)?;
+            if note_type == NT_GNU_BUILD_ID && data.get(name_start..name_start + name_size) == Some(b"GNU\0") {
+                return Some(desc.to_vec());
+            }
+            at = desc_start + align_up(desc_size);
+        }
+        None
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    type Ehdr = libc::Elf64_Ehdr;
+    #[cfg(target_pointer_width = "64")]
+    type Phdr = libc::Elf64_Phdr;
+    #[cfg(target_pointer_width = "32")]
+    type Ehdr = libc::Elf32_Ehdr;
+    #[cfg(target_pointer_width = "32")]
+    type Phdr = libc::Elf32_Phdr;
+
+    // Describes the module loaded at `base` with the program headers
+    // `headers`. Notes are only read where `readable` says they are mapped.
+    //
+    // SAFETY: `readable` must only accept ranges that are mapped readable.
+    unsafe fn describe(
+        base: usize,
+        headers: &[Phdr],
+        code_file: Option<String>,
+        readable: impl Fn(usize, usize) -> bool,
+    ) -> Option<DebugImage> {
+        let mut start = usize::MAX;
+        let mut end = 0;
+        let mut build = None;
+        for header in headers {
+            let vaddr = header.p_vaddr as usize;
+            let size = header.p_memsz as usize;
+            if header.p_type == libc::PT_LOAD {
+                start = start.min(vaddr);
+                end = end.max(vaddr.saturating_add(size));
+            } else if header.p_type == libc::PT_NOTE && build.is_none() && readable(base.wrapping_add(vaddr), size) {
+                let notes = unsafe { std::slice::from_raw_parts(base.wrapping_add(vaddr) as *const u8, size) };
+                build = build_id(notes, (header.p_align as usize).max(4));
+            }
+        }
+        if start > end {
+            return None;
+        }
+        Some(DebugImage {
+            image_type: "elf",
+            code_file,
+            code_id: build.as_deref().map(hex),
+            debug_id: build.as_deref().map(elf_debug_id),
+            debug_file: None,
+            image_addr: format!("{:#x}", base.wrapping_add(start)),
+            image_size: (end - start) as u64,
+        })
+    }
+
+    unsafe extern "C" fn visit(info: *mut libc::dl_phdr_info, _size: libc::size_t, data: *mut libc::c_void) -> libc::c_int {
+        // SAFETY: `data` is the vector passed to `dl_iterate_phdr` below, and
+        // the loader keeps `info` and the segments it describes mapped for
+        // the duration of the callback.
+        let images = unsafe { &mut *(data as *mut Vec<DebugImage>) };
+        let info = unsafe { &*info };
+        if info.dlpi_phdr.is_null() {
+            return 0;
+        }
+        let headers = unsafe { std::slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize) };
+
+        // The main executable has an empty name.
+        let name = if info.dlpi_name.is_null() {
+            String::new()
+        } else {
+            unsafe { std::ffi::CStr::from_ptr(info.dlpi_name) }.to_string_lossy().into_owned()
+        };
+        let code_file = if name.is_empty() {
+            std::env::current_exe().ok().map(|p| p.to_string_lossy().into_owned())
+        } else {
+            Some(name)
+        };
+        images.extend(unsafe { describe(info.dlpi_addr as usize, headers, code_file, |_, _| true) });
+        0
+    }
+
+    // The modules the dynamic loader knows of.
+    fn from_loader() -> Vec<DebugImage> {
+        let mut images: Vec<DebugImage> = Vec::new();
+        // SAFETY: `visit` only uses `data` as the vector it is given here.
+        unsafe { libc::dl_iterate_phdr(Some(visit), &mut images as *mut Vec<DebugImage> as *mut libc::c_void) };
+        images
+    }
+
+    // A file-backed mapping from /proc/self/maps.
+    struct Mapping {
+        start: usize,
+        end: usize,
+        readable: bool,
+        offset: u64,
+        path: String,
+    }
+
+    // Parses `start-end perms offset dev inode path`; the path may contain
+    // spaces.
+    fn parse_mapping(line: &str) -> Option<Mapping> {
+        let mut fields = line.splitn(6, ' ');
+        let (start, end) = fields.next()?.split_once('-')?;
+        let perms = fields.next()?;
+        let offset = u64::from_str_radix(fields.next()?, 16).ok()?;
+        let path = fields.nth(2)?.trim_start();
+        let path = path.strip_suffix(" (deleted)").unwrap_or(path);
+        if !path.starts_with('/') && path != "[vdso]" {
+            return None;
+        }
+        Some(Mapping {
+            start: usize::from_str_radix(start, 16).ok()?,
+            end: usize::from_str_radix(end, 16).ok()?,
+            readable: perms.starts_with('r'),
+            offset,
+            path: path.to_string(),
+        })
+    }
+
+    // The module whose ELF header is mapped at the start of `mappings[0]`,
+    // the other mappings being the rest of the same file.
+    fn describe_mapped(mappings: &[Mapping]) -> Option<DebugImage> {
+        let first = mappings.first()?;
+        let readable = |at: usize, size: usize| {
+            mappings
+                .iter()
+                .any(|m| m.readable && m.start <= at && at.checked_add(size).is_some_and(|end| end <= m.end))
+        };
+        if !readable(first.start, std::mem::size_of::<Ehdr>()) {
+            return None;
+        }
+        // SAFETY: the header lies in a readable mapping, checked above.
+        let header = unsafe { std::ptr::read_unaligned(first.start as *const Ehdr) };
+        if header.e_ident[..4] != *b"\x7fELF" || header.e_phentsize as usize != std::mem::size_of::<Phdr>() {
+            return None;
+        }
+        let phdrs_at = first.start.checked_add(header.e_phoff as usize)?;
+        let phdrs_size = header.e_phnum as usize * std::mem::size_of::<Phdr>();
+        if !readable(phdrs_at, phdrs_size) || phdrs_at % std::mem::align_of::<Phdr>() != 0 {
+            return None;
+        }
+        // SAFETY: as above, and aligned.
+        let headers = unsafe { std::slice::from_raw_parts(phdrs_at as *const Phdr, header.e_phnum as usize) };
+        // The file is mapped from offset 0 at `first.start`; the lowest
+        // segment tells which address that corresponds to.
+        let lowest = headers
+            .iter()
+            .filter(|h| h.p_type == libc::PT_LOAD)
+            .min_by_key(|h| h.p_vaddr)?;
+        let base = first.start.checked_sub(lowest.p_vaddr.checked_sub(lowest.p_offset)? as usize)?;
+        let code_file = Some(first.path.clone());
+        // SAFETY: `readable` only accepts ranges in readable mappings.
+        unsafe { describe(base, headers, code_file, readable) }
+    }
+
+    // Every ELF file mapped into the process, or `None` if /proc is not
+    // available.
+    fn from_maps() -> Option<Vec<DebugImage>> {
+        let maps = std::fs::read_to_string("/proc/self/maps").ok()?;
+        let mappings: Vec<Mapping> = maps.lines().filter_map(parse_mapping).collect();
+        let mut images = Vec::new();
+        let mut at = 0;
+        while at < mappings.len() {
+            // A module starts with the mapping of offset 0 of its file and
+            // goes on while the next mappings are of the same file.
+            let path = &mappings[at].path;
+            let len = 1 + mappings[at + 1..]
+                .iter()
+                .take_while(|m| m.path == *path && m.offset != 0)
+                .count();
+            if mappings[at].offset == 0 {
+                images.extend(describe_mapped(&mappings[at..at + len]));
+            }
+            at += len;
+        }
+        Some(images)
+    }
+
+    pub fn images() -> Vec<DebugImage> {
+        // The maps also list modules mapped without the dynamic loader, and
+        // reading them does not take the loader lock, which the crashing
+        // thread may hold.
+        match from_maps() {
+            Some(images) if !images.is_empty() => images,
+            _ => from_loader(),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::hex;
+    use crate::event::DebugImage;
+
+    const MH_MAGIC_64: u32 = 0xfeed_facf;
+    const LC_SEGMENT_64: u32 = 0x19;
+    const LC_UUID: u32 = 0x1b;
+    const MACH_HEADER_64_SIZE: usize = 32;
+
+    extern "C" {
+        fn _dyld_image_count() -> u32;
+        fn _dyld_get_image_header(index: u32) -> *const u8;
+        fn _dyld_get_image_name(index: u32) -> *const libc::c_char;
+    }
+
+    unsafe fn read_u32(at: *const u8) -> u32 {
+        unsafe { std::ptr::read_unaligned(at as *const u32) }
+    }
+
+    unsafe fn read_u64(at: *const u8) -> u64 {
+        unsafe { std::ptr::read_unaligned(at as *const u64) }
+    }
+
+    // The UUID and mapped size from the load commands of the image at `header`.
+    unsafe fn parse(header: *const u8) -> Option<(Option<[u8; 16]>, u64)> {
+        if unsafe { read_u32(header) } != MH_MAGIC_64 {
+            return None;
+        }
+        let commands = unsafe { read_u32(header.add(16)) };
+        let mut command = unsafe { header.add(MACH_HEADER_64_SIZE) };
+        let mut uuid = None;
+        let mut size = 0;
+        for _ in 0..commands {
+            let (kind, command_size) = unsafe { (read_u32(command), read_u32(command.add(4))) };
+            match kind {
+                LC_UUID => {
+                    let mut bytes = [0u8; 16];
+                    unsafe { std::ptr::copy_nonoverlapping(command.add(8), bytes.as_mut_ptr(), 16) };
+                    uuid = Some(bytes);
+                }
+                LC_SEGMENT_64 => {
+                    let name = unsafe { std::slice::from_raw_parts(command.add(8), 16) };
+                    if !name.starts_with(b"__PAGEZERO") {
+                        size += unsafe { read_u64(command.add(32)) };
+                    }
+                }
+                _ => {}
+            }
+            if command_size == 0 {
+                break;
+            }
+            command = unsafe { command.add(command_size as usize) };
+        }
+        Some((uuid, size))
+    }
+
+    pub fn images() -> Vec<DebugImage> {
+        let mut images = Vec::new();
+        // SAFETY: dyld keeps headers and names valid while the image is
+        // loaded; images are not unloaded while we look at them in practice.
+        for index in 0..unsafe { _dyld_image_count() } {
+            let header = unsafe { _dyld_get_image_header(index) };
+            if header.is_null() {
+                continue;
+            }
+            let Some((uuid, size)) = (unsafe { parse(header) }) else {
+                continue;
+            };
+            let name = unsafe { _dyld_get_image_name(index) };
+            let code_file = (!name.is_null())
+                .then(|| unsafe { std::ffi::CStr::from_ptr(name) }.to_string_lossy().into_owned());
+            images.push(DebugImage {
+                image_type: "macho",
+                code_file,
+                code_id: uuid.as_ref().map(|u| hex(u)),
+                debug_id: uuid.map(|u| uuid::Uuid::from_bytes(u).to_string()),
+                debug_file: None,
+                image_addr: format!("{:#x}", header as usize),
+                image_size: size,
+            });
+        }
+        images
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use crate::event::DebugImage;
+
+    const IMAGE_DIRECTORY_ENTRY_DEBUG: usize = 6;
+    const IMAGE_DEBUG_TYPE_CODEVIEW: u32 = 2;
+    const DEBUG_DIRECTORY_SIZE: usize = 28;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetModuleHandleW(name: *const u16) -> *const u8;
+    }
+
+    unsafe fn read_u16(at: *const u8) -> u16 {
+        unsafe { std::ptr::read_unaligned(at as *const u16) }
+    }
+
+    unsafe fn read_u32(at: *const u8) -> u32 {
+        unsafe { std::ptr::read_unaligned(at as *const u32) }
+    }
+
+    pub fn images() -> Vec<DebugImage> {
+        // SAFETY: the main module stays mapped for the life of the process;
+        // offsets follow the PE/COFF headers it was loaded from.
+        unsafe {
+            let base = GetModuleHandleW(std::ptr::null());
+            if base.is_null() {
+                return Vec::new();
+            }
+            let pe = base.add(read_u32(base.add(0x3c)) as usize);
+            if read_u32(pe) != 0x0000_4550 {
+                return Vec::new();
+            }
+            let timestamp = read_u32(pe.add(8));
+            let optional = pe.add(24);
+            let image_size = read_u32(optional.add(56));
+            let directories = match read_u16(optional) {
+                0x20b => optional.add(112), // PE32+
+                _ => optional.add(96),
+            };
+            let debug = directories.add(IMAGE_DIRECTORY_ENTRY_DEBUG * 8);
+            let (debug_rva, debug_size) = (read_u32(debug) as usize, read_u32(debug.add(4)) as usize);
+
+            let mut debug_id = None;
+            let mut debug_file = None;
+            for entry in (0..debug_size / DEBUG_DIRECTORY_SIZE).map(|i| base.add(debug_rva + i * DEBUG_DIRECTORY_SIZE)) {
+                if read_u32(entry.add(12)) != IMAGE_DEBUG_TYPE_CODEVIEW {
+                    continue;
+                }
+                let codeview = base.add(read_u32(entry.add(20)) as usize);
+                if std::slice::from_raw_parts(codeview, 4) != b"RSDS" {
+                    continue;
+                }
+                let mut data4 = [0u8; 8];
+                std::ptr::copy_nonoverlapping(codeview.add(12), data4.as_mut_ptr(), 8);
+                let guid = uuid::Uuid::from_fields(
+                    read_u32(codeview.add(4)),
+                    read_u16(codeview.add(8)),
+                    read_u16(codeview.add(10)),
+                    &data4,
+                );
+                debug_id = Some(format!("{}-{:x}", guid, read_u32(codeview.add(20))));
+                debug_file = Some(
+                    std::ffi::CStr::from_ptr(codeview.add(24) as *const std::ffi::c_char)
+                        .to_string_lossy()
+                        .into_owned(),
+                );
+                break;
+            }
+
+            vec![DebugImage {
+                image_type: "pe",
+                code_file: std::env::current_exe().ok().map(|p| p.to_string_lossy().into_owned()),
+                code_id: Some(format!("{:08X}{:x}", timestamp, image_size)),
+                debug_id,
+                debug_file,
+                image_addr: format!("{:#x}", base as usize),
+                image_size: image_size as u64,
+            }]
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod platform {
+    use crate::event::DebugImage;
+
+    pub fn images() -> Vec<DebugImage> {
+        Vec::new()
+    }
+}
+
+/// The modules currently loaded, or `None` if they cannot be listed here.
+pub fn collect() -> Option<DebugMeta> {
+    let images = platform::images();
+    (!images.is_empty()).then_some(DebugMeta { images })
+}
@@ -0,0 +1,533 @@
+// Ultra-low-overhead panic hook with deferred processing.
+//
+// The regular hook symbolicates the backtrace, pretty-prints JSON and writes a
+// minidump, which can take hundreds of milliseconds. In deferred mode the hook
+// only records raw instruction pointers (as module-relative offsets) and the
+// message into a buffer allocated at install time, and writes that compact
+// record to `crash_raw_<id>.txt`. Symbolication and conversion to a regular
+// `crash_report_<id>.json` happen on the next start, in `process_pending`.
+//
+// With `Builder::preallocate` the hook does not touch the heap at all, for
+// crashes caused by a corrupt or exhausted allocator: the record never grows
+// beyond its buffer (the message is cut at MAX_MESSAGE bytes and frames that
+// do not fit are dropped), and the file name is built in a buffer allocated
+// by `install` too. What happens before the hook runs is out of our hands:
+// `panic!` with format arguments allocates the message, and std converts
+// file names longer than a few hundred bytes to C strings on the heap.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::panic;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::capture::Level;
+use crate::event::{Exception, MyFrame, MyStacktrace, SentryEvent};
+use crate::config::Config;
+use crate::feedback::LastCrash;
+use crate::install_id;
+use crate::transport::{self, Delivery};
+
+const RAW_PREFIX: &str = "crash_raw_";
+const RAW_SUFFIX: &str = ".txt";
+const RAW_VERSION: &str = "crash-raw v1";
+const MAX_FRAMES: usize = 128;
+const BUFFER_SIZE: usize = 64 * 1024;
+const MAX_MESSAGE: usize = 16 * 1024;
+// Upper bound of a frame line without its module path.
+const FRAME_LINE: usize = 64;
+
+struct DeferredState {
+    dir: PathBuf,
+    exe_id: String,        // Identifies the binary, so stale records are not mis-symbolicated.
+    preallocated: bool,    // Never grow `buffers`, see `Builder::preallocate`
+    buffers: Mutex<Buffers>,
+}
+
+// Allocated at install time and reused by the hook.
+struct Buffers {
+    record: Vec<u8>,
+    path: PathBuf,
+}
+
+static STATE: OnceLock<DeferredState> = OnceLock::new();
+
+// Size and mtime of the running executable. Good enough to notice upgrades.
+fn current_exe_id() -> String {
+    std::env::current_exe()
+        .and_then(fs::metadata)
+        .map(|m| {
+            let mtime = m
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            format!("{}:{}", m.len(), mtime)
+        })
+        .unwrap_or_default()
+}
+
+/// Installs the deferred panic hook, writing raw records into `dir`, and
+/// with `chain_previous` calling the replaced hook after it. With
+/// `preallocated` the hook never allocates. Returns false if it was already
+/// installed.
+pub fn install(dir: impl Into<PathBuf>, chain_previous: bool, preallocated: bool) -> bool {
+    let dir = dir.into();
+    let path_len = dir.as_os_str().len() + 1 + RAW_PREFIX.len() + uuid::fmt::Hyphenated::LENGTH + RAW_SUFFIX.len();
+    let state = DeferredState {
+        exe_id: current_exe_id(),
+        preallocated,
+        buffers: Mutex::new(Buffers {
+            record: Vec::with_capacity(BUFFER_SIZE),
+            path: PathBuf::with_capacity(path_len),
+        }),
+        dir,
+    };
+    if STATE.set(state).is_err() {
+        return false;
+    }
+    crate::hook::set_hook(deferred_panic_hook, chain_previous);
+    true
+}
+
+// Writes `s` with newlines and backslashes escaped, keeping one field per
+// line, and cut where `buf` would grow beyond `limit` bytes.
+fn write_escaped(buf: &mut Vec<u8>, s: &str, limit: usize) {
+    for c in s.chars() {
+        let mut utf8 = [0u8; 4];
+        let bytes: &[u8] = match c {
+            '\n' => b"\\n",
+            '\r' => b"\\r",
+            '\\' => b"\\\\",
+            _ => c.encode_utf8(&mut utf8).as_bytes(),
+        };
+        if buf.len() + bytes.len() > limit {
+            return;
+        }
+        buf.extend_from_slice(bytes);
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+// Returns the load base and path of the module containing `addr`.
+#[cfg(unix)]
+fn module_of(addr: usize) -> Option<(usize, &'static std::ffi::CStr)> {
+    // SAFETY: dladdr only reads loader data structures; the returned name
+    // points into them and stays valid while the module is loaded.
+    unsafe {
+        let mut info: libc::Dl_info = std::mem::zeroed();
+        if libc::dladdr(addr as *const libc::c_void, &mut info) == 0 || info.dli_fname.is_null() {
+            return None;
+        }
+        Some((info.dli_fbase as usize, std::ffi::CStr::from_ptr(info.dli_fname)))
+    }
+}
+
+#[cfg(not(unix))]
+fn module_of(_addr: usize) -> Option<(usize, &'static std::ffi::CStr)> {
+    None
+}
+
+fn deferred_panic_hook(info: &panic::PanicHookInfo) {
+    let Some(state) = STATE.get() else {
+        return;
+    };
+    crate::metrics::PANICS.inc();
+    // A concurrent panic already owns the buffers; one record is enough.
+    let Ok(mut buffers) = state.buffers.try_lock() else {
+        return;
+    };
+    let Buffers { record: buf, path } = &mut *buffers;
+    buf.clear();
+
+    let mut ips = [0usize; MAX_FRAMES];
+    let count = crate::unwind::trace(&mut ips);
+
+    // `init` stores the config right after installing this hook.
+    let event_id = crate::hook::config().map_or_else(Uuid::new_v4, Config::new_event_id);
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0);
+    let payload = info.payload();
+    let message = if let Some(s) = payload.downcast_ref::<&str>() {
+        *s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "Panic occurred without a string message."
+    };
+
+    let _ = writeln!(buf, "{}", RAW_VERSION);
+    let _ = writeln!(buf, "event_id {}", event_id);
+    let _ = writeln!(buf, "timestamp {}", timestamp);
+    let _ = writeln!(buf, "exe_id {}", state.exe_id);
+    if let Some(id) = install_id::installation_id() {
+        let _ = writeln!(buf, "installation_id {}", id);
+    }
+    let caught = crate::capture::is_catching() || crate::integration::panic_is_caught();
+    let level = match crate::hook::config() {
+        Some(config) => config.level_of_panic(info, caught),
+        None if caught => Level::Error,
+        None => Level::Fatal,
+    };
+    let _ = writeln!(buf, "level {}", level.as_str());
+    if !caught {
+        let _ = writeln!(buf, "crashed");
+        // In memory only: the session file is written when it ends.
+        crate::session::mark_crashed(false);
+        crate::crash_loop::mark_crashed();
+        // The abort std may raise next is this crash, see `crate::signals`.
+        #[cfg(unix)]
+        crate::signals::mark_panic_reported();
+    }
+    let limit = if state.preallocated { buf.capacity() } else { usize::MAX };
+    buf.extend_from_slice(b"message ");
+    let message_limit = if state.preallocated { buf.len() + MAX_MESSAGE } else { usize::MAX };
+    write_escaped(buf, message, message_limit);
+    buf.push(b'\n');
+    for &ip in &ips[..count] {
+        let module = module_of(ip).map(|(base, file)| (base, file.to_string_lossy()));
+        // Escaping at most doubles the path.
+        let needed = FRAME_LINE + module.as_ref().map_or(0, |(_, file)| 2 * file.len());
+        if buf.len() + needed > limit {
+            break;
+        }
+        match module {
+            Some((base, file)) => {
+                let _ = write!(buf, "frame {:x} {:x} ", ip, ip.wrapping_sub(base));
+                write_escaped(buf, &file, limit);
+                buf.push(b'\n');
+            }
+            None => {
+                let _ = writeln!(buf, "frame {:x} - -", ip);
+            }
+        }
+    }
+
+    // Within the capacity `install` gave it, so without allocating.
+    let mut id = [0u8; uuid::fmt::Hyphenated::LENGTH];
+    path.as_mut_os_string().clear();
+    path.as_mut_os_string().push(&state.dir);
+    path.push(RAW_PREFIX);
+    path.as_mut_os_string().push(event_id.hyphenated().encode_lower(&mut id));
+    path.as_mut_os_string().push(RAW_SUFFIX);
+    match File::create(&*path) {
+        Ok(mut file) => {
+            if let Err(e) = file.write_all(buf) {
+                eprintln!("Failed to write deferred crash record: {}", e);
+            }
+        }
+        Err(e) => eprintln!("Failed to create deferred crash record: {}", e),
+    }
+}
+
+// A frame as recorded by the hook.
+struct RawFrame {
+    ip: usize,
+    offset: Option<usize>,
+    module: Option<String>,
+}
+
+struct RawRecord {
+    event_id: String,
+    timestamp: String,
+    exe_id: String,
+    installation_id: Option<String>,
+    level: Option<String>, // Missing from records of older versions, which were `fatal` unless caught
+    crashed: bool,         // The panic ended the process
+    message: Option<String>,
+    frames: Vec<RawFrame>,
+}
+
+fn parse_record(data: &str) -> Option<RawRecord> {
+    let mut lines = data.lines();
+    if lines.next()? != RAW_VERSION {
+        return None;
+    }
+    let mut record = RawRecord {
+        event_id: String::new(),
+        timestamp: String::new(),
+        exe_id: String::new(),
+        installation_id: None,
+        level: None,
+        crashed: false,
+        message: None,
+        frames: Vec::new(),
+    };
+    for line in lines {
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "event_id" => record.event_id = value.to_string(),
+            "timestamp" => record.timestamp = value.to_string(),
+            "exe_id" => record.exe_id = value.to_string(),
+            "installation_id" => record.installation_id = Some(value.to_string()),
+            "level" => record.level = Some(value.to_string()),
+            "crashed" => record.crashed = true,
+            "message" => record.message = Some(unescape(value)),
+            "frame" => {
+                let mut parts = value.splitn(3, ' ');
+                let ip = usize::from_str_radix(parts.next()?, 16).ok()?;
+                let offset = parts.next().and_then(|o| usize::from_str_radix(o, 16).ok());
+                let module = parts.next().filter(|m| *m != "-").map(unescape);
+                record.frames.push(RawFrame { ip, offset, module });
+            }
+            _ => {}
+        }
+    }
+    if record.event_id.is_empty() {
+        return None;
+    }
+    Some(record)
+}
+
+// Symbolicates a recorded frame against the current process image. Only
+// frames from the main executable can be rebased, and only when the binary
+// is unchanged since the crash.
+fn symbolicate(frame: &RawFrame, exe: Option<(usize, &str)>, same_binary: bool) -> Vec<MyFrame> {
+    let raw = || MyFrame {
+        filename: frame.module.clone(),
+        lineno: None,
+        colno: None,
+        function: None,
+        instruction_addr: Some(format!("{:#x}", frame.ip)),
+        in_app: None,
+        pre_context: Vec::new(),
+        context_line: None,
+        post_context: Vec::new(),
+    };
+
+    let (Some(offset), Some(module), Some((base, exe_path))) = (frame.offset, &frame.module, exe) else {
+        return vec![raw()];
+    };
+    if !same_binary || module != exe_path {
+        return vec![raw()];
+    }
+
+    let mut frames = Vec::new();
+    backtrace::resolve((base + offset) as *mut std::ffi::c_void, |symbol| {
+        frames.push(MyFrame {
+            filename: symbol.filename().map(|p| p.to_string_lossy().into_owned()),
+            lineno: symbol.lineno(),
+            colno: symbol.colno(),
+            function: symbol.name().map(|s| s.to_string()),
+            instruction_addr: Some(format!("{:#x}", frame.ip)),
+            in_app: None,
+            pre_context: Vec::new(),
+            context_line: None,
+            post_context: Vec::new(),
+        });
+    });
+    if frames.is_empty() {
+        frames.push(raw());
+    }
+    frames
+}
+
+fn convert_record(config: &Config, record: RawRecord) -> SentryEvent {
+    // The module holding this code is the main executable when statically linked.
+    let exe = module_of(convert_record as *const () as usize)
+        .map(|(base, path)| (base, path.to_string_lossy().into_owned()));
+    let same_binary = record.exe_id == current_exe_id();
+
+    let mut frames: Vec<MyFrame> = record
+        .frames
+        .iter()
+        .flat_map(|f| symbolicate(f, exe.as_ref().map(|(b, p)| (*b, p.as_str())), same_binary))
+        .collect();
+    // Match the ordering of reports written by the regular hook.
+    frames.reverse();
+
+    let mut stacktrace = if frames.is_empty() {
+        None
+    } else {
+        Some(MyStacktrace { frames })
+    };
+    if let Some(stacktrace) = &mut stacktrace {
+        crate::frames::process_stacktrace(config, stacktrace);
+        crate::source_context::add_to_stacktrace(config, stacktrace);
+    }
+    let exception = record
+        .message
+        .as_deref()
+        .and_then(|message| crate::unwrap_error::parse(message, stacktrace.as_ref()))
+        .map(|unwrapped| Exception { values: unwrapped.values });
+    SentryEvent {
+        fingerprint: crate::fingerprint::compute(record.message.as_deref(), stacktrace.as_ref()),
+        exception,
+        event_id: record.event_id,
+        schema_version: crate::event::SCHEMA_VERSION,
+        timestamp: record.timestamp,
+        message: record.message,
+        level: Some(record.level.unwrap_or_else(|| "fatal".to_string())),
+        platform: Some("rust".to_string()),
+        stacktrace,
+        installation_id: record.installation_id,
+        // Collected by this run; the crashed one was the same installation.
+        contexts: crate::contexts::get(),
+        ..Default::default()
+    }
+}
+
+/// Converts raw records left by the deferred hook in the output directory into
+/// regular reports named after `config.filename_template`, and delivers them
+/// like any other report. Returns where they were delivered to.
+pub fn process_pending(config: &Config) -> std::io::Result<Vec<Delivery>> {
+    let dir = config.output_dir.as_path();
+    let mut written = Vec::new();
+    let mut last: Option<(u64, LastCrash)> = None;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_raw = path
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .is_some_and(|n| n.starts_with(RAW_PREFIX) && n.ends_with(RAW_SUFFIX));
+        if !is_raw {
+            continue;
+        }
+
+        let Some(record) = fs::read_to_string(&path).ok().and_then(|d| parse_record(&d)) else {
+            eprintln!("Skipping unreadable deferred crash record {}", path.display());
+            continue;
+        };
+        // Integrations are not run: their state belongs to this process, not
+        // to the one that crashed. `before_send` is part of the configuration
+        // and applies to these events too.
+        let same_binary = record.exe_id == current_exe_id();
+        let crashed = record.crashed;
+        let mut event = convert_record(config, record);
+        config.apply_release(&mut event);
+        if !same_binary {
+            // The crashed run was an earlier build.
+            event.release = None;
+        }
+        let Some(event) = config.apply_before_send(event) else {
+            fs::remove_file(&path)?;
+            continue;
+        };
+        let json = serde_json::to_string_pretty(&event)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        let timestamp = event.timestamp.parse::<f64>().unwrap_or(0.0) as u64;
+        let file_name = config.report_file_name(&event.event_id, timestamp);
+        let deliveries = transport::deliver_report(config, &file_name, json.as_bytes());
+        if deliveries.is_empty() {
+            // Keep the record and try again on the next start.
+            continue;
+        }
+        fs::remove_file(&path)?;
+        written.extend(deliveries);
+        // The newest is the crash the previous run ended in.
+        if crashed && last.as_ref().is_none_or(|(newest, _)| *newest < timestamp) {
+            let crash = LastCrash {
+                event_id: event.event_id.clone(),
+                timestamp: event.timestamp.clone(),
+                level: event.level.clone().unwrap_or_default(),
+                message: event.message.clone(),
+            };
+            last = Some((timestamp, crash));
+        }
+    }
+    if let Some((_, crash)) = &last {
+        crate::feedback::mark(config, crash);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn escaped(s: &str, limit: usize) -> String {
+        let mut buf = Vec::new();
+        write_escaped(&mut buf, s, limit);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn escaping_round_trips() {
+        for s in ["", "plain", "two\nlines\r\n", "back\\slash \\n literal", "tail\\", "ünïcödé ✓"] {
+            let line = escaped(s, usize::MAX);
+            assert!(!line.contains('\n') && !line.contains('\r'), "{:?}", line);
+            assert_eq!(unescape(&line), s);
+        }
+    }
+
+    #[test]
+    fn escaping_stops_at_the_limit() {
+        // An escape or a multi-byte character is never split.
+        assert_eq!(escaped("ab\ncd", 3), "ab");
+        assert_eq!(escaped("ab\ncd", 4), "ab\\n");
+        assert_eq!(escaped("aé", 2), "a");
+        assert_eq!(escaped("aé", 3), "aé");
+    }
+
+    #[test]
+    fn unescape_keeps_unknown_escapes() {
+        assert_eq!(unescape("a\\tb"), "atb");
+        assert_eq!(unescape("trailing\\"), "trailing\\");
+    }
+
+    #[test]
+    fn records_parse() {
+        let mut data = format!(
+            "{}\nevent_id 0190a7e4-3c6b-7f00-8000-000000000001\ntimestamp 1700000000.5\nexe_id 10:20\n\
+             installation_id inst\nlevel error\ncrashed\nmessage ",
+            RAW_VERSION
+        );
+        data.push_str(&escaped("it broke\nbadly", usize::MAX));
+        data.push_str("\nframe 7f00 1f00 /usr/lib/my lib.so\nframe 1234 - -\nunknown future line\n");
+        let record = parse_record(&data).unwrap();
+        assert_eq!(record.event_id, "0190a7e4-3c6b-7f00-8000-000000000001");
+        assert_eq!(record.timestamp, "1700000000.5");
+        assert_eq!(record.exe_id, "10:20");
+        assert_eq!(record.installation_id.as_deref(), Some("inst"));
+        assert_eq!(record.level.as_deref(), Some("error"));
+        assert!(record.crashed);
+        assert_eq!(record.message.as_deref(), Some("it broke\nbadly"));
+        assert_eq!(record.frames.len(), 2);
+        assert_eq!(
+            (record.frames[0].ip, record.frames[0].offset, record.frames[0].module.as_deref()),
+            (0x7f00, Some(0x1f00), Some("/usr/lib/my lib.so"))
+        );
+        assert_eq!(
+            (record.frames[1].ip, record.frames[1].offset, record.frames[1].module.as_deref()),
+            (0x1234, None, None)
+        );
+    }
+
+    #[test]
+    fn older_records_parse() {
+        let record = parse_record(&format!("{}\nevent_id id\ntimestamp 1\nexe_id x\nmessage m\n", RAW_VERSION)).unwrap();
+        assert_eq!(record.level, None);
+        assert!(!record.crashed);
+        assert!(record.frames.is_empty());
+    }
+
+    #[test]
+    fn invalid_records_are_rejected() {
+        assert!(parse_record("").is_none());
+        assert!(parse_record("crash-raw v0\nevent_id id\n").is_none());
+        assert!(parse_record(&format!("{}\nmessage no id\n", RAW_VERSION)).is_none());
+        assert!(parse_record(&format!("{}\nevent_id id\nframe zz 0 -\n", RAW_VERSION)).is_none());
+    }
+}
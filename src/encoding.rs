@@ -0,0 +1,74 @@
+// Encoding of crash reports.
+//
+// Reports are pretty-printed JSON by default, readable as is. MessagePack and
+// CBOR are binary alternatives that are considerably smaller, with a `.msgpack`
+// or `.cbor` file name extension instead of `.json` and a matching
+// `Content-Type` when uploaded. Everything before delivery (integrations,
+// `before_send`, scrubbing) works on JSON; the encoding is applied when the
+// report is written or sent to the crash server, which reads all three.
+// Reports sent to Sentry and reports written from signal handlers are always
+// JSON.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Encoding {
+    /// Parses `json`, `msgpack` or `cbor`.
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name {
+            "json" => Some(Encoding::Json),
+            "msgpack" => Some(Encoding::MessagePack),
+            "cbor" => Some(Encoding::Cbor),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::MessagePack => "msgpack",
+            Encoding::Cbor => "cbor",
+        }
+    }
+
+    /// File name extension, including the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Encoding::Json => ".json",
+            Encoding::MessagePack => ".msgpack",
+            Encoding::Cbor => ".cbor",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::MessagePack => "application/msgpack",
+            Encoding::Cbor => "application/cbor",
+        }
+    }
+
+    /// `file_name`, a JSON report name, with a matching extension.
+    pub fn file_name(self, file_name: &str) -> String {
+        if self == Encoding::Json {
+            return file_name.to_string();
+        }
+        let stem = file_name.strip_suffix(".json").unwrap_or(file_name);
+        format!("{}{}", stem, self.extension())
+    }
+
+    /// Re-encodes a JSON report.
+    pub fn encode(self, json: &[u8]) -> std::io::Result<Vec<u8>> {
+        let value = || serde_json::from_slice::<serde_json::Value>(json);
+        match self {
+            Encoding::Json => Ok(json.to_vec()),
+            Encoding::MessagePack => rmp_serde::to_vec_named(&value()?).map_err(std::io::Error::other),
+            Encoding::Cbor => serde_cbor::to_vec(&value()?).map_err(std::io::Error::other),
+        }
+    }
+}
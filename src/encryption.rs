@@ -0,0 +1,112 @@
+// Optional encryption of crash artifacts at rest.
+//
+// Minidumps hold whatever was in the process's memory, reports and
+// attachments whatever the application logged; where that may include
+// sensitive data, `Builder::encrypt_to` takes an age X25519 recipient
+// (`age1...`, as printed by `age-keygen`) and everything this crate writes or
+// sends for a crash is encrypted to it with age:
+//
+// - local files get an `.age` suffix after any compression suffix
+//   (`crash_report_<id>.json.gz.age`, `crash_dump_<id>.dmp.age`) and can be
+//   read with `age -d -i <identity file>`,
+// - uploads to the crash server carry `X-Crash-Encryption: age` instead of a
+//   `Content-Encoding`; what was compressed is compressed inside the
+//   ciphertext. The server decrypts them with the identities named by
+//   CRASH_AGE_IDENTITY (see `server/src/encryption.rs`).
+//
+// Only the holder of the identity can read the artifacts; the client never
+// sees it. Reports queued for the crash server stay encrypted in the queue.
+// Sentry cannot decrypt, so reports for it are sent as before but not queued
+// when that fails; they are written locally, encrypted, instead.
+//
+// Not encrypted: native crash reports written from signal handlers (message,
+// signal and return addresses only; the minidump taken with them is),
+// deferred records until they are completed on the next start, and session
+// records. The minimal report the panic hook writes first leaves out the
+// panic message, as with `scrub_pii`.
+//
+// Needs the `encryption` feature; `crash::init` fails without it. Not
+// available on wasm32.
+
+use crate::config::Config;
+
+/// Suffix of encrypted files.
+pub const EXTENSION: &str = ".age";
+
+/// Header marking an encrypted upload.
+pub const HEADER: &str = "X-Crash-Encryption";
+
+// What every age file starts with.
+const MAGIC: &[u8] = b"age-encryption.org/";
+
+#[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+fn parse(recipient: &str) -> std::io::Result<age::x25519::Recipient> {
+    recipient.trim().parse().map_err(|e: &str| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid age recipient '{}': {}", recipient, e),
+        )
+    })
+}
+
+#[cfg(not(all(feature = "encryption", not(target_arch = "wasm32"))))]
+fn unsupported() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "encryption needs the `encryption` feature and is not available on wasm32",
+    )
+}
+
+/// Checks that `recipient` is an age X25519 recipient that can be encrypted
+/// to in this build.
+pub fn validate(recipient: &str) -> std::io::Result<()> {
+    #[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+    {
+        parse(recipient).map(|_| ())
+    }
+    #[cfg(not(all(feature = "encryption", not(target_arch = "wasm32"))))]
+    {
+        let _ = recipient;
+        Err(unsupported())
+    }
+}
+
+/// Whether `data` is already an age file, such as a minidump written
+/// encrypted and then attached to a report.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypts `data` to `recipient`. Data that is already encrypted is
+/// returned as is.
+pub fn encrypt(recipient: &str, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    if is_encrypted(data) {
+        return Ok(data.to_vec());
+    }
+    #[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+    {
+        age::encrypt(&parse(recipient)?, data).map_err(std::io::Error::other)
+    }
+    #[cfg(not(all(feature = "encryption", not(target_arch = "wasm32"))))]
+    {
+        let _ = recipient;
+        Err(unsupported())
+    }
+}
+
+/// `data` encrypted as configured, or unchanged without encryption.
+pub fn seal(config: &Config, data: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    match &config.encrypt_to {
+        Some(recipient) => encrypt(recipient, &data),
+        None => Ok(data),
+    }
+}
+
+/// Suffix of the files written under `config`, empty without encryption.
+pub fn extension(config: &Config) -> &'static str {
+    if config.encrypt_to.is_some() {
+        EXTENSION
+    } else {
+        ""
+    }
+}
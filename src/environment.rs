@@ -0,0 +1,76 @@
+// Optional capture of the process environment.
+//
+// Disabled by default. When enabled, variables are filtered by name with
+// patterns where `*` matches any run of characters, compared ignoring case:
+// with an allowlist only matching variables are kept, and the denylist then
+// removes variables. Values of variables whose name looks like it holds a
+// secret are replaced by `[Filtered]` before the event is ever serialized.
+
+use std::collections::BTreeMap;
+
+use crate::config::Config;
+
+pub const FILTERED: &str = "[Filtered]";
+
+// Name fragments that mark a variable as holding a secret.
+const SECRET_MARKERS: [&str; 10] = [
+    "TOKEN",
+    "PASSWORD",
+    "PASSWD",
+    "SECRET",
+    "API_KEY",
+    "APIKEY",
+    "ACCESS_KEY",
+    "PRIVATE_KEY",
+    "CREDENTIAL",
+    "AUTH",
+];
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_ascii_uppercase();
+    let name = name.to_ascii_uppercase();
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty(); // No `*` at all
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+pub fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    SECRET_MARKERS.iter().any(|marker| name.contains(marker))
+}
+
+/// Filters and masks `vars` according to `config`.
+pub fn scrub(config: &Config, vars: impl IntoIterator<Item = (String, String)>) -> BTreeMap<String, String> {
+    vars.into_iter()
+        .filter(|(name, _)| config.env_allowlist.is_empty() || config.env_allowlist.iter().any(|p| matches(p, name)))
+        .filter(|(name, _)| !config.env_denylist.iter().any(|p| matches(p, name)))
+        .map(|(name, value)| {
+            let value = if is_secret(&name) { FILTERED.to_string() } else { value };
+            (name, value)
+        })
+        .collect()
+}
+
+/// The scrubbed environment of this process, or `None` if capture is off.
+pub fn capture(config: &Config) -> Option<BTreeMap<String, String>> {
+    if !config.capture_env {
+        return None;
+    }
+    // Variables that are not valid Unicode are skipped rather than mangled.
+    let vars = std::env::vars_os().filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)));
+    Some(scrub(config, vars))
+}
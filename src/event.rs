@@ -0,0 +1,172 @@
+// Sentry-compatible event structures shared by every capture path.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// Represents a single frame in a stack trace, compatible with Sentry's format.
+#[derive(Serialize, Debug, Clone)]
+pub struct MyFrame {
+    pub filename: Option<String>, // The name of the file in which this frame is located.
+    pub lineno: Option<u32>,     // The line number in the file.
+    pub colno: Option<u32>,      // The column number in the file.
+    pub function: Option<String>,// The name of the function in which this frame is located.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instruction_addr: Option<String>, // Raw address, kept when the frame could not be symbolicated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_app: Option<bool>,    // Whether the frame belongs to the application, see `crate::frames`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pre_context: Vec<String>, // Source lines before `lineno`, see `crate::source_context`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_line: Option<String>, // The source line at `lineno`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub post_context: Vec<String>, // Source lines after `lineno`.
+}
+
+// Represents a stack trace, containing a list of frames.
+#[derive(Serialize, Debug, Clone)]
+pub struct MyStacktrace {
+    pub frames: Vec<MyFrame>, // A list of frames, ordered from outermost to innermost call.
+}
+
+/// Version of the report format, in `schema_version`. Reports without the
+/// field are version 1; version 2 added it, with time-ordered (UUIDv7) event
+/// ids. Bumped whenever a field changes meaning or goes away.
+pub const SCHEMA_VERSION: u32 = 2;
+
+// Represents the overall Sentry event structure to be serialized.
+#[derive(Serialize, Debug, Default)]
+pub struct SentryEvent {
+    pub event_id: String,             // A unique identifier for this event (UUIDv7, or v4, see `Config::new_event_id`).
+    pub schema_version: u32,          // SCHEMA_VERSION of the crate that wrote it.
+    pub timestamp: String,            // Timestamp of the event (seconds since UNIX epoch).
+    pub message: Option<String>,      // The panic message.
+    pub level: Option<String>,        // The severity level of the event (e.g., "fatal").
+    pub platform: Option<String>,     // The platform on which the event occurred (e.g., "rust").
+    pub stacktrace: Option<MyStacktrace>, // The stack trace information.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exception: Option<Exception>,     // Error chain of a captured error, see `crate::capture`, or the native crash.
+    pub installation_id: Option<String>,  // Anonymous, persistent ID of this installation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release: Option<String>,          // Build the crash came from, e.g. `app@1.2.0`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,      // Deployment environment, e.g. `production`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dist: Option<String>,             // Distribution of the release.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,      // Host the crash happened on.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub breadcrumbs: Vec<Breadcrumb>,     // Trail of events leading up to the crash, oldest first.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,   // Indexed key/value pairs for searching and grouping.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<User>,               // The user affected by the crash.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, serde_json::Value>, // Arbitrary additional context.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub threads: Vec<Thread>,             // Every thread of the process at crash time.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub async_tasks: Vec<AsyncTask>,      // Tasks of an async runtime, see `crate::tokio_integration`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub contexts: BTreeMap<String, serde_json::Value>, // OS, device, runtime and app, see `crate::contexts`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_meta: Option<DebugMeta>,    // Loaded modules and their debug ids, see `crate::debug_meta`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fingerprint: Vec<String>,         // Groups recurrences of the same crash, see `crate::fingerprint`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<AttachmentRef>,  // Files stored alongside the report, see `crate::attachments`.
+}
+
+// Errors of a captured error's `source()` chain, or the signal of a native
+// crash, compatible with Sentry's format.
+#[derive(Serialize, Debug, Clone)]
+pub struct Exception {
+    pub values: Vec<ExceptionValue>, // Root cause first, the captured error last.
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ExceptionValue {
+    #[serde(rename = "type")]
+    pub exception_type: String, // Type of the error, e.g. `ParseIntError`, or the signal, e.g. `SIGSEGV`.
+    pub value: String,          // Its `Display` output, or what the signal means.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fault_address: Option<String>, // Address a native crash faulted at, hex.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub registers: BTreeMap<String, String>, // Registers of the crashed thread, hex, see `crate::signals`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stacktrace: Option<MyStacktrace>, // Where an exception of another language was raised, see `crash::capture_exception`.
+}
+
+// Modules loaded in the crashed process, compatible with Sentry's format.
+#[derive(Serialize, Debug, Clone)]
+pub struct DebugMeta {
+    pub images: Vec<DebugImage>,
+}
+
+// A loaded executable or shared library.
+#[derive(Serialize, Debug, Clone)]
+pub struct DebugImage {
+    #[serde(rename = "type")]
+    pub image_type: &'static str,         // "elf", "macho" or "pe".
+    pub code_file: Option<String>,        // Path of the module.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_id: Option<String>,          // GNU build-id, Mach-O UUID or PE timestamp and size, hex.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_id: Option<String>,         // Identifies the symbols matching the module.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_file: Option<String>,       // PDB path, on Windows.
+    pub image_addr: String,               // Load address, hex.
+    pub image_size: u64,                  // Size of the mapped module in bytes.
+}
+
+// An attachment stored next to the report.
+#[derive(Serialize, Debug, Clone)]
+pub struct AttachmentRef {
+    pub name: String,     // Name the attachment was registered under.
+    pub filename: String, // `crash_attachment_<id>_<name>`, as written and as served by the server.
+    pub size: u64,        // Size in bytes.
+    pub sha256: String,   // Hex SHA-256 of the contents, see `crate::integrity`.
+}
+
+// A thread of the crashed process, compatible with Sentry's format.
+#[derive(Serialize, Debug)]
+pub struct Thread {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,                  // OS thread id.
+    pub name: Option<String>,             // Thread name, if set.
+    pub crashed: bool,                    // Whether this thread crashed.
+    pub current: bool,                    // Whether the report was captured on this thread.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stacktrace: Option<MyStacktrace>, // Stack at crash time, if it could be captured.
+}
+
+// A task of an async runtime at crash time.
+#[derive(Serialize, Debug, Clone)]
+pub struct AsyncTask {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>, // Runtime's id of the task.
+    pub trace: String,      // Async backtrace: the futures the task is suspended in, as a tree.
+}
+
+// The affected user, compatible with Sentry's format.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct User {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_address: Option<String>,
+}
+
+// A single breadcrumb, compatible with Sentry's format.
+#[derive(Serialize, Debug, Clone)]
+pub struct Breadcrumb {
+    pub timestamp: String,          // Seconds since UNIX epoch.
+    pub category: Option<String>,   // Dotted category, e.g. "http" or "ui.click".
+    pub message: Option<String>,    // Human readable description.
+    pub level: Option<String>,      // Severity, e.g. "info" or "error".
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub data: BTreeMap<String, serde_json::Value>, // Arbitrary structured data.
+}
@@ -0,0 +1,87 @@
+// User feedback on the crash of the previous run.
+//
+// A crash that ends the process leaves `crash_last.json` in the output
+// directory with the event id of its report: the panic hook writes it for
+// fatal panics, the signal handlers for native crashes (prepared at install,
+// like the report itself, see `crate::raw_report`) and the watchdog for the
+// crashes it captures; deferred records are marked when `init` completes
+// them. The next `crash::init` takes the note, so `crash::last_crash()`
+// tells the application its previous run crashed, e.g. to ask the user what
+// they were doing. `crash::submit_feedback` attaches the answer to the report
+// of that crash as `feedback.json`, uploaded to the crash server or written
+// next to the report like any attachment.
+
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+pub const LAST_CRASH_FILE: &str = "crash_last.json";
+pub const FEEDBACK_ATTACHMENT: &str = "feedback.json";
+
+/// The crash the previous run ended in.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LastCrash {
+    pub event_id: String,
+    pub timestamp: String, // Seconds since the UNIX epoch, as in the report
+    pub level: String,
+    pub message: Option<String>,
+}
+
+/// What the user says about a crash, in the shape of Sentry's user reports.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct UserFeedback {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    pub comments: String,
+}
+
+static LAST_CRASH: OnceLock<Option<LastCrash>> = OnceLock::new();
+
+/// Notes that the process is ending in the crash `crash`, for the next run.
+pub(crate) fn mark(config: &Config, crash: &LastCrash) {
+    let written = serde_json::to_vec(crash)
+        .map_err(std::io::Error::other)
+        .and_then(|json| std::fs::write(config.output_dir.join(LAST_CRASH_FILE), json));
+    if let Err(e) = written {
+        eprintln!("Failed to note crash {} for the next run: {}", crash.event_id, e);
+    }
+}
+
+/// Takes the note an earlier run left of its crash. Called by `crash::init`,
+/// after the deferred records are completed.
+pub(crate) fn load(config: &Config) {
+    let path = config.output_dir.join(LAST_CRASH_FILE);
+    let crash = std::fs::read(&path)
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok());
+    let _ = std::fs::remove_file(&path);
+    let _ = LAST_CRASH.set(crash);
+}
+
+/// The crash the previous run ended in, or `None` if it did not crash (as
+/// far as it could tell) or `crash::init` did not run.
+pub fn last_crash() -> Option<LastCrash> {
+    LAST_CRASH.get().cloned().flatten()
+}
+
+/// Attaches `feedback` to the report of event `event_id`, usually that of
+/// `crash::last_crash()`. Returns false if the crash handler is not installed
+/// or the feedback could not be uploaded or written.
+pub fn submit_feedback(event_id: &str, feedback: &UserFeedback) -> bool {
+    let Some(config) = crate::hook::config() else {
+        return false;
+    };
+    let mut json = match serde_json::to_value(feedback) {
+        Ok(json) => json,
+        Err(_) => return false,
+    };
+    if let Some(map) = json.as_object_mut() {
+        map.insert("event_id".to_string(), event_id.into());
+    }
+    let data = json.to_string();
+    crate::attachments::deliver(config, event_id, FEEDBACK_ATTACHMENT, data.as_bytes()).is_some()
+}
@@ -0,0 +1,82 @@
+// Client-side grouping of crashes.
+//
+// Two panics are the same problem when they come from the same place with the
+// same message, give or take the values in it. The fingerprint is a hash of
+// the panic message with numbers and addresses replaced by placeholders
+// (`index out of bounds: the len is <n> but the index is <n>`) and of the
+// innermost in-app frames (see `crate::frames`). For panics of `unwrap` and
+// `expect` the error in the message counts with its types only, see
+// `crate::unwrap_error`. It is stored in the event's `fingerprint`, which
+// both the bundled server and Sentry group issues by.
+
+use crate::event::MyStacktrace;
+
+// In-app frames that go into the fingerprint.
+const FRAMES: usize = 3;
+
+// FNV-1a, so fingerprints stay stable across builds and Rust versions.
+fn stable_hash(input: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in input.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// `message` with hex addresses replaced by `<hex>` and numbers by `<n>`.
+/// Digits that are part of a word, as in `u32`, are kept.
+pub fn message_template(message: &str) -> String {
+    let mut template = String::with_capacity(message.len());
+    let mut chars = message.chars().peekable();
+    let mut in_word = false;
+    while let Some(c) = chars.next() {
+        if !c.is_ascii_digit() || in_word {
+            in_word = c.is_alphanumeric() || c == '_';
+            template.push(c);
+            continue;
+        }
+        if c == '0' && matches!(chars.peek(), Some('x') | Some('X')) {
+            chars.next();
+            while chars.next_if(|c| c.is_ascii_hexdigit()).is_some() {}
+            template.push_str("<hex>");
+        } else {
+            while chars.next_if(|c| c.is_ascii_digit()).is_some() {}
+            template.push_str("<n>");
+        }
+    }
+    template
+}
+
+// `function` without the `::h<hash>` suffix rustc appends to symbol names.
+fn strip_symbol_hash(function: &str) -> &str {
+    match function.rsplit_once("::h") {
+        Some((name, hash)) if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) => name,
+        _ => function,
+    }
+}
+
+/// Fingerprint of a crash with `message` and `stacktrace` (outermost frame
+/// first).
+pub fn compute(message: Option<&str>, stacktrace: Option<&MyStacktrace>) -> Vec<String> {
+    let message = message.unwrap_or_default();
+    let mut key = match crate::unwrap_error::parse(message, stacktrace) {
+        Some(unwrapped) => message_template(&unwrapped.grouping_key()),
+        None => message_template(message),
+    };
+    let frames = stacktrace.map(|s| s.frames.as_slice()).unwrap_or_default();
+    for function in frames
+        .iter()
+        .rev()
+        .filter_map(|frame| {
+            let function = frame.function.as_deref()?;
+            frame.in_app.unwrap_or_else(|| crate::frames::is_in_app(function)).then_some(function)
+        })
+        .map(strip_symbol_hash)
+        .take(FRAMES)
+    {
+        key.push('\n');
+        key.push_str(function);
+    }
+    vec![format!("{:016x}", stable_hash(&key))]
+}
@@ -0,0 +1,86 @@
+// Waiting for background deliveries before the process exits.
+//
+// Reports are delivered on the thread that captures them, but the retries of
+// queued reports (see `crate::queue`) and metrics pushes at `init` run on
+// background threads, which a short-lived program can exit in the middle of.
+// `crash::flush` waits for them, up to a timeout, and flushes the logger the
+// log integration forwards to. `crash::Builder::init_guarded` returns a
+// `FlushGuard` that does the same when it is dropped at the end of `main`:
+//
+//     let _guard = crash::Builder::new().init_guarded()?;
+//
+// Work started after the timeout ran out, or still running at exit, is not
+// lost: a report whose upload did not finish is still in the queue.
+
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+// Background deliveries still running.
+static PENDING: Mutex<usize> = Mutex::new(0);
+static FINISHED: Condvar = Condvar::new();
+
+// Counts a delivery off when its thread ends, panicking or not.
+struct Tracked;
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+        *pending -= 1;
+        FINISHED.notify_all();
+    }
+}
+
+/// Runs `work` on a background thread named `name` that `flush` waits for.
+pub(crate) fn spawn(name: &str, work: impl FnOnce() + Send + 'static) -> std::io::Result<()> {
+    *PENDING.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+    let tracked = Tracked;
+    std::thread::Builder::new().name(name.to_string()).spawn(move || {
+        let _tracked = tracked;
+        work();
+    })?;
+    Ok(())
+}
+
+/// Waits up to `timeout` for the background deliveries to finish. Returns
+/// false if some were still running when it ran out.
+pub fn flush(timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+    while *pending > 0 {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        pending = FINISHED
+            .wait_timeout(pending, left)
+            .unwrap_or_else(|e| e.into_inner())
+            .0;
+    }
+    let finished = *pending == 0;
+    drop(pending);
+    #[cfg(feature = "log")]
+    log::logger().flush();
+    finished
+}
+
+/// Flushes when dropped, see `crash::flush`.
+#[must_use = "the guard flushes when it is dropped"]
+#[derive(Debug)]
+pub struct FlushGuard {
+    timeout: Duration,
+}
+
+impl FlushGuard {
+    /// A guard waiting up to `timeout` on drop.
+    pub fn new(timeout: Duration) -> Self {
+        FlushGuard { timeout }
+    }
+}
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        if !flush(self.timeout) {
+            eprintln!("Crash reports were still being delivered at shutdown");
+        }
+    }
+}
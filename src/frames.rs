@@ -0,0 +1,203 @@
+// In-app classification and trimming of stack frames.
+//
+// Every symbolicated frame of an event is marked `in_app` or not before the
+// event is fingerprinted and delivered, so the server and Sentry can tell the
+// application's frames from those of its dependencies. By default every Rust
+// function outside the standard library, `backtrace` and this crate is
+// in-app; with `Builder::in_app_include` only frames of the given crates or
+// modules are, and `Builder::in_app_exclude` takes crates or modules out. A prefix names a
+// crate or module path (`my_app`, `my_app::net`) and matches it and
+// everything below; trait implementations (`<my_app::Foo as Display>::fmt`)
+// count for the implementing type. Crate disambiguators of v0 symbol names
+// (`std[e28293b1aa0f68bd]::`) are ignored.
+//
+// With `trim_frames` (on by default) the frames of the panic and capture
+// machinery are cut from the top of each stack, up to the innermost in-app
+// frame at most, and the runtime's startup frames (`_start`,
+// `std::rt::lang_start`, thread entry points) from the bottom. `max_frames`
+// bounds what is left, dropping frames from the middle so both the entry
+// point and the crash site stay in the report.
+
+use crate::config::Config;
+use crate::event::{MyStacktrace, SentryEvent};
+
+// Rust crates that are not part of the application.
+const NOT_IN_APP: &[&str] = &["std::", "core::", "alloc::", "__rustc::", "backtrace::", "crash::"];
+
+// Innermost frames of a captured stack that only show how it was captured:
+// the panic runtime, the panic hook, the unwinder and signal trampolines.
+const MACHINERY: &[&str] = &[
+    "backtrace::",
+    "crash::",
+    "std::panicking::",
+    "std::panic::panic_any",
+    "core::panicking::",
+    "std::sys::backtrace::__rust_end_short_backtrace",
+    "std::backtrace::",
+    "std::backtrace_rs::",
+    "alloc::boxed::Box<",
+    "core::ops::function::",
+    "rust_begin_unwind",
+    "__rustc::rust_begin_unwind",
+    "__restore_rt",
+    "_sigtramp",
+];
+
+// Outermost frames every thread starts with.
+const STARTUP: &[&str] = &[
+    "_start",
+    "__libc_start",
+    "start_thread",
+    "clone",
+    "thread_start",
+    "_pthread_start",
+    "BaseThreadInitThunk",
+    "RtlUserThreadStart",
+    "std::rt::",
+    "std::panicking::try",
+    "std::panicking::catch_unwind",
+    "std::panic::catch_unwind",
+    "__rust_try",
+    "std::sys::",
+    "std::thread::",
+    "core::ops::function::",
+    "alloc::boxed::Box<",
+    "core::panic::unwind_safe::AssertUnwindSafe<",
+];
+
+// `function` without the crate disambiguators of v0 symbol names
+// (`std[e28293b1aa0f68bd]::rt::lang_start` becomes `std::rt::lang_start`).
+fn strip_disambiguators(function: &str) -> String {
+    let mut stripped = String::with_capacity(function.len());
+    let mut rest = function;
+    while let Some(start) = rest.find('[') {
+        let after = &rest[start + 1..];
+        match after.find(']') {
+            Some(end) if end > 0 && after[..end].bytes().all(|b| b.is_ascii_hexdigit()) => {
+                stripped.push_str(&rest[..start]);
+                rest = &after[end + 1..];
+            }
+            _ => {
+                stripped.push_str(&rest[..=start]);
+                rest = after;
+            }
+        }
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+// The path `function` is classified by. For a trait implementation
+// (`<T as Trait>::f`) that is the implementing type, or the trait when the
+// type is not a path (`<usize as core::slice::index::SliceIndex<[T]>>`).
+fn path_of(function: &str) -> String {
+    let function = strip_disambiguators(function);
+    let Some(rest) = function.strip_prefix('<') else {
+        return function;
+    };
+    match rest.split_once(" as ") {
+        Some((ty, tr)) => {
+            let ty = ty.trim_start_matches(['&', '*']);
+            let ty = ty.trim_start_matches("mut ").trim_start_matches("const ").trim_start_matches("dyn ");
+            if ty.contains("::") {
+                ty.to_string()
+            } else {
+                tr.to_string()
+            }
+        }
+        None => rest.to_string(),
+    }
+}
+
+// Whether `path` is the crate or module `prefix` or lies below it.
+fn matches_prefix(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches("::").replace('-', "_");
+    match path.strip_prefix(prefix.as_str()) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+fn starts_with_any(function: &str, prefixes: &[&str]) -> bool {
+    let path = path_of(function);
+    prefixes.iter().any(|prefix| path.starts_with(prefix))
+}
+
+/// Whether `function` belongs to the application by the default rules: a
+/// Rust function outside the standard library, `backtrace` and this crate.
+/// C functions, such as those of libc, are not.
+pub fn is_in_app(function: &str) -> bool {
+    function.contains("::") && !starts_with_any(function, NOT_IN_APP)
+}
+
+/// Whether `function` belongs to the application under the rules of
+/// `config`.
+pub fn classify(config: &Config, function: &str) -> bool {
+    let path = path_of(function);
+    if config.in_app_exclude.iter().any(|prefix| matches_prefix(&path, prefix)) {
+        return false;
+    }
+    if !config.in_app_include.is_empty() {
+        return config.in_app_include.iter().any(|prefix| matches_prefix(&path, prefix));
+    }
+    is_in_app(function)
+}
+
+/// Marks the frames of `stacktrace` and trims it as configured.
+pub fn process_stacktrace(config: &Config, stacktrace: &mut MyStacktrace) {
+    // Frames of other languages come classified already.
+    for frame in &mut stacktrace.frames {
+        if let (Some(function), None) = (&frame.function, frame.in_app) {
+            frame.in_app = Some(classify(config, function));
+        }
+    }
+
+    let frames = &mut stacktrace.frames;
+    if config.trim_frames {
+        // Frames are stored outermost first, so the top of the stack is the
+        // end. Unsymbolicated frames there are trampolines and the like.
+        let machinery = frames
+            .iter()
+            .rev()
+            .take_while(|frame| match &frame.function {
+                Some(function) => frame.in_app != Some(true) && starts_with_any(function, MACHINERY),
+                None => true,
+            })
+            .count();
+        // Keep a stack that is all machinery as it is.
+        if machinery < frames.len() {
+            frames.truncate(frames.len() - machinery);
+        }
+        // The C `main` calls Rust's `main` through `std::rt::lang_start`.
+        let startup = frames
+            .iter()
+            .take_while(|frame| match &frame.function {
+                Some(function) => function == "main" || starts_with_any(function, STARTUP),
+                None => true,
+            })
+            .count();
+        if startup < frames.len() {
+            frames.drain(..startup);
+        }
+    }
+
+    if let Some(max) = config.max_frames {
+        if frames.len() > max {
+            let outer = max / 2;
+            frames.drain(outer..frames.len() - (max - outer));
+        }
+    }
+}
+
+/// Classifies and trims every stack of `event`: its main stack trace and
+/// those of its threads.
+pub fn process(config: &Config, event: &mut SentryEvent) {
+    if let Some(stacktrace) = &mut event.stacktrace {
+        process_stacktrace(config, stacktrace);
+    }
+    for thread in &mut event.threads {
+        if let Some(stacktrace) = &mut thread.stacktrace {
+            process_stacktrace(config, stacktrace);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+// Application hang detection.
+//
+// With `Builder::hang_timeout` a watchdog thread expects the application to
+// call `crash::heartbeat()` regularly, typically once per turn of its main or
+// event loop. When no heartbeat arrives for longer than the timeout it files
+// an `error` report with an `app_hang` exception and the stacks of every
+// thread; the thread that sent the last heartbeat is marked as crashed and
+// its stack becomes the event's. A hang is reported once, until heartbeats
+// come in again.
+//
+// The watchdog only starts counting with the first heartbeat, so a slow
+// startup is not a hang, and applications that never call `heartbeat` are
+// never reported. Stacks of other threads are sampled as for panic reports,
+// so only on Linux; elsewhere the report carries no stacks.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::capture::Level;
+use crate::event::{Exception, ExceptionValue};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Milliseconds since EPOCH of the last heartbeat, and the OS id of the thread
+// that sent it. Only meaningful once BEATING is set.
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+static LAST_BEAT: AtomicU64 = AtomicU64::new(0);
+static BEAT_THREAD: AtomicU64 = AtomicU64::new(0);
+static BEATING: AtomicBool = AtomicBool::new(false);
+
+fn now_ms() -> u64 {
+    EPOCH.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+/// Tells the hang watchdog the application is responsive, see `crate::hang`.
+/// Cheap enough to call on every iteration of a main loop.
+pub fn heartbeat() {
+    LAST_BEAT.store(now_ms(), Ordering::Relaxed);
+    if let Some(id) = crate::threads::current_thread_id() {
+        BEAT_THREAD.store(id, Ordering::Relaxed);
+    }
+    BEATING.store(true, Ordering::Release);
+}
+
+/// Starts the thread that reports heartbeats more than `timeout` apart.
+/// Reports go through the installed crash handler.
+pub fn spawn_monitor(timeout: Duration) -> std::io::Result<()> {
+    if timeout.is_zero() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "hang timeout must be greater than zero",
+        ));
+    }
+    std::thread::Builder::new()
+        .name("crash-hang-monitor".to_string())
+        .spawn(move || monitor(timeout))?;
+    Ok(())
+}
+
+fn monitor(timeout: Duration) {
+    let interval = CHECK_INTERVAL.min(timeout / 2);
+    let mut armed = true;
+    loop {
+        std::thread::sleep(interval);
+        if !BEATING.load(Ordering::Acquire) {
+            continue;
+        }
+        let silent = Duration::from_millis(now_ms().saturating_sub(LAST_BEAT.load(Ordering::Relaxed)));
+        if armed && silent > timeout {
+            armed = false;
+            report_hang(silent, BEAT_THREAD.load(Ordering::Relaxed));
+        } else if !armed && silent <= timeout {
+            armed = true;
+        }
+    }
+}
+
+// Reported from the monitor thread, which is left out of the thread list;
+// the stack of interest is the hung thread's.
+fn report_hang(silent: Duration, hung: u64) -> Option<String> {
+    crate::capture::capture(|event_id, timestamp| {
+        let message = format!("App hanging: no heartbeat for {} s", silent.as_secs());
+        let mut event = crate::hook::base_event(event_id, timestamp, Level::Error.as_str(), message.clone());
+        let mut threads = crate::threads::other_threads();
+        for thread in &mut threads {
+            thread.crashed = thread.id == Some(hung);
+        }
+        // Like the crashed thread of a panic, its stack is the event's.
+        event.stacktrace = threads
+            .iter_mut()
+            .find(|thread| thread.crashed)
+            .and_then(|thread| thread.stacktrace.take());
+        event.threads = threads;
+        event.exception = Some(Exception {
+            values: vec![ExceptionValue {
+                exception_type: "app_hang".to_string(),
+                value: message,
+                ..Default::default()
+            }],
+        });
+        event
+            .extra
+            .insert("hang_seconds".to_string(), serde_json::json!(silent.as_secs_f64()));
+        event
+    })
+}
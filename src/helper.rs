@@ -0,0 +1,223 @@
+// Out-of-process minidump capture through a bundled helper executable.
+//
+// Writing a minidump from inside a signal handler is unsafe: the heap may be
+// corrupted and most of the minidump writer is not async-signal-safe. Instead,
+// the signal handler forks and exec's the `crash-helper` binary, grants it
+// permission to ptrace us, and hands over the crashing pid/tid through a pipe.
+// The helper then writes the dump of the stopped process from the outside.
+//
+// Everything the signal handler needs (paths, argv) is prepared up front in
+// `install`, so the handler itself only performs async-signal-safe syscalls.
+
+use std::ffi::CString;
+use std::io::Read;
+use std::os::raw::c_char;
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use minidump_writer::minidump_writer::MinidumpWriter;
+
+use crate::upload::{self, DumpDestination};
+
+/// File descriptor on which the helper receives the handshake message.
+pub const HANDSHAKE_FD: i32 = 3;
+
+/// Size of the handshake message: crashing pid and tid as little-endian u32.
+const HANDSHAKE_LEN: usize = 8;
+
+// State prepared at install time and read from the signal handler.
+struct HelperState {
+    helper_path: CString,
+    _args: Vec<CString>,              // Owns the strings `argv` points into.
+    argv: Vec<*const c_char>,         // NULL-terminated argv for execv.
+}
+
+// The raw pointers only reference the CStrings owned by the same struct,
+// which is never mutated or dropped once stored.
+unsafe impl Send for HelperState {}
+unsafe impl Sync for HelperState {}
+
+static HELPER_STATE: OnceLock<HelperState> = OnceLock::new();
+
+/// Prepares the helper executable at `helper_path` to capture a minidump into
+/// `destination`, encrypted to `encrypt_to` if given, when one of the signal
+/// handlers in `crate::signals` fires.
+pub fn install(helper_path: &Path, destination: &DumpDestination, encrypt_to: Option<&str>) -> std::io::Result<()> {
+    let state = prepare(helper_path, destination, encrypt_to, None)?;
+    if HELPER_STATE.set(state).is_err() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "crash helper already installed",
+        ));
+    }
+    Ok(())
+}
+
+/// Dumps the current process through the helper right away, e.g. from the
+/// panic hook. The calling thread is reported as the crashing thread. The
+/// helper adds the application streams handed over in the file `streams`, if
+/// given, see `crate::minidump_streams`.
+pub fn dump_current_process(
+    helper_path: &Path,
+    destination: &DumpDestination,
+    encrypt_to: Option<&str>,
+    streams: Option<&Path>,
+) -> std::io::Result<()> {
+    let state = prepare(helper_path, destination, encrypt_to, streams)?;
+    // SAFETY: the state outlives the helper, which is waited for.
+    match unsafe { launch_helper(&state) } {
+        Some(0) => Ok(()),
+        Some(code) => Err(std::io::Error::other(format!("crash helper exited with {}", code))),
+        None => Err(std::io::Error::other("failed to launch crash helper")),
+    }
+}
+
+fn prepare(
+    helper_path: &Path,
+    destination: &DumpDestination,
+    encrypt_to: Option<&str>,
+    streams: Option<&Path>,
+) -> std::io::Result<HelperState> {
+    let to_cstring = |s: &str| {
+        CString::new(s).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+    };
+
+    let helper = to_cstring(&helper_path.to_string_lossy())?;
+    let (flag, target) = match destination {
+        DumpDestination::File(path) => ("--output", path.to_string_lossy().into_owned()),
+        DumpDestination::Upload(url) => ("--upload", url.clone()),
+    };
+    let mut args = vec![helper.clone(), to_cstring(flag)?, to_cstring(&target)?];
+    if let Some(recipient) = encrypt_to {
+        args.extend([to_cstring("--encrypt-to")?, to_cstring(recipient)?]);
+    }
+    if let Some(path) = streams {
+        args.extend([to_cstring("--streams")?, to_cstring(&path.to_string_lossy())?]);
+    }
+    let mut argv: Vec<*const c_char> = args.iter().map(|a| a.as_ptr()).collect();
+    argv.push(std::ptr::null());
+
+    Ok(HelperState {
+        helper_path: helper,
+        _args: args,
+        argv,
+    })
+}
+
+/// Returns the default location of the helper: next to the current executable.
+pub fn default_helper_path() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join("crash-helper")))
+        .unwrap_or_else(|| PathBuf::from("crash-helper"))
+}
+
+/// Launches the helper, if installed, and waits for it to dump this process.
+/// Called from the crash signal handler.
+///
+/// # Safety
+/// Must only be called from a signal handler of a crashing thread.
+pub(crate) unsafe fn launch_from_signal() {
+    if let Some(state) = HELPER_STATE.get() {
+        launch_helper(state);
+    }
+}
+
+// Forks and execs the helper, then waits for it. Returns its exit code, or
+// None if it could not be started or was killed.
+unsafe fn launch_helper(state: &HelperState) -> Option<i32> {
+    let mut fds = [0i32; 2];
+    if libc::pipe(fds.as_mut_ptr()) != 0 {
+        return None;
+    }
+    let (read_fd, write_fd) = (fds[0], fds[1]);
+
+    let pid = libc::getpid();
+    let tid = libc::syscall(libc::SYS_gettid) as libc::pid_t;
+
+    let child = libc::fork();
+    if child < 0 {
+        libc::close(read_fd);
+        libc::close(write_fd);
+        return None;
+    }
+    if child == 0 {
+        // Child: move the read end to the well-known fd and become the helper.
+        libc::close(write_fd);
+        if read_fd != HANDSHAKE_FD {
+            libc::dup2(read_fd, HANDSHAKE_FD);
+            libc::close(read_fd);
+        }
+        libc::execv(state.helper_path.as_ptr(), state.argv.as_ptr());
+        libc::_exit(127);
+    }
+
+    // Parent: allow the helper to ptrace us (needed under Yama ptrace_scope=1),
+    // then hand over the ids of the crashing thread.
+    libc::close(read_fd);
+    libc::prctl(libc::PR_SET_PTRACER, child as libc::c_ulong, 0, 0, 0);
+
+    let mut message = [0u8; HANDSHAKE_LEN];
+    message[..4].copy_from_slice(&(pid as u32).to_le_bytes());
+    message[4..].copy_from_slice(&(tid as u32).to_le_bytes());
+    libc::write(write_fd, message.as_ptr() as *const libc::c_void, HANDSHAKE_LEN);
+    libc::close(write_fd);
+
+    // Stay stopped in the handler until the helper has finished the dump.
+    let mut status = 0;
+    if libc::waitpid(child, &mut status, 0) < 0 || !libc::WIFEXITED(status) {
+        return None;
+    }
+    Some(libc::WEXITSTATUS(status))
+}
+
+/// Entry point of the helper executable. Reads the handshake from
+/// `HANDSHAKE_FD` and writes (`--output <path>`) or uploads (`--upload <url>`)
+/// the minidump of the crashing process, encrypted to the age recipient
+/// given with `--encrypt-to`, if any, with the application streams handed
+/// over in the file given with `--streams`, or with `--watchdog` serves crash
+/// notifications (see `crate::watchdog`). Returns the process exit code.
+pub fn run_helper(args: &[String]) -> i32 {
+    if args.iter().any(|a| a == "--watchdog") {
+        return crate::watchdog::run(args);
+    }
+    let value_of = |flag: &str| {
+        let i = args.iter().position(|a| a == flag)?;
+        args.get(i + 1).cloned()
+    };
+    let destination = match (value_of("--output"), value_of("--upload")) {
+        (Some(path), _) => DumpDestination::File(PathBuf::from(path)),
+        (None, Some(url)) => DumpDestination::Upload(url),
+        (None, None) => {
+            eprintln!("crash-helper: missing --output <path> or --upload <url>");
+            return 2;
+        }
+    };
+
+    // SAFETY: the parent process set up HANDSHAKE_FD before exec'ing us.
+    let mut handshake = unsafe { std::fs::File::from_raw_fd(HANDSHAKE_FD) };
+    let mut message = [0u8; HANDSHAKE_LEN];
+    if let Err(e) = handshake.read_exact(&mut message) {
+        eprintln!("crash-helper: handshake failed: {}", e);
+        return 1;
+    }
+    let pid = u32::from_le_bytes([message[0], message[1], message[2], message[3]]) as i32;
+    let tid = u32::from_le_bytes([message[4], message[5], message[6], message[7]]) as i32;
+
+    let encrypt_to = value_of("--encrypt-to");
+    let streams = value_of("--streams")
+        .map(|path| crate::minidump_streams::take_handoff(Path::new(&path)))
+        .unwrap_or_default();
+    match upload::write_dump(&mut MinidumpWriter::new(pid, tid), &destination, encrypt_to.as_deref(), &streams) {
+        Ok(()) => {
+            eprintln!("crash-helper: minidump saved to {}", destination);
+            0
+        }
+        Err(e) => {
+            eprintln!("crash-helper: failed to write minidump '{}': {}", destination, e);
+            1
+        }
+    }
+}
+
@@ -0,0 +1,468 @@
+// Custom panic handler. Its purpose is to capture detailed crash information,
+// format it into a Sentry-like JSON structure, and save it to a file. This
+// allows for post-mortem analysis of application crashes. The hook that was
+// installed before, such as the default one printing the panic or another
+// SDK's, is called afterwards unless `chain_previous_hook` is turned off.
+
+use backtrace::Backtrace;
+#[cfg(not(any(target_os = "linux", windows, target_arch = "wasm32")))]
+use minidump_writer::minidump_writer::MinidumpWriter;
+use std::io::Write;
+use std::panic;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::event::{Exception, MyFrame, MyStacktrace, SentryEvent};
+use crate::transport::Delivery;
+use crate::unwind::Unwinder;
+#[cfg(not(any(target_os = "linux", windows, target_arch = "wasm32")))]
+use crate::upload;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::upload::DumpDestination;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// Set while a thread captures a full report. Panics meanwhile, on other
+// threads, only get the minimal report.
+static IN_HOOK: AtomicBool = AtomicBool::new(false);
+
+// Clears IN_HOOK however the full capture ends.
+struct HookGuard;
+
+impl Drop for HookGuard {
+    fn drop(&mut self) {
+        IN_HOOK.store(false, Ordering::Release);
+    }
+}
+
+/// Installs the panic hook. Returns false if it was already installed.
+pub fn install(config: Config) -> bool {
+    let chain_previous = config.chain_previous_hook;
+    if !store_config(config) {
+        return false;
+    }
+    set_hook(custom_panic_hook, chain_previous);
+    true
+}
+
+/// Makes `hook` the panic hook. With `chain_previous`, the hook it replaces
+/// (the default one that prints the panic, or another library's) runs right
+/// after it.
+pub(crate) fn set_hook(hook: fn(&panic::PanicHookInfo), chain_previous: bool) {
+    if chain_previous {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            hook(info);
+            previous(info);
+        }));
+    } else {
+        panic::set_hook(Box::new(hook));
+    }
+}
+
+/// Keeps `config` for `crash::capture_error` and friends without installing
+/// the hook, as in deferred mode. Returns false if a config was already set.
+pub(crate) fn store_config(config: Config) -> bool {
+    CONFIG.set(config).is_ok()
+}
+
+/// The configuration passed to `crash::init`, if it ran.
+pub(crate) fn config() -> Option<&'static Config> {
+    CONFIG.get()
+}
+
+/// Custom panic hook that captures panic information and writes it to a JSON file.
+fn custom_panic_hook(info: &panic::PanicHookInfo) {
+    let Some(config) = CONFIG.get() else {
+        return;
+    };
+    crate::metrics::PANICS.inc();
+    capture_panic(config, info);
+    // Before the process is gone, if the panic ends it.
+    #[cfg(not(target_arch = "wasm32"))]
+    crate::metrics::push(config);
+}
+
+// Captures the report of a panic and delivers it.
+fn capture_panic(config: &Config, info: &panic::PanicHookInfo) {
+    // Panics caught by `crash::catch_and_report` or, as integrations tell,
+    // by an async runtime do not end the process.
+    let caught = crate::capture::is_catching() || crate::integration::panic_is_caught();
+    // The session counts the panic whether or not it is reported.
+    #[cfg(not(target_arch = "wasm32"))]
+    if caught {
+        crate::session::record_error();
+    } else {
+        crate::session::mark_crashed(true);
+        crate::crash_loop::mark_crashed();
+    }
+    // Reported here, or deliberately not; the abort std may raise next is
+    // the same crash.
+    #[cfg(unix)]
+    if !caught {
+        crate::signals::mark_panic_reported();
+    }
+
+    // Generate a unique ID for this crash event.
+    let event_id = config.new_event_id();
+    // Panic storms are capped before anything touches the disk. A panic that
+    // ends the process is always reported.
+    let decision = if caught {
+        crate::sampling::decide(config, &event_id)
+    } else {
+        crate::sampling::decide_fatal(config)
+    };
+    match decision {
+        crate::sampling::Decision::Capture => {}
+        crate::sampling::Decision::RateLimited => {
+            eprintln!("Crash report {} dropped: more than the configured reports per minute.", event_id);
+            return;
+        }
+        crate::sampling::Decision::SampledOut => {
+            eprintln!("Crash report {} dropped by sampling.", event_id);
+            return;
+        }
+        crate::sampling::Decision::CrashLoop => {
+            eprintln!("Crash report {} dropped: the crash loop was reported already.", event_id);
+            return;
+        }
+    }
+    let event_id_str = event_id.to_string();
+    // Get the current timestamp as seconds since UNIX epoch.
+    let timestamp = crate::clock::since_epoch().as_secs_f64();
+
+    // Extract the panic payload (the message passed to panic!).
+    // Tries to downcast the payload to common string types.
+    let payload = info.payload();
+    let message_str = if let Some(s) = payload.downcast_ref::<&str>() {
+        *s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "Panic occurred without a string message." // Fallback message.
+    };
+
+    // Leave a minimal report on disk before anything that could panic: a
+    // panic inside the hook aborts the process without running the hook
+    // again. The full report replaces it below.
+    let level = config.level_of_panic(info, caught).as_str();
+    let fallback = write_fallback_report(config, &event_id_str, timestamp, level, message_str, info.location());
+    if IN_HOOK.swap(true, Ordering::AcqRel) {
+        eprintln!("Panic while another crash report is being captured; only a minimal report was written.");
+        return;
+    }
+    let _guard = HookGuard;
+
+    let mut sentry_event = base_event(&event_id, timestamp, level, message_str.to_string());
+    // The error of a failed `unwrap`/`expect`, as a chain of exceptions.
+    if let Some(unwrapped) = crate::unwrap_error::parse(message_str, sentry_event.stacktrace.as_ref()) {
+        sentry_event.exception = Some(Exception { values: unwrapped.values });
+    }
+    sentry_event.threads = if config.capture_threads {
+        crate::threads::capture_threads() // Stacks of the other threads.
+    } else {
+        vec![crate::threads::current_thread(true)]
+    };
+
+    let deliveries = match process_and_deliver(config, sentry_event) {
+        Ok(deliveries) => deliveries,
+        Err(dropped_by) => {
+            config.log_debug(format_args!("Crash event dropped by {}.", dropped_by));
+            if let Some(path) = &fallback {
+                let _ = config.storage().remove(path);
+            }
+            return;
+        }
+    };
+    // The minimal report is kept only if the full one went nowhere.
+    if let Some(path) = &fallback {
+        let replaced = deliveries.iter().any(|d| matches!(d, Delivery::Written(p) if p == path));
+        if !deliveries.is_empty() && !replaced {
+            let _ = config.storage().remove(path);
+        }
+    }
+    for delivery in deliveries {
+        match delivery {
+            Delivery::Uploaded(url) => config.log_debug(format_args!("Crash report uploaded to {}", url)),
+            Delivery::Queued(path) => {
+                config.log_debug(format_args!("Crash report queued for upload in {}", path.display()))
+            }
+            Delivery::Written(path) => {
+                // Try to print the absolute path of the saved file for user convenience.
+                let path = std::fs::canonicalize(&path).unwrap_or(path);
+                config.log_debug(format_args!("Crash report saved to {}", path.display()));
+            }
+        }
+    }
+    // For `crash::last_crash` in the next run.
+    #[cfg(not(target_arch = "wasm32"))]
+    if !caught {
+        let crash = crate::feedback::LastCrash {
+            event_id: event_id_str.clone(),
+            timestamp: timestamp.to_string(),
+            level: level.to_string(),
+            message: Some(message_str.to_string()),
+        };
+        crate::feedback::mark(config, &crash);
+    }
+
+    // The process goes on after a caught panic; dumping it would only stall it.
+    #[cfg(not(target_arch = "wasm32"))]
+    if config.minidump && !caught {
+        write_panic_minidump(config, &event_id_str);
+    }
+}
+
+// Writes a Breakpad-compatible minidump of the panicking process.
+#[cfg(not(target_arch = "wasm32"))]
+fn write_panic_minidump(config: &Config, event_id_str: &str) {
+    // ---------- Generate a Breakpad-compatible minidump ----------
+    // With an upload URL configured, the dump is sent to the server instead
+    // of being written next to the report.
+    let dump_destination = config.dump_destination(event_id_str);
+    match write_minidump(config, event_id_str, &dump_destination) {
+        Ok(()) => match &dump_destination {
+            DumpDestination::File(path) => {
+                let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+                config.log_debug(format_args!("Minidump saved to {}", path.display()));
+            }
+            DumpDestination::Upload(url) => config.log_debug(format_args!("Minidump uploaded to {}", url)),
+        },
+        Err(e) => {
+            eprintln!("Failed to write minidump '{}': {}", dump_destination, e);
+        }
+    }
+}
+
+// Frames deeper than this are cut from stacks walked into a buffer.
+const MAX_FRAMES: usize = 256;
+
+/// The current stack, outermost frame first, walked with the unwinder `init`
+/// selected, see `crate::unwind`.
+pub(crate) fn capture_stacktrace() -> Option<MyStacktrace> {
+    let mut frames = match crate::unwind::selected() {
+        Unwinder::Std => crate::unwind::std_frames(),
+        Unwinder::Framehop => {
+            let mut ips = vec![0usize; MAX_FRAMES];
+            let count = crate::unwind::trace(&mut ips);
+            resolve(ips[..count].iter().map(|&ip| ip as *mut std::ffi::c_void))
+        }
+        Unwinder::Backtrace => resolve(Backtrace::new_unresolved().frames().iter().map(|frame| frame.ip())),
+    };
+
+    // Sentry expects frames from innermost to outermost.
+    // `backtrace` provides them outermost to innermost, so we reverse.
+    frames.reverse();
+
+    if !frames.is_empty() {
+        Some(MyStacktrace { frames })
+    } else {
+        None
+    }
+}
+
+// The frames at the instruction pointers `ips`, with inlined functions as
+// frames of their own.
+fn resolve(ips: impl Iterator<Item = *mut std::ffi::c_void>) -> Vec<MyFrame> {
+    let mut frames = Vec::new();
+
+    // Process each frame in the backtrace.
+    // `backtrace::resolve` is used to get symbol information (function name, file, line)
+    // for each instruction pointer in the backtrace.
+    for ip in ips {
+        backtrace::resolve(ip, |symbol| {
+            let name = symbol.name().map(|s| s.to_string());
+            let filename = symbol.filename().map(|p| p.to_string_lossy().into_owned());
+            let lineno = symbol.lineno();
+            let colno = symbol.colno();
+
+            // Create our custom MyFrame struct from the symbol information.
+            frames.push(MyFrame {
+                filename,
+                lineno,
+                colno,
+                function: name,
+                instruction_addr: None,
+                in_app: None,
+                pre_context: Vec::new(),
+                context_line: None,
+                post_context: Vec::new(),
+            });
+        });
+    }
+    frames
+}
+
+/// An event captured on the current thread now, with the stack, breadcrumbs
+/// and everything else collected up front.
+pub(crate) fn base_event(event_id: &Uuid, timestamp: f64, level: &str, message: String) -> SentryEvent {
+    let mut contexts = crate::contexts::get(); // Collected by `crash::init`.
+    contexts.extend(crate::resources::snapshot()); // Memory and resource usage right now.
+    SentryEvent {
+        event_id: event_id.to_string(),
+        schema_version: crate::event::SCHEMA_VERSION,
+        timestamp: timestamp.to_string(),
+        message: Some(message),
+        level: Some(level.to_string()),
+        platform: Some("rust".to_string()),     // Indicate the platform.
+        stacktrace: capture_stacktrace(),
+        installation_id: crate::install_id::installation_id().map(|s| s.to_string()),
+        breadcrumbs: crate::breadcrumbs::snapshot(), // Recorded with `crash::add_breadcrumb`.
+        contexts,
+        threads: vec![crate::threads::current_thread(false)], // Name and id of this thread.
+        debug_meta: crate::debug_meta::collect(),    // Modules loaded right now.
+        ..Default::default()
+    }
+}
+
+/// Completes `event` (fingerprint, release, scopes, attachments), runs it
+/// through the integrations and `before_send`, then uploads and/or writes
+/// it. Returns where it was delivered to, or what dropped it.
+pub(crate) fn process_and_deliver(config: &Config, mut event: SentryEvent) -> Result<Vec<Delivery>, &'static str> {
+    // In-app frames decide the fingerprint.
+    crate::frames::process(config, &mut event);
+    crate::source_context::add(config, &mut event);
+    event.fingerprint = crate::fingerprint::compute(event.message.as_deref(), event.stacktrace.as_ref());
+    config.apply_release(&mut event);
+    // Tags, user and extra context from `crash::set_tag` and friends.
+    crate::scope::apply_scopes(&mut event);
+    // Registered with `crash::attach_file` / `crash::attach_bytes`, after any
+    // the event came with.
+    let registered = crate::attachments::save(config, &event.event_id);
+    event.attachments.extend(registered);
+
+    // Let registered integrations add breadcrumbs, enrich or drop the event,
+    // then give the application's `before_send` the last word.
+    let event = crate::integration::process_event(event)
+        .ok_or("an integration")
+        .and_then(|event| config.apply_before_send(event).ok_or("before_send"))
+        .inspect_err(|_| crate::metrics::REPORTS_DROPPED.inc())?;
+
+    // Serialize the SentryEvent to a pretty JSON string.
+    let json_payload = match serde_json::to_string_pretty(&event) {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("Failed to serialize Sentry event to JSON: {}", e);
+            crate::metrics::REPORTS_DROPPED.inc();
+            return Ok(Vec::new());
+        }
+    };
+
+    // Generate the filename for the crash report from the configured template.
+    let timestamp_secs = event.timestamp.parse::<f64>().unwrap_or(0.0) as u64;
+    let filename = config.report_file_name(&event.event_id, timestamp_secs);
+
+    // Upload and/or write the JSON payload. Local writes fall back to the
+    // temp dir if the output directory has become unwritable.
+    Ok(crate::transport::deliver_report(config, &filename, json_payload.as_bytes()))
+}
+
+// Writes `s` as a JSON string.
+fn write_json_str(out: &mut impl Write, s: &str) -> std::io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_all(c.encode_utf8(&mut [0u8; 4]).as_bytes())?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+// Writes a report holding only the message and location, without serde or
+// anything else that could fail along with the panicking code. Returns where
+// it was stored.
+fn write_fallback_report(
+    config: &Config,
+    event_id: &str,
+    timestamp: f64,
+    level: &str,
+    message: &str,
+    location: Option<&panic::Location>,
+) -> Option<PathBuf> {
+    let file_name = config.report_file_name(event_id, timestamp as u64);
+    let write = || -> std::io::Result<PathBuf> {
+        let mut file = Vec::with_capacity(512);
+        write!(
+            file,
+            "{{\"event_id\":\"{}\",\"schema_version\":{},\"timestamp\":\"{}\",\"message\":",
+            event_id,
+            crate::event::SCHEMA_VERSION,
+            timestamp
+        )?;
+        // The message is not run through the scrubber or encrypted here, so
+        // leave it out when either is configured.
+        let filtered = config.scrub_pii || config.encrypt_to.is_some();
+        write_json_str(&mut file, if filtered { crate::scrub::FILTERED } else { message })?;
+        write!(file, ",\"level\":\"{}\",\"platform\":\"rust\"", level)?;
+        if let Some(location) = location {
+            file.write_all(b",\"culprit\":")?;
+            write_json_str(&mut file, &format!("{}:{}:{}", location.file(), location.line(), location.column()))?;
+        }
+        file.write_all(b",\"extra\":{\"partial_report\":true}}")?;
+        config.storage().write(&file_name, &file)
+    };
+    match write() {
+        Ok(path) => Some(path),
+        Err(e) => {
+            eprintln!("Failed to write minimal crash report '{}': {}", file_name, e);
+            None
+        }
+    }
+}
+
+// On Linux a process cannot ptrace itself, so the dump is taken by the
+// watchdog or the helper executable, exactly as for native crashes.
+// The application streams go to them in a file.
+#[cfg(target_os = "linux")]
+fn write_minidump(config: &Config, event_id: &str, destination: &DumpDestination) -> Result<(), String> {
+    let streams = crate::minidump_streams::collect();
+    let mut handoff = None;
+    if !streams.is_empty() {
+        let path = crate::minidump_streams::handoff_path(event_id);
+        match crate::minidump_streams::write_handoff(&path, &streams) {
+            Ok(()) => handoff = Some(path),
+            Err(e) => eprintln!("Failed to hand over minidump streams '{}': {}", path.display(), e),
+        }
+    }
+    let written = if crate::watchdog::notify_dump(event_id, destination, handoff.as_deref()) {
+        Ok(())
+    } else {
+        let helper_path = config
+            .helper_path
+            .clone()
+            .unwrap_or_else(crate::helper::default_helper_path);
+        crate::helper::dump_current_process(&helper_path, destination, config.encrypt_to.as_deref(), handoff.as_deref())
+            .map_err(|e| e.to_string())
+    };
+    // The watchdog or the helper removes the streams once read, and is done
+    // with them by now; they are left behind when the dump failed first.
+    if let Some(path) = handoff {
+        let _ = std::fs::remove_file(path);
+    }
+    written
+}
+
+#[cfg(windows)]
+fn write_minidump(config: &Config, event_id: &str, destination: &DumpDestination) -> Result<(), String> {
+    let streams = crate::minidump_streams::collect();
+    crate::windows_dump::write_dump(
+        config.minidump_type,
+        event_id,
+        destination,
+        config.encrypt_to.as_deref(),
+        &streams,
+    )
+}
+
+#[cfg(not(any(target_os = "linux", windows, target_arch = "wasm32")))]
+fn write_minidump(config: &Config, _event_id: &str, destination: &DumpDestination) -> Result<(), String> {
+    let mut writer = MinidumpWriter::new(None, None);
+    let streams = crate::minidump_streams::collect();
+    upload::write_dump(&mut writer, destination, config.encrypt_to.as_deref(), &streams)
+}
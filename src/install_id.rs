@@ -0,0 +1,70 @@
+// Persistent anonymous installation ID.
+//
+// A random UUID is generated the first time the application runs and stored in
+// the per-user data directory. It is attached to every report so the server can
+// count affected installations without collecting any personal data. On
+// wasm32 it is kept in `localStorage` instead.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+#[cfg(not(target_arch = "wasm32"))]
+use uuid::Uuid;
+
+#[cfg(not(target_arch = "wasm32"))]
+const INSTALL_ID_FILE: &str = "installation_id";
+
+static INSTALLATION_ID: OnceLock<Option<String>> = OnceLock::new();
+
+/// Returns the per-user data directory used by the crash handler.
+pub fn data_dir() -> PathBuf {
+    let env_dir = |var: &str| std::env::var_os(var).filter(|v| !v.is_empty()).map(PathBuf::from);
+
+    let base = if cfg!(target_os = "windows") {
+        env_dir("LOCALAPPDATA")
+    } else if cfg!(target_os = "macos") {
+        env_dir("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        env_dir("XDG_DATA_HOME").or_else(|| env_dir("HOME").map(|home| home.join(".local").join("share")))
+    };
+    base.unwrap_or_else(std::env::temp_dir).join("crash")
+}
+
+/// Returns the installation ID, creating and persisting it on first use.
+/// The value is cached, so only the first call touches the filesystem;
+/// call it once at startup to keep I/O out of the panic hook.
+pub fn installation_id() -> Option<&'static str> {
+    INSTALLATION_ID
+        .get_or_init(|| match load_or_create() {
+            Ok(id) => Some(id),
+            Err(e) => {
+                eprintln!("Failed to load installation ID: {}", e);
+                None
+            }
+        })
+        .as_deref()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn load_or_create() -> std::io::Result<String> {
+    crate::web::installation_id()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn load_or_create() -> std::io::Result<String> {
+    let dir = data_dir();
+    let path = dir.join(INSTALL_ID_FILE);
+
+    if let Ok(existing) = fs::read_to_string(&path) {
+        let existing = existing.trim();
+        if Uuid::parse_str(existing).is_ok() {
+            return Ok(existing.to_string());
+        }
+    }
+
+    let id = Uuid::new_v4().to_string();
+    fs::create_dir_all(&dir)?;
+    fs::write(&path, &id)?;
+    Ok(id)
+}
@@ -0,0 +1,88 @@
+// Pluggable capture extensions.
+//
+// Features such as log or tracing adapters, async runtime capture and resource
+// samplers hook into event capture through the `Integration` trait instead of
+// being hard-wired into the panic hook. Third parties can implement it too.
+
+use std::sync::RwLock;
+
+use crate::event::{Breadcrumb, SentryEvent};
+
+/// An extension of the crash handler. All methods have no-op defaults, so an
+/// integration only implements the hooks it needs.
+pub trait Integration: Send + Sync {
+    /// Short identifier, used to avoid registering the same integration twice.
+    fn name(&self) -> &'static str;
+
+    /// Called once when the integration is registered.
+    fn setup(&self) {}
+
+    /// Contributes breadcrumbs to an event about to be captured.
+    fn breadcrumbs(&self) -> Vec<Breadcrumb> {
+        Vec::new()
+    }
+
+    /// Whether a panic on the current thread, right now, will be caught and
+    /// the process keep running, e.g. inside an async runtime's task. Such
+    /// panics are reported at `Builder::caught_panic_level` and without a
+    /// minidump.
+    fn panic_is_caught(&self) -> bool {
+        false
+    }
+
+    /// Enriches an event, or drops it by returning `None`.
+    fn process_event(&self, event: SentryEvent) -> Option<SentryEvent> {
+        Some(event)
+    }
+}
+
+static INTEGRATIONS: RwLock<Vec<Box<dyn Integration>>> = RwLock::new(Vec::new());
+
+/// Registers an integration and runs its setup hook. Returns `false` if an
+/// integration with the same name is already registered.
+pub fn register_integration<I: Integration + 'static>(integration: I) -> bool {
+    let mut integrations = INTEGRATIONS.write().unwrap_or_else(|e| e.into_inner());
+    if integrations.iter().any(|i| i.name() == integration.name()) {
+        return false;
+    }
+    integration.setup();
+    integrations.push(Box::new(integration));
+    true
+}
+
+/// Returns the names of all registered integrations.
+pub fn registered_integrations() -> Vec<&'static str> {
+    INTEGRATIONS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|i| i.name())
+        .collect()
+}
+
+/// Runs an event through every registered integration: breadcrumb sources
+/// first, then event processors in registration order.
+pub fn process_event(mut event: SentryEvent) -> Option<SentryEvent> {
+    let integrations = INTEGRATIONS.read().unwrap_or_else(|e| e.into_inner());
+    for integration in integrations.iter() {
+        event.breadcrumbs.extend(integration.breadcrumbs());
+    }
+    event.breadcrumbs.sort_by(|a, b| {
+        let parse = |t: &str| t.parse::<f64>().unwrap_or(0.0);
+        parse(&a.timestamp).total_cmp(&parse(&b.timestamp))
+    });
+    for integration in integrations.iter() {
+        event = integration.process_event(event)?;
+    }
+    Some(event)
+}
+
+/// Whether any registered integration reports that a panic on the current
+/// thread will be caught. Called from the panic hook, so a registry being
+/// modified at the time is skipped.
+pub fn panic_is_caught() -> bool {
+    match INTEGRATIONS.try_read() {
+        Ok(integrations) => integrations.iter().any(|i| i.panic_is_caught()),
+        Err(_) => false,
+    }
+}
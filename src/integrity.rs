@@ -0,0 +1,123 @@
+// Integrity hashes and signatures of crash artifacts.
+//
+// Every report, minidump and attachment gets the SHA-256 of its content: the
+// bytes as encoded, before compression and encryption, so the digest is the
+// same however the artifact is sent or stored. With a signing key
+// (`Builder::signing_key`, by default from CRASH_SIGNING_KEY) reports are
+// also signed, with HMAC-SHA256 of the same bytes under the key. A report
+// lists the digests of its attachments, so its signature covers them too.
+// Minidumps are hashed but not signed: the helper and the watchdog that
+// often write them do not hold the key.
+//
+// Uploads carry the digest in `X-Crash-Sha256` and the signature in
+// `X-Crash-Signature`, both hex. Reports and minidumps written locally get a
+// `<file name>.sum` file next to them with the same as JSON. The crash server
+// checks both and flags what does not match (see `server/src/integrity.rs`),
+// so truncated or altered reports stand out. Not covered: the minimal report
+// the panic hook writes first, and encrypted reports retried from the upload
+// queue, whose content cannot be read any more.
+
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::{Digest as _, Sha256};
+
+/// Header carrying the SHA-256 of an upload's content.
+pub const SHA256_HEADER: &str = "X-Crash-Sha256";
+
+/// Header carrying the signature of an uploaded report.
+pub const SIGNATURE_HEADER: &str = "X-Crash-Signature";
+
+/// Suffix of the file holding the digest of a local artifact.
+pub const SUFFIX: &str = ".sum";
+
+pub const SIGNING_KEY_ENV: &str = "CRASH_SIGNING_KEY";
+
+/// Secret reports are signed with, shared with the crash server. Not shown
+/// by `Debug`.
+#[derive(Clone)]
+pub struct SigningKey(String);
+
+impl SigningKey {
+    pub fn new(key: impl Into<String>) -> Self {
+        SigningKey(key.into())
+    }
+
+    /// The key in CRASH_SIGNING_KEY, if set.
+    pub fn from_env() -> Option<Self> {
+        std::env::var(SIGNING_KEY_ENV).ok().filter(|k| !k.is_empty()).map(SigningKey)
+    }
+}
+
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SigningKey(..)")
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hex SHA-256 of `data`.
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// Hex HMAC-SHA256 signature of `data` under `key`.
+pub fn sign(key: &SigningKey, data: &[u8]) -> String {
+    // HMAC takes keys of any length.
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.0.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(data);
+    hex(&mac.finalize().into_bytes())
+}
+
+/// Digest, and signature if any, of an artifact's content.
+#[derive(Clone, Debug, Serialize)]
+pub struct Digest {
+    pub sha256: String,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl Digest {
+    /// The digest of `data`, signed with `key` if given.
+    pub fn new(data: &[u8], key: Option<&SigningKey>) -> Self {
+        Digest {
+            sha256: sha256_hex(data),
+            size: data.len() as u64,
+            signature: key.map(|key| sign(key, data)),
+        }
+    }
+
+    /// The unsigned digest of everything `reader` yields.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn of_reader(mut reader: impl std::io::Read) -> std::io::Result<Self> {
+        let mut hasher = Sha256::new();
+        let size = std::io::copy(&mut reader, &mut hasher)?;
+        Ok(Digest {
+            sha256: hex(&hasher.finalize()),
+            size,
+            signature: None,
+        })
+    }
+
+    /// The headers announcing this digest on an upload.
+    pub fn headers(&self) -> Vec<(&'static str, &str)> {
+        let mut headers = vec![(SHA256_HEADER, self.sha256.as_str())];
+        if let Some(signature) = &self.signature {
+            headers.push((SIGNATURE_HEADER, signature.as_str()));
+        }
+        headers
+    }
+
+    /// Contents of the `.sum` file stored next to the artifact.
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap_or_default()
+    }
+}
+
+/// Name of the `.sum` file of the artifact stored as `file_name`.
+pub fn sum_file_name(file_name: &str) -> String {
+    format!("{}{}", file_name, SUFFIX)
+}
@@ -0,0 +1,303 @@
+// Crash capture library. Applications call `crash::init` (or use
+// `crash::Builder`) once at startup and keep their own `main`. On wasm32 only
+// the panic hook and the capture functions are available, see `crate::web`.
+
+pub mod arguments;
+pub mod attachments;
+pub mod breadcrumbs;
+pub mod capture;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod child;
+mod clock;
+pub mod compression;
+pub mod config;
+pub mod contexts;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod crash_loop;
+#[cfg(all(feature = "deadlock", not(target_arch = "wasm32")))]
+pub mod deadlock;
+pub mod debug_meta;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod deferred;
+pub mod encoding;
+pub mod encryption;
+pub mod environment;
+pub mod event;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod feedback;
+pub mod fingerprint;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod flush;
+pub mod frames;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod hang;
+#[cfg(target_os = "linux")]
+pub mod helper;
+pub mod hook;
+pub mod install_id;
+pub mod integrity;
+pub mod integration;
+#[cfg(feature = "log")]
+pub mod log_integration;
+#[cfg(target_os = "macos")]
+pub mod mach_exceptions;
+pub mod metrics;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod minidump_streams;
+pub mod oom;
+pub mod output;
+#[cfg(unix)]
+pub mod output_capture;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod queue;
+#[cfg(unix)]
+pub mod raw_report;
+pub mod resources;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod retention;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod s3;
+pub mod sampling;
+pub mod scope;
+pub mod scrub;
+pub mod sentry;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod session;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod settings;
+#[cfg(unix)]
+pub mod signals;
+pub mod source_context;
+pub mod storage;
+pub mod threads;
+#[cfg(feature = "tokio")]
+pub mod tokio_integration;
+#[cfg(feature = "tracing")]
+pub mod tracing_integration;
+pub mod transport;
+pub mod unwind;
+pub mod unwrap_error;
+pub mod upload;
+#[cfg(target_os = "linux")]
+pub mod watchdog;
+#[cfg(target_arch = "wasm32")]
+pub mod web;
+pub mod windows_dump;
+
+pub use attachments::{attach_bytes, attach_file, attach_with, clear_attachments};
+pub use breadcrumbs::{add_breadcrumb, clear_breadcrumbs};
+pub use capture::{capture_error, capture_exception, capture_message, catch_and_report, Level};
+#[cfg(not(target_arch = "wasm32"))]
+pub use child::{monitor_child, ChildMonitor};
+pub use compression::Compression;
+#[cfg(not(target_arch = "wasm32"))]
+pub use crash_loop::in_crash_loop;
+pub use encoding::Encoding;
+#[cfg(not(target_arch = "wasm32"))]
+pub use flush::{flush, FlushGuard};
+#[cfg(not(target_arch = "wasm32"))]
+pub use hang::heartbeat;
+pub use config::{Builder, Config};
+pub use event::{Breadcrumb, SentryEvent, User};
+#[cfg(not(target_arch = "wasm32"))]
+pub use feedback::{last_crash, submit_feedback, LastCrash, UserFeedback};
+#[cfg(not(target_arch = "wasm32"))]
+pub use minidump_streams::{add_minidump_stream, clear_minidump_streams};
+pub use oom::CrashAllocator;
+#[cfg(not(target_arch = "wasm32"))]
+pub use s3::S3Transport;
+pub use scope::{configure_scope, push_scope, set_extra, set_tag, set_user, with_scope, Scope};
+#[cfg(not(target_arch = "wasm32"))]
+pub use session::{end_session, start_session};
+pub use storage::{FileStorage, Storage};
+pub use transport::{Delivery, Transport};
+pub use unwind::Unwinder;
+pub use windows_dump::MinidumpType;
+#[cfg(unix)]
+pub use transport::UnixSocketTransport;
+
+/// `<package name>@<package version>` of the crate this is invoked from, for
+/// `Builder::release`.
+#[macro_export]
+macro_rules! release_name {
+    () => {
+        concat!(env!("CARGO_PKG_NAME"), "@", env!("CARGO_PKG_VERSION"))
+    };
+}
+
+/// Installs the crash handler described by `config`: the panic hook (regular
+/// or deferred), and on Unix signal handlers for native crashes, which on
+/// Linux also dump the process through the out-of-process helper. Reports
+/// left behind by a previous deferred crash are completed first. Settings from
+/// `crash.toml` and CRASH_* variables are laid over `config`, see
+/// `crate::settings`.
+pub fn init(mut config: Config) -> std::io::Result<()> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if config.external_config {
+            settings::apply(&mut config)?;
+        }
+        config.output_dir = output::writable_dir(&config.output_dir);
+    }
+    if let Some(dsn) = &config.sentry_dsn {
+        sentry::Dsn::parse(dsn).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    }
+    if !(0.0..=1.0).contains(&config.sample_rate) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("sample rate {} is not between 0.0 and 1.0", config.sample_rate),
+        ));
+    }
+    scrub::Scrubber::from_config(&config)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    if let Some(recipient) = &config.encrypt_to {
+        encryption::validate(recipient)?;
+    }
+    if config.app_name.is_none() {
+        config.app_name = Some(output::default_app_name());
+    }
+    if config.release.is_none() {
+        if let (Some(name), Some(version)) = (&config.app_name, &config.app_version) {
+            config.release = Some(format!("{}@{}", name, version));
+        }
+    }
+    if config.server_name.is_none() {
+        config.server_name = contexts::hostname();
+    }
+    breadcrumbs::set_max_breadcrumbs(config.max_breadcrumbs);
+    unwind::select(config.unwinder);
+    contexts::init(&config);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    complete_previous_run(&config);
+    // Before the hooks, so their reports carry the tag.
+    #[cfg(not(target_arch = "wasm32"))]
+    crash_loop::start(&config)?;
+
+    // Resolve the installation ID up front so the panic hook never has to
+    // touch the filesystem to obtain it.
+    install_id::installation_id();
+
+    #[cfg(unix)]
+    if config.capture_output > 0 {
+        output_capture::install(config.capture_output)?;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(addr) = &config.metrics_addr {
+        metrics::serve(addr)?;
+    }
+
+    // Native crashes (SIGSEGV and friends) bypass the panic hook. The signal
+    // handlers write the report; on Linux they also hand the process to the
+    // bundled helper, which dumps us from outside under the same event id.
+    #[cfg(unix)]
+    if config.native_crashes {
+        let event_id = config.new_event_id().to_string();
+        #[cfg(target_os = "linux")]
+        {
+            let helper_path = config.helper_path.clone().unwrap_or_else(helper::default_helper_path);
+            // The on-demand helper stays installed as a fallback for when the
+            // watchdog does not answer.
+            if config.watchdog {
+                watchdog::spawn(&helper_path, &config)?;
+            }
+            helper::install(&helper_path, &config.dump_destination(&event_id), config.encrypt_to.as_deref())?;
+        }
+        // The report path is fixed now, so `{timestamp}` is the time of init.
+        let install_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let report_path = config
+            .output_dir
+            .join(config.report_file_name(&event_id, install_time));
+        signals::install(&report_path, &event_id, &config)?;
+        // Faults reach the Mach exception port before they become signals.
+        #[cfg(target_os = "macos")]
+        mach_exceptions::install(&config, &event_id)?;
+    }
+
+    // Deferred mode trades report richness for a hook that finishes in well
+    // under a millisecond; the record is completed on the next start.
+    #[cfg(not(target_arch = "wasm32"))]
+    let memory_limit = config.memory_limit;
+    #[cfg(not(target_arch = "wasm32"))]
+    let auto_session = config.auto_session;
+    #[cfg(all(feature = "deadlock", not(target_arch = "wasm32")))]
+    let deadlock_timeout = config.deadlock_timeout;
+    #[cfg(not(target_arch = "wasm32"))]
+    let hang_timeout = config.hang_timeout;
+    #[cfg(not(target_arch = "wasm32"))]
+    let installed = if config.deferred || config.preallocate {
+        deferred::install(config.output_dir.clone(), config.chain_previous_hook, config.preallocate)
+            && hook::store_config(config)
+    } else {
+        hook::install(config)
+    };
+    #[cfg(target_arch = "wasm32")]
+    let installed = hook::install(config);
+    if !installed {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "crash handler already installed",
+        ));
+    }
+
+    // Reports go through the hook's config, so the monitor starts last.
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(limit) = memory_limit {
+        oom::spawn_monitor(limit)?;
+    }
+    #[cfg(all(feature = "deadlock", not(target_arch = "wasm32")))]
+    deadlock::spawn_monitor(deadlock_timeout)?;
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(timeout) = hang_timeout {
+        hang::spawn_monitor(timeout)?;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if auto_session {
+        session::start_session();
+    }
+    // The gateway gets the counters now, with the reports completed from
+    // earlier runs, rather than with the first report.
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(config) = hook::config().filter(|config| config.metrics_push_url.is_some()) {
+        let _ = flush::spawn("crash-metrics", move || metrics::push(config));
+    }
+    Ok(())
+}
+
+// Completes the deferred records, prunes old crashes, ends open sessions and
+// retries the uploads left behind by earlier runs.
+#[cfg(not(target_arch = "wasm32"))]
+fn complete_previous_run(config: &Config) {
+    match deferred::process_pending(config) {
+        Ok(reports) => {
+            for report in reports {
+                config.log_debug(format_args!("Completed deferred crash report {}", report));
+            }
+        }
+        Err(e) => eprintln!("Failed to process deferred crash records: {}", e),
+    }
+    // Completed records may have noted the crash of the previous run.
+    feedback::load(config);
+
+    // Pending records became reports above, so they count towards the limits.
+    match retention::sweep(config) {
+        Ok(swept) if swept.crashes > 0 => config.log_debug(format_args!(
+            "Removed {} old crash(es), {} file(s), {} bytes",
+            swept.crashes, swept.files, swept.bytes
+        )),
+        Ok(_) => {}
+        Err(e) => eprintln!("Failed to prune old crash files: {}", e),
+    }
+    // Sessions that ended with the process that ran them.
+    match session::complete_previous(config) {
+        Ok(0) => {}
+        Ok(ended) => config.log_debug(format_args!("Ended {} session(s) left open by earlier runs", ended)),
+        Err(e) => eprintln!("Failed to complete earlier sessions: {}", e),
+    }
+    // Reports that could not be uploaded last time.
+    queue::spawn_retry(config);
+}
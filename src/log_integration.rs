@@ -0,0 +1,146 @@
+// `log` crate integration (feature `log`).
+//
+// `CrashLogger` is a `log::Log` implementation that keeps the most recent log
+// lines in a bounded in-memory buffer. When a panic is captured the lines are
+// embedded in the event as `extra.log` and written next to the report as the
+// `log.txt` attachment. Records can also be forwarded to another logger, so
+// an application keeps its usual output, e.g.
+//
+//     crash::log_integration::CrashLogger::new(200)
+//         .forward_to(Box::new(env_logger::Logger::from_default_env()))
+//         .install()?;
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+use crate::event::SentryEvent;
+use crate::integration::Integration;
+
+pub const DEFAULT_MAX_LOG_LINES: usize = 100;
+pub const LOG_ATTACHMENT_NAME: &str = "log.txt";
+
+struct LogBuffer {
+    capacity: usize,
+    lines: VecDeque<String>,
+}
+
+static BUFFER: Mutex<LogBuffer> = Mutex::new(LogBuffer {
+    capacity: DEFAULT_MAX_LOG_LINES,
+    lines: VecDeque::new(),
+});
+
+/// Logger recording the last lines logged for crash reports.
+pub struct CrashLogger {
+    level: LevelFilter,
+    inner: Option<Box<dyn Log>>,
+}
+
+impl CrashLogger {
+    /// Creates a logger keeping the last `capacity` lines, at `Info` and above.
+    pub fn new(capacity: usize) -> Self {
+        let mut buffer = BUFFER.lock().unwrap_or_else(|e| e.into_inner());
+        buffer.capacity = capacity;
+        while buffer.lines.len() > capacity {
+            buffer.lines.pop_front();
+        }
+        CrashLogger {
+            level: LevelFilter::Info,
+            inner: None,
+        }
+    }
+
+    /// Most verbose level recorded.
+    pub fn with_level(mut self, level: LevelFilter) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Passes every record on to `logger` as well.
+    pub fn forward_to(mut self, logger: Box<dyn Log>) -> Self {
+        self.inner = Some(logger);
+        self
+    }
+
+    /// Installs the logger as the global `log` logger and registers the
+    /// integration embedding the buffer in crash reports.
+    pub fn install(self) -> Result<(), SetLoggerError> {
+        let level = match &self.inner {
+            // The forwarded logger may want more than the buffer keeps.
+            Some(_) => LevelFilter::max(),
+            None => self.level,
+        };
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(level);
+        crate::integration::register_integration(LogIntegration);
+        Ok(())
+    }
+}
+
+impl Log for CrashLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level || self.inner.as_ref().is_some_and(|inner| inner.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= self.level {
+            let timestamp = crate::clock::since_epoch().as_secs_f64();
+            let line = format!("{:.3} {:<5} {}: {}", timestamp, record.level(), record.target(), record.args());
+            let mut buffer = BUFFER.lock().unwrap_or_else(|e| e.into_inner());
+            if buffer.capacity > 0 {
+                while buffer.lines.len() >= buffer.capacity {
+                    buffer.lines.pop_front();
+                }
+                buffer.lines.push_back(line);
+            }
+        }
+        if let Some(inner) = &self.inner {
+            if inner.enabled(record.metadata()) {
+                inner.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(inner) = &self.inner {
+            inner.flush();
+        }
+    }
+}
+
+/// The buffered log lines, oldest first. Used by the panic hook, so a buffer
+/// locked by the panicking thread is skipped rather than waited for.
+pub fn snapshot() -> Vec<String> {
+    match BUFFER.try_lock() {
+        Ok(buffer) => buffer.lines.iter().cloned().collect(),
+        Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner().lines.iter().cloned().collect(),
+        Err(std::sync::TryLockError::WouldBlock) => Vec::new(),
+    }
+}
+
+fn attachment() -> Vec<u8> {
+    let mut text = snapshot().join("\n");
+    text.push('\n');
+    text.into_bytes()
+}
+
+struct LogIntegration;
+
+impl Integration for LogIntegration {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    fn setup(&self) {
+        crate::attachments::attach_with(LOG_ATTACHMENT_NAME, attachment);
+    }
+
+    fn process_event(&self, mut event: SentryEvent) -> Option<SentryEvent> {
+        let lines = snapshot();
+        if !lines.is_empty() {
+            event.extra.insert("log".to_string(), lines.into());
+        }
+        Some(event)
+    }
+}
@@ -0,0 +1,319 @@
+// Native crash capture through Mach exceptions (macOS).
+//
+// On macOS a hardware fault is first delivered as a Mach exception to the
+// task's exception port and only turned into a POSIX signal when nobody
+// handles it there. Signals are unreliable for some crashes (a thread with
+// signals blocked, a corrupted stack without an alternate one, a handler
+// replaced by another library), so like Crashpad we also register an
+// exception port for EXC_BAD_ACCESS, EXC_BAD_INSTRUCTION, EXC_ARITHMETIC and
+// EXC_BREAKPOINT, served by a dedicated thread.
+//
+// That thread is not the one that crashed: it reads the crashed thread's
+// registers, walks its frame pointers with `mach_vm_read_overwrite` (so a
+// corrupt stack cannot fault the handler), writes the same report as the
+// signal handlers (see `crate::signals`), and with minidumps enabled dumps
+// the process with the crash context attached. It then declines the
+// exception, so the kernel raises the matching signal as usual; the signal
+// handler sees the report already written and only re-raises. A debugger
+// attached later replaces the exception port and takes precedence.
+
+use std::sync::OnceLock;
+
+use crate::config::Config;
+use crate::upload::DumpDestination;
+
+type MachPort = u32;
+type KernReturn = i32;
+
+const KERN_SUCCESS: KernReturn = 0;
+const KERN_FAILURE: KernReturn = 5;
+const MACH_PORT_NULL: MachPort = 0;
+const MACH_PORT_RIGHT_RECEIVE: u32 = 1;
+const MACH_MSG_TYPE_MAKE_SEND: u32 = 20;
+const MACH_SEND_MSG: i32 = 1;
+const MACH_RCV_MSG: i32 = 2;
+
+const EXC_BAD_ACCESS: i32 = 1;
+const EXC_BAD_INSTRUCTION: i32 = 2;
+const EXC_ARITHMETIC: i32 = 3;
+const EXC_BREAKPOINT: i32 = 6;
+const EXCEPTION_MASK: u32 =
+    1 << EXC_BAD_ACCESS | 1 << EXC_BAD_INSTRUCTION | 1 << EXC_ARITHMETIC | 1 << EXC_BREAKPOINT;
+// EXCEPTION_DEFAULT with 64-bit codes (MACH_EXCEPTION_CODES).
+const EXCEPTION_BEHAVIOR: i32 = (1 | 0x8000_0000u32) as i32;
+
+// Register state of the crashed thread: flavor, word count, and the indices
+// of the program counter and frame pointer in it.
+#[cfg(target_arch = "x86_64")]
+mod state {
+    pub const THREAD_STATE_NONE: i32 = 13;
+    pub const FLAVOR: i32 = 4; // x86_THREAD_STATE64
+    pub const COUNT: usize = 42;
+    pub const PC: usize = 16; // rip
+    pub const FP: usize = 6; // rbp
+}
+
+#[cfg(target_arch = "aarch64")]
+mod state {
+    pub const THREAD_STATE_NONE: i32 = 5;
+    pub const FLAVOR: i32 = 6; // ARM_THREAD_STATE64
+    pub const COUNT: usize = 68;
+    pub const PC: usize = 32;
+    pub const FP: usize = 29;
+}
+
+const MAX_FRAMES: usize = 128;
+
+// Message layouts, filled in and read by the kernel.
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+struct MessageHeader {
+    bits: u32,
+    size: u32,
+    remote_port: MachPort,
+    local_port: MachPort,
+    voucher_port: MachPort,
+    id: i32,
+}
+
+#[repr(C, packed(4))]
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+struct PortDescriptor {
+    name: MachPort,
+    pad: u32,
+    disposition_and_type: u32,
+}
+
+// `__Request__mach_exception_raise_t`, with room for the trailer.
+#[repr(C, packed(4))]
+#[allow(dead_code)]
+struct ExceptionRequest {
+    header: MessageHeader,
+    descriptor_count: u32,
+    thread: PortDescriptor,
+    task: PortDescriptor,
+    ndr: [u8; 8],
+    exception: i32,
+    code_count: u32,
+    code: [i64; 2],
+    trailer: [u8; 256],
+}
+
+// `__Reply__mach_exception_raise_t`.
+#[repr(C, packed(4))]
+#[allow(dead_code)]
+struct ExceptionReply {
+    header: MessageHeader,
+    ndr: [u8; 8],
+    return_code: KernReturn,
+}
+
+// NDR_record for little-endian two's complement integers.
+const NDR_RECORD: [u8; 8] = [0, 0, 0, 0, 1, 0, 0, 0];
+
+extern "C" {
+    static mach_task_self_: MachPort;
+    fn mach_thread_self() -> MachPort;
+    fn mach_port_allocate(task: MachPort, right: u32, name: *mut MachPort) -> KernReturn;
+    fn mach_port_insert_right(task: MachPort, name: MachPort, right: MachPort, right_type: u32) -> KernReturn;
+    fn task_set_exception_ports(task: MachPort, mask: u32, port: MachPort, behavior: i32, flavor: i32) -> KernReturn;
+    fn thread_get_state(thread: MachPort, flavor: i32, state: *mut u32, count: *mut u32) -> KernReturn;
+    fn mach_vm_read_overwrite(task: MachPort, address: u64, size: u64, data: u64, out_size: *mut u64) -> KernReturn;
+    fn mach_msg(
+        msg: *mut MessageHeader,
+        option: i32,
+        send_size: u32,
+        receive_size: u32,
+        receive_port: MachPort,
+        timeout: u32,
+        notify: MachPort,
+    ) -> KernReturn;
+}
+
+// Where the minidump goes, if one is wanted; decided at install time.
+static DUMP_DESTINATION: OnceLock<Option<DumpDestination>> = OnceLock::new();
+
+fn task_self() -> MachPort {
+    // SAFETY: set up by the runtime before `main`.
+    unsafe { mach_task_self_ }
+}
+
+fn exception_name(exception: i32) -> (&'static str, &'static str) {
+    match exception {
+        EXC_BAD_ACCESS => ("EXC_BAD_ACCESS", "invalid memory access"),
+        EXC_BAD_INSTRUCTION => ("EXC_BAD_INSTRUCTION", "illegal instruction"),
+        EXC_ARITHMETIC => ("EXC_ARITHMETIC", "arithmetic exception"),
+        EXC_BREAKPOINT => ("EXC_BREAKPOINT", "trace/breakpoint trap"),
+        _ => ("EXC_UNKNOWN", "unknown exception"),
+    }
+}
+
+/// Registers the exception port and starts the thread serving it. Call
+/// after `crate::signals::install`, whose report it writes; `event_id` is
+/// the id used there.
+pub fn install(config: &Config, event_id: &str) -> std::io::Result<()> {
+    let destination = config.minidump.then(|| config.dump_destination(event_id));
+    if DUMP_DESTINATION.set(destination).is_err() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "Mach exception handler already installed",
+        ));
+    }
+
+    let check = |result: KernReturn, call: &str| {
+        if result == KERN_SUCCESS {
+            Ok(())
+        } else {
+            Err(std::io::Error::other(format!("{} failed: {}", call, result)))
+        }
+    };
+    let mut port = MACH_PORT_NULL;
+    // SAFETY: plain Mach calls on our own task with valid out pointers.
+    unsafe {
+        check(mach_port_allocate(task_self(), MACH_PORT_RIGHT_RECEIVE, &mut port), "mach_port_allocate")?;
+        check(
+            mach_port_insert_right(task_self(), port, port, MACH_MSG_TYPE_MAKE_SEND),
+            "mach_port_insert_right",
+        )?;
+    }
+
+    std::thread::Builder::new()
+        .name("crash-mach-exceptions".to_string())
+        .spawn(move || serve(port))?;
+
+    // SAFETY: `port` holds a send right for the task to use.
+    unsafe {
+        check(
+            task_set_exception_ports(task_self(), EXCEPTION_MASK, port, EXCEPTION_BEHAVIOR, state::THREAD_STATE_NONE),
+            "task_set_exception_ports",
+        )
+    }
+}
+
+// A fault in this thread would be sent to the port it serves and never
+// answered, so it only touches the crashed thread's memory through the kernel.
+fn serve(port: MachPort) {
+    loop {
+        // SAFETY: an all-zero request is a valid receive buffer.
+        let mut request: ExceptionRequest = unsafe { std::mem::zeroed() };
+        let received = unsafe {
+            mach_msg(
+                &mut request.header,
+                MACH_RCV_MSG,
+                0,
+                std::mem::size_of::<ExceptionRequest>() as u32,
+                port,
+                0,
+                MACH_PORT_NULL,
+            )
+        };
+        if received != KERN_SUCCESS {
+            continue;
+        }
+        handle(&request);
+
+        // Decline the exception so the kernel raises the signal.
+        let header = request.header;
+        let mut reply = ExceptionReply {
+            header: MessageHeader {
+                bits: header.bits & 0x1f, // MACH_MSGH_BITS_REMOTE of the request
+                size: std::mem::size_of::<ExceptionReply>() as u32,
+                remote_port: header.remote_port,
+                local_port: MACH_PORT_NULL,
+                voucher_port: MACH_PORT_NULL,
+                id: header.id + 100,
+            },
+            ndr: NDR_RECORD,
+            return_code: KERN_FAILURE,
+        };
+        // SAFETY: the reply is fully initialized and sized.
+        unsafe {
+            mach_msg(
+                &mut reply.header,
+                MACH_SEND_MSG,
+                reply.header.size,
+                0,
+                MACH_PORT_NULL,
+                0,
+                MACH_PORT_NULL,
+            );
+        }
+    }
+}
+
+// Reports the exception described by `request`. The crashed thread may hold
+// the allocator lock, so nothing allocates before the report is written.
+fn handle(request: &ExceptionRequest) {
+    let thread = request.thread.name;
+    let task = request.task.name;
+    let exception = request.exception;
+    let code = request.code;
+
+    let mut ips = [0usize; MAX_FRAMES];
+    let count = crashed_stack(task, thread, &mut ips);
+    // The faulting address for EXC_BAD_ACCESS, the instruction otherwise.
+    let fault_addr = match exception {
+        EXC_BAD_ACCESS => code[1] as usize,
+        _ => ips[0],
+    };
+    let (name, description) = exception_name(exception);
+    if !crate::signals::report_exception(name, description, fault_addr, &ips[..count]) {
+        return;
+    }
+
+    if let Some(Some(destination)) = DUMP_DESTINATION.get() {
+        let context = crash_context::CrashContext {
+            task,
+            thread,
+            // SAFETY: no preconditions.
+            handler_thread: unsafe { mach_thread_self() },
+            exception: Some(crash_context::ExceptionInfo {
+                kind: exception as u32,
+                code: code[0] as u64,
+                subcode: (exception == EXC_BAD_ACCESS).then_some(code[1] as u64),
+            }),
+        };
+        let mut writer = minidump_writer::minidump_writer::MinidumpWriter::with_crash_context(context);
+        let encrypt_to = crate::hook::config().and_then(|config| config.encrypt_to.as_deref());
+        if let Err(e) = crate::upload::write_dump(&mut writer, destination, encrypt_to, &[]) {
+            eprintln!("Failed to write minidump '{}': {}", destination, e);
+        }
+    }
+}
+
+// Fills `ips` with the stack of `thread`, innermost first: its program
+// counter, then the return addresses along the frame pointer chain. Returns
+// the number of frames.
+fn crashed_stack(task: MachPort, thread: MachPort, ips: &mut [usize; MAX_FRAMES]) -> usize {
+    let mut registers = [0u64; state::COUNT / 2];
+    let mut count = state::COUNT as u32;
+    // SAFETY: the buffer holds `count` 32-bit words.
+    let result = unsafe { thread_get_state(thread, state::FLAVOR, registers.as_mut_ptr() as *mut u32, &mut count) };
+    if result != KERN_SUCCESS {
+        return 0;
+    }
+
+    ips[0] = registers[state::PC] as usize;
+    let mut frames = 1;
+    let mut fp = registers[state::FP];
+    while fp != 0 && frames < MAX_FRAMES {
+        // A frame record is the caller's frame pointer and the return address.
+        let mut record = [0u64; 2];
+        let mut read = 0u64;
+        // SAFETY: the kernel copies at most 16 bytes into `record`.
+        let result = unsafe { mach_vm_read_overwrite(task, fp, 16, record.as_mut_ptr() as u64, &mut read) };
+        if result != KERN_SUCCESS || read != 16 || record[1] == 0 {
+            break;
+        }
+        ips[frames] = record[1] as usize;
+        frames += 1;
+        // Callers' frames sit at higher addresses; anything else is corrupt.
+        if record[0] <= fp {
+            break;
+        }
+        fp = record[0];
+    }
+    frames
+}
@@ -0,0 +1,200 @@
+// Counters of what the crash handler did, for alerting on crash spikes
+// without reading the reports.
+//
+// The counters live in the process and start at zero. `crash::metrics::snapshot`
+// returns them and `crash::metrics::render` formats them in the Prometheus text
+// format, labelled with the release when there is one:
+//
+//     crash_panics_total           panics seen by the panic hook
+//     crash_reports_written_total  reports written to the storage
+//     crash_reports_uploaded_total reports taken by the crash server, Sentry
+//                                  or another transport, retries included
+//     crash_reports_queued_total   reports queued after a failed upload
+//     crash_uploads_failed_total   failed attempts to deliver a report
+//     crash_reports_dropped_total  reports sampled out, rate limited, dropped
+//                                  by `before_send` or an integration, or lost
+//
+// `Builder::metrics_addr` serves them at `GET /metrics` on a background
+// thread for Prometheus to scrape. `Builder::metrics_push_url` instead PUTs
+// them to a Prometheus Pushgateway, grouped by `job` (the app name) and
+// `instance` (the server name), at `init` and after each report, before a
+// crashing process is gone. Minidumps and reports of native crashes are not
+// counted; the process is usually not in a state to.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::config::Config;
+
+pub(crate) struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub(crate) fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub(crate) static PANICS: Counter = Counter::new();
+pub(crate) static REPORTS_WRITTEN: Counter = Counter::new();
+pub(crate) static REPORTS_UPLOADED: Counter = Counter::new();
+pub(crate) static REPORTS_QUEUED: Counter = Counter::new();
+pub(crate) static UPLOADS_FAILED: Counter = Counter::new();
+pub(crate) static REPORTS_DROPPED: Counter = Counter::new();
+
+/// The counters at one point in time.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Metrics {
+    pub panics: u64,
+    pub reports_written: u64,
+    pub reports_uploaded: u64,
+    pub reports_queued: u64,
+    pub uploads_failed: u64,
+    pub reports_dropped: u64,
+}
+
+pub fn snapshot() -> Metrics {
+    Metrics {
+        panics: PANICS.get(),
+        reports_written: REPORTS_WRITTEN.get(),
+        reports_uploaded: REPORTS_UPLOADED.get(),
+        reports_queued: REPORTS_QUEUED.get(),
+        uploads_failed: UPLOADS_FAILED.get(),
+        reports_dropped: REPORTS_DROPPED.get(),
+    }
+}
+
+/// Counts where a report was delivered to; nowhere counts as dropped.
+pub(crate) fn record_deliveries(deliveries: &[crate::transport::Delivery]) {
+    use crate::transport::Delivery;
+    if deliveries.is_empty() {
+        REPORTS_DROPPED.inc();
+    }
+    for delivery in deliveries {
+        match delivery {
+            Delivery::Written(_) => REPORTS_WRITTEN.inc(),
+            Delivery::Uploaded(_) => REPORTS_UPLOADED.inc(),
+            Delivery::Queued(_) => {
+                REPORTS_QUEUED.inc();
+                UPLOADS_FAILED.inc();
+            }
+        }
+    }
+}
+
+// Escapes a label value of the text format.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// The counters in the Prometheus text exposition format.
+pub fn render() -> String {
+    let metrics = snapshot();
+    let labels = match crate::hook::config().and_then(|config| config.release.as_deref()) {
+        Some(release) => format!("{{release=\"{}\"}}", escape(release)),
+        None => String::new(),
+    };
+    let counters = [
+        ("crash_panics_total", "Panics seen by the panic hook.", metrics.panics),
+        ("crash_reports_written_total", "Crash reports written to the storage.", metrics.reports_written),
+        ("crash_reports_uploaded_total", "Crash reports delivered to a server.", metrics.reports_uploaded),
+        ("crash_reports_queued_total", "Crash reports queued after a failed upload.", metrics.reports_queued),
+        ("crash_uploads_failed_total", "Failed attempts to deliver a crash report.", metrics.uploads_failed),
+        ("crash_reports_dropped_total", "Crash reports sampled out, filtered or lost.", metrics.reports_dropped),
+    ];
+    let mut text = String::new();
+    for (name, help, value) in counters {
+        text.push_str(&format!("# HELP {} {}\n# TYPE {} counter\n{}{} {}\n", name, help, name, name, labels, value));
+    }
+    text
+}
+
+// Percent-encodes a value of the Pushgateway grouping key.
+#[cfg(not(target_arch = "wasm32"))]
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Pushes the counters to the configured Pushgateway, if any.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn push(config: &Config) {
+    let Some(gateway) = &config.metrics_push_url else {
+        return;
+    };
+    let job = config.app_name.clone().unwrap_or_else(crate::output::default_app_name);
+    let mut url = format!("{}/metrics/job/{}", gateway.trim_end_matches('/'), encode(&job));
+    if let Some(instance) = &config.server_name {
+        url.push_str(&format!("/instance/{}", encode(instance)));
+    }
+    let agent = ureq::AgentBuilder::new().timeout(config.upload_timeout).build();
+    let sent = agent
+        .put(&url)
+        .set("Content-Type", "text/plain; version=0.0.4")
+        .send_string(&render());
+    if let Err(e) = sent {
+        eprintln!("Failed to push crash metrics to {}: {}", gateway, e);
+    }
+}
+
+// Answers one scrape.
+#[cfg(not(target_arch = "wasm32"))]
+fn respond(mut stream: std::net::TcpStream) -> std::io::Result<()> {
+    use std::io::{Read, Write};
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    // Only the request line matters, but the headers are read so the client
+    // does not see a reset.
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render()),
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Serves the counters at `GET /metrics` on `addr` from a background thread.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn serve(addr: &str) -> std::io::Result<std::net::SocketAddr> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    let local = listener.local_addr()?;
+    std::thread::Builder::new()
+        .name("crash-metrics".to_string())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = respond(stream) {
+                    eprintln!("Failed to serve crash metrics: {}", e);
+                }
+            }
+        })?;
+    Ok(local)
+}
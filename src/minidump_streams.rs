@@ -0,0 +1,172 @@
+// Application data in minidumps.
+//
+// Applications register callbacks with `crash::add_minidump_stream` for data
+// that belongs with the process state: a snapshot of their configuration, the
+// state of a scheduler, a protocol log. The callbacks run when the panic hook
+// writes a minidump, and each result is appended to the dump as a stream of
+// type `APP_STREAM_TYPE`, which minidump readers skip as an unknown user
+// stream. Its data is the length of the name (u32, little-endian), the UTF-8
+// name and the bytes the callback returned. The crash server lists these
+// streams in the minidump summary.
+//
+// On Linux the dump is taken by the watchdog or the helper executable, so the
+// panic hook hands the streams over in a file in the temporary directory,
+// which they remove. Minidumps of native crashes have no application streams:
+// the callbacks cannot run in a signal handler.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+#[cfg(target_os = "linux")]
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Stream type of application streams, in the range left to users.
+pub const APP_STREAM_TYPE: u32 = 0x4352_0001; // "CR" 1
+
+const MINIDUMP_SIGNATURE: u32 = 0x504d_444d; // "MDMP"
+const HEADER_SIZE: usize = 32;
+const DIRECTORY_ENTRY_SIZE: usize = 12;
+
+#[derive(Clone, Debug)]
+struct Stream {
+    name: String,
+    provider: fn() -> Vec<u8>,
+}
+
+static STREAMS: Mutex<Vec<Stream>> = Mutex::new(Vec::new());
+
+/// Adds the output of `provider`, called when a minidump is written for a
+/// panic, to future minidumps as the stream `name`. The provider runs inside
+/// the panic hook and must not block.
+pub fn add_minidump_stream(name: impl Into<String>, provider: fn() -> Vec<u8>) {
+    let name = name.into();
+    let mut streams = STREAMS.lock().unwrap_or_else(|e| e.into_inner());
+    // Registering the same name again replaces the earlier provider.
+    streams.retain(|s| s.name != name);
+    streams.push(Stream { name, provider });
+}
+
+/// Removes every registered minidump stream.
+pub fn clear_minidump_streams() {
+    STREAMS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Runs the registered providers. Used by the panic hook, so a registry
+/// locked by the panicking code is skipped.
+pub(crate) fn collect() -> Vec<(String, Vec<u8>)> {
+    let providers = match STREAMS.try_lock() {
+        Ok(streams) => streams.clone(),
+        Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner().clone(),
+        Err(std::sync::TryLockError::WouldBlock) => return Vec::new(),
+    };
+    providers
+        .into_iter()
+        .map(|stream| (stream.name, (stream.provider)()))
+        .collect()
+}
+
+/// Data of the application stream `name` holding `data`.
+pub fn encode(name: &str, data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(4 + name.len() + data.len());
+    encoded.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    encoded.extend_from_slice(name.as_bytes());
+    encoded.extend_from_slice(data);
+    encoded
+}
+
+/// Name and data of an application stream, or None if it is malformed.
+pub fn decode(encoded: &[u8]) -> Option<(String, &[u8])> {
+    let len = u32::from_le_bytes(encoded.get(..4)?.try_into().ok()?) as usize;
+    let name = std::str::from_utf8(encoded.get(4..4 + len)?).ok()?;
+    Some((name.to_string(), &encoded[4 + len..]))
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+fn rva(offset: u64) -> std::io::Result<u32> {
+    u32::try_from(offset).map_err(|_| invalid("minidump is too large for more streams"))
+}
+
+// Pads the end of `dump` to a multiple of four and returns its length.
+fn align<F: Write + Seek>(dump: &mut F) -> std::io::Result<u64> {
+    let end = dump.seek(SeekFrom::End(0))?;
+    let padding = (4 - end % 4) % 4;
+    dump.write_all(&[0u8; 3][..padding as usize])?;
+    Ok(end + padding)
+}
+
+/// Appends `streams` to the finished minidump in `dump`. The stream data goes
+/// to the end and the stream directory is moved after it, so the streams
+/// already written stay where they are.
+pub(crate) fn append<F: Read + Write + Seek>(dump: &mut F, streams: &[(String, Vec<u8>)]) -> std::io::Result<()> {
+    if streams.is_empty() {
+        return Ok(());
+    }
+    let mut header = [0u8; HEADER_SIZE];
+    dump.seek(SeekFrom::Start(0))?;
+    dump.read_exact(&mut header)?;
+    let field = |offset: usize| u32::from_le_bytes([header[offset], header[offset + 1], header[offset + 2], header[offset + 3]]);
+    if field(0) != MINIDUMP_SIGNATURE {
+        return Err(invalid("missing MDMP signature"));
+    }
+    let stream_count = field(8);
+    let mut directory = vec![0u8; stream_count as usize * DIRECTORY_ENTRY_SIZE];
+    dump.seek(SeekFrom::Start(u64::from(field(12))))?;
+    dump.read_exact(&mut directory)?;
+
+    for (name, data) in streams {
+        let encoded = encode(name, data);
+        let offset = align(dump)?;
+        dump.write_all(&encoded)?;
+        directory.extend_from_slice(&APP_STREAM_TYPE.to_le_bytes());
+        directory.extend_from_slice(&rva(encoded.len() as u64)?.to_le_bytes());
+        directory.extend_from_slice(&rva(offset)?.to_le_bytes());
+    }
+    let directory_rva = rva(align(dump)?)?;
+    dump.write_all(&directory)?;
+
+    dump.seek(SeekFrom::Start(8))?;
+    dump.write_all(&(stream_count + streams.len() as u32).to_le_bytes())?;
+    dump.write_all(&directory_rva.to_le_bytes())?;
+    dump.flush()
+}
+
+/// File the panic hook hands the streams of event `event_id` over in.
+#[cfg(target_os = "linux")]
+pub(crate) fn handoff_path(event_id: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("crash_streams_{}.bin", event_id))
+}
+
+/// Writes `streams` to `path` for the process taking the dump, as a sequence
+/// of encoded streams, each preceded by its length (u32, little-endian).
+#[cfg(target_os = "linux")]
+pub(crate) fn write_handoff(path: &Path, streams: &[(String, Vec<u8>)]) -> std::io::Result<()> {
+    let mut data = Vec::new();
+    for (name, stream) in streams {
+        let encoded = encode(name, stream);
+        data.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+        data.extend_from_slice(&encoded);
+    }
+    std::fs::write(path, data)
+}
+
+/// Reads and removes the streams handed over in `path`. A missing file means
+/// there are none.
+#[cfg(target_os = "linux")]
+pub(crate) fn take_handoff(path: &Path) -> Vec<(String, Vec<u8>)> {
+    let Ok(data) = std::fs::read(path) else {
+        return Vec::new();
+    };
+    let _ = std::fs::remove_file(path);
+    let mut streams = Vec::new();
+    let mut rest = data.as_slice();
+    while let Some(len) = rest.get(..4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize) {
+        let Some((name, stream)) = rest.get(4..4 + len).and_then(decode) else {
+            break;
+        };
+        streams.push((name, stream.to_vec()));
+        rest = &rest[4 + len..];
+    }
+    streams
+}
@@ -0,0 +1,269 @@
+// Out-of-memory reporting.
+//
+// When an allocation fails, Rust prints "memory allocation of N bytes failed"
+// and aborts; the signal handlers would only see a SIGABRT. The hook std
+// offers for this (`std::alloc::set_alloc_error_hook`) is unstable, so the
+// failure is caught one step earlier, in the allocator: applications that
+// want dedicated reports install `CrashAllocator` as their global allocator.
+//
+//     #[global_allocator]
+//     static ALLOCATOR: crash::CrashAllocator = crash::CrashAllocator::system();
+//
+// It forwards to the wrapped allocator and keeps count of the bytes in use.
+// When the wrapped allocator returns null it writes the native crash report
+// (see `crate::signals`, so this needs `native_crashes`, Unix only) with the
+// size of the failed allocation and the memory statistics, then returns the
+// null pointer; std aborts as usual and the signal handler leaves the report
+// alone. No minidump is written for these, a dump of an exhausted process is
+// rarely worth the disk space.
+//
+// Processes are often killed by the kernel or a container runtime long before
+// an allocation fails, and SIGKILL cannot be caught. With
+// `Builder::memory_limit` a thread checks the resident set size every second
+// and files an `error` report with the statistics when it goes above the
+// limit, once until it falls below 90% of it again.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+#[cfg(not(target_arch = "wasm32"))]
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+// Bytes handed out by `CrashAllocator` and not freed yet, and the most there
+// ever were. Only meaningful once `TRACKING` is set.
+static HEAP_IN_USE: AtomicUsize = AtomicUsize::new(0);
+static HEAP_PEAK: AtomicUsize = AtomicUsize::new(0);
+static TRACKING: AtomicBool = AtomicBool::new(false);
+
+/// Global allocator wrapper that counts heap usage and reports allocation
+/// failures before the process aborts.
+#[derive(Debug, Default)]
+pub struct CrashAllocator<A = System> {
+    inner: A,
+}
+
+impl CrashAllocator<System> {
+    /// Wraps the system allocator.
+    pub const fn system() -> Self {
+        CrashAllocator { inner: System }
+    }
+}
+
+impl<A> CrashAllocator<A> {
+    /// Wraps `inner`, e.g. another allocator crate's type.
+    pub const fn new(inner: A) -> Self {
+        CrashAllocator { inner }
+    }
+
+    // Accounts for a successful allocation of `size` bytes, or reports a
+    // failed one.
+    fn allocated(&self, ptr: *mut u8, size: usize) -> *mut u8 {
+        if ptr.is_null() {
+            allocation_failed(size);
+        } else {
+            let in_use = HEAP_IN_USE.fetch_add(size, Ordering::Relaxed) + size;
+            HEAP_PEAK.fetch_max(in_use, Ordering::Relaxed);
+        }
+        ptr
+    }
+}
+
+// SAFETY: every call is forwarded to `inner` unchanged; only counters are
+// updated around it.
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CrashAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        TRACKING.store(true, Ordering::Relaxed);
+        self.allocated(self.inner.alloc(layout), layout.size())
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        TRACKING.store(true, Ordering::Relaxed);
+        self.allocated(self.inner.alloc_zeroed(layout), layout.size())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        HEAP_IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if new_ptr.is_null() {
+            // The old block is still allocated.
+            allocation_failed(new_size);
+        } else if new_size >= layout.size() {
+            let grown = new_size - layout.size();
+            let in_use = HEAP_IN_USE.fetch_add(grown, Ordering::Relaxed) + grown;
+            HEAP_PEAK.fetch_max(in_use, Ordering::Relaxed);
+        } else {
+            HEAP_IN_USE.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+// Called from inside the allocator, so nothing here may allocate.
+fn allocation_failed(size: usize) {
+    #[cfg(unix)]
+    {
+        let stats = MemoryStats::current(Some(size));
+        crate::signals::report_out_of_memory(size, &stats);
+    }
+    #[cfg(not(unix))]
+    let _ = size;
+}
+
+/// Memory usage of the process at the time of a report. Written as the
+/// `memory` extra of out-of-memory reports.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemoryStats {
+    pub resident_bytes: Option<u64>,      // Resident set size, where the OS reports it
+    pub heap_in_use: Option<usize>,       // Bytes allocated through `CrashAllocator`
+    pub heap_peak: Option<usize>,         // Most bytes ever allocated through `CrashAllocator`
+    pub failed_allocation: Option<usize>, // Size of the allocation that failed
+    pub limit: Option<u64>,               // `Config::memory_limit` that was exceeded
+}
+
+impl MemoryStats {
+    /// The statistics available right now. Does not allocate.
+    pub fn current(failed_allocation: Option<usize>) -> Self {
+        let tracking = TRACKING.load(Ordering::Relaxed);
+        MemoryStats {
+            resident_bytes: resident_bytes(),
+            heap_in_use: tracking.then(|| HEAP_IN_USE.load(Ordering::Relaxed)),
+            heap_peak: tracking.then(|| HEAP_PEAK.load(Ordering::Relaxed)),
+            failed_allocation,
+            limit: None,
+        }
+    }
+}
+
+// JSON object with the known fields, formatted without allocating so the
+// allocator can use it.
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields = [
+            ("resident_bytes", self.resident_bytes),
+            ("heap_in_use", self.heap_in_use.map(|n| n as u64)),
+            ("heap_peak", self.heap_peak.map(|n| n as u64)),
+            ("failed_allocation", self.failed_allocation.map(|n| n as u64)),
+            ("limit", self.limit),
+        ];
+        f.write_str("{")?;
+        let mut separator = "";
+        for (name, value) in fields {
+            if let Some(value) = value {
+                write!(f, "{}\"{}\":{}", separator, name, value)?;
+                separator = ",";
+            }
+        }
+        f.write_str("}")
+    }
+}
+
+/// Resident set size of the process in bytes, from `/proc/self/statm`.
+/// Does not allocate.
+#[cfg(target_os = "linux")]
+pub fn resident_bytes() -> Option<u64> {
+    let mut buf = [0u8; 128];
+    // SAFETY: a NUL-terminated path and a read into a stack buffer.
+    let read = unsafe {
+        let fd = libc::open(c"/proc/self/statm".as_ptr(), libc::O_RDONLY);
+        if fd < 0 {
+            return None;
+        }
+        let read = libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len());
+        libc::close(fd);
+        read
+    };
+    // The second field is the resident size in pages.
+    let text = std::str::from_utf8(buf.get(..usize::try_from(read).ok()?)?).ok()?;
+    let pages: u64 = text.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: no preconditions.
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * u64::try_from(page_size).ok()?)
+}
+
+/// Resident set size of the process in bytes, from `task_info`. Does not
+/// allocate.
+#[cfg(target_os = "macos")]
+pub fn resident_bytes() -> Option<u64> {
+    // `mach_task_basic_info`.
+    #[repr(C)]
+    #[derive(Default)]
+    struct BasicInfo {
+        virtual_size: u64,
+        resident_size: u64,
+        resident_size_max: u64,
+        user_time: [i32; 2],
+        system_time: [i32; 2],
+        policy: i32,
+        suspend_count: i32,
+    }
+    const MACH_TASK_BASIC_INFO: i32 = 20;
+    extern "C" {
+        static mach_task_self_: u32;
+        fn task_info(task: u32, flavor: i32, info: *mut i32, count: *mut u32) -> i32;
+    }
+
+    let mut info = BasicInfo::default();
+    let mut count = (std::mem::size_of::<BasicInfo>() / 4) as u32;
+    // SAFETY: `info` holds `count` 32-bit words.
+    let result = unsafe { task_info(mach_task_self_, MACH_TASK_BASIC_INFO, &mut info as *mut BasicInfo as *mut i32, &mut count) };
+    (result == 0).then_some(info.resident_size)
+}
+
+/// The resident set size is not available on this platform.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn resident_bytes() -> Option<u64> {
+    None
+}
+
+/// Starts the thread that reports the resident set size going above `limit`
+/// bytes. Reports go through the installed crash handler.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn spawn_monitor(limit: u64) -> std::io::Result<()> {
+    resident_bytes().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "memory limit: resident set size is not available on this platform",
+        )
+    })?;
+    std::thread::Builder::new()
+        .name("crash-memory-monitor".to_string())
+        .spawn(move || monitor(limit))?;
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn monitor(limit: u64) {
+    let mut armed = true;
+    loop {
+        std::thread::sleep(CHECK_INTERVAL);
+        let Some(resident) = resident_bytes() else {
+            continue;
+        };
+        if armed && resident > limit {
+            armed = false;
+            report_limit_exceeded(limit);
+        } else if !armed && resident < limit / 10 * 9 {
+            armed = true;
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn report_limit_exceeded(limit: u64) -> Option<String> {
+    let mut stats = MemoryStats::current(None);
+    stats.limit = Some(limit);
+    let resident = stats.resident_bytes.unwrap_or(0);
+    crate::capture::capture(|event_id, timestamp| {
+        let message = format!("Memory limit exceeded: {} bytes resident, limit {}", resident, limit);
+        let mut event = crate::hook::base_event(event_id, timestamp, crate::Level::Error.as_str(), message);
+        if let Ok(memory) = serde_json::from_str(&stats.to_string()) {
+            event.extra.insert("memory".to_string(), memory);
+        }
+        event
+    })
+}
@@ -0,0 +1,105 @@
+// Where crash reports are written.
+//
+// Report file names come from a template with `{event_id}`, `{timestamp}`
+// (seconds since the UNIX epoch) and `{app_name}` placeholders. The server
+// only picks up `crash_report_<id>.json`, which is the default. If the
+// configured directory cannot be written, reports go to a `crash` directory
+// under the system temp dir instead.
+//
+// Reports are written to `<name>.tmp` first and renamed to their name once
+// complete, so a crash or kill halfway through a write never leaves a
+// truncated report for the server to list; it ignores `.tmp` files.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_FILENAME_TEMPLATE: &str = "crash_report_{event_id}.json";
+/// Suffix of a report while it is being written.
+pub const TEMP_SUFFIX: &str = ".tmp";
+
+/// Name of the running executable, used for `{app_name}` by default.
+pub fn default_app_name() -> String {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.file_stem().map(|s| s.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "app".to_string())
+}
+
+// Placeholder values must not introduce path separators.
+pub(crate) fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
+        .collect()
+}
+
+/// Expands the placeholders of a file name template.
+pub fn render(template: &str, event_id: &str, timestamp: u64, app_name: &str) -> String {
+    template
+        .replace("{event_id}", &sanitize(event_id))
+        .replace("{timestamp}", &timestamp.to_string())
+        .replace("{app_name}", &sanitize(app_name))
+}
+
+fn fallback_dir() -> PathBuf {
+    std::env::temp_dir().join("crash")
+}
+
+fn is_writable(dir: &Path) -> bool {
+    if fs::create_dir_all(dir).is_err() {
+        return false;
+    }
+    let probe = dir.join(format!(".crash_probe_{}", std::process::id()));
+    let writable = fs::write(&probe, b"").is_ok();
+    let _ = fs::remove_file(&probe);
+    writable
+}
+
+/// Returns `dir` if it exists or can be created and is writable, otherwise
+/// the temp dir fallback.
+pub fn writable_dir(dir: &Path) -> PathBuf {
+    if is_writable(dir) {
+        return dir.to_path_buf();
+    }
+    let fallback = fallback_dir();
+    eprintln!(
+        "Crash output directory '{}' is not writable, using '{}'",
+        dir.display(),
+        fallback.display()
+    );
+    let _ = fs::create_dir_all(&fallback);
+    fallback
+}
+
+/// Writes `contents` to `path` through `<path>.tmp`, renamed to `path` once
+/// complete. The temporary file is removed if the write fails.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(TEMP_SUFFIX);
+    let temp = PathBuf::from(temp);
+    let written = fs::write(&temp, contents).and_then(|()| fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+/// Writes a report named `file_name` into `dir`, falling back to the temp
+/// dir if that fails. Returns the path written.
+pub fn write_report(dir: &Path, file_name: &str, contents: &[u8]) -> std::io::Result<PathBuf> {
+    let path = dir.join(file_name);
+    match write_atomic(&path, contents) {
+        Ok(()) => Ok(path),
+        Err(e) => {
+            let fallback = fallback_dir();
+            if fallback == dir {
+                return Err(e);
+            }
+            eprintln!("Failed to write crash report '{}': {}", path.display(), e);
+            fs::create_dir_all(&fallback)?;
+            let path = fallback.join(file_name);
+            write_atomic(&path, contents)?;
+            Ok(path)
+        }
+    }
+}
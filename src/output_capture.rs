@@ -0,0 +1,214 @@
+// Tail of the process's stdout and stderr (Unix).
+//
+// Crashes are often preceded by a library printing an error. With
+// `capture_output` set, `crash::init` replaces file descriptors 1 and 2 by
+// pipes; a background thread copies everything read from them to the
+// original descriptors and keeps the last bytes of each stream in a ring
+// buffer. Panic reports get the buffers as the `stdout.txt` and `stderr.txt`
+// attachments. At exit the original descriptors are restored and the pipes
+// drained, so no output is lost.
+
+use std::collections::VecDeque;
+use std::os::fd::RawFd;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+// How long the panic hook waits for output still in a pipe, and exit for the
+// readers to finish.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+struct Tail {
+    capacity: usize,
+    bytes: VecDeque<u8>,
+}
+
+struct Stream {
+    fd: RawFd,                // Descriptor being captured, 1 or 2
+    original: Mutex<RawFd>,   // Duplicate of what `fd` pointed to before, -1 if not captured
+    pipe: Mutex<RawFd>,       // Read end of the pipe, -1 if not captured
+    tail: Mutex<Tail>,
+    reader: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Stream {
+    const fn new(fd: RawFd) -> Self {
+        Stream {
+            fd,
+            original: Mutex::new(-1),
+            pipe: Mutex::new(-1),
+            tail: Mutex::new(Tail {
+                capacity: 0,
+                bytes: VecDeque::new(),
+            }),
+            reader: Mutex::new(None),
+        }
+    }
+}
+
+static STDOUT: Stream = Stream::new(1);
+static STDERR: Stream = Stream::new(2);
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn last_error() -> std::io::Error {
+    std::io::Error::last_os_error()
+}
+
+// Writes all of `data` to `fd`, retrying on EINTR.
+fn write_all(fd: RawFd, mut data: &[u8]) {
+    while !data.is_empty() {
+        let n = unsafe { libc::write(fd, data.as_ptr().cast(), data.len()) };
+        if n < 0 {
+            if last_error().kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return;
+        }
+        data = &data[n as usize..];
+    }
+}
+
+fn forward(stream: &'static Stream, pipe: RawFd, original: RawFd) {
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = unsafe { libc::read(pipe, buf.as_mut_ptr().cast(), buf.len()) };
+        if n < 0 && last_error().kind() == std::io::ErrorKind::Interrupted {
+            continue;
+        }
+        if n <= 0 {
+            break;
+        }
+        let data = &buf[..n as usize];
+        write_all(original, data);
+        let mut tail = lock(&stream.tail);
+        let capacity = tail.capacity;
+        tail.bytes.extend(data);
+        let excess = tail.bytes.len().saturating_sub(capacity);
+        tail.bytes.drain(..excess);
+    }
+}
+
+fn capture(stream: &'static Stream, capacity: usize) -> std::io::Result<()> {
+    if *lock(&stream.pipe) >= 0 {
+        return Ok(());
+    }
+    lock(&stream.tail).capacity = capacity;
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(last_error());
+    }
+    let [read_end, write_end] = fds;
+    let original = unsafe { libc::dup(stream.fd) };
+    if original < 0 || unsafe { libc::dup2(write_end, stream.fd) } < 0 {
+        let e = last_error();
+        unsafe {
+            libc::close(read_end);
+            libc::close(write_end);
+            if original >= 0 {
+                libc::close(original);
+            }
+        }
+        return Err(e);
+    }
+    unsafe {
+        libc::close(write_end);
+        libc::fcntl(read_end, libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(original, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+
+    let reader = std::thread::Builder::new()
+        .name(format!("crash-output-{}", stream.fd))
+        .spawn(move || forward(stream, read_end, original));
+    match reader {
+        Ok(reader) => {
+            *lock(&stream.original) = original;
+            *lock(&stream.pipe) = read_end;
+            *lock(&stream.reader) = Some(reader);
+            Ok(())
+        }
+        Err(e) => {
+            unsafe {
+                libc::dup2(original, stream.fd);
+                libc::close(original);
+                libc::close(read_end);
+            }
+            Err(e)
+        }
+    }
+}
+
+// Puts the original descriptor back and gives the reader time to forward
+// what is left in the pipe. Child processes may still hold the write end, in
+// which case the reader never sees EOF and is left behind.
+fn restore(stream: &Stream) {
+    let original = *lock(&stream.original);
+    if original < 0 {
+        return;
+    }
+    unsafe { libc::dup2(original, stream.fd) };
+    if let Some(reader) = lock(&stream.reader).take() {
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        while !reader.is_finished() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+extern "C" fn restore_at_exit() {
+    restore(&STDOUT);
+    restore(&STDERR);
+}
+
+/// Starts capturing the last `capacity` bytes written to stdout and stderr.
+pub fn install(capacity: usize) -> std::io::Result<()> {
+    static AT_EXIT: std::sync::Once = std::sync::Once::new();
+    capture(&STDOUT, capacity)?;
+    capture(&STDERR, capacity)?;
+    AT_EXIT.call_once(|| unsafe {
+        libc::atexit(restore_at_exit);
+    });
+    crate::attachments::attach_with("stdout.txt", || tail(&STDOUT));
+    crate::attachments::attach_with("stderr.txt", || tail(&STDERR));
+    Ok(())
+}
+
+// Bytes written to `stream` that its reader has not consumed yet.
+fn pending(stream: &Stream) -> usize {
+    let pipe = match stream.pipe.try_lock() {
+        Ok(pipe) => *pipe,
+        Err(_) => return 0,
+    };
+    if pipe < 0 {
+        return 0;
+    }
+    let mut pending: libc::c_int = 0;
+    if unsafe { libc::ioctl(pipe, libc::FIONREAD, &mut pending) } != 0 {
+        return 0;
+    }
+    pending.max(0) as usize
+}
+
+// The captured tail of `stream`. Called from the panic hook: output written
+// just before the panic is given a moment to reach the buffer, and a buffer
+// that stays locked is skipped.
+fn tail(stream: &Stream) -> Vec<u8> {
+    let deadline = Instant::now() + DRAIN_TIMEOUT;
+    loop {
+        if pending(stream) == 0 {
+            if let Ok(tail) = stream.tail.try_lock() {
+                return tail.bytes.iter().copied().collect();
+            }
+        }
+        if Instant::now() >= deadline {
+            return match stream.tail.try_lock() {
+                Ok(tail) => tail.bytes.iter().copied().collect(),
+                Err(_) => Vec::new(),
+            };
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
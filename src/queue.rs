@@ -0,0 +1,209 @@
+// Offline queue of reports whose upload failed.
+//
+// When the crash server or Sentry cannot be reached, `transport` keeps the
+// report in `<output_dir>/crash_queue` as `<target>_<attempts>_<file name>`,
+// where the target is `server` or `sentry`. On the next run `crash::init`
+// starts a background thread that sends queued reports again, oldest first.
+// After a failed retry a report waits a minute before the next attempt, and
+// twice as long after every further failure, up to six hours. A round stops
+// at the first failure, since the others would most likely fail too. At most
+// `max_queued_reports` are kept; the oldest are dropped beyond that. With
+// encryption, reports are queued encrypted (`<file name>.age`).
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::config::Config;
+
+pub const QUEUE_DIR: &str = "crash_queue";
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(60);
+const MAX_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Where a queued report goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Server,
+    Sentry,
+}
+
+impl Target {
+    fn name(self) -> &'static str {
+        match self {
+            Target::Server => "server",
+            Target::Sentry => "sentry",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Target> {
+        match name {
+            "server" => Some(Target::Server),
+            "sentry" => Some(Target::Sentry),
+            _ => None,
+        }
+    }
+}
+
+struct Entry {
+    path: PathBuf,
+    target: Target,
+    attempts: u32,
+    file_name: String,
+    modified: SystemTime, // Time of the last attempt
+}
+
+fn queue_dir(config: &Config) -> PathBuf {
+    config.output_dir.join(QUEUE_DIR)
+}
+
+// Delay after `attempts` failed attempts before the next one. The failure
+// that queued the report is retried on the next run straight away.
+fn backoff(attempts: u32) -> Duration {
+    match attempts {
+        0 | 1 => Duration::ZERO,
+        n => INITIAL_BACKOFF.saturating_mul(1u32 << (n - 2).min(16)).min(MAX_BACKOFF),
+    }
+}
+
+fn parse(path: &Path) -> Option<Entry> {
+    let name = path.file_name()?.to_str()?;
+    // Left by a write that did not finish.
+    if name.ends_with(crate::output::TEMP_SUFFIX) {
+        return None;
+    }
+    let mut parts = name.splitn(3, '_');
+    let target = Target::from_name(parts.next()?)?;
+    let attempts = parts.next()?.parse().ok()?;
+    let file_name = parts.next()?.to_string();
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    Some(Entry {
+        path: path.to_path_buf(),
+        target,
+        attempts,
+        file_name,
+        modified,
+    })
+}
+
+// Queued reports, oldest first.
+fn entries(config: &Config) -> Vec<Entry> {
+    let Ok(dir) = fs::read_dir(queue_dir(config)) else {
+        return Vec::new();
+    };
+    let mut entries: Vec<Entry> = dir.flatten().filter_map(|e| parse(&e.path())).collect();
+    entries.sort_by_key(|entry| entry.modified);
+    entries
+}
+
+// Drops the oldest reports so that at most `keep` remain.
+fn trim(config: &Config, keep: usize) {
+    let entries = entries(config);
+    let excess = entries.len().saturating_sub(keep);
+    for entry in &entries[..excess] {
+        eprintln!("Crash report queue full, dropping {}", entry.file_name);
+        let _ = fs::remove_file(&entry.path);
+    }
+}
+
+/// Queues the report `file_name` for another attempt at `target` on the next
+/// run. Returns where it was stored.
+pub fn enqueue(config: &Config, target: Target, file_name: &str, json: &[u8]) -> std::io::Result<PathBuf> {
+    if config.max_queued_reports == 0 {
+        return Err(std::io::Error::other("the upload queue is disabled"));
+    }
+    // Sentry could not read an encrypted report.
+    if target == Target::Sentry && config.encrypt_to.is_some() {
+        return Err(std::io::Error::other("encrypted reports are not queued for Sentry"));
+    }
+    let dir = queue_dir(config);
+    fs::create_dir_all(&dir)?;
+    trim(config, config.max_queued_reports - 1);
+    let file_name = format!("{}{}", file_name, crate::encryption::extension(config));
+    let path = dir.join(format!("{}_1_{}", target.name(), file_name));
+    crate::output::write_atomic(&path, &crate::encryption::seal(config, json.to_vec())?)?;
+    Ok(path)
+}
+
+fn send(config: &Config, entry: &Entry, json: &[u8]) -> Result<(), String> {
+    match entry.target {
+        // Encrypted JSON, sent as it is.
+        Target::Server if entry.file_name.ends_with(crate::encryption::EXTENSION) => match &config.upload_url {
+            Some(server) => crate::transport::post(
+                config,
+                &crate::transport::report_endpoint(server),
+                &[("Content-Type", "application/json"), (crate::encryption::HEADER, "age")],
+                json,
+            ),
+            None => Err("no upload URL configured".to_string()),
+        },
+        Target::Server => match &config.upload_url {
+            Some(server) => crate::transport::send_report(config, server, json),
+            None => Err("no upload URL configured".to_string()),
+        },
+        Target::Sentry => match &config.sentry_dsn {
+            Some(dsn) => crate::sentry::send_report(config, dsn, json).map(|_| ()),
+            None => Err("no Sentry DSN configured".to_string()),
+        },
+    }
+}
+
+/// Sends the queued reports that are due. Returns how many were delivered.
+pub fn retry_pending(config: &Config) -> usize {
+    trim(config, config.max_queued_reports);
+    let now = SystemTime::now();
+    let mut sent = 0;
+    for entry in entries(config) {
+        let waited = now.duration_since(entry.modified).unwrap_or(Duration::ZERO);
+        if waited < backoff(entry.attempts) {
+            continue;
+        }
+        let Ok(json) = fs::read(&entry.path) else {
+            continue;
+        };
+        match send(config, &entry, &json) {
+            Ok(()) => {
+                let _ = fs::remove_file(&entry.path);
+                crate::metrics::REPORTS_UPLOADED.inc();
+                sent += 1;
+            }
+            Err(e) => {
+                eprintln!("Failed to send queued crash report {}: {}", entry.file_name, e);
+                crate::metrics::UPLOADS_FAILED.inc();
+                let retry = entry.path.with_file_name(format!(
+                    "{}_{}_{}",
+                    entry.target.name(),
+                    entry.attempts + 1,
+                    entry.file_name
+                ));
+                if fs::rename(&entry.path, &retry).is_ok() {
+                    // The backoff counts from this attempt.
+                    let _ = fs::File::options()
+                        .append(true)
+                        .open(&retry)
+                        .and_then(|file| file.set_modified(SystemTime::now()));
+                }
+                break;
+            }
+        }
+    }
+    sent
+}
+
+/// Retries the queued reports on a background thread, if there are any.
+pub fn spawn_retry(config: &Config) {
+    if entries(config).is_empty() {
+        return;
+    }
+    let config = config.clone();
+    // `crash::flush` waits for it.
+    let spawned = crate::flush::spawn("crash-upload-queue", move || {
+        let sent = retry_pending(&config);
+        if sent > 0 {
+            config.log_debug(format_args!("Uploaded {} queued crash report(s)", sent));
+        }
+    });
+    if let Err(e) = spawned {
+        eprintln!("Failed to start the crash report upload thread: {}", e);
+    }
+}
@@ -0,0 +1,210 @@
+// Report writing that is safe from a signal handler.
+//
+// The panic hook builds reports with `serde_json` and `format!`, which
+// allocate and may take locks; that is fine for a panic but not in a signal
+// handler, where the heap may be corrupt or its lock held by the crashed
+// thread. A `RawReport` prepares everything up front instead: the output
+// directory is opened when the handler is installed (so a later `chdir` or
+// descriptor exhaustion does not matter), and the formatting buffer is
+// allocated then too. At crash time the report is formatted into that buffer
+// and written with `openat`/`write` to `<name>.tmp`, then renamed with
+// `renameat`, all async-signal-safe, so a crash while writing the report
+// leaves no truncated report behind.
+
+use std::cell::UnsafeCell;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Formats into a fixed buffer; output that does not fit is dropped. A
+/// value written piece by piece may be cut off, so parts of a report that
+/// can overflow are written with `write_whole`.
+pub struct FixedBuffer<'a> {
+    data: &'a mut [u8],
+    len: usize,
+}
+
+impl FixedBuffer<'_> {
+    /// Bytes still available.
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.len
+    }
+
+    /// Writes `args` if it fits with `keep` bytes to spare, else nothing.
+    /// Returns whether it was written.
+    pub fn write_whole(&mut self, args: std::fmt::Arguments, keep: usize) -> bool {
+        if formatted_len(args).saturating_add(keep) > self.remaining() {
+            return false;
+        }
+        std::fmt::Write::write_fmt(self, args).is_ok()
+    }
+}
+
+/// Bytes `args` formats to, counted without writing them anywhere.
+pub fn formatted_len(args: std::fmt::Arguments) -> usize {
+    struct Counter(usize);
+
+    impl std::fmt::Write for Counter {
+        fn write_str(&mut self, s: &str) -> std::fmt::Result {
+            self.0 += s.len();
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    let _ = std::fmt::Write::write_fmt(&mut counter, args);
+    counter.0
+}
+
+impl std::fmt::Write for FixedBuffer<'_> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        let end = self.len + s.len();
+        if end > self.data.len() {
+            return Err(std::fmt::Error);
+        }
+        self.data[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// A report file prepared ahead of the crash.
+pub struct RawReport {
+    dir_fd: i32,
+    file_name: CString,
+    temp_name: CString, // `file_name` with `crate::output::TEMP_SUFFIX`
+    buffer: UnsafeCell<Box<[u8]>>,
+    busy: AtomicBool, // Set while a thread formats into `buffer`
+}
+
+// SAFETY: `buffer` is only accessed by the thread that set `busy`.
+unsafe impl Sync for RawReport {}
+
+impl RawReport {
+    /// Opens the directory of `path` and allocates a `capacity` byte buffer
+    /// for the report to be written there.
+    pub fn prepare(path: &Path, capacity: usize) -> std::io::Result<RawReport> {
+        let invalid = |e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e);
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = CString::new(dir.as_os_str().as_bytes()).map_err(invalid)?;
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "report path has no file name"))?;
+        let mut temp_name = file_name.to_os_string();
+        temp_name.push(crate::output::TEMP_SUFFIX);
+        let file_name = CString::new(file_name.as_bytes()).map_err(invalid)?;
+        let temp_name = CString::new(temp_name.as_bytes()).map_err(invalid)?;
+
+        // SAFETY: `dir` is NUL-terminated.
+        let dir_fd = unsafe { libc::open(dir.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC) };
+        if dir_fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(RawReport {
+            dir_fd,
+            file_name,
+            temp_name,
+            buffer: UnsafeCell::new(vec![0; capacity].into_boxed_slice()),
+            busy: AtomicBool::new(false),
+        })
+    }
+
+    /// Formats the report with `format` and writes it. Returns false if the
+    /// report is already being written (a crash while writing it) or the
+    /// file could not be written. Async-signal-safe, provided `format` only
+    /// writes to the buffer.
+    pub fn write_with(&self, format: impl FnOnce(&mut FixedBuffer)) -> bool {
+        if self.busy.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        // SAFETY: `busy` gives this thread exclusive access to the buffer.
+        let data = unsafe { &mut *self.buffer.get() };
+        let mut buf = FixedBuffer { data, len: 0 };
+        format(&mut buf);
+        let len = buf.len;
+        let written = self.write_file(&data[..len]);
+        self.busy.store(false, Ordering::Release);
+        written
+    }
+
+    fn write_file(&self, contents: &[u8]) -> bool {
+        // SAFETY: openat/write/close/renameat/unlinkat are async-signal-safe.
+        unsafe {
+            let fd = libc::openat(
+                self.dir_fd,
+                self.temp_name.as_ptr(),
+                libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_CLOEXEC,
+                0o644,
+            );
+            if fd < 0 {
+                return false;
+            }
+            let mut written = 0;
+            while written < contents.len() {
+                let n = libc::write(
+                    fd,
+                    contents[written..].as_ptr() as *const libc::c_void,
+                    contents.len() - written,
+                );
+                if n <= 0 {
+                    break;
+                }
+                written += n as usize;
+            }
+            libc::close(fd);
+            if written == contents.len()
+                && libc::renameat(self.dir_fd, self.temp_name.as_ptr(), self.dir_fd, self.file_name.as_ptr()) == 0
+            {
+                return true;
+            }
+            libc::unlinkat(self.dir_fd, self.temp_name.as_ptr(), 0);
+            false
+        }
+    }
+}
+
+impl Drop for RawReport {
+    fn drop(&mut self) {
+        // SAFETY: the descriptor was opened by `prepare` and is not shared.
+        unsafe { libc::close(self.dir_fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write as _;
+
+    #[test]
+    fn writes_that_do_not_fit_are_dropped() {
+        let mut data = [0u8; 8];
+        let mut buf = FixedBuffer { data: &mut data, len: 0 };
+        assert!(buf.write_str("12345").is_ok());
+        assert!(buf.write_str("6789").is_err());
+        assert!(buf.write_str("678").is_ok());
+        assert_eq!(buf.remaining(), 0);
+        assert_eq!(&data, b"12345678");
+    }
+
+    #[test]
+    fn whole_writes_keep_room() {
+        let mut data = [0u8; 10];
+        let mut buf = FixedBuffer { data: &mut data, len: 0 };
+        assert!(buf.write_whole(format_args!("{}-{}", 12, 34), 1));
+        assert!(!buf.write_whole(format_args!("{}", "abcde"), 1));
+        assert_eq!(buf.remaining(), 5);
+        assert!(buf.write_whole(format_args!("{}", "abcd"), 1));
+        assert!(buf.write_str("}").is_ok());
+        assert_eq!(&data, b"12-34abcd}");
+    }
+
+    #[test]
+    fn formatted_len_counts_bytes() {
+        assert_eq!(formatted_len(format_args!("{:#x}", 255)), 4);
+        assert_eq!(formatted_len(format_args!("é{}", "")), 2);
+    }
+}
@@ -0,0 +1,146 @@
+// Memory and resource usage at the time of a report.
+//
+// Unlike the contexts collected by `crash::init` (see `crate::contexts`),
+// these change all the time, so they are read when an event is captured and
+// added as two more contexts:
+//
+// - `memory`: resident and virtual size, peak resident size, the heap counted
+//   by `crash::CrashAllocator` when it is installed, and the cgroup's memory
+//   limit and usage when the process runs in one (containers),
+// - `resources`: open file descriptors and their limit, threads, CPU time
+//   and page faults from getrusage(2), and the address space limit.
+//
+// What is available depends on the platform; missing values are left out.
+// Linux reads `/proc/self` and `/sys/fs/cgroup`. Native crash reports written
+// from signal handlers carry the values from `init` only, as do deferred ones.
+
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value};
+
+// Members of a context, leaving out unknown values.
+#[derive(Default)]
+struct Context(Map<String, Value>);
+
+impl Context {
+    fn set(&mut self, key: &str, value: Option<impl Into<Value>>) {
+        if let Some(value) = value {
+            self.0.insert(key.to_string(), value.into());
+        }
+    }
+}
+
+// Fields of `/proc/self/status` in bytes, e.g. `VmRSS:  1234 kB`.
+#[cfg(target_os = "linux")]
+fn proc_status() -> impl Fn(&str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+    move |key| {
+        let value = status
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))?
+            .trim();
+        match value.strip_suffix(" kB") {
+            Some(kb) => kb.trim().parse::<u64>().ok().map(|kb| kb * 1024),
+            None => value.parse().ok(),
+        }
+    }
+}
+
+// Memory limit and usage of the process's cgroup, v2 or v1. `None` for the
+// limit when there is none.
+#[cfg(target_os = "linux")]
+fn cgroup_memory() -> (Option<u64>, Option<u64>) {
+    let read = |path: &str| std::fs::read_to_string(path).ok().map(|s| s.trim().to_string());
+    let number = |value: Option<String>| value.and_then(|v| v.parse::<u64>().ok());
+
+    let cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap_or_default();
+    if let Some(path) = cgroup.lines().find_map(|line| line.strip_prefix("0::")) {
+        let dir = format!("/sys/fs/cgroup{}", path.trim_end_matches('/'));
+        // "max" means no limit.
+        let limit = number(read(&format!("{}/memory.max", dir)));
+        let usage = number(read(&format!("{}/memory.current", dir)));
+        if limit.is_some() || usage.is_some() {
+            return (limit, usage);
+        }
+    }
+    // cgroup v1 reports "no limit" as a huge number near i64::MAX.
+    let limit = number(read("/sys/fs/cgroup/memory/memory.limit_in_bytes")).filter(|&l| l < 1 << 62);
+    let usage = number(read("/sys/fs/cgroup/memory/memory.usage_in_bytes"));
+    (limit, usage)
+}
+
+fn memory_context() -> Context {
+    let mut memory = Context::default();
+    let stats = crate::oom::MemoryStats::current(None);
+    memory.set("resident_bytes", stats.resident_bytes);
+    #[cfg(target_os = "linux")]
+    {
+        let status = proc_status();
+        memory.set("virtual_bytes", status("VmSize"));
+        memory.set("peak_resident_bytes", status("VmHWM"));
+        let (limit, usage) = cgroup_memory();
+        memory.set("cgroup_limit_bytes", limit);
+        memory.set("cgroup_usage_bytes", usage);
+    }
+    memory.set("heap_in_use", stats.heap_in_use);
+    memory.set("heap_peak", stats.heap_peak);
+    memory
+}
+
+fn resources_context() -> Context {
+    #[cfg_attr(not(unix), allow(unused_mut))]
+    let mut resources = Context::default();
+    #[cfg(target_os = "linux")]
+    {
+        let fds = std::fs::read_dir("/proc/self/fd").map(|entries| entries.count());
+        resources.set("open_fds", fds.ok());
+        resources.set("threads", proc_status()("Threads"));
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let fds = std::fs::read_dir("/dev/fd").map(|entries| entries.count());
+        resources.set("open_fds", fds.ok());
+    }
+    #[cfg(unix)]
+    {
+        // Current and maximum value of a resource limit, `None` if unlimited.
+        let rlimit = |resource| {
+            // SAFETY: `rlimit` is plain data filled in by the call.
+            let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+            if unsafe { libc::getrlimit(resource, &mut limit) } != 0 {
+                return (None, None);
+            }
+            let value = |v: libc::rlim_t| (v != libc::RLIM_INFINITY).then_some(v);
+            (value(limit.rlim_cur), value(limit.rlim_max))
+        };
+        let (soft, hard) = rlimit(libc::RLIMIT_NOFILE);
+        resources.set("max_open_fds", soft);
+        resources.set("max_open_fds_hard", hard);
+        resources.set("address_space_limit", rlimit(libc::RLIMIT_AS).0);
+
+        // SAFETY: `rusage` is plain data filled in by the call.
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } == 0 {
+            let seconds = |t: libc::timeval| t.tv_sec as f64 + t.tv_usec as f64 / 1e6;
+            resources.set("user_time", Some(seconds(usage.ru_utime)));
+            resources.set("system_time", Some(seconds(usage.ru_stime)));
+            resources.set("minor_page_faults", Some(usage.ru_minflt as i64));
+            resources.set("major_page_faults", Some(usage.ru_majflt as i64));
+            // Kilobytes on Linux, bytes on macOS.
+            let scale = if cfg!(target_os = "macos") { 1 } else { 1024 };
+            resources.set("max_resident_bytes", Some(usage.ru_maxrss as i64 * scale));
+        }
+    }
+    resources
+}
+
+/// The `memory` and `resources` contexts as of now.
+pub fn snapshot() -> BTreeMap<String, Value> {
+    let mut contexts = BTreeMap::new();
+    for (name, context) in [("memory", memory_context()), ("resources", resources_context())] {
+        if !context.0.is_empty() {
+            contexts.insert(name.to_string(), Value::Object(context.0));
+        }
+    }
+    contexts
+}
@@ -0,0 +1,106 @@
+// Pruning of old crash files in the output directory.
+//
+// Long-running deployments that crash repeatedly would otherwise fill the
+// disk. `crash::init` runs a sweep over `crash_report_*`, `crash_dump_*` and
+// `crash_attachment_*` files, grouped per crash so a report is never kept
+// without its dump or the other way round. Crashes are kept newest first
+// until one of the configured limits is reached; the rest are removed. With
+// no limit configured nothing is touched.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::attachments::ATTACHMENT_PREFIX;
+use crate::config::Config;
+
+const PREFIXES: [&str; 3] = ["crash_report_", "crash_dump_", ATTACHMENT_PREFIX];
+const EVENT_ID_LEN: usize = 36;
+
+/// Files removed by a sweep.
+#[derive(Debug, Default)]
+pub struct Swept {
+    pub crashes: usize,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Default)]
+struct Crash {
+    files: Vec<PathBuf>,
+    bytes: u64,
+    modified: Option<SystemTime>, // Newest file of the crash
+}
+
+// The crash a file belongs to: its event id, or the whole name for files
+// that do not carry one right after the prefix.
+fn crash_key(file_name: &str) -> Option<&str> {
+    let rest = PREFIXES.iter().find_map(|prefix| file_name.strip_prefix(prefix))?;
+    match rest.get(..EVENT_ID_LEN) {
+        Some(id) if id.chars().all(|c| c.is_ascii_hexdigit() || c == '-') => Some(id),
+        _ => Some(file_name),
+    }
+}
+
+fn has_limits(config: &Config) -> bool {
+    config.max_reports.is_some() || config.max_age.is_some() || config.max_total_bytes.is_some()
+}
+
+/// Removes the crashes in `config.output_dir` that exceed the configured
+/// `max_reports`, `max_age` or `max_total_bytes`.
+pub fn sweep(config: &Config) -> std::io::Result<Swept> {
+    let mut swept = Swept::default();
+    if !has_limits(config) {
+        return Ok(swept);
+    }
+
+    let mut crashes: HashMap<String, Crash> = HashMap::new();
+    for entry in std::fs::read_dir(&config.output_dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(key) = name.to_str().and_then(crash_key) else {
+            continue;
+        };
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        let crash = crashes.entry(key.to_string()).or_default();
+        crash.files.push(entry.path());
+        crash.bytes += metadata.len();
+        crash.modified = crash.modified.max(metadata.modified().ok());
+    }
+
+    let mut crashes: Vec<Crash> = crashes.into_values().collect();
+    crashes.sort_by_key(|crash| std::cmp::Reverse(crash.modified));
+
+    let now = SystemTime::now();
+    let mut kept = 0;
+    let mut kept_bytes = 0u64;
+    for crash in crashes {
+        let age = crash
+            .modified
+            .and_then(|m| now.duration_since(m).ok())
+            .unwrap_or(Duration::ZERO);
+        let keep = config.max_reports.is_none_or(|max| kept < max)
+            && config.max_age.is_none_or(|max| age <= max)
+            && config.max_total_bytes.is_none_or(|max| kept_bytes + crash.bytes <= max);
+        if keep {
+            kept += 1;
+            kept_bytes += crash.bytes;
+            continue;
+        }
+
+        swept.crashes += 1;
+        for file in &crash.files {
+            match std::fs::remove_file(file) {
+                Ok(()) => swept.files += 1,
+                Err(e) => eprintln!("Failed to remove old crash file '{}': {}", file.display(), e),
+            }
+        }
+        swept.bytes += crash.bytes;
+    }
+    Ok(swept)
+}
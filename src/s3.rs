@@ -0,0 +1,199 @@
+// Delivery of reports to S3 and compatible object stores.
+//
+// `S3Transport` stores each report as an object named after the report file
+// (`<prefix>crash_report_<id>.json`, with the extensions of the configured
+// encoding, compression and encryption), as it would be written locally. The
+// digest and signature of its content (see `crate::integrity`) go into the
+// object metadata (`x-amz-meta-sha256`, `x-amz-meta-signature`). Requests
+// are signed with AWS Signature Version 4 and use path-style URLs
+// (`{endpoint}/{bucket}/{key}`), which MinIO, R2 and other S3-compatible
+// stores accept too. Credentials default to AWS_ACCESS_KEY_ID,
+// AWS_SECRET_ACCESS_KEY and AWS_SESSION_TOKEN. Nothing is queued when the
+// store cannot be reached.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::Config;
+use crate::integrity::sha256_hex;
+use crate::transport::{Delivery, Transport};
+
+/// Puts reports into an S3 bucket, see above.
+#[derive(Clone)]
+pub struct S3Transport {
+    endpoint: String,
+    bucket: String,
+    region: String,
+    prefix: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl std::fmt::Debug for S3Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Transport")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl S3Transport {
+    /// Reports go to `bucket` in AWS region `region`, with the credentials
+    /// from the environment.
+    pub fn new(bucket: impl Into<String>, region: impl Into<String>) -> Self {
+        let region = region.into();
+        let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        S3Transport {
+            endpoint: format!("https://s3.{}.amazonaws.com", region),
+            bucket: bucket.into(),
+            region,
+            prefix: String::new(),
+            access_key: env("AWS_ACCESS_KEY_ID").unwrap_or_default(),
+            secret_key: env("AWS_SECRET_ACCESS_KEY").unwrap_or_default(),
+            session_token: env("AWS_SESSION_TOKEN"),
+        }
+    }
+
+    /// Another S3-compatible store, e.g. `http://localhost:9000` for MinIO.
+    pub fn endpoint(mut self, url: impl Into<String>) -> Self {
+        self.endpoint = url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Put in front of every object name, e.g. `crashes/`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn credentials(mut self, access_key: impl Into<String>, secret_key: impl Into<String>) -> Self {
+        self.access_key = access_key.into();
+        self.secret_key = secret_key.into();
+        self.session_token = None;
+        self
+    }
+
+    pub fn session_token(mut self, token: impl Into<String>) -> Self {
+        self.session_token = Some(token.into());
+        self
+    }
+
+    // `Authorization` header of a request with the given canonical URI,
+    // query and headers (lowercase names, sorted), at `timestamp`
+    // (`YYYYMMDD'T'HHMMSS'Z'`).
+    fn authorization(
+        &self,
+        method: &str,
+        uri: &str,
+        query: &str,
+        headers: &[(String, String)],
+        payload_hash: &str,
+        timestamp: &str,
+    ) -> String {
+        let date = &timestamp[..8];
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+        let signed_headers = headers.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(";");
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method, uri, query, canonical_headers, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", timestamp, scope, sha256_hex(canonical_request.as_bytes()));
+        let key = [date, &self.region, "s3", "aws4_request"]
+            .iter()
+            .fold(format!("AWS4{}", self.secret_key).into_bytes(), |key, part| hmac(&key, part.as_bytes()));
+        let signature: String = hmac(&key, string_to_sign.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, signed_headers, signature
+        )
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Percent-encodes `path` as SigV4 wants it, keeping the slashes.
+fn encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// `YYYYMMDD'T'HHMMSS'Z'` for `secs` since the UNIX epoch.
+fn amz_date(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rest = secs % 86400;
+    // Days to a civil date (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+impl Transport for S3Transport {
+    fn send(&self, config: &Config, file_name: &str, json: &[u8]) -> Result<Delivery, String> {
+        let (file_name, data, digest) =
+            crate::transport::encode_artifact(config, file_name, json).map_err(|e| e.to_string())?;
+        let uri = encode_path(&format!("/{}/{}{}", self.bucket, self.prefix, file_name));
+        let url = format!("{}{}", self.endpoint, uri);
+        let host = self
+            .endpoint
+            .split_once("://")
+            .map_or(self.endpoint.as_str(), |(_, rest)| rest)
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let payload_hash = sha256_hex(&data);
+        let timestamp = amz_date(crate::clock::since_epoch().as_secs());
+
+        let mut headers = vec![
+            ("content-type".to_string(), "application/octet-stream".to_string()),
+            ("host".to_string(), host),
+            ("x-amz-content-sha256".to_string(), payload_hash.clone()),
+            ("x-amz-date".to_string(), timestamp.clone()),
+            ("x-amz-meta-sha256".to_string(), digest.sha256.clone()),
+        ];
+        if let Some(signature) = &digest.signature {
+            headers.push(("x-amz-meta-signature".to_string(), signature.clone()));
+        }
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+        headers.sort();
+        let authorization = self.authorization("PUT", &uri, "", &headers, &payload_hash, &timestamp);
+
+        let agent = ureq::AgentBuilder::new().timeout(config.upload_timeout).build();
+        let mut request = agent.put(&url).set("Authorization", &authorization);
+        for (name, value) in headers.iter().filter(|(name, _)| name != "host") {
+            request = request.set(name, value);
+        }
+        request.send_bytes(&data).map_err(|e| e.to_string())?;
+        Ok(Delivery::Uploaded(url))
+    }
+}
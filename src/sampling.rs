@@ -0,0 +1,85 @@
+// Rate limiting and sampling of panic reports.
+//
+// An application that catches panics and keeps running can panic thousands of
+// times in a loop. Before the panic hook captures a caught panic, or an error
+// or message is captured, it asks `decide`: at most `max_reports_per_minute`
+// reports are written per process and minute, and of those only a
+// `sample_rate` fraction is kept. Sampling uses the random bits of the event
+// id, so no extra randomness is needed. Runs in a crash loop that was
+// reported already report nothing when crash-loop throttling is on, see
+// `crate::crash_loop`. Uncaught panics and native crashes end the process;
+// they are never sampled or rate limited, see `decide_fatal`.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use uuid::Uuid;
+
+use crate::config::Config;
+
+// Start of the current one-minute window (seconds since the epoch) and the
+// number of reports captured in it.
+static WINDOW_START: AtomicU64 = AtomicU64::new(0);
+static WINDOW_COUNT: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Capture,
+    RateLimited,
+    SampledOut,
+    CrashLoop,
+}
+
+// Whether another report fits in the current window. Lock-free, since it
+// runs in the panic hook; a race at a window boundary can let one extra
+// report through.
+fn within_rate_limit(limit: u32) -> bool {
+    let now = crate::clock::since_epoch().as_secs();
+    let start = WINDOW_START.load(Ordering::Acquire);
+    if now >= start + 60
+        && WINDOW_START
+            .compare_exchange(start, now, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    {
+        WINDOW_COUNT.store(0, Ordering::Release);
+    }
+    WINDOW_COUNT.fetch_add(1, Ordering::AcqRel) < limit
+}
+
+// Maps the low 53 bits of a v4 or v7 UUID, all random (the version, variant
+// and v7 timestamp bits sit higher), onto [0, 1).
+fn sample_point(event_id: &Uuid) -> f64 {
+    const MANTISSA: u64 = 1 << 53;
+    (event_id.as_u128() as u64 % MANTISSA) as f64 / MANTISSA as f64
+}
+
+/// Whether a crash that ends the process should be reported: always, unless
+/// crash-loop throttling drops it.
+pub fn decide_fatal(config: &Config) -> Decision {
+    #[cfg(not(target_arch = "wasm32"))]
+    if crate::crash_loop::throttled(config) {
+        crate::metrics::REPORTS_DROPPED.inc();
+        return Decision::CrashLoop;
+    }
+    #[cfg(target_arch = "wasm32")]
+    let _ = config;
+    Decision::Capture
+}
+
+/// Whether the non-fatal capture with id `event_id` should be reported.
+pub fn decide(config: &Config, event_id: &Uuid) -> Decision {
+    if decide_fatal(config) == Decision::CrashLoop {
+        return Decision::CrashLoop;
+    }
+    let decision = if config.sample_rate < 1.0 && sample_point(event_id) >= config.sample_rate {
+        Decision::SampledOut
+    } else {
+        match config.max_reports_per_minute {
+            Some(limit) if !within_rate_limit(limit) => Decision::RateLimited,
+            _ => Decision::Capture,
+        }
+    };
+    if decision != Decision::Capture {
+        crate::metrics::REPORTS_DROPPED.inc();
+    }
+    decision
+}
@@ -0,0 +1,143 @@
+// Tags, user and extra context attached to captured events.
+//
+// There is one global scope shared by all threads, plus a per-thread stack of
+// scopes pushed with `push_scope` / `with_scope`. The `set_*` functions write
+// to the innermost pushed scope of the calling thread, or to the global scope
+// when none is pushed. When an event is captured the global scope is applied
+// first and the thread's scopes on top, innermost last.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+use crate::event::{SentryEvent, User};
+
+#[derive(Clone, Debug, Default)]
+pub struct Scope {
+    tags: BTreeMap<String, String>,
+    user: Option<User>,
+    extra: BTreeMap<String, serde_json::Value>,
+}
+
+impl Scope {
+    pub fn set_tag(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.tags.insert(key.into(), value.into());
+    }
+
+    pub fn remove_tag(&mut self, key: &str) {
+        self.tags.remove(key);
+    }
+
+    pub fn set_user(&mut self, user: Option<User>) {
+        self.user = user;
+    }
+
+    pub fn set_extra(&mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) {
+        self.extra.insert(key.into(), value.into());
+    }
+
+    pub fn remove_extra(&mut self, key: &str) {
+        self.extra.remove(key);
+    }
+
+    /// Copies this scope's context into `event`, overriding existing keys.
+    pub fn apply_to(&self, event: &mut SentryEvent) {
+        event.tags.extend(self.tags.iter().map(|(k, v)| (k.clone(), v.clone())));
+        event.extra.extend(self.extra.iter().map(|(k, v)| (k.clone(), v.clone())));
+        if self.user.is_some() {
+            event.user = self.user.clone();
+        }
+    }
+}
+
+static GLOBAL_SCOPE: Mutex<Scope> = Mutex::new(Scope {
+    tags: BTreeMap::new(),
+    user: None,
+    extra: BTreeMap::new(),
+});
+
+thread_local! {
+    static SCOPE_STACK: RefCell<Vec<Scope>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Modifies the innermost scope of the calling thread, or the global scope
+/// if no scope has been pushed.
+pub fn configure_scope<F: FnOnce(&mut Scope)>(f: F) {
+    let f = SCOPE_STACK.with(|stack| match stack.borrow_mut().last_mut() {
+        Some(scope) => {
+            f(scope);
+            None
+        }
+        None => Some(f),
+    });
+    if let Some(f) = f {
+        f(&mut GLOBAL_SCOPE.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+}
+
+pub fn set_tag(key: impl Into<String>, value: impl Into<String>) {
+    configure_scope(|scope| scope.set_tag(key, value));
+}
+
+pub fn set_user(user: Option<User>) {
+    configure_scope(|scope| scope.set_user(user));
+}
+
+pub fn set_extra(key: impl Into<String>, value: impl Into<serde_json::Value>) {
+    configure_scope(|scope| scope.set_extra(key, value));
+}
+
+/// Pops the scope pushed by `push_scope` when dropped.
+#[must_use = "the scope is popped as soon as the guard is dropped"]
+pub struct ScopeGuard {
+    depth: usize,
+    _not_send: PhantomData<*const ()>, // The scope lives on this thread's stack.
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        SCOPE_STACK.with(|stack| stack.borrow_mut().truncate(self.depth));
+    }
+}
+
+/// Pushes a new scope for the calling thread. Context set while the guard is
+/// alive is discarded when it is dropped.
+pub fn push_scope() -> ScopeGuard {
+    SCOPE_STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        let depth = stack.len();
+        stack.push(Scope::default());
+        ScopeGuard {
+            depth,
+            _not_send: PhantomData,
+        }
+    })
+}
+
+/// Runs `f` in a new scope prepared by `configure`.
+pub fn with_scope<C, F, R>(configure: C, f: F) -> R
+where
+    C: FnOnce(&mut Scope),
+    F: FnOnce() -> R,
+{
+    let _guard = push_scope();
+    configure_scope(configure);
+    f()
+}
+
+/// Applies the global scope and the calling thread's scopes to `event`. Used
+/// by the panic hook, so it never blocks: a scope that is locked or borrowed
+/// by the panicking code is skipped.
+pub fn apply_scopes(event: &mut SentryEvent) {
+    match GLOBAL_SCOPE.try_lock() {
+        Ok(scope) => scope.apply_to(event),
+        Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner().apply_to(event),
+        Err(std::sync::TryLockError::WouldBlock) => {}
+    }
+    let _ = SCOPE_STACK.try_with(|stack| {
+        if let Ok(stack) = stack.try_borrow() {
+            stack.iter().for_each(|scope| scope.apply_to(event));
+        }
+    });
+}
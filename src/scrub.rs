@@ -0,0 +1,125 @@
+// Client-side scrubbing of personal data.
+//
+// When enabled, every finished report passes through a `Scrubber` before it is
+// written or sent: each string value of the event is matched against a list of
+// regexes and matches are replaced by `[Filtered]`. The built-in rules cover
+// email addresses, IPv4 and full-form IPv6 addresses and credit card numbers
+// (checked with the Luhn algorithm to spare other long numbers); applications
+// add their own patterns on top. Identifiers and timestamps of the event are
+// left alone. The server applies its own rules as well, see
+// `server/src/scrub.rs`.
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::config::Config;
+
+pub const FILTERED: &str = "[Filtered]";
+
+const EMAIL: &str = r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}";
+const IPV4: &str = r"\b(?:(?:25[0-5]|2[0-4]\d|1?\d?\d)\.){3}(?:25[0-5]|2[0-4]\d|1?\d?\d)\b";
+const IPV6: &str = r"\b(?:[0-9A-Fa-f]{1,4}:){7}[0-9A-Fa-f]{1,4}\b";
+const CARD: &str = r"\b(?:\d[ -]?){12,18}\d\b";
+
+// Fields that never hold personal data and must stay intact.
+const SKIPPED_KEYS: [&str; 4] = ["event_id", "timestamp", "installation_id", "instruction_addr"];
+
+#[derive(Clone, Debug)]
+struct Rule {
+    regex: Regex,
+    luhn: bool, // Only replace matches that pass the Luhn check
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Scrubber {
+    rules: Vec<Rule>,
+}
+
+fn luhn_valid(candidate: &str) -> bool {
+    let digits: Vec<u32> = candidate.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match (i % 2 == 1, d * 2) {
+            (true, doubled) if doubled > 9 => doubled - 9,
+            (true, doubled) => doubled,
+            (false, _) => d,
+        })
+        .sum();
+    digits.len() >= 13 && sum.is_multiple_of(10)
+}
+
+impl Scrubber {
+    /// A scrubber with the built-in rules only.
+    pub fn with_defaults() -> Self {
+        let rule = |pattern: &str, luhn| Rule {
+            regex: Regex::new(pattern).expect("built-in scrub rule"),
+            luhn,
+        };
+        Scrubber {
+            rules: vec![rule(EMAIL, false), rule(IPV4, false), rule(IPV6, false), rule(CARD, true)],
+        }
+    }
+
+    /// The scrubber described by `config`, or `None` if scrubbing is off.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, regex::Error> {
+        if !config.scrub_pii {
+            return Ok(None);
+        }
+        let mut scrubber = Scrubber::with_defaults();
+        for pattern in &config.scrub_rules {
+            scrubber.add_rule(pattern)?;
+        }
+        Ok(Some(scrubber))
+    }
+
+    /// Adds a rule; matches of `pattern` are replaced by `[Filtered]`.
+    pub fn add_rule(&mut self, pattern: &str) -> Result<(), regex::Error> {
+        self.rules.push(Rule {
+            regex: Regex::new(pattern)?,
+            luhn: false,
+        });
+        Ok(())
+    }
+
+    pub fn scrub_str(&self, value: &str) -> String {
+        let mut value = value.to_string();
+        for rule in &self.rules {
+            if !rule.regex.is_match(&value) {
+                continue;
+            }
+            value = rule
+                .regex
+                .replace_all(&value, |caps: &regex::Captures| {
+                    if rule.luhn && !luhn_valid(&caps[0]) {
+                        caps[0].to_string()
+                    } else {
+                        FILTERED.to_string()
+                    }
+                })
+                .into_owned();
+        }
+        value
+    }
+
+    /// Scrubs every string in `value`, recursively.
+    pub fn scrub_value(&self, value: &mut Value) {
+        match value {
+            Value::String(s) => *s = self.scrub_str(s),
+            Value::Array(items) => items.iter_mut().for_each(|item| self.scrub_value(item)),
+            Value::Object(map) => map
+                .iter_mut()
+                .filter(|(key, _)| !SKIPPED_KEYS.contains(&key.as_str()))
+                .for_each(|(_, item)| self.scrub_value(item)),
+            _ => {}
+        }
+    }
+
+    /// Scrubs a serialized report, keeping it pretty-printed.
+    pub fn scrub_report(&self, json: &[u8]) -> Result<Vec<u8>, String> {
+        let mut event: Value = serde_json::from_slice(json).map_err(|e| e.to_string())?;
+        self.scrub_value(&mut event);
+        serde_json::to_vec_pretty(&event).map_err(|e| e.to_string())
+    }
+}
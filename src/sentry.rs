@@ -0,0 +1,120 @@
+// Direct delivery to Sentry.
+//
+// With a DSN configured, reports are wrapped in a Sentry envelope and sent to
+// the project's envelope endpoint, so the bundled server is not needed. A DSN
+// looks like `https://<public_key>@<host>[/<path>]/<project_id>`.
+
+use crate::config::Config;
+
+const SENTRY_CLIENT: &str = concat!("crash/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Clone, Debug)]
+pub struct Dsn {
+    raw: String,
+    scheme: String,
+    public_key: String,
+    host: String, // Including the port, if any
+    path: String, // Prefix before the project id, without trailing slash
+    project_id: String,
+}
+
+impl Dsn {
+    pub fn parse(dsn: &str) -> Result<Dsn, String> {
+        let invalid = |reason: &str| format!("Invalid Sentry DSN '{}': {}", dsn, reason);
+        let (scheme, rest) = dsn.split_once("://").ok_or_else(|| invalid("missing scheme"))?;
+        if scheme != "http" && scheme != "https" {
+            return Err(invalid("scheme must be http or https"));
+        }
+        let (credentials, location) = rest.split_once('@').ok_or_else(|| invalid("missing public key"))?;
+        // The secret key (`public:secret`) is deprecated and not needed.
+        let public_key = credentials.split(':').next().unwrap_or_default();
+        let location = location.trim_end_matches('/');
+        let (host_and_path, project_id) = location.rsplit_once('/').ok_or_else(|| invalid("missing project id"))?;
+        let (host, path) = match host_and_path.split_once('/') {
+            Some((host, path)) => (host, format!("/{}", path)),
+            None => (host_and_path, String::new()),
+        };
+        if public_key.is_empty() || host.is_empty() || project_id.is_empty() {
+            return Err(invalid("empty component"));
+        }
+        Ok(Dsn {
+            raw: dsn.to_string(),
+            scheme: scheme.to_string(),
+            public_key: public_key.to_string(),
+            host: host.to_string(),
+            path,
+            project_id: project_id.to_string(),
+        })
+    }
+
+    pub fn envelope_url(&self) -> String {
+        format!("{}://{}{}/api/{}/envelope/", self.scheme, self.host, self.path, self.project_id)
+    }
+
+    fn auth_header(&self) -> String {
+        format!(
+            "Sentry sentry_version=7, sentry_key={}, sentry_client={}",
+            self.public_key, SENTRY_CLIENT
+        )
+    }
+}
+
+// Adapts a report to what Sentry accepts: dash-less event ids, numeric
+// timestamps and no `schema_version`, which is ours.
+fn to_sentry_event(report: &[u8]) -> Result<serde_json::Value, String> {
+    let mut event: serde_json::Value = serde_json::from_slice(report).map_err(|e| e.to_string())?;
+    let map = event.as_object_mut().ok_or("report is not a JSON object")?;
+    if let Some(id) = map.get("event_id").and_then(|v| v.as_str()) {
+        let id = id.replace('-', "");
+        map.insert("event_id".to_string(), id.into());
+    }
+    if let Some(ts) = map.get("timestamp").and_then(|v| v.as_str()).and_then(|t| t.parse::<f64>().ok()) {
+        map.insert("timestamp".to_string(), ts.into());
+    }
+    map.remove("schema_version");
+    Ok(event)
+}
+
+/// Builds an envelope holding a single event item.
+pub fn envelope(dsn: &Dsn, report: &[u8]) -> Result<Vec<u8>, String> {
+    let event = to_sentry_event(report)?;
+    let payload = serde_json::to_vec(&event).map_err(|e| e.to_string())?;
+    let header = serde_json::json!({
+        "event_id": event.get("event_id"),
+        "dsn": dsn.raw,
+    });
+    let item_header = serde_json::json!({ "type": "event", "length": payload.len() });
+
+    let mut envelope = Vec::with_capacity(payload.len() + 256);
+    for part in [header.to_string().as_bytes(), item_header.to_string().as_bytes(), &payload] {
+        envelope.extend_from_slice(part);
+        envelope.push(b'\n');
+    }
+    Ok(envelope)
+}
+
+/// Sends a report to the Sentry project identified by `dsn`.
+pub fn send_report(config: &Config, dsn: &str, report: &[u8]) -> Result<String, String> {
+    let dsn = Dsn::parse(dsn)?;
+    let body = envelope(&dsn, report)?;
+    let url = dsn.envelope_url();
+    #[cfg(not(target_arch = "wasm32"))]
+    ureq::AgentBuilder::new()
+        .timeout(config.upload_timeout)
+        .build()
+        .post(&url)
+        .set("Content-Type", "application/x-sentry-envelope")
+        .set("X-Sentry-Auth", &dsn.auth_header())
+        .send_bytes(&body)
+        .map_err(|e| e.to_string())?;
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = config;
+        let headers = [
+            ("Content-Type", "application/x-sentry-envelope"),
+            ("X-Sentry-Auth", &dsn.auth_header()),
+        ];
+        crate::web::post(&url, &headers, &body)?;
+    }
+    Ok(url)
+}
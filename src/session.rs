@@ -0,0 +1,244 @@
+// Sessions, for crash-free rates per release.
+//
+// A session is a run of the application, or whatever part of it the
+// application treats as one (a request, an open document). The server counts
+// how many sessions, and how many installations, ended without a crash in
+// each release (`GET /stats/sessions`), like Sentry's release health.
+// `crash::start_session` begins a session and `crash::end_session` ends it
+// cleanly; with `Builder::auto_session` one is started by `crash::init`. A
+// session still open when the process exits normally is ended by an
+// `atexit` handler, so a plain return from `main` or `std::process::exit`
+// count as clean exits.
+//
+// While the session runs, errors reported through this crate are counted and
+// a fatal panic marks it `crashed`. Its record is kept in the output
+// directory as `crash_session_<sid>.json`. A native crash, an abort or a kill
+// leaves the record open; the next `crash::init` finds it and ends it as
+// `abnormal` unless its process is still running (checked on Unix only).
+// Ended records are POSTed to `{upload_url}/sessions` and removed, or left in
+// the output directory until an upload succeeds. Without an upload URL they
+// stay there for the server to read.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard, Once};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+pub const SESSION_PREFIX: &str = "crash_session_";
+
+/// How a session ended, or `Ok` while it runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStatus {
+    Ok,
+    Exited,
+    Crashed,
+    Abnormal,
+}
+
+/// The record of a session, as stored and uploaded.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub sid: String,                 // Session id
+    pub did: Option<String>,         // Installation id, see `crate::install_id`
+    pub pid: u32,                    // Process the session ran in
+    pub started: f64,                // Seconds since the UNIX epoch
+    pub timestamp: f64,              // Last update
+    pub duration: f64,               // Seconds from start to the last update
+    pub status: SessionStatus,
+    pub errors: u64,                 // Events of level `error` or `fatal` reported during it
+    pub release: Option<String>,
+    pub environment: Option<String>,
+}
+
+impl SessionRecord {
+    fn file_name(&self) -> String {
+        format!("{}{}.json", SESSION_PREFIX, self.sid)
+    }
+}
+
+static CURRENT: Mutex<Option<SessionRecord>> = Mutex::new(None);
+
+fn current() -> MutexGuard<'static, Option<SessionRecord>> {
+    CURRENT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn now() -> f64 {
+    crate::clock::since_epoch().as_secs_f64()
+}
+
+// Writes `record` to the output directory.
+fn store(config: &Config, record: &SessionRecord) -> std::io::Result<PathBuf> {
+    let path = config.output_dir.join(record.file_name());
+    let json = serde_json::to_vec(record).map_err(std::io::Error::other)?;
+    fs::write(&path, json)?;
+    Ok(path)
+}
+
+fn endpoint(server: &str) -> String {
+    format!("{}/sessions", server.trim_end_matches('/'))
+}
+
+// Uploads an ended record, removing its file once it is on the server. The
+// file is kept, up to date, when there is no server or it cannot be reached.
+fn deliver(config: &Config, record: &SessionRecord) {
+    if let Some(server) = &config.upload_url {
+        let sent = serde_json::to_vec(record)
+            .map_err(|e| e.to_string())
+            .and_then(|json| {
+                let headers = [("Content-Type", "application/json")];
+                crate::transport::post(config, &endpoint(server), &headers, &json)
+            });
+        match sent {
+            Ok(()) => {
+                let _ = fs::remove_file(config.output_dir.join(record.file_name()));
+                return;
+            }
+            Err(e) => eprintln!("Failed to upload session {} to {}: {}", record.sid, server, e),
+        }
+    }
+    if let Err(e) = store(config, record) {
+        eprintln!("Failed to write session {}: {}", record.sid, e);
+    }
+}
+
+// Ends `record` as `status`, unless it already ended as `crashed`.
+fn finish(config: &Config, mut record: SessionRecord, status: SessionStatus) {
+    if record.status == SessionStatus::Ok {
+        record.status = status;
+    }
+    record.timestamp = now();
+    record.duration = (record.timestamp - record.started).max(0.0);
+    deliver(config, &record);
+}
+
+extern "C" fn end_at_exit() {
+    end_session();
+}
+
+/// Starts a session, ending the current one first. Returns the session id,
+/// or `None` if the crash handler is not installed or the record cannot be
+/// written.
+pub fn start_session() -> Option<String> {
+    static AT_EXIT: Once = Once::new();
+    let config = crate::hook::config()?;
+    let timestamp = now();
+    let record = SessionRecord {
+        sid: uuid::Uuid::new_v4().to_string(),
+        did: crate::install_id::installation_id().map(|s| s.to_string()),
+        pid: std::process::id(),
+        started: timestamp,
+        timestamp,
+        duration: 0.0,
+        status: SessionStatus::Ok,
+        errors: 0,
+        release: config.release.clone(),
+        environment: config.environment.clone(),
+    };
+    if let Err(e) = store(config, &record) {
+        eprintln!("Failed to write session {}: {}", record.sid, e);
+        return None;
+    }
+    let sid = record.sid.clone();
+    let previous = current().replace(record);
+    if let Some(previous) = previous {
+        finish(config, previous, SessionStatus::Exited);
+    }
+    AT_EXIT.call_once(|| unsafe {
+        libc::atexit(end_at_exit);
+    });
+    Some(sid)
+}
+
+/// Ends the current session as exited, or as crashed if a panic ended it.
+/// Does nothing without one.
+pub fn end_session() {
+    let Some(config) = crate::hook::config() else {
+        return;
+    };
+    if let Some(record) = current().take() {
+        finish(config, record, SessionStatus::Exited);
+    }
+}
+
+/// Counts an event of level `error` or `fatal` against the current session.
+pub(crate) fn record_error() {
+    let Some(config) = crate::hook::config() else {
+        return;
+    };
+    let mut current = current();
+    if let Some(record) = current.as_mut() {
+        record.errors += 1;
+        record.timestamp = now();
+        let _ = store(config, record);
+    }
+}
+
+/// Marks the current session as crashed by a fatal panic. With `persist` the
+/// record on disk is updated too, for when the process aborts before the
+/// session is ended; the deferred hook leaves the disk alone.
+pub(crate) fn mark_crashed(persist: bool) {
+    let mut current = current();
+    let Some(record) = current.as_mut() else {
+        return;
+    };
+    record.status = SessionStatus::Crashed;
+    if persist {
+        if let Some(config) = crate::hook::config() {
+            record.timestamp = now();
+            let _ = store(config, record);
+        }
+    }
+}
+
+// Whether process `pid` is still running.
+#[cfg(unix)]
+pub(crate) fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks that the process exists.
+    unsafe { libc::kill(pid, 0) == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM) }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_running(_pid: u32) -> bool {
+    false
+}
+
+/// Ends the sessions earlier runs left open as `abnormal` and uploads the
+/// records that could not be uploaded then. Returns how many were ended.
+pub fn complete_previous(config: &Config) -> std::io::Result<usize> {
+    let own = std::process::id();
+    let mut ended = 0;
+    for entry in fs::read_dir(&config.output_dir)?.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !name.starts_with(SESSION_PREFIX) || !name.ends_with(".json") {
+            continue;
+        }
+        let Some(record) = fs::read(entry.path())
+            .ok()
+            .and_then(|data| serde_json::from_slice::<SessionRecord>(&data).ok())
+        else {
+            continue;
+        };
+        if record.status == SessionStatus::Ok {
+            if record.pid == own || is_running(record.pid) {
+                continue;
+            }
+            ended += 1;
+            // The last update is as close to the end as is known.
+            let mut record = record;
+            record.status = SessionStatus::Abnormal;
+            record.duration = (record.timestamp - record.started).max(0.0);
+            deliver(config, &record);
+        } else if config.upload_url.is_some() {
+            deliver(config, &record);
+        }
+    }
+    Ok(ended)
+}
@@ -0,0 +1,325 @@
+// Configuration from the environment and a `crash.toml` file, so operators
+// can tune reporting without rebuilding the application.
+//
+// `crash::init` reads the file named by CRASH_CONFIG (`Builder::config_file`
+// in code), or else `crash.toml` in the current directory if there is one,
+// then the CRASH_* environment variables, and lays them over the `Config` the
+// application passed: the file wins over the code and the environment over
+// the file. Keys of the file are the names of the `Config` fields:
+//
+//     output_dir = "/var/crash"
+//     sample_rate = 0.25
+//     compression = "zstd"
+//     in_app_include = ["my_app"]
+//
+// Each variable is `CRASH_` and the key in capitals (`CRASH_SAMPLE_RATE`),
+// except CRASH_DIR for `output_dir` and CRASH_DSN for `sentry_dsn`. Lists are
+// comma-separated there, flags `true`/`false`, `1`/`0`, `yes`/`no` or
+// `on`/`off`, and durations (`upload_timeout`, `max_age`, ...) in seconds in
+// both. Lists add to what the code configured, and `scrub_rules` enables
+// scrubbing, as the `Builder` methods do. Empty variables are ignored. An
+// unknown key or a value that does not parse makes `init` fail, as does a
+// missing file that was named explicitly. Transports, storage and callbacks
+// can only be set in code. `Builder::external_config(false)` turns all of
+// this off.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::capture::Level;
+use crate::compression::Compression;
+use crate::config::Config;
+use crate::encoding::Encoding;
+use crate::integrity::SigningKey;
+use crate::unwind::Unwinder;
+use crate::windows_dump::MinidumpType;
+
+/// Names the configuration file, instead of `crash.toml`.
+pub const CONFIG_FILE_ENV: &str = "CRASH_CONFIG";
+
+pub const DEFAULT_CONFIG_FILE: &str = "crash.toml";
+
+/// The settings that can be given outside the code.
+pub const KEYS: &[&str] = &[
+    "output_dir",
+    "filename_template",
+    "app_name",
+    "app_version",
+    "release",
+    "environment",
+    "dist",
+    "server_name",
+    "minidump",
+    "minidump_type",
+    "compression",
+    "encoding",
+    "encrypt_to",
+    "signing_key",
+    "upload_url",
+    "upload_minidump",
+    "sentry_dsn",
+    "upload_timeout",
+    "shutdown_timeout",
+    "write_local",
+    "max_queued_reports",
+    "deferred",
+    "preallocate",
+    "chain_previous_hook",
+    "native_crashes",
+    "watchdog",
+    "capture_threads",
+    "panic_level",
+    "caught_panic_level",
+    "signal_level",
+    "unwinder",
+    "in_app_include",
+    "in_app_exclude",
+    "trim_frames",
+    "max_frames",
+    "source_context",
+    "source_root",
+    "max_breadcrumbs",
+    "max_reports_per_minute",
+    "sample_rate",
+    "max_reports",
+    "max_age",
+    "max_total_bytes",
+    "capture_env",
+    "env_allowlist",
+    "env_denylist",
+    "capture_args",
+    "secret_args",
+    "scrub_pii",
+    "scrub_rules",
+    "capture_output",
+    "memory_limit",
+    "deadlock_timeout",
+    "hang_timeout",
+    "auto_session",
+    "sortable_event_ids",
+    "crash_loop_threshold",
+    "crash_loop_window",
+    "crash_loop_throttle",
+    "metrics_addr",
+    "metrics_push_url",
+    "helper_path",
+    "debug",
+];
+
+/// Environment variable of the setting `key`.
+pub fn env_var(key: &str) -> String {
+    match key {
+        "output_dir" => "CRASH_DIR".to_string(),
+        "sentry_dsn" => "CRASH_DSN".to_string(),
+        _ => format!("CRASH_{}", key.to_ascii_uppercase()),
+    }
+}
+
+// A setting as found in the file or in a variable.
+enum Value<'a> {
+    File(&'a toml::Value),
+    Env(&'a str),
+}
+
+impl Value<'_> {
+    fn string(&self) -> Result<String, String> {
+        match self {
+            Value::File(toml::Value::String(s)) => Ok(s.clone()),
+            Value::Env(s) => Ok(s.to_string()),
+            _ => Err("expected a string".to_string()),
+        }
+    }
+
+    fn flag(&self) -> Result<bool, String> {
+        match self {
+            Value::File(toml::Value::Boolean(b)) => Ok(*b),
+            Value::Env(s) => match s.trim().to_ascii_lowercase().as_str() {
+                "true" | "1" | "yes" | "on" => Ok(true),
+                "false" | "0" | "no" | "off" => Ok(false),
+                _ => Err(format!("expected true or false, got '{}'", s)),
+            },
+            _ => Err("expected true or false".to_string()),
+        }
+    }
+
+    fn count(&self) -> Result<u64, String> {
+        match self {
+            Value::File(toml::Value::Integer(n)) => u64::try_from(*n).map_err(|_| format!("expected a count, got {}", n)),
+            Value::Env(s) => s.trim().parse().map_err(|_| format!("expected a count, got '{}'", s)),
+            _ => Err("expected a count".to_string()),
+        }
+    }
+
+    fn number(&self) -> Result<f64, String> {
+        match self {
+            Value::File(toml::Value::Integer(n)) => Ok(*n as f64),
+            Value::File(toml::Value::Float(n)) => Ok(*n),
+            Value::Env(s) => s.trim().parse().map_err(|_| format!("expected a number, got '{}'", s)),
+            _ => Err("expected a number".to_string()),
+        }
+    }
+
+    fn seconds(&self) -> Result<Duration, String> {
+        let secs = self.number()?;
+        Duration::try_from_secs_f64(secs).map_err(|_| format!("expected seconds, got {}", secs))
+    }
+
+    fn list(&self) -> Result<Vec<String>, String> {
+        match self {
+            Value::File(toml::Value::Array(items)) => items
+                .iter()
+                .map(|item| item.as_str().map(str::to_string).ok_or_else(|| "expected a list of strings".to_string()))
+                .collect(),
+            Value::Env(s) => Ok(s.split(',').map(str::trim).filter(|s| !s.is_empty()).map(str::to_string).collect()),
+            _ => Err("expected a list of strings".to_string()),
+        }
+    }
+
+    fn level(&self) -> Result<Level, String> {
+        let name = self.string()?;
+        Level::from_name(&name)
+            .ok_or_else(|| format!("unknown level '{}', expected debug, info, warning, error or fatal", name))
+    }
+}
+
+fn small<T: TryFrom<u64>>(n: u64) -> Result<T, String> {
+    T::try_from(n).map_err(|_| format!("{} is too large", n))
+}
+
+// Sets the setting `key` of `config` to `value`.
+fn set(config: &mut Config, key: &str, value: &Value) -> Result<(), String> {
+    match key {
+        "output_dir" => config.output_dir = PathBuf::from(value.string()?),
+        "filename_template" => config.filename_template = value.string()?,
+        "app_name" => config.app_name = Some(value.string()?),
+        "app_version" => config.app_version = Some(value.string()?),
+        "release" => config.release = Some(value.string()?),
+        "environment" => config.environment = Some(value.string()?),
+        "dist" => config.dist = Some(value.string()?),
+        "server_name" => config.server_name = Some(value.string()?),
+        "minidump" => config.minidump = value.flag()?,
+        "minidump_type" => {
+            let name = value.string()?;
+            config.minidump_type = MinidumpType::from_name(&name).ok_or_else(|| {
+                format!("unknown minidump type '{}', expected normal, with_data_segs or full_memory", name)
+            })?;
+        }
+        "compression" => {
+            let name = value.string()?;
+            config.compression = Compression::from_name(&name)
+                .ok_or_else(|| format!("unknown compression '{}', expected none, gzip or zstd", name))?;
+        }
+        "encoding" => {
+            let name = value.string()?;
+            config.encoding = Encoding::from_name(&name)
+                .ok_or_else(|| format!("unknown encoding '{}', expected json, msgpack or cbor", name))?;
+        }
+        "encrypt_to" => config.encrypt_to = Some(value.string()?),
+        "signing_key" => config.signing_key = Some(SigningKey::new(value.string()?)),
+        "upload_url" => config.upload_url = Some(value.string()?),
+        "upload_minidump" => config.upload_minidump = value.flag()?,
+        "sentry_dsn" => config.sentry_dsn = Some(value.string()?),
+        "upload_timeout" => config.upload_timeout = value.seconds()?,
+        "shutdown_timeout" => config.shutdown_timeout = value.seconds()?,
+        "write_local" => config.write_local = value.flag()?,
+        "max_queued_reports" => config.max_queued_reports = small(value.count()?)?,
+        "deferred" => config.deferred = value.flag()?,
+        "preallocate" => config.preallocate = value.flag()?,
+        "chain_previous_hook" => config.chain_previous_hook = value.flag()?,
+        "native_crashes" => config.native_crashes = value.flag()?,
+        "watchdog" => config.watchdog = value.flag()?,
+        "capture_threads" => config.capture_threads = value.flag()?,
+        "panic_level" => config.panic_level = value.level()?,
+        "caught_panic_level" => config.caught_panic_level = value.level()?,
+        "signal_level" => config.signal_level = value.level()?,
+        "unwinder" => {
+            let name = value.string()?;
+            config.unwinder = Unwinder::from_name(&name)
+                .ok_or_else(|| format!("unknown unwinder '{}', expected backtrace, std or framehop", name))?;
+        }
+        "in_app_include" => config.in_app_include.extend(value.list()?),
+        "in_app_exclude" => config.in_app_exclude.extend(value.list()?),
+        "trim_frames" => config.trim_frames = value.flag()?,
+        "max_frames" => config.max_frames = Some(small(value.count()?)?),
+        "source_context" => config.source_context = small(value.count()?)?,
+        "source_root" => config.source_root = Some(PathBuf::from(value.string()?)),
+        "max_breadcrumbs" => config.max_breadcrumbs = small(value.count()?)?,
+        "max_reports_per_minute" => config.max_reports_per_minute = Some(small(value.count()?)?),
+        "sample_rate" => config.sample_rate = value.number()?,
+        "max_reports" => config.max_reports = Some(small(value.count()?)?),
+        "max_age" => config.max_age = Some(value.seconds()?),
+        "max_total_bytes" => config.max_total_bytes = Some(value.count()?),
+        "capture_env" => config.capture_env = value.flag()?,
+        "env_allowlist" => config.env_allowlist.extend(value.list()?),
+        "env_denylist" => config.env_denylist.extend(value.list()?),
+        "capture_args" => config.capture_args = value.flag()?,
+        "secret_args" => config.secret_args.extend(value.list()?),
+        "scrub_pii" => config.scrub_pii = value.flag()?,
+        "scrub_rules" => {
+            config.scrub_rules.extend(value.list()?);
+            config.scrub_pii = true;
+        }
+        "capture_output" => config.capture_output = small(value.count()?)?,
+        "memory_limit" => config.memory_limit = Some(value.count()?),
+        "deadlock_timeout" => config.deadlock_timeout = value.seconds()?,
+        "hang_timeout" => config.hang_timeout = Some(value.seconds()?),
+        "auto_session" => config.auto_session = value.flag()?,
+        "sortable_event_ids" => config.sortable_event_ids = value.flag()?,
+        "crash_loop_threshold" => config.crash_loop_threshold = Some(small(value.count()?)?),
+        "crash_loop_window" => config.crash_loop_window = value.seconds()?,
+        "crash_loop_throttle" => config.crash_loop_throttle = value.flag()?,
+        "metrics_addr" => config.metrics_addr = Some(value.string()?),
+        "metrics_push_url" => config.metrics_push_url = Some(value.string()?),
+        "helper_path" => config.helper_path = Some(PathBuf::from(value.string()?)),
+        "debug" => config.debug = value.flag()?,
+        _ => return Err("unknown setting".to_string()),
+    }
+    Ok(())
+}
+
+fn invalid(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+// Applies the settings in the file at `path`.
+fn apply_file(config: &mut Config, path: &Path) -> std::io::Result<()> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    let table: toml::Table = text
+        .parse()
+        .map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+    for (key, value) in &table {
+        set(config, key, &Value::File(value)).map_err(|e| invalid(format!("{}: {}: {}", path.display(), key, e)))?;
+    }
+    Ok(())
+}
+
+/// Lays the configuration file and the CRASH_* variables over `config`, see
+/// above.
+pub fn apply(config: &mut Config) -> std::io::Result<()> {
+    let file = std::env::var_os(CONFIG_FILE_ENV)
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| config.config_file.clone())
+        .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|p| p.exists()));
+    if let Some(path) = file {
+        apply_file(config, &path)?;
+    }
+    for key in KEYS {
+        let name = env_var(key);
+        let Ok(text) = std::env::var(&name) else {
+            continue;
+        };
+        if text.is_empty() {
+            continue;
+        }
+        set(config, key, &Value::Env(&text)).map_err(|e| invalid(format!("{}: {}", name, e)))?;
+    }
+    Ok(())
+}
+
+/// Sets `key` to `text`, written as in a CRASH_* variable. The C API
+/// configures the handler this way.
+pub fn set_text(config: &mut Config, key: &str, text: &str) -> std::io::Result<()> {
+    set(config, key, &Value::Env(text)).map_err(|e| invalid(format!("{}: {}", key, e)))
+}
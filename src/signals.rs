@@ -0,0 +1,651 @@
+// Native crash capture through signal handlers.
+//
+// Crashes such as segmentation faults never reach the panic hook. The handlers
+// installed here write a `crash_report_<id>.json` with the signal, the fault
+// address and the raw instruction pointers of the crashing thread, in the same
+// shape as the events produced by the panic hook. The signal goes into the
+// exception, with the fault address of faults (not of SIGABRT, which is sent
+// rather than caused) and, on Linux x86_64 and aarch64, the registers of the
+// crashed thread from the `ucontext_t` the kernel passes.
+//
+// A signal handler may run while the heap or stdio locks are in an
+// inconsistent state, so the handler does not allocate or lock: the report
+// file, its buffer and the constant parts of the event are prepared in
+// `install` (see `crate::raw_report`), and the JSON is formatted into that
+// buffer and written with raw syscalls.
+//
+// A thread that overflows its stack has no stack left to run a handler on, so
+// the handlers run on the thread's alternate signal stack. `install` gives the
+// calling thread one of 64 KiB; the standard library gives the threads it
+// spawns smaller ones, and threads started by C code have none, which
+// `install_alt_stack` fixes for the thread calling it. A SIGSEGV or SIGBUS
+// close to the stack pointer of the crashed thread is a stack overflow and is
+// reported as the exception `stack_overflow` (Linux x86_64 and aarch64, where
+// the registers are known). Frames are left unsymbolicated; the server
+// symbolicates them alongside the minidump. On macOS the Mach exception
+// handler (`crate::mach_exceptions`) usually writes the report first; the
+// signal handler then only re-raises.
+
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use crate::config::Config;
+use crate::event::SentryEvent;
+use crate::raw_report::{self, FixedBuffer, RawReport};
+
+// Signals that indicate a crash.
+pub const HANDLED_SIGNALS: [i32; 5] = [
+    libc::SIGSEGV,
+    libc::SIGBUS,
+    libc::SIGABRT,
+    libc::SIGILL,
+    libc::SIGFPE,
+];
+
+const MAX_FRAMES: usize = 128;
+const MAX_REGISTERS: usize = 40;
+const REPORT_BUFFER_SIZE: usize = 16 * 1024;
+const LAST_CRASH_BUFFER_SIZE: usize = 1024;
+// Room left after the frames for the members that follow them: the exception
+// with all MAX_REGISTERS registers, the installation id and the closing
+// brackets. Release, debug_meta and contexts are left out if they do not fit.
+const TAIL_RESERVE: usize = 2048;
+
+// Alternate signal stacks smaller than this are replaced: the handler walks
+// the stack and formats the report on it, and on Linux forks the helper.
+const ALT_STACK_SIZE: usize = 64 * 1024;
+
+// Faults this close to the stack pointer are taken as stack overflows. The
+// guard page is hit by the push or stack probe that crossed it, or by a
+// store into a frame that was just allocated past it.
+const STACK_OVERFLOW_DISTANCE: usize = 64 * 1024;
+
+/// Set in the signal number sent to the watchdog for a stack overflow.
+pub(crate) const STACK_OVERFLOW: i32 = 0x1_0000;
+
+// State prepared at install time and read from the signal handler.
+struct SignalState {
+    report: RawReport,
+    last_crash: Option<RawReport>, // Note for `crash::last_crash` in the next run
+    event_id: String,
+    level: &'static str, // `Builder::signal_level`
+    installation_id: Option<String>,
+    contexts: String, // Serialized `crate::contexts`
+    release: String,  // `,"release":..` and friends, serialized, possibly empty
+    debug_meta: String, // Serialized `crate::debug_meta`, modules loaded at install time
+}
+
+// The release fields of `config` as JSON members, each preceded by a comma.
+fn release_members(config: &Config) -> String {
+    let mut event = SentryEvent::default();
+    config.apply_release(&mut event);
+    let fields = [
+        ("release", event.release),
+        ("environment", event.environment),
+        ("dist", event.dist),
+        ("server_name", event.server_name),
+    ];
+    fields
+        .into_iter()
+        .filter_map(|(key, value)| Some(format!(",\"{}\":{}", key, serde_json::to_string(&value?).ok()?)))
+        .collect()
+}
+
+static SIGNAL_STATE: OnceLock<SignalState> = OnceLock::new();
+
+// Set once the crash report has been written, by whichever handler got there
+// first.
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+// Set by the panic hooks once they have handled a panic that ends the process.
+// With `panic = "abort"` std aborts right after the hook returns; that SIGABRT
+// is the panic already reported and is only re-raised, leaving its report and
+// its note for `crash::last_crash` in place. Other signals are still
+// reported, as a panic on a thread of its own need not end the process.
+static PANIC_REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Tells the signal handlers that a panic ending the process was handled by a
+/// panic hook, so the abort that may follow it is not reported again.
+pub(crate) fn mark_panic_reported() {
+    PANIC_REPORTED.store(true, Ordering::Release);
+}
+
+/// Exception type and description of a crash by `signal`, which may have
+/// STACK_OVERFLOW set.
+pub(crate) fn exception_name(signal: i32) -> (&'static str, &'static str) {
+    if signal & STACK_OVERFLOW != 0 {
+        ("stack_overflow", "stack overflow")
+    } else {
+        signal_name(signal)
+    }
+}
+
+/// Whether `signal` carries the faulting address in `si_addr`. For signals
+/// sent with `kill` or `raise`, such as SIGABRT, that slot holds the sender's
+/// pid instead.
+pub(crate) fn has_fault_address(signal: i32) -> bool {
+    matches!(signal & !STACK_OVERFLOW, libc::SIGSEGV | libc::SIGBUS | libc::SIGILL | libc::SIGFPE)
+}
+
+pub(crate) fn signal_name(signal: i32) -> (&'static str, &'static str) {
+    match signal {
+        libc::SIGSEGV => ("SIGSEGV", "invalid memory reference"),
+        libc::SIGBUS => ("SIGBUS", "bus error"),
+        libc::SIGABRT => ("SIGABRT", "abort"),
+        libc::SIGILL => ("SIGILL", "illegal instruction"),
+        libc::SIGFPE => ("SIGFPE", "floating point exception"),
+        _ => ("UNKNOWN", "unknown signal"),
+    }
+}
+
+/// Installs handlers for `HANDLED_SIGNALS` that write a crash report with
+/// event id `event_id` to `path`, with the release of `config`.
+pub fn install(path: &Path, event_id: &str, config: &Config) -> std::io::Result<()> {
+    let state = SignalState {
+        report: RawReport::prepare(path, REPORT_BUFFER_SIZE)?,
+        last_crash: RawReport::prepare(&path.with_file_name(crate::feedback::LAST_CRASH_FILE), LAST_CRASH_BUFFER_SIZE).ok(),
+        event_id: event_id.to_string(),
+        level: config.signal_level.as_str(),
+        installation_id: crate::install_id::installation_id().map(|s| s.to_string()),
+        contexts: serde_json::to_string(&crate::contexts::get()).unwrap_or_else(|_| "{}".to_string()),
+        release: release_members(config),
+        debug_meta: crate::debug_meta::collect()
+            .and_then(|meta| serde_json::to_string(&meta).ok())
+            .unwrap_or_default(),
+    };
+    if SIGNAL_STATE.set(state).is_err() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "signal handlers already installed",
+        ));
+    }
+    install_alt_stack()?;
+
+    for signal in HANDLED_SIGNALS {
+        // SAFETY: the handler only uses async-signal-safe functions.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = signal_handler as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_RESETHAND | libc::SA_ONSTACK;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+// An alternate signal stack mapped by `install_alt_stack`, with a guard page
+// below it.
+struct AltStack {
+    mapping: *mut libc::c_void,
+    len: usize,
+    stack: *mut libc::c_void,
+}
+
+impl Drop for AltStack {
+    fn drop(&mut self) {
+        // SAFETY: the mapping is ours, and is only unmapped once the thread
+        // no longer uses it.
+        unsafe {
+            let mut current: libc::stack_t = std::mem::zeroed();
+            if libc::sigaltstack(std::ptr::null(), &mut current) == 0 && current.ss_sp == self.stack {
+                let mut disable: libc::stack_t = std::mem::zeroed();
+                disable.ss_flags = libc::SS_DISABLE;
+                libc::sigaltstack(&disable, std::ptr::null_mut());
+            }
+            libc::munmap(self.mapping, self.len);
+        }
+    }
+}
+
+thread_local! {
+    // Unmapped when the thread exits.
+    static ALT_STACK: std::cell::RefCell<Option<AltStack>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Gives the calling thread an alternate signal stack the crash handlers can
+/// report a stack overflow on, unless it has one of 64 KiB or more. `init`
+/// does this for its own thread; call it at the start of other threads whose
+/// stack overflows should be reported.
+pub fn install_alt_stack() -> std::io::Result<()> {
+    // SAFETY: sigaltstack only reads and writes the `stack_t`s given, and the
+    // new stack is mapped and kept until the thread exits.
+    unsafe {
+        let mut current: libc::stack_t = std::mem::zeroed();
+        if libc::sigaltstack(std::ptr::null(), &mut current) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if current.ss_flags & libc::SS_DISABLE == 0 && current.ss_size >= ALT_STACK_SIZE {
+            return Ok(());
+        }
+        let page = usize::try_from(libc::sysconf(libc::_SC_PAGESIZE)).unwrap_or(4096);
+        let len = page + ALT_STACK_SIZE;
+        let mapping = libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANON,
+            -1,
+            0,
+        );
+        if mapping == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        // A handler overflowing the alternate stack faults on the guard page
+        // instead of writing over whatever is mapped below.
+        let alt_stack = AltStack {
+            mapping,
+            len,
+            stack: mapping.cast::<u8>().add(page).cast(),
+        };
+        if libc::mprotect(mapping, page, libc::PROT_NONE) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut stack: libc::stack_t = std::mem::zeroed();
+        stack.ss_sp = alt_stack.stack;
+        stack.ss_size = ALT_STACK_SIZE;
+        if libc::sigaltstack(&stack, std::ptr::null_mut()) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // A stack set up here before is no longer in use and goes away.
+        ALT_STACK.with(|slot| slot.replace(Some(alt_stack)));
+    }
+    Ok(())
+}
+
+// Registers of a crashed thread, by name. Fixed size, so that the handler
+// does not allocate.
+struct Registers {
+    values: [(&'static str, u64); MAX_REGISTERS],
+    len: usize,
+}
+
+impl Registers {
+    const EMPTY: Registers = Registers {
+        values: [("", 0); MAX_REGISTERS],
+        len: 0,
+    };
+
+    fn push(&mut self, name: &'static str, value: u64) {
+        if self.len < MAX_REGISTERS {
+            self.values[self.len] = (name, value);
+            self.len += 1;
+        }
+    }
+
+    fn stack_pointer(&self) -> Option<usize> {
+        self.values[..self.len]
+            .iter()
+            .find(|(name, _)| matches!(*name, "rsp" | "sp"))
+            .map(|(_, value)| *value as usize)
+    }
+
+    // SAFETY: `ctx` must be null or the context passed to an SA_SIGINFO
+    // handler.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    unsafe fn from_context(ctx: *mut libc::c_void) -> Registers {
+        let mut registers = Registers::EMPTY;
+        let Some(context) = (unsafe { (ctx as *const libc::ucontext_t).as_ref() }) else {
+            return registers;
+        };
+        let gregs = &context.uc_mcontext.gregs;
+        let names = [
+            ("rax", libc::REG_RAX),
+            ("rbx", libc::REG_RBX),
+            ("rcx", libc::REG_RCX),
+            ("rdx", libc::REG_RDX),
+            ("rsi", libc::REG_RSI),
+            ("rdi", libc::REG_RDI),
+            ("rbp", libc::REG_RBP),
+            ("rsp", libc::REG_RSP),
+            ("r8", libc::REG_R8),
+            ("r9", libc::REG_R9),
+            ("r10", libc::REG_R10),
+            ("r11", libc::REG_R11),
+            ("r12", libc::REG_R12),
+            ("r13", libc::REG_R13),
+            ("r14", libc::REG_R14),
+            ("r15", libc::REG_R15),
+            ("rip", libc::REG_RIP),
+            ("eflags", libc::REG_EFL),
+        ];
+        for (name, index) in names {
+            registers.push(name, gregs[index as usize] as u64);
+        }
+        registers
+    }
+
+    // SAFETY: as above.
+    #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+    unsafe fn from_context(ctx: *mut libc::c_void) -> Registers {
+        const NAMES: [&str; 29] = [
+            "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14", "x15",
+            "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27", "x28",
+        ];
+        let mut registers = Registers::EMPTY;
+        let Some(context) = (unsafe { (ctx as *const libc::ucontext_t).as_ref() }) else {
+            return registers;
+        };
+        let mcontext = &context.uc_mcontext;
+        for (name, value) in NAMES.iter().zip(&mcontext.regs) {
+            registers.push(name, *value);
+        }
+        registers.push("fp", mcontext.regs[29]);
+        registers.push("lr", mcontext.regs[30]);
+        registers.push("sp", mcontext.sp);
+        registers.push("pc", mcontext.pc);
+        registers.push("pstate", mcontext.pstate);
+        registers
+    }
+
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+    unsafe fn from_context(_ctx: *mut libc::c_void) -> Registers {
+        Registers::EMPTY
+    }
+}
+
+// The `exception` member of a native crash report, preceded by a comma.
+struct ExceptionMember<'a> {
+    name: &'a str,
+    description: &'a str,
+    fault_addr: Option<usize>,
+    registers: &'a Registers,
+}
+
+impl std::fmt::Display for ExceptionMember<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            ",\"exception\":{{\"values\":[{{\"type\":\"{}\",\"value\":\"{}\"",
+            self.name, self.description
+        )?;
+        if let Some(fault_addr) = self.fault_addr {
+            write!(f, ",\"fault_address\":\"{:#x}\"", fault_addr)?;
+        }
+        if self.registers.len > 0 {
+            f.write_str(",\"registers\":{")?;
+            for (i, (name, value)) in self.registers.values[..self.registers.len].iter().enumerate() {
+                let separator = if i == 0 { "" } else { "," };
+                write!(f, "{}\"{}\":\"{:#018x}\"", separator, name, value)?;
+            }
+            f.write_str("}")?;
+        }
+        f.write_str("}]}")
+    }
+}
+
+extern "C" fn signal_handler(signal: i32, info: *mut libc::siginfo_t, ctx: *mut libc::c_void) {
+    if let Some(state) = SIGNAL_STATE.get() {
+        // SAFETY: `info` is provided by the kernel for SA_SIGINFO handlers.
+        let fault_addr = if has_fault_address(signal) {
+            unsafe { info.as_ref().map(|i| i.si_addr() as usize) }
+        } else {
+            None
+        };
+
+        // SAFETY: `ctx` is provided by the kernel for SA_SIGINFO handlers.
+        let registers = unsafe { Registers::from_context(ctx) };
+        let stack_overflow = matches!(signal, libc::SIGSEGV | libc::SIGBUS)
+            && registers
+                .stack_pointer()
+                .zip(fault_addr)
+                .is_some_and(|(sp, fault_addr)| fault_addr.abs_diff(sp) <= STACK_OVERFLOW_DISTANCE);
+        let crash = if stack_overflow { signal | STACK_OVERFLOW } else { signal };
+
+        // An out-of-memory report (see `crate::oom`) and a panic under
+        // `panic = "abort"` end in an abort; keep their report rather than
+        // adding a SIGABRT one, from here or from the watchdog.
+        let reported = REPORTED.load(Ordering::Acquire)
+            || (signal == libc::SIGABRT && PANIC_REPORTED.load(Ordering::Acquire));
+
+        // A running watchdog writes both the report and the minidump.
+        #[cfg(target_os = "linux")]
+        let captured = !reported && crate::watchdog::notify(crash, fault_addr.unwrap_or(0), &state.event_id);
+        #[cfg(not(target_os = "linux"))]
+        let captured = false;
+
+        if !captured && !reported && !REPORTED.swap(true, Ordering::AcqRel) {
+            write_report(state, crash, fault_addr, &registers);
+            // Let the helper capture a minidump from outside the process.
+            #[cfg(target_os = "linux")]
+            // SAFETY: only async-signal-safe syscalls are used by the helper launch.
+            unsafe {
+                crate::helper::launch_from_signal();
+            }
+        }
+    }
+
+    // SA_RESETHAND restored the default disposition; re-raise so the process
+    // terminates with the original signal.
+    unsafe {
+        libc::raise(signal);
+    }
+}
+
+// Fills `ips` with the current thread's stack, innermost first, and returns
+// the number of frames. Does not allocate.
+fn current_stack(ips: &mut [usize; MAX_FRAMES]) -> usize {
+    // SAFETY: no other thread unwinds concurrently while we are crashing.
+    unsafe { crate::unwind::trace_unsynchronized(ips) }
+}
+
+/// ` at <address>` in crash messages, or nothing for crashes without a fault
+/// address.
+pub(crate) struct FaultAt(pub Option<usize>);
+
+impl std::fmt::Display for FaultAt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(fault_addr) => write!(f, " at {:#x}", fault_addr),
+            None => Ok(()),
+        }
+    }
+}
+
+// `signal` may have STACK_OVERFLOW set.
+fn write_report(state: &SignalState, signal: i32, fault_addr: Option<usize>, registers: &Registers) {
+    // Walk the stack into a fixed array first; nothing below allocates.
+    let mut ips = [0usize; MAX_FRAMES];
+    let count = current_stack(&mut ips);
+
+    let (name, description) = exception_name(signal);
+    let exception = ExceptionMember {
+        name,
+        description,
+        fault_addr,
+        registers,
+    };
+    let (signal_name, signal_description) = signal_name(signal & !STACK_OVERFLOW);
+    let at = FaultAt(fault_addr);
+    let message = if signal & STACK_OVERFLOW != 0 {
+        format_args!("Fatal stack overflow ({}){}", signal_name, at)
+    } else {
+        format_args!("Fatal signal {} ({}){}", signal_name, signal_description, at)
+    };
+    write_event(state, message, format_args!("{}", exception), &ips[..count]);
+}
+
+/// Writes the native crash report for an exception caught outside the
+/// signal handlers, with the stack `ips` of the crashed thread, innermost
+/// first. Returns false if a report was already written or no handlers are
+/// installed. Does not allocate.
+#[cfg(target_os = "macos")]
+pub(crate) fn report_exception(name: &str, description: &str, fault_addr: usize, ips: &[usize]) -> bool {
+    let Some(state) = SIGNAL_STATE.get() else {
+        return false;
+    };
+    if REPORTED.swap(true, Ordering::AcqRel) {
+        return false;
+    }
+    let exception = ExceptionMember {
+        name,
+        description,
+        fault_addr: Some(fault_addr),
+        registers: &Registers::EMPTY,
+    };
+    write_event(
+        state,
+        format_args!("Fatal exception {} ({}) at {:#x}", name, description, fault_addr),
+        format_args!("{}", exception),
+        ips,
+    );
+    true
+}
+
+/// Writes the native crash report for a failed allocation of `size` bytes,
+/// with `memory` as the `memory` extra, from the allocating thread. Returns
+/// false if a report was already written or no handlers are installed. Does
+/// not allocate.
+pub(crate) fn report_out_of_memory(size: usize, memory: &crate::oom::MemoryStats) -> bool {
+    let Some(state) = SIGNAL_STATE.get() else {
+        return false;
+    };
+    if REPORTED.swap(true, Ordering::AcqRel) {
+        return false;
+    }
+    let mut ips = [0usize; MAX_FRAMES];
+    let count = current_stack(&mut ips);
+    write_event(
+        state,
+        format_args!("Out of memory: allocation of {} bytes failed", size),
+        format_args!(",\"extra\":{{\"memory\":{}}}", memory),
+        &ips[..count],
+    );
+    true
+}
+
+// Writes the frames of the stack `ips`, innermost first, as the items of a
+// JSON array, outermost first. If they do not all fit with TAIL_RESERVE bytes
+// to spare, the outermost are left out.
+fn write_frames(buf: &mut FixedBuffer, ips: &[usize]) {
+    let room = buf.remaining().saturating_sub(TAIL_RESERVE);
+    let (mut used, mut count) = (0, 0);
+    for (i, ip) in ips.iter().enumerate() {
+        let len = raw_report::formatted_len(format_args!("{{\"instruction_addr\":\"{:#x}\"}}", ip)) + usize::from(i > 0);
+        if used + len > room {
+            break;
+        }
+        used += len;
+        count += 1;
+    }
+    for (i, ip) in ips[..count].iter().rev().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        let _ = write!(buf, "{}{{\"instruction_addr\":\"{:#x}\"}}", separator, ip);
+    }
+}
+
+// Writes the report with `message`, which must not need JSON escaping, and
+// `members`, further JSON members each preceded by a comma.
+fn write_event(state: &SignalState, message: std::fmt::Arguments, members: std::fmt::Arguments, ips: &[usize]) {
+    let mut now: libc::timespec = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_REALTIME, &mut now);
+    }
+
+    state.report.write_with(|buf| {
+        let _ = write!(
+            buf,
+            "{{\"event_id\":\"{}\",\"schema_version\":{},\"timestamp\":\"{}.{:06}\",\
+             \"message\":\"{}\",\
+             \"level\":\"{}\",\"platform\":\"native\",\"stacktrace\":{{\"frames\":[",
+            state.event_id,
+            crate::event::SCHEMA_VERSION,
+            now.tv_sec,
+            now.tv_nsec / 1000,
+            message,
+            state.level,
+        );
+        write_frames(buf, ips);
+        let _ = write!(buf, "]}},\"installation_id\":");
+        let _ = match &state.installation_id {
+            Some(id) => write!(buf, "\"{}\"", id),
+            None => write!(buf, "null"),
+        };
+        // Each is written whole or not at all, with a byte left for the
+        // closing brace, so the report stays valid JSON however full it gets.
+        buf.write_whole(members, 1);
+        buf.write_whole(format_args!("{}", state.release), 1);
+        // Frames are raw addresses, so the module list matters more than contexts.
+        if !state.debug_meta.is_empty() {
+            buf.write_whole(format_args!(",\"debug_meta\":{}", state.debug_meta), 1);
+        }
+        // Contexts may be large with environment capture; skip rather than truncate.
+        buf.write_whole(format_args!(",\"contexts\":{}", state.contexts), 1);
+        let _ = write!(buf, "}}");
+    });
+    if let Some(last_crash) = &state.last_crash {
+        last_crash.write_with(|buf| {
+            let _ = write!(
+                buf,
+                "{{\"event_id\":\"{}\",\"timestamp\":\"{}.{:06}\",\"level\":\"{}\",\"message\":\"{}\"}}",
+                state.event_id,
+                now.tv_sec,
+                now.tv_nsec / 1000,
+                state.level,
+                message,
+            );
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The JSON `write` leaves in a buffer of `capacity` bytes.
+    fn written(capacity: usize, write: impl FnOnce(&mut FixedBuffer)) -> serde_json::Value {
+        let dir = std::env::temp_dir().join(format!("crash-signals-test-{}-{}", std::process::id(), capacity));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("report.json");
+        let report = RawReport::prepare(&path, capacity).unwrap();
+        assert!(report.write_with(write));
+        let json = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        json
+    }
+
+    fn frames(capacity: usize, ips: &[usize]) -> Vec<String> {
+        let json = written(capacity, |buf| {
+            let _ = buf.write_str("{\"frames\":[");
+            write_frames(buf, ips);
+            let _ = buf.write_str("]}");
+        });
+        json["frames"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|frame| frame["instruction_addr"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn frames_are_written_outermost_first() {
+        assert_eq!(frames(REPORT_BUFFER_SIZE, &[0x10, 0x20, 0x30]), ["0x30", "0x20", "0x10"]);
+        assert!(frames(REPORT_BUFFER_SIZE, &[]).is_empty());
+    }
+
+    #[test]
+    fn frames_that_do_not_fit_drop_the_outermost() {
+        let ips: Vec<usize> = (1..=MAX_FRAMES).map(|n| 0x1000 * n).collect();
+        let kept = frames(TAIL_RESERVE + 200, &ips);
+        assert!(!kept.is_empty() && kept.len() < ips.len());
+        // The innermost frames, where the crash happened, are the ones kept.
+        assert_eq!(kept.last().map(String::as_str), Some("0x1000"));
+        assert!(frames(TAIL_RESERVE, &ips).is_empty());
+    }
+
+    #[test]
+    fn members_that_do_not_fit_are_left_out() {
+        let json = written(64, |buf| {
+            let _ = buf.write_str("{\"a\":1");
+            buf.write_whole(format_args!(",\"big\":\"{}\"", "x".repeat(100)), 1);
+            buf.write_whole(format_args!(",\"small\":2"), 1);
+            let _ = buf.write_str("}");
+        });
+        assert_eq!(json, serde_json::json!({ "a": 1, "small": 2 }));
+    }
+}
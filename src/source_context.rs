@@ -0,0 +1,108 @@
+// Source lines around in-app frames.
+//
+// When the source files named by the debug info are readable where the report
+// is produced (development machines, CI, containers that ship the sources),
+// each in-app frame gets `pre_context`, `context_line` and `post_context`, the
+// lines before, at and after its line, as Sentry expects them. It runs when
+// the event is captured, and for deferred reports when they are completed on
+// the next start. Relative paths, as rustc records them for workspace crates,
+// are looked up under `Builder::source_root`, the current directory by
+// default. Frames whose file is missing, too large or not text are left
+// alone. `Builder::source_context(0)` turns it off.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::event::{MyStacktrace, SentryEvent};
+
+/// Lines of context on each side of a frame's line by default.
+pub const DEFAULT_SOURCE_CONTEXT: usize = 5;
+
+// Files larger than this are not read.
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+// Longer lines are cut, e.g. minified or generated code.
+const MAX_LINE_LENGTH: usize = 200;
+
+// Source files read for one event, `None` for those that could not be.
+#[derive(Default)]
+struct Files(HashMap<String, Option<Vec<String>>>);
+
+impl Files {
+    fn lines(&mut self, config: &Config, filename: &str) -> Option<&[String]> {
+        self.0
+            .entry(filename.to_string())
+            .or_insert_with(|| read_lines(&resolve(config, filename)))
+            .as_deref()
+    }
+}
+
+fn resolve(config: &Config, filename: &str) -> PathBuf {
+    let path = Path::new(filename);
+    match &config.source_root {
+        Some(root) if path.is_relative() => root.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn read_lines(path: &Path) -> Option<Vec<String>> {
+    if std::fs::metadata(path).ok()?.len() > MAX_FILE_SIZE {
+        return None;
+    }
+    let text = std::fs::read_to_string(path).ok()?;
+    Some(text.lines().map(truncate).collect())
+}
+
+fn truncate(line: &str) -> String {
+    match line.char_indices().nth(MAX_LINE_LENGTH) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+fn fill(config: &Config, files: &mut Files, stacktrace: &mut MyStacktrace) {
+    let context = config.source_context;
+    for frame in &mut stacktrace.frames {
+        if frame.in_app != Some(true) {
+            continue;
+        }
+        let (Some(filename), Some(lineno)) = (&frame.filename, frame.lineno) else {
+            continue;
+        };
+        let Some(lines) = files.lines(config, filename) else {
+            continue;
+        };
+        // Line numbers start at 1.
+        let index = lineno as usize;
+        if index == 0 || index > lines.len() {
+            continue;
+        }
+        let line = index - 1;
+        frame.pre_context = lines[line.saturating_sub(context)..line].to_vec();
+        frame.context_line = Some(lines[line].clone());
+        frame.post_context = lines[line + 1..(line + 1 + context).min(lines.len())].to_vec();
+    }
+}
+
+/// Adds source context to the in-app frames of `stacktrace`.
+pub fn add_to_stacktrace(config: &Config, stacktrace: &mut MyStacktrace) {
+    if config.source_context > 0 {
+        fill(config, &mut Files::default(), stacktrace);
+    }
+}
+
+/// Adds source context to the in-app frames of every stack of `event`.
+pub fn add(config: &Config, event: &mut SentryEvent) {
+    if config.source_context == 0 {
+        return;
+    }
+    let mut files = Files::default();
+    if let Some(stacktrace) = &mut event.stacktrace {
+        fill(config, &mut files, stacktrace);
+    }
+    for thread in &mut event.threads {
+        if let Some(stacktrace) = &mut thread.stacktrace {
+            fill(config, &mut files, stacktrace);
+        }
+    }
+}
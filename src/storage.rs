@@ -0,0 +1,61 @@
+// Where reports and attachments are kept locally.
+//
+// Everything the panic hook writes for later (reports, the minimal report
+// written first, attachments) goes through a `Storage`. The default is
+// `FileStorage`, the configured output directory; on wasm32, which has no
+// filesystem, it is `crate::web::LocalStorage`, the browser's
+// `localStorage`. Applications can plug in their own with
+// `Builder::storage`. Minidumps, the deferred records, the upload queue,
+// session records and the signal handlers are native only and keep using
+// files directly.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A place to keep crash artifacts. Called from the panic hook, so
+/// implementations should not block for long or panic.
+pub trait Storage: Send + Sync + std::fmt::Debug {
+    /// Stores `contents` under `name`, e.g. `crash_report_<id>.json`, and
+    /// returns where they went: a path, or a key for storages without paths.
+    fn write(&self, name: &str, contents: &[u8]) -> std::io::Result<PathBuf>;
+
+    /// Removes what `write` stored at `location`.
+    fn remove(&self, location: &Path) -> std::io::Result<()>;
+}
+
+/// Files in a directory, falling back to the temp dir when it cannot be
+/// written, see `crate::output`.
+#[derive(Clone, Debug)]
+pub struct FileStorage {
+    dir: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileStorage { dir: dir.into() }
+    }
+}
+
+impl Storage for FileStorage {
+    fn write(&self, name: &str, contents: &[u8]) -> std::io::Result<PathBuf> {
+        crate::output::write_report(&self.dir, name, contents)
+    }
+
+    fn remove(&self, location: &Path) -> std::io::Result<()> {
+        std::fs::remove_file(location)
+    }
+}
+
+/// The storage used when none is configured: files in `output_dir`, or
+/// `localStorage` on wasm32.
+pub fn default_storage(output_dir: &Path) -> Arc<dyn Storage> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        Arc::new(FileStorage::new(output_dir))
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = output_dir;
+        Arc::new(crate::web::LocalStorage::default())
+    }
+}
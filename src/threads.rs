@@ -0,0 +1,270 @@
+// Stacks of every thread at crash time.
+//
+// On Linux the other threads are enumerated through /proc/self/task and asked
+// one at a time, with a real-time signal, to unwind their own stack into a
+// shared slot; the crashing thread then symbolicates the addresses. Threads
+// that do not answer within a short timeout (e.g. blocked with signals
+// masked) are listed without a stack. Elsewhere only the crashing thread is
+// reported; the minidump still carries all stacks. `Builder::capture_threads`
+// turns the sampling off, e.g. for servers with large thread pools.
+//
+// Every event lists at least the thread it was captured on, with its OS
+// thread id and name, so the main stack can be told apart in reports from
+// thread pools. The name is the one given to `std::thread::Builder`; other
+// threads on Linux are named by their `comm`, which the kernel cuts to 15
+// bytes.
+
+#[cfg(any(target_os = "linux", all(feature = "deadlock", not(target_arch = "wasm32"))))]
+use crate::event::MyStacktrace;
+use crate::event::{MyFrame, Thread};
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+    use std::sync::{Mutex, OnceLock};
+    use std::time::{Duration, Instant};
+
+    const MAX_FRAMES: usize = 128;
+    const REPLY_TIMEOUT: Duration = Duration::from_millis(100);
+
+    // Slot the signalled thread unwinds into. Only one thread is sampled at a
+    // time, serialized by SAMPLING; SLOT_TARGET makes late replies from a
+    // thread that already timed out leave the slot alone.
+    static SLOT: [AtomicUsize; MAX_FRAMES] = [const { AtomicUsize::new(0) }; MAX_FRAMES];
+    static SLOT_TARGET: AtomicU64 = AtomicU64::new(0);
+    static SLOT_LEN: AtomicUsize = AtomicUsize::new(0);
+    static SLOT_DONE: AtomicBool = AtomicBool::new(false);
+    static SAMPLING: Mutex<()> = Mutex::new(());
+
+    fn sample_signal() -> i32 {
+        libc::SIGRTMIN() + 3
+    }
+
+    extern "C" fn sample_handler(_signal: i32, _info: *mut libc::siginfo_t, _ctx: *mut libc::c_void) {
+        if SLOT_TARGET.load(Ordering::Acquire) != current_tid() {
+            return;
+        }
+        let mut ips = [0usize; MAX_FRAMES];
+        // SAFETY: only this thread unwinds into the slot until SLOT_DONE is set.
+        let len = unsafe { crate::unwind::trace_unsynchronized(&mut ips) };
+        for (slot, ip) in SLOT.iter().zip(&ips[..len]) {
+            slot.store(*ip, Ordering::Relaxed);
+        }
+        SLOT_LEN.store(len, Ordering::Relaxed);
+        SLOT_DONE.store(true, Ordering::Release);
+    }
+
+    pub fn current_tid() -> u64 {
+        // SAFETY: plain syscall without arguments.
+        unsafe { libc::syscall(libc::SYS_gettid) as u64 }
+    }
+
+    pub fn thread_ids() -> Vec<u64> {
+        let Ok(entries) = std::fs::read_dir("/proc/self/task") else {
+            return Vec::new();
+        };
+        let mut ids: Vec<u64> = entries
+            .filter_map(|e| e.ok()?.file_name().to_str()?.parse().ok())
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    pub fn thread_name(tid: u64) -> Option<String> {
+        std::fs::read_to_string(format!("/proc/self/task/{}/comm", tid))
+            .ok()
+            .map(|name| name.trim_end().to_string())
+    }
+
+    /// Returns the instruction pointers of every thread in `tids`, innermost
+    /// first, or `None` for threads that did not answer.
+    pub fn sample_threads(tids: &[u64]) -> Vec<Option<Vec<usize>>> {
+        let Ok(_guard) = SAMPLING.try_lock() else {
+            return vec![None; tids.len()];
+        };
+
+        // The handler stays installed: restoring the default action (which
+        // terminates the process) could turn a late reply into a crash.
+        static INSTALLED: OnceLock<bool> = OnceLock::new();
+        let signal = sample_signal();
+        let installed = *INSTALLED.get_or_init(|| {
+            // SAFETY: the handler only uses async-signal-safe code.
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = sample_handler as *const () as libc::sighandler_t;
+                action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(signal, &action, std::ptr::null_mut()) == 0
+            }
+        });
+        if !installed {
+            return vec![None; tids.len()];
+        }
+
+        let pid = std::process::id() as libc::pid_t;
+        let stacks = tids
+            .iter()
+            .map(|&tid| {
+                SLOT_DONE.store(false, Ordering::Release);
+                SLOT_TARGET.store(tid, Ordering::Release);
+                // SAFETY: tgkill only targets threads of this process.
+                let sent = unsafe { libc::syscall(libc::SYS_tgkill, pid, tid as libc::pid_t, signal) };
+                if sent != 0 {
+                    return None;
+                }
+                let deadline = Instant::now() + REPLY_TIMEOUT;
+                while !SLOT_DONE.load(Ordering::Acquire) {
+                    if Instant::now() > deadline {
+                        return None;
+                    }
+                    std::thread::yield_now();
+                }
+                let len = SLOT_LEN.load(Ordering::Relaxed);
+                Some(SLOT[..len].iter().map(|ip| ip.load(Ordering::Relaxed)).collect())
+            })
+            .collect();
+        SLOT_TARGET.store(0, Ordering::Release);
+        stacks
+    }
+}
+
+/// Symbolicates raw instruction pointers, innermost first, into frames
+/// ordered outermost first like the rest of the event.
+pub fn frames_from_ips(ips: &[usize]) -> Vec<MyFrame> {
+    let mut frames = Vec::new();
+    for &ip in ips {
+        let before = frames.len();
+        backtrace::resolve(ip as *mut std::ffi::c_void, |symbol| {
+            frames.push(MyFrame {
+                filename: symbol.filename().map(|p| p.to_string_lossy().into_owned()),
+                lineno: symbol.lineno(),
+                colno: symbol.colno(),
+                function: symbol.name().map(|s| s.to_string()),
+                instruction_addr: Some(format!("{:#x}", ip)),
+                in_app: None,
+                pre_context: Vec::new(),
+                context_line: None,
+                post_context: Vec::new(),
+            });
+        });
+        if frames.len() == before {
+            frames.push(MyFrame {
+                filename: None,
+                lineno: None,
+                colno: None,
+                function: None,
+                instruction_addr: Some(format!("{:#x}", ip)),
+                in_app: None,
+                pre_context: Vec::new(),
+                context_line: None,
+                post_context: Vec::new(),
+            });
+        }
+    }
+    frames.reverse();
+    frames
+}
+
+/// Stacks of the threads with the OS ids `tids`, sampled as for
+/// `capture_threads`: `None` for threads that did not answer, and for all
+/// of them outside Linux.
+#[cfg(any(target_os = "linux", all(feature = "deadlock", not(target_arch = "wasm32"))))]
+pub(crate) fn sample_stacks(tids: &[u64]) -> Vec<Option<MyStacktrace>> {
+    #[cfg(target_os = "linux")]
+    {
+        linux::sample_threads(tids)
+            .into_iter()
+            .map(|ips| {
+                ips.map(|ips| frames_from_ips(&ips))
+                    .filter(|frames| !frames.is_empty())
+                    .map(|frames| MyStacktrace { frames })
+            })
+            .collect()
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        vec![None; tids.len()]
+    }
+}
+
+// OS id of the calling thread, where there is a notion of one.
+pub(crate) fn current_thread_id() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        Some(linux::current_tid())
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let mut id = 0u64;
+        // SAFETY: a null thread means the calling one.
+        let result = unsafe { libc::pthread_threadid_np(0, &mut id) };
+        (result == 0).then_some(id)
+    }
+    #[cfg(windows)]
+    {
+        #[link(name = "kernel32")]
+        extern "system" {
+            fn GetCurrentThreadId() -> u32;
+        }
+        // SAFETY: no preconditions.
+        Some(unsafe { GetCurrentThreadId() } as u64)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        None
+    }
+}
+
+/// The calling thread, without a stack; its stack is the event's main
+/// `stacktrace`.
+pub fn current_thread(crashed: bool) -> Thread {
+    let name = std::thread::current().name().map(|n| n.to_string());
+    // Threads not started by Rust are only named by the OS.
+    #[cfg(target_os = "linux")]
+    let name = name.or_else(|| linux::thread_name(linux::current_tid()));
+    Thread {
+        id: current_thread_id(),
+        name,
+        crashed,
+        current: true,
+        stacktrace: None,
+    }
+}
+
+/// Lists the threads of the process other than the calling one, with their
+/// stacks. Empty outside Linux.
+pub(crate) fn other_threads() -> Vec<Thread> {
+    #[cfg(target_os = "linux")]
+    {
+        let current = linux::current_tid();
+        let others: Vec<u64> = linux::thread_ids().into_iter().filter(|&t| t != current).collect();
+        let stacks = sample_stacks(&others);
+
+        let pid = std::process::id() as u64;
+        others
+            .into_iter()
+            .zip(stacks)
+            .map(|(tid, stacktrace)| Thread {
+                id: Some(tid),
+                // The main thread's `comm` is the process name; Rust calls it `main`.
+                name: if tid == pid { Some("main".to_string()) } else { linux::thread_name(tid) },
+                crashed: false,
+                current: false,
+                stacktrace,
+            })
+            .collect()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        Vec::new()
+    }
+}
+
+/// Lists the threads of the process. The calling thread is marked as crashed
+/// and gets no stack here; its stack is the event's main `stacktrace`.
+pub fn capture_threads() -> Vec<Thread> {
+    let mut threads = vec![current_thread(true)];
+    threads.extend(other_threads());
+    threads
+}
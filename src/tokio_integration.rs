@@ -0,0 +1,190 @@
+// Tokio integration (feature `tokio`).
+//
+// Tokio catches panics in spawned tasks and hands them to whoever awaits the
+// `JoinHandle`, if anyone does; the process keeps running. The panic hook
+// still fires on the worker thread, so after `crash::tokio_integration::init()`
+// a task panic gets a full report, at the level of caught panics and without
+// a minidump like a panic caught by `crash::catch_and_report`, tagged with:
+//
+// - `tokio.task_id`: the id of the task,
+// - `tokio.task`: its name, for tasks started with `spawn(name, ..)` or
+//   wrapped with `named(name, ..)`,
+// - `tokio.worker`: the runtime thread it ran on.
+//
+// The future passed to `Runtime::block_on` is not a task; a panic there ends
+// the process and is reported as fatal.
+//
+// Thread stacks of an async service mostly show idle workers. Built with
+// tokio's unstable task dumps (`RUSTFLAGS="--cfg tokio_unstable --cfg
+// tokio_taskdump"`, Linux only), `dump_tasks(handle)` makes `error` and
+// `fatal` reports list every task of that runtime with the futures it is
+// suspended in, under `async_tasks`. Tokio needs all workers of the runtime
+// to take a dump, so events captured inside one of its tasks, a task panic
+// included, go without; so do reports whose dump takes longer than
+// DUMP_TIMEOUT, e.g. from the thread driving a current-thread runtime.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::task::{JoinError, JoinHandle};
+
+use crate::event::SentryEvent;
+use crate::integration::Integration;
+
+#[cfg(all(tokio_unstable, tokio_taskdump, target_os = "linux"))]
+mod taskdump {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use tokio::runtime::Handle;
+
+    use crate::event::AsyncTask;
+
+    const DUMP_TIMEOUT: Duration = Duration::from_secs(2);
+
+    static RUNTIME: Mutex<Option<Handle>> = Mutex::new(None);
+
+    pub fn set_runtime(handle: Handle) {
+        *RUNTIME.lock().unwrap_or_else(|e| e.into_inner()) = Some(handle);
+    }
+
+    /// The tasks of the runtime passed to `dump_tasks`, or nothing if there
+    /// is none or the dump is not done in time.
+    pub fn dump() -> Vec<AsyncTask> {
+        let Some(handle) = RUNTIME.lock().unwrap_or_else(|e| e.into_inner()).clone() else {
+            return Vec::new();
+        };
+        // `block_on` cannot run on a thread of the runtime, and the dump
+        // cannot run at all if this thread holds up one of its workers.
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let spawned = std::thread::Builder::new()
+            .name("crash-task-dump".to_string())
+            .spawn(move || {
+                let dump = handle.block_on(handle.dump());
+                let tasks: Vec<AsyncTask> = dump
+                    .tasks()
+                    .iter()
+                    .map(|task| AsyncTask {
+                        id: Some(task.id().to_string()),
+                        trace: task.trace().to_string(),
+                    })
+                    .collect();
+                let _ = sender.send(tasks);
+            });
+        if spawned.is_err() {
+            return Vec::new();
+        }
+        receiver.recv_timeout(DUMP_TIMEOUT).unwrap_or_default()
+    }
+}
+
+thread_local! {
+    // Name of the task being polled on this thread, if it has one.
+    static CURRENT_TASK: RefCell<Option<Arc<str>>> = const { RefCell::new(None) };
+}
+
+/// Registers the integration. Call once, e.g. right after `crash::init`.
+pub fn init() {
+    crate::integration::register_integration(TokioIntegration);
+}
+
+/// A future that carries a task name into crash reports.
+pub struct Named<F> {
+    name: Arc<str>,
+    future: Pin<Box<F>>,
+}
+
+/// Wraps `future` so that a panic while polling it is reported under `name`.
+pub fn named<F: Future>(name: &str, future: F) -> Named<F> {
+    Named {
+        name: name.into(),
+        future: Box::pin(future),
+    }
+}
+
+/// `tokio::spawn` for a task named `name`.
+pub fn spawn<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(named(name, future))
+}
+
+impl<F: Future> Future for Named<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let name = self.name.clone();
+        let previous = CURRENT_TASK.with(|task| task.replace(Some(name)));
+        // Restores the enclosing name however the poll ends.
+        struct Restore(Option<Arc<str>>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let previous = self.0.take();
+                let _ = CURRENT_TASK.try_with(|task| task.replace(previous));
+            }
+        }
+        let _restore = Restore(previous);
+        self.future.as_mut().poll(cx)
+    }
+}
+
+/// Lists the tasks of the runtime of `handle` in `error` and `fatal` reports,
+/// see `crate::tokio_integration`. Only with tokio's unstable task dumps;
+/// replaces the runtime given before.
+#[cfg(all(tokio_unstable, tokio_taskdump, target_os = "linux"))]
+pub fn dump_tasks(handle: tokio::runtime::Handle) {
+    taskdump::set_runtime(handle);
+}
+
+/// The message of the panic that ended a task, or `None` if it was
+/// cancelled. The panic itself was reported when it happened.
+pub fn panic_message(error: JoinError) -> Option<String> {
+    let payload = error.try_into_panic().ok()?;
+    let message = if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Panic occurred without a string message.".to_string()
+    };
+    Some(message)
+}
+
+struct TokioIntegration;
+
+impl Integration for TokioIntegration {
+    fn name(&self) -> &'static str {
+        "tokio"
+    }
+
+    fn panic_is_caught(&self) -> bool {
+        tokio::task::try_id().is_some()
+    }
+
+    fn process_event(&self, mut event: SentryEvent) -> Option<SentryEvent> {
+        let Some(id) = tokio::task::try_id() else {
+            #[cfg(all(tokio_unstable, tokio_taskdump, target_os = "linux"))]
+            if matches!(event.level.as_deref(), Some("error" | "fatal")) {
+                event.async_tasks = taskdump::dump();
+            }
+            return Some(event);
+        };
+        event.tags.insert("tokio.task_id".to_string(), id.to_string());
+        let name = CURRENT_TASK.try_with(|task| task.borrow().clone()).ok().flatten();
+        if let Some(name) = name {
+            event.tags.insert("tokio.task".to_string(), name.to_string());
+        }
+        let thread = std::thread::current();
+        let worker = match thread.name() {
+            Some(name) => format!("{} {:?}", name, thread.id()),
+            None => format!("{:?}", thread.id()),
+        };
+        event.tags.insert("tokio.worker".to_string(), worker);
+        Some(event)
+    }
+}
@@ -0,0 +1,188 @@
+// `tracing` integration (feature `tracing`).
+//
+// `crash::tracing_integration::layer()` returns a `tracing_subscriber` layer
+// that mirrors every tracing event into the breadcrumb trail and keeps track
+// of the spans each thread is in. When a panic is captured, the spans the
+// panicking thread was in are added to the event as the `tracing` context,
+// outermost first, e.g.
+//
+//     tracing_subscriber::registry()
+//         .with(crash::tracing_integration::layer())
+//         .init();
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::event::{Breadcrumb, SentryEvent};
+use crate::integration::Integration;
+
+// A span the thread is in, as reported in the event.
+#[derive(Clone)]
+struct SpanInfo {
+    id: Id,
+    name: &'static str,
+    target: &'static str,
+    fields: BTreeMap<String, Value>,
+}
+
+thread_local! {
+    // Spans entered by this thread, innermost last.
+    static ACTIVE_SPANS: RefCell<Vec<SpanInfo>> = const { RefCell::new(Vec::new()) };
+}
+
+// Collects the fields of an event or span; `message` is kept apart.
+#[derive(Default)]
+struct FieldVisitor {
+    message: Option<String>,
+    fields: BTreeMap<String, Value>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_string());
+        } else {
+            self.fields.insert(field.name().to_string(), value.into());
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.fields.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.fields.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.fields.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+// Fields recorded for a span, stored in its extensions.
+struct SpanFields(BTreeMap<String, Value>);
+
+fn level_name(level: &Level) -> &'static str {
+    match *level {
+        Level::ERROR => "error",
+        Level::WARN => "warning",
+        Level::INFO => "info",
+        Level::DEBUG | Level::TRACE => "debug",
+    }
+}
+
+/// Layer feeding tracing events and spans into crash reports.
+pub struct CrashLayer {
+    _private: (),
+}
+
+/// Creates the layer and registers the integration that adds the active
+/// spans to captured events.
+pub fn layer() -> CrashLayer {
+    crate::integration::register_integration(TracingIntegration);
+    CrashLayer { _private: () }
+}
+
+impl<S> Layer<S> for CrashLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        if let Some(message) = visitor.message {
+            visitor.fields.insert("message".to_string(), message.into());
+        }
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(visitor.fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+            let mut visitor = FieldVisitor::default();
+            values.record(&mut visitor);
+            fields.extend(visitor.fields);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let mut breadcrumb = Breadcrumb::new(metadata.target(), visitor.message.unwrap_or_default())
+            .with_level(level_name(metadata.level()));
+        breadcrumb.data = visitor.fields;
+        crate::breadcrumbs::add_breadcrumb(breadcrumb);
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let fields = span
+            .extensions()
+            .get::<SpanFields>()
+            .map(|SpanFields(fields)| fields.clone())
+            .unwrap_or_default();
+        let info = SpanInfo {
+            id: id.clone(),
+            name: span.metadata().name(),
+            target: span.metadata().target(),
+            fields,
+        };
+        let _ = ACTIVE_SPANS.try_with(|spans| spans.borrow_mut().push(info));
+    }
+
+    fn on_exit(&self, id: &Id, _ctx: Context<'_, S>) {
+        let _ = ACTIVE_SPANS.try_with(|spans| {
+            let mut spans = spans.borrow_mut();
+            if let Some(i) = spans.iter().rposition(|span| span.id == *id) {
+                spans.remove(i);
+            }
+        });
+    }
+}
+
+struct TracingIntegration;
+
+impl Integration for TracingIntegration {
+    fn name(&self) -> &'static str {
+        "tracing"
+    }
+
+    fn process_event(&self, mut event: SentryEvent) -> Option<SentryEvent> {
+        let spans: Vec<Value> = ACTIVE_SPANS
+            .try_with(|spans| {
+                spans.try_borrow().map(|spans| {
+                    spans
+                        .iter()
+                        .map(|span| json!({ "name": span.name, "target": span.target, "fields": span.fields }))
+                        .collect()
+                })
+            })
+            .ok()
+            .and_then(|spans| spans.ok())
+            .unwrap_or_default();
+        if !spans.is_empty() {
+            event.contexts.insert("tracing".to_string(), json!({ "spans": spans }));
+        }
+        Some(event)
+    }
+}
@@ -0,0 +1,282 @@
+// Delivery of crash reports.
+//
+// Every report goes through a list of `Transport`s, each on its own, so one
+// that fails does not keep the report from the others. The list follows the
+// configuration: the crash server when an upload URL is set (reports are
+// POSTed to `{url}/crashes`), Sentry with a DSN (as an envelope, see
+// `crate::sentry`), then the transports added with `Builder::transport`
+// (e.g. `S3Transport`, `UnixSocketTransport` or the application's own), and
+// last the local storage when `write_local` is on. Sends are blocking,
+// bounded by `Config::upload_timeout`, and best-effort: a report the crash
+// server or Sentry does not take is queued for another attempt on the next
+// run (see `crate::queue`), and one that went nowhere is written locally
+// even if `write_local` is off, so nothing is lost. The watchdog, a separate
+// process, only has the transports it can rebuild from its arguments. On
+// wasm32 the request goes out with `fetch` and is not waited for, see
+// `crate::web`.
+
+use std::path::PathBuf;
+
+use crate::compression::Compression;
+use crate::config::Config;
+use crate::integrity::Digest;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::queue::Target;
+
+/// Endpoint reports are sent to on `server`.
+pub fn report_endpoint(server: &str) -> String {
+    format!("{}/crashes", server.trim_end_matches('/'))
+}
+
+/// POSTs `body` with `headers` to an endpoint of the crash server.
+pub(crate) fn post(config: &Config, url: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<(), String> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let agent = ureq::AgentBuilder::new().timeout(config.upload_timeout).build();
+        let mut request = agent.post(url);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        if let Ok(project) = std::env::var("CRASH_PROJECT") {
+            request = request.set("X-Crash-Project", &project);
+        }
+        request.send_bytes(body).map(|_| ()).map_err(|e| e.to_string())
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = config;
+        crate::web::post(url, headers, body)
+    }
+}
+
+/// POSTs an artifact already compressed with `compression` to the crash
+/// server, with the `digest` of its content (see `crate::integrity`),
+/// encrypting it first if configured (see `crate::encryption`).
+pub(crate) fn post_artifact(
+    config: &Config,
+    url: &str,
+    content_type: &str,
+    compression: Compression,
+    digest: &Digest,
+    body: Vec<u8>,
+) -> Result<(), String> {
+    let mut headers = vec![("Content-Type", content_type)];
+    headers.extend(digest.headers());
+    if config.encrypt_to.is_some() {
+        let body = crate::encryption::seal(config, body).map_err(|e| e.to_string())?;
+        headers.push((crate::encryption::HEADER, "age"));
+        return post(config, url, &headers, &body);
+    }
+    if let Some(encoding) = compression.content_encoding() {
+        headers.push(("Content-Encoding", encoding));
+    }
+    post(config, url, &headers, &body)
+}
+
+/// POSTs a serialized report to the server, encoded, signed, compressed and
+/// encrypted as configured.
+pub fn send_report(config: &Config, server: &str, json: &[u8]) -> Result<(), String> {
+    let content = config.encoding.encode(json).map_err(|e| e.to_string())?;
+    let digest = Digest::new(&content, config.signing_key.as_ref());
+    let body = config.compression.compress(&content).map_err(|e| e.to_string())?;
+    post_artifact(
+        config,
+        &report_endpoint(server),
+        config.encoding.content_type(),
+        config.compression,
+        &digest,
+        body,
+    )
+}
+
+/// Where a report ended up.
+pub enum Delivery {
+    Uploaded(String),
+    Written(PathBuf),
+    Queued(PathBuf),
+}
+
+impl std::fmt::Display for Delivery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Delivery::Uploaded(url) => write!(f, "{}", url),
+            Delivery::Written(path) | Delivery::Queued(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// A destination for crash reports. Called from the panic hook, so
+/// implementations should be bounded in time and not panic.
+pub trait Transport: Send + Sync + std::fmt::Debug {
+    /// Delivers the report `file_name`, serialized as `json` and already
+    /// scrubbed. Returns where it went.
+    fn send(&self, config: &Config, file_name: &str, json: &[u8]) -> Result<Delivery, String>;
+}
+
+// Queues a report `target` could not take, if the queue is enabled.
+#[cfg(not(target_arch = "wasm32"))]
+fn queue(config: &Config, target: Target, file_name: &str, json: &[u8], error: String) -> Result<Delivery, String> {
+    if config.max_queued_reports == 0 {
+        return Err(error);
+    }
+    eprintln!("Failed to deliver crash report '{}', queueing it: {}", file_name, error);
+    crate::queue::enqueue(config, target, file_name, json)
+        .map(Delivery::Queued)
+        .map_err(|e| format!("failed to queue it: {}", e))
+}
+
+/// The crash server at `upload_url`. Reports it does not take are queued.
+#[derive(Debug)]
+pub(crate) struct HttpTransport {
+    pub server: String,
+}
+
+impl Transport for HttpTransport {
+    fn send(&self, config: &Config, file_name: &str, json: &[u8]) -> Result<Delivery, String> {
+        match send_report(config, &self.server, json) {
+            Ok(()) => Ok(Delivery::Uploaded(report_endpoint(&self.server))),
+            #[cfg(not(target_arch = "wasm32"))]
+            Err(e) => queue(config, Target::Server, file_name, json, format!("upload to {} failed: {}", self.server, e)),
+            #[cfg(target_arch = "wasm32")]
+            Err(e) => {
+                let _ = file_name;
+                Err(format!("upload to {} failed: {}", self.server, e))
+            }
+        }
+    }
+}
+
+/// Sentry, at `sentry_dsn`. Reports it does not take are queued.
+#[derive(Debug)]
+pub(crate) struct SentryTransport {
+    pub dsn: String,
+}
+
+impl Transport for SentryTransport {
+    fn send(&self, config: &Config, file_name: &str, json: &[u8]) -> Result<Delivery, String> {
+        match crate::sentry::send_report(config, &self.dsn, json) {
+            Ok(url) => Ok(Delivery::Uploaded(url)),
+            #[cfg(not(target_arch = "wasm32"))]
+            Err(e) => queue(config, Target::Sentry, file_name, json, format!("sending to Sentry failed: {}", e)),
+            #[cfg(target_arch = "wasm32")]
+            Err(e) => {
+                let _ = file_name;
+                Err(format!("sending to Sentry failed: {}", e))
+            }
+        }
+    }
+}
+
+/// The configured storage (see `crate::storage`), encoded, compressed and
+/// encrypted as configured, with the `.sum` file next to it.
+#[derive(Debug)]
+pub(crate) struct FileTransport;
+
+impl Transport for FileTransport {
+    fn send(&self, config: &Config, file_name: &str, json: &[u8]) -> Result<Delivery, String> {
+        let (file_name, data, digest) = encode_artifact(config, file_name, json).map_err(|e| e.to_string())?;
+        let storage = config.storage();
+        let path = storage
+            .write(&file_name, &data)
+            .map_err(|e| format!("failed to write '{}': {}", file_name, e))?;
+        if let Err(e) = storage.write(&crate::integrity::sum_file_name(&file_name), &digest.to_json()) {
+            eprintln!("Failed to write the digest of crash report '{}': {}", file_name, e);
+        }
+        Ok(Delivery::Written(path))
+    }
+}
+
+/// A report as stored outside the crash server: the file name with the
+/// extensions of the configured encoding, compression and encryption, the
+/// data, and the digest of its content.
+pub(crate) fn encode_artifact(config: &Config, file_name: &str, json: &[u8]) -> std::io::Result<(String, Vec<u8>, Digest)> {
+    let file_name = format!(
+        "{}{}{}",
+        config.encoding.file_name(file_name),
+        config.compression.extension(),
+        crate::encryption::extension(config)
+    );
+    let content = config.encoding.encode(json)?;
+    let digest = Digest::new(&content, config.signing_key.as_ref());
+    let data = config.compression.compress(&content)?;
+    let data = crate::encryption::seal(config, data)?;
+    Ok((file_name, data, digest))
+}
+
+/// Sends each report as one line of compact JSON to the Unix socket at
+/// `path`, e.g. of a log shipper or a local agent.
+#[cfg(unix)]
+#[derive(Clone, Debug)]
+pub struct UnixSocketTransport {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixSocketTransport {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        UnixSocketTransport { path: path.into() }
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixSocketTransport {
+    fn send(&self, config: &Config, _file_name: &str, json: &[u8]) -> Result<Delivery, String> {
+        use std::io::Write;
+        let value: serde_json::Value = serde_json::from_slice(json).map_err(|e| e.to_string())?;
+        let mut line = serde_json::to_vec(&value).map_err(|e| e.to_string())?;
+        line.push(b'\n');
+        let fail = |e: std::io::Error| format!("{}: {}", self.path.display(), e);
+        let mut socket = std::os::unix::net::UnixStream::connect(&self.path).map_err(fail)?;
+        socket.set_write_timeout(Some(config.upload_timeout)).map_err(fail)?;
+        socket.write_all(&line).map_err(fail)?;
+        Ok(Delivery::Uploaded(format!("unix:{}", self.path.display())))
+    }
+}
+
+/// Uploads and/or writes a report as configured, after scrubbing it if
+/// enabled. Returns every place the report was delivered to; an empty list
+/// means it was lost.
+pub fn deliver_report(config: &Config, file_name: &str, json: &[u8]) -> Vec<Delivery> {
+    let delivered = deliver(config, file_name, json);
+    crate::metrics::record_deliveries(&delivered);
+    delivered
+}
+
+fn deliver(config: &Config, file_name: &str, json: &[u8]) -> Vec<Delivery> {
+    // A report that cannot be scrubbed is dropped rather than leaked.
+    let scrubbed = match crate::scrub::Scrubber::from_config(config) {
+        Ok(Some(scrubber)) => match scrubber.scrub_report(json) {
+            Ok(scrubbed) => Some(scrubbed),
+            Err(e) => {
+                eprintln!("Failed to scrub crash report '{}', dropping it: {}", file_name, e);
+                return Vec::new();
+            }
+        },
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("Invalid scrub rule, dropping crash report '{}': {}", file_name, e);
+            return Vec::new();
+        }
+    };
+    let json = scrubbed.as_deref().unwrap_or(json);
+
+    // Each transport on its own: one failing does not keep the report from
+    // the others.
+    let mut delivered = Vec::new();
+    for transport in config.transports() {
+        match transport.send(config, file_name, json) {
+            Ok(delivery) => delivered.push(delivery),
+            Err(e) => {
+                eprintln!("Failed to deliver crash report '{}' via {:?}: {}", file_name, transport, e);
+                crate::metrics::UPLOADS_FAILED.inc();
+            }
+        }
+    }
+    if delivered.is_empty() && !config.write_local {
+        match FileTransport.send(config, file_name, json) {
+            Ok(delivery) => delivered.push(delivery),
+            Err(e) => eprintln!("Failed to write crash report '{}': {}", file_name, e),
+        }
+    }
+    delivered
+}
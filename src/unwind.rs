@@ -0,0 +1,373 @@
+// Stack unwinding backends.
+//
+// `Builder::unwinder` picks how stacks are walked, trading symbol quality
+// against capture speed and signal safety:
+//
+// - `Unwinder::Backtrace`, the default, uses the `backtrace` crate, which
+//   walks the stack with the system unwinder and resolves names, files and
+//   lines from the debug info. It goes through the dynamic loader, which can
+//   take locks, so a walk from a signal handler is best effort.
+// - `Unwinder::Std` uses `std::backtrace::Backtrace`, with the same symbol
+//   quality, for panic reports only: std hands out its frames only as text,
+//   from a capture that allocates, so the code that has to unwind without
+//   allocating (signal handlers, the deferred hook, thread sampling) keeps
+//   using `backtrace`.
+// - `Unwinder::Framehop` (Linux on x86_64 and aarch64, feature `framehop`)
+//   walks the DWARF call frame information in `.eh_frame` with the
+//   `framehop` crate. It needs no frame pointers, so it works in optimized
+//   release builds, and never allocates or calls into the loader, so it is
+//   safe in signal handlers. The modules are read when `crash::init` runs:
+//   a walk ends at a frame in a library loaded after that. Names are
+//   resolved with `backtrace` afterwards, outside signal handlers. Without
+//   the feature, `init` falls back to `Unwinder::Backtrace`.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::event::MyFrame;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Unwinder {
+    #[default]
+    Backtrace,
+    Std,
+    Framehop,
+}
+
+impl Unwinder {
+    /// Parses `backtrace`, `std` or `framehop`.
+    pub fn from_name(name: &str) -> Option<Unwinder> {
+        match name {
+            "backtrace" => Some(Unwinder::Backtrace),
+            "std" => Some(Unwinder::Std),
+            "framehop" => Some(Unwinder::Framehop),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Unwinder::Backtrace => "backtrace",
+            Unwinder::Std => "std",
+            Unwinder::Framehop => "framehop",
+        }
+    }
+}
+
+// The unwinder `init` selected, read from signal handlers.
+static SELECTED: AtomicU8 = AtomicU8::new(Unwinder::Backtrace as u8);
+
+/// Makes `unwinder` the one stacks are walked with. Called by `crash::init`;
+/// for `Unwinder::Framehop` this reads the unwind sections of the loaded
+/// modules.
+pub(crate) fn select(unwinder: Unwinder) {
+    #[cfg(all(feature = "framehop", target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    if unwinder == Unwinder::Framehop {
+        dwarf::load();
+    }
+    #[cfg(not(all(feature = "framehop", target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+    let unwinder = if unwinder == Unwinder::Framehop {
+        eprintln!("The framehop unwinder needs the `framehop` feature on Linux (x86_64, aarch64); using backtrace");
+        Unwinder::Backtrace
+    } else {
+        unwinder
+    };
+    SELECTED.store(unwinder as u8, Ordering::Relaxed);
+}
+
+pub(crate) fn selected() -> Unwinder {
+    match SELECTED.load(Ordering::Relaxed) {
+        x if x == Unwinder::Std as u8 => Unwinder::Std,
+        x if x == Unwinder::Framehop as u8 => Unwinder::Framehop,
+        _ => Unwinder::Backtrace,
+    }
+}
+
+/// Fills `ips` with the return addresses of the current stack, innermost
+/// first, and returns how many it found. Does not allocate.
+pub(crate) fn trace(ips: &mut [usize]) -> usize {
+    #[cfg(all(feature = "framehop", target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    if selected() == Unwinder::Framehop {
+        if let Some(count) = dwarf::trace(ips) {
+            return count;
+        }
+    }
+    if ips.is_empty() {
+        return 0;
+    }
+    let mut count = 0;
+    backtrace::trace(|frame| {
+        ips[count] = frame.ip() as usize;
+        count += 1;
+        count < ips.len()
+    });
+    count
+}
+
+/// Like `trace`, for signal handlers, which must not take the lock
+/// `backtrace::trace` does.
+///
+/// # Safety
+///
+/// No other thread may walk its stack with `backtrace` at the same time.
+#[cfg(unix)]
+pub(crate) unsafe fn trace_unsynchronized(ips: &mut [usize]) -> usize {
+    #[cfg(all(feature = "framehop", target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    if selected() == Unwinder::Framehop {
+        if let Some(count) = dwarf::trace(ips) {
+            return count;
+        }
+    }
+    if ips.is_empty() {
+        return 0;
+    }
+    let mut count = 0;
+    unsafe {
+        backtrace::trace_unsynchronized(|frame| {
+            ips[count] = frame.ip() as usize;
+            count += 1;
+            count < ips.len()
+        });
+    }
+    count
+}
+
+/// The current stack from `std::backtrace::Backtrace`, innermost first.
+pub(crate) fn std_frames() -> Vec<MyFrame> {
+    let backtrace = std::backtrace::Backtrace::force_capture();
+    parse_std(&format!("{:#}", backtrace))
+}
+
+// Parses the full format of `std::backtrace::Backtrace`: a line
+// `<index>: <address> - <function>` per frame, followed by a line
+// `at <file>:<line>:<column>` when the location is known.
+fn parse_std(text: &str) -> Vec<MyFrame> {
+    let mut frames: Vec<MyFrame> = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if let Some(location) = line.strip_prefix("at ") {
+            if let Some(frame) = frames.last_mut() {
+                let (filename, lineno, colno) = parse_location(location);
+                frame.filename = Some(filename);
+                frame.lineno = lineno;
+                frame.colno = colno;
+            }
+            continue;
+        }
+        let Some((index, rest)) = line.split_once(':') else {
+            continue;
+        };
+        if index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+        let (address, function) = match rest.trim().split_once(" - ") {
+            Some((address, function)) => (Some(address), function),
+            None => (None, rest.trim()),
+        };
+        let function = (function != "<unknown>").then(|| function.to_string());
+        frames.push(MyFrame {
+            filename: None,
+            lineno: None,
+            colno: None,
+            instruction_addr: function.is_none().then(|| address.map(str::to_string)).flatten(),
+            function,
+            in_app: None,
+            pre_context: Vec::new(),
+            context_line: None,
+            post_context: Vec::new(),
+        });
+    }
+    frames
+}
+
+// `file:line:column`, where the file may contain colons itself.
+fn parse_location(location: &str) -> (String, Option<u32>, Option<u32>) {
+    let number = |s: &str| s.parse::<u32>().ok();
+    match location.rsplit_once(':') {
+        Some((rest, last)) if number(last).is_some() => match rest.rsplit_once(':') {
+            Some((file, line)) if number(line).is_some() => (file.to_string(), number(line), number(last)),
+            _ => (rest.to_string(), number(last), None),
+        },
+        _ => (location.to_string(), None, None),
+    }
+}
+
+#[cfg(all(feature = "framehop", target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod dwarf {
+    use std::ops::Range;
+    use std::sync::{Mutex, OnceLock, TryLockError};
+
+    use framehop::{
+        CacheNative, ExplicitModuleSectionInfo, Module, MustNotAllocateDuringUnwind, UnwindRegsNative, Unwinder,
+        UnwinderNative,
+    };
+
+    // Sections are read in place, from the mapped modules.
+    type Section = &'static [u8];
+
+    struct State {
+        unwinder: UnwinderNative<Section, MustNotAllocateDuringUnwind>,
+        cache: CacheNative<MustNotAllocateDuringUnwind>,
+    }
+
+    static STATE: OnceLock<Mutex<State>> = OnceLock::new();
+
+    // Reads further above the stack pointer than this end the walk, so a
+    // corrupt frame cannot send it off the stack.
+    const MAX_STACK_READ: u64 = 8 << 20;
+
+    // How `.eh_frame_hdr` normally points at `.eh_frame`: a signed 32-bit
+    // offset relative to the pointer itself.
+    const DW_EH_PE_PCREL_SDATA4: u8 = 0x1b;
+
+    // SAFETY: `len` bytes at `svma` in the module loaded at `bias` must be
+    // mapped readable for as long as the module stays loaded.
+    unsafe fn section(bias: u64, svma: &Range<u64>) -> Section {
+        unsafe { std::slice::from_raw_parts((bias + svma.start) as *const u8, (svma.end - svma.start) as usize) }
+    }
+
+    // The module described by `info`, with the unwind sections the loader
+    // mapped. None for modules without `.eh_frame_hdr`.
+    //
+    // SAFETY: `info` must come from `dl_iterate_phdr`.
+    unsafe fn module(info: &libc::dl_phdr_info) -> Option<Module<Section>> {
+        if info.dlpi_phdr.is_null() {
+            return None;
+        }
+        let headers = unsafe { std::slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize) };
+        let bias = info.dlpi_addr;
+        let range = |header: &libc::Elf64_Phdr| header.p_vaddr..header.p_vaddr + header.p_memsz;
+
+        let text = headers
+            .iter()
+            .find(|h| h.p_type == libc::PT_LOAD && h.p_flags & libc::PF_X != 0)
+            .map(range)?;
+        let eh_frame_hdr_svma = headers.iter().find(|h| h.p_type == libc::PT_GNU_EH_FRAME).map(range)?;
+        let eh_frame_hdr = unsafe { section(bias, &eh_frame_hdr_svma) };
+        // Version 1, then the encoding of the pointer to `.eh_frame`, which
+        // follows two more encoding bytes.
+        if eh_frame_hdr.len() < 8 || eh_frame_hdr[0] != 1 || eh_frame_hdr[1] != DW_EH_PE_PCREL_SDATA4 {
+            return None;
+        }
+        let offset = i32::from_ne_bytes(eh_frame_hdr[4..8].try_into().ok()?);
+        let eh_frame_start = (eh_frame_hdr_svma.start + 4).wrapping_add_signed(i64::from(offset));
+        // `.eh_frame` has no size of its own; it ends with the file contents
+        // of its segment at the latest.
+        let segment = headers
+            .iter()
+            .find(|h| h.p_type == libc::PT_LOAD && range(h).contains(&eh_frame_start))?;
+        let eh_frame_svma = eh_frame_start..segment.p_vaddr + segment.p_filesz;
+
+        let name = if info.dlpi_name.is_null() {
+            String::new()
+        } else {
+            unsafe { std::ffi::CStr::from_ptr(info.dlpi_name) }.to_string_lossy().into_owned()
+        };
+        let section_info = ExplicitModuleSectionInfo {
+            base_svma: 0,
+            text_svma: Some(text.clone()),
+            eh_frame: Some(unsafe { section(bias, &eh_frame_svma) }),
+            eh_frame_svma: Some(eh_frame_svma),
+            eh_frame_hdr: Some(eh_frame_hdr),
+            eh_frame_hdr_svma: Some(eh_frame_hdr_svma),
+            ..Default::default()
+        };
+        Some(Module::new(name, bias + text.start..bias + text.end, bias, section_info))
+    }
+
+    unsafe extern "C" fn visit(info: *mut libc::dl_phdr_info, _size: libc::size_t, data: *mut libc::c_void) -> libc::c_int {
+        // SAFETY: `data` is the vector passed to `dl_iterate_phdr` below.
+        let modules = unsafe { &mut *(data as *mut Vec<Module<Section>>) };
+        modules.extend(unsafe { module(&*info) });
+        0
+    }
+
+    /// Reads the unwind sections of the modules loaded now. Only the first
+    /// call does anything.
+    pub(super) fn load() {
+        STATE.get_or_init(|| {
+            let mut modules: Vec<Module<Section>> = Vec::new();
+            // SAFETY: `visit` only uses `data` as the vector it is given here.
+            unsafe { libc::dl_iterate_phdr(Some(visit), &mut modules as *mut Vec<Module<Section>> as *mut libc::c_void) };
+            let mut unwinder = UnwinderNative::new();
+            for module in modules {
+                unwinder.add_module(module);
+            }
+            Mutex::new(State {
+                unwinder,
+                cache: CacheNative::new_in(),
+            })
+        });
+    }
+
+    // The program counter, stack pointer and the registers the walk starts
+    // from, in the frame this is inlined into.
+    #[cfg(target_arch = "x86_64")]
+    #[inline(always)]
+    fn registers() -> (u64, u64, UnwindRegsNative) {
+        let (pc, sp, bp): (u64, u64, u64);
+        unsafe {
+            std::arch::asm!(
+                "lea {pc}, [rip]",
+                "mov {sp}, rsp",
+                "mov {bp}, rbp",
+                pc = out(reg) pc,
+                sp = out(reg) sp,
+                bp = out(reg) bp,
+                options(nomem, nostack, preserves_flags),
+            );
+        }
+        (pc, sp, UnwindRegsNative::new(pc, sp, bp))
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[inline(always)]
+    fn registers() -> (u64, u64, UnwindRegsNative) {
+        let (pc, sp, fp, lr): (u64, u64, u64, u64);
+        unsafe {
+            std::arch::asm!(
+                "adr {pc}, .",
+                "mov {sp}, sp",
+                "mov {fp}, x29",
+                "mov {lr}, x30",
+                pc = out(reg) pc,
+                sp = out(reg) sp,
+                fp = out(reg) fp,
+                lr = out(reg) lr,
+                options(nomem, nostack, preserves_flags),
+            );
+        }
+        (pc, sp, UnwindRegsNative::new(lr, sp, fp))
+    }
+
+    /// Walks the current stack into `ips`. None when the modules were not
+    /// loaded or another thread is walking, so the caller falls back.
+    #[inline(never)]
+    pub(super) fn trace(ips: &mut [usize]) -> Option<usize> {
+        let mut state = match STATE.get()?.try_lock() {
+            Ok(state) => state,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        let State { unwinder, cache } = &mut *state;
+        let (pc, sp, regs) = registers();
+        let mut read_stack = |addr: u64| {
+            if !addr.is_multiple_of(8) || addr < sp || addr - sp >= MAX_STACK_READ {
+                return Err(());
+            }
+            // SAFETY: the address lies on the stack of this thread, which the
+            // walk does not leave before its outermost frame.
+            Ok(unsafe { *(addr as *const u64) })
+        };
+        let mut frames = unwinder.iter_frames(pc, regs, cache, &mut read_stack);
+        let mut count = 0;
+        while count < ips.len() {
+            match frames.next() {
+                Ok(Some(frame)) => {
+                    ips[count] = frame.address() as usize;
+                    count += 1;
+                }
+                _ => break,
+            }
+        }
+        Some(count)
+    }
+}
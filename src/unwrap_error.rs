@@ -0,0 +1,380 @@
+// Errors behind panics of `Result::unwrap` and `Result::expect`.
+//
+// `unwrap` on an `Err` panics with "called `Result::unwrap()` on an `Err`
+// value: " and the `Debug` output of the error, `expect` with its message, a
+// colon and the same. As a flat message, every path, id or text inside the
+// error makes a panic look new. The hook reads the `Debug` output back into
+// the chain of errors it shows and reports it as `exception.values`, root
+// cause first:
+//
+// - derived `Debug` output, `Io(Os { code: 2, .. })`: the type or variant
+//   names, following single-field tuple variants and the `source`, `error`,
+//   `inner`, `cause` and `err` fields of structs;
+// - `std::io::Error`, whose `Debug` output names its representation (`Os`,
+//   `Custom`, `Kind`, `SimpleMessage`), as `std::io::Error` with its message;
+// - `anyhow` and `eyre` errors, which print their chain as `Caused by:`;
+// - errors made from strings, which print as a quoted string.
+//
+// `expect` panics are told from other panics whose message has a colon by the
+// `core::result::unwrap_failed` frame on their stack. The fingerprint (see
+// `crate::fingerprint`) uses the `expect` message and the error types, and
+// the kinds of I/O errors, instead of the whole message.
+
+use crate::event::{ExceptionValue, MyStacktrace};
+
+const UNWRAP_PREFIX: &str = "called `Result::unwrap()` on an `Err` value: ";
+const UNWRAP_FAILED: &str = "result::unwrap_failed";
+// Fields of derived `Debug` output that hold the underlying error.
+const SOURCE_FIELDS: &[&str] = &["source", "error", "inner", "cause", "err"];
+// Fields that hold the message of an error.
+const MESSAGE_FIELDS: &[&str] = &["message", "msg", "description"];
+// Representations of `std::io::Error` in its `Debug` output.
+const IO_ERRORS: &[&str] = &["Os", "Custom", "SimpleMessage"];
+// Errors followed into, against runaway nesting.
+const MAX_DEPTH: usize = 8;
+
+/// The error a panic of `unwrap` or `expect` was raised with.
+#[derive(Debug)]
+pub struct UnwrappedError {
+    pub context: Option<String>,     // The message passed to `expect`
+    pub values: Vec<ExceptionValue>, // The error and its causes, root cause first
+    kinds: Vec<String>,              // Kinds of the I/O errors in the chain, for grouping
+}
+
+impl UnwrappedError {
+    /// What panics with this error are grouped by: the `expect` message and
+    /// the types of the errors, not their values.
+    pub fn grouping_key(&self) -> String {
+        let mut key = self.context.clone().unwrap_or_else(|| UNWRAP_PREFIX.trim_end().to_string());
+        for value in &self.values {
+            key.push('\n');
+            key.push_str(&value.exception_type);
+        }
+        for kind in &self.kinds {
+            key.push('\n');
+            key.push_str(kind);
+        }
+        key
+    }
+}
+
+/// The error of a panic with `message` raised by `unwrap` or `expect`, or
+/// None for other panics. `stacktrace` is the stack of the panic, needed to
+/// recognize `expect`.
+pub fn parse(message: &str, stacktrace: Option<&MyStacktrace>) -> Option<UnwrappedError> {
+    if let Some(debug) = message.strip_prefix(UNWRAP_PREFIX) {
+        return Some(chain(None, debug));
+    }
+    let unwrapped = stacktrace.is_some_and(|stacktrace| {
+        stacktrace
+            .frames
+            .iter()
+            .any(|frame| frame.function.as_deref().is_some_and(|f| f.contains(UNWRAP_FAILED)))
+    });
+    if !unwrapped {
+        return None;
+    }
+    // The message of `expect` may have colons of its own: the error starts at
+    // the first that is followed by complete `Debug` output.
+    let mut splits = message.match_indices(": ").map(|(at, _)| (&message[..at], &message[at + 2..]));
+    let (context, debug) = splits
+        .clone()
+        .find(|(_, debug)| Parser::new(debug).complete().is_some())
+        .or_else(|| splits.find(|(_, debug)| debug.contains("\n\nCaused by:")))
+        .or_else(|| message.split_once(": "))?;
+    Some(chain(Some(context.to_string()), debug))
+}
+
+// The chain of errors in the `Debug` output `debug`.
+fn chain(context: Option<String>, debug: &str) -> UnwrappedError {
+    let mut unwrapped = UnwrappedError {
+        context,
+        values: Vec::new(),
+        kinds: Vec::new(),
+    };
+    if let Some((first, causes)) = debug.split_once("\n\nCaused by:") {
+        // anyhow and eyre: the error, then its causes, one per line, numbered
+        // when there are several, then possibly a location or backtrace.
+        push(&mut unwrapped, "Error", first.trim());
+        let causes = causes.split("\n\n").next().unwrap_or_default();
+        for line in causes.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let cause = match line.split_once(": ") {
+                Some((index, cause)) if index.bytes().all(|b| b.is_ascii_digit()) => cause,
+                _ => line,
+            };
+            push(&mut unwrapped, "Error", cause);
+        }
+    } else {
+        match Parser::new(debug).complete() {
+            Some(node) => follow(&mut unwrapped, &node, 0),
+            None => push(&mut unwrapped, "Error", debug),
+        }
+    }
+    unwrapped.values.reverse();
+    unwrapped
+}
+
+fn push(unwrapped: &mut UnwrappedError, exception_type: &str, value: &str) {
+    unwrapped.values.push(ExceptionValue {
+        exception_type: exception_type.to_string(),
+        value: value.to_string(),
+        ..Default::default()
+    });
+}
+
+// Adds the error `node` and, after it, the errors it wraps.
+fn follow(unwrapped: &mut UnwrappedError, node: &Node, depth: usize) {
+    if depth == MAX_DEPTH {
+        return;
+    }
+    let Node::Named { name, body, text } = node else {
+        push(unwrapped, "Error", &node.message());
+        return;
+    };
+    // `std::io::Error`: `Os { code, kind, message }`, `Custom { kind, error }`,
+    // `SimpleMessage { kind, message }` or `Kind(kind)`.
+    if let Some(kind) = node.field("kind").filter(|_| IO_ERRORS.contains(name)) {
+        unwrapped.kinds.push(kind.message());
+        match node.field("error") {
+            Some(error @ Node::Named { .. }) => {
+                push(unwrapped, "std::io::Error", &kind.message());
+                follow(unwrapped, error, depth + 1);
+            }
+            error => {
+                let message = error.or(node.field("message")).unwrap_or(kind);
+                push(unwrapped, "std::io::Error", &message.message());
+            }
+        }
+        return;
+    }
+    if let Body::Tuple(fields) = body {
+        if let ("Kind", [kind @ Node::Named { body: Body::Unit, .. }]) = (*name, fields.as_slice()) {
+            unwrapped.kinds.push(kind.message());
+            push(unwrapped, "std::io::Error", &kind.message());
+            return;
+        }
+    }
+
+    let source = match body {
+        Body::Tuple(fields) if fields.len() == 1 => Some(fields[0].unwrap_option()),
+        Body::Struct(_) => SOURCE_FIELDS
+            .iter()
+            .find_map(|field| node.field(field))
+            .map(Node::unwrap_option),
+        _ => None,
+    };
+    // A wrapped error is followed, a wrapped string is the message.
+    let message = match source {
+        Some(Node::Str(message)) => message.clone(),
+        _ => MESSAGE_FIELDS
+            .iter()
+            .find_map(|field| node.field(field))
+            .map_or_else(|| text.to_string(), Node::message),
+    };
+    push(unwrapped, name, &message);
+    if let Some(source @ Node::Named { body: Body::Tuple(_) | Body::Struct(_), .. }) = source {
+        follow(unwrapped, source, depth + 1);
+    }
+}
+
+// A value in `Debug` output.
+enum Node<'a> {
+    Named { name: &'a str, body: Body<'a>, text: &'a str }, // `Name`, `Name(..)` or `Name { .. }`
+    Str(String),                                             // A quoted string, unescaped
+    Other(&'a str),                                          // Numbers, lists, maps and the like
+}
+
+enum Body<'a> {
+    Unit,
+    Tuple(Vec<Node<'a>>),
+    Struct(Vec<(&'a str, Node<'a>)>),
+}
+
+impl<'a> Node<'a> {
+    fn field(&self, name: &str) -> Option<&Node<'a>> {
+        match self {
+            Node::Named { body: Body::Struct(fields), .. } => {
+                fields.iter().find(|(field, _)| *field == name).map(|(_, value)| value)
+            }
+            _ => None,
+        }
+    }
+
+    // The value inside `Some(..)`, as sources are often optional.
+    fn unwrap_option(&self) -> &Node<'a> {
+        match self {
+            Node::Named {
+                name: "Some",
+                body: Body::Tuple(fields),
+                ..
+            } if fields.len() == 1 => &fields[0],
+            _ => self,
+        }
+    }
+
+    // The value as text: strings without quotes, anything else as printed.
+    fn message(&self) -> String {
+        match self {
+            Node::Named { text, .. } | Node::Other(text) => text.to_string(),
+            Node::Str(s) => s.clone(),
+        }
+    }
+}
+
+// Parser for the output of derived `Debug` implementations.
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        Parser { text, pos: 0 }
+    }
+
+    // The value making up all of the text.
+    fn complete(mut self) -> Option<Node<'a>> {
+        let node = self.value()?;
+        self.skip_whitespace();
+        (self.pos == self.text.len()).then_some(node)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn identifier(&mut self) -> Option<&'a str> {
+        let start = self.pos;
+        let rest = &self.text[start..];
+        if !rest.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            return None;
+        }
+        let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        self.pos += len;
+        Some(&self.text[start..self.pos])
+    }
+
+    fn value(&mut self) -> Option<Node<'a>> {
+        self.skip_whitespace();
+        let start = self.pos;
+        if self.peek() == Some('"') {
+            return self.string().map(Node::Str);
+        }
+        let Some(name) = self.identifier() else {
+            return self.other().map(Node::Other);
+        };
+        let after_name = self.pos;
+        let body = if self.eat('(') {
+            let mut fields = Vec::new();
+            while !self.eat(')') {
+                fields.push(self.value()?);
+                if !self.eat(',') && self.peek() != Some(')') {
+                    return None;
+                }
+            }
+            Body::Tuple(fields)
+        } else if self.eat('{') {
+            let mut fields = Vec::new();
+            while !self.eat('}') {
+                self.skip_whitespace();
+                // Fails on the `..` of `finish_non_exhaustive`.
+                let field = self.identifier()?;
+                if !self.eat(':') {
+                    return None;
+                }
+                fields.push((field, self.value()?));
+                if !self.eat(',') && self.peek() != Some('}') {
+                    return None;
+                }
+            }
+            Body::Struct(fields)
+        } else {
+            self.pos = after_name;
+            Body::Unit
+        };
+        Some(Node::Named {
+            name,
+            body,
+            text: &self.text[start..self.pos],
+        })
+    }
+
+    // A quoted string, unescaped.
+    fn string(&mut self) -> Option<String> {
+        let mut chars = self.text[self.pos + 1..].char_indices();
+        let mut unescaped = String::new();
+        while let Some((at, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += 1 + at + 1;
+                    return Some(unescaped);
+                }
+                '\\' => match chars.next()?.1 {
+                    'n' => unescaped.push('\n'),
+                    'r' => unescaped.push('\r'),
+                    't' => unescaped.push('\t'),
+                    '0' => unescaped.push('\0'),
+                    'u' => {
+                        let code: String = chars.by_ref().map(|(_, c)| c).skip(1).take_while(|c| *c != '}').collect();
+                        unescaped.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                    }
+                    c => unescaped.push(c),
+                },
+                c => unescaped.push(c),
+            }
+        }
+        None
+    }
+
+    // Anything else, up to the end of the value: a number, a list, a map.
+    fn other(&mut self) -> Option<&'a str> {
+        let start = self.pos;
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        for (at, c) in self.text[start..].char_indices() {
+            if in_string {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match c {
+                '"' => in_string = true,
+                '[' | '(' | '{' => depth += 1,
+                ']' | ')' | '}' if depth == 0 => {
+                    self.pos = start + at;
+                    break;
+                }
+                ']' | ')' | '}' => depth -= 1,
+                ',' if depth == 0 => {
+                    self.pos = start + at;
+                    break;
+                }
+                _ => {}
+            }
+            self.pos = start + at + c.len_utf8();
+        }
+        let text = self.text[start..self.pos].trim_end();
+        (!text.is_empty()).then_some(text)
+    }
+}
@@ -0,0 +1,152 @@
+// Minidump upload without touching the filesystem.
+//
+// In containers with a read-only root filesystem there is nowhere to write a
+// multi-hundred-megabyte dump. When an upload URL is configured (by default
+// from CRASH_UPLOAD_URL), the dump is assembled in memory instead and sent to
+// `POST {url}/crashes/{id}/minidump` with chunked transfer encoding. The writer
+// has to seek back to patch the stream directory, so the dump cannot be sent
+// before it is complete.
+
+#[cfg(not(any(windows, target_arch = "wasm32")))]
+use std::io::Cursor;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use std::path::PathBuf;
+
+#[cfg(not(any(windows, target_arch = "wasm32")))]
+use minidump_writer::minidump_writer::MinidumpWriter;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::compression::Compression;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::integrity::Digest;
+
+pub const UPLOAD_URL_ENV: &str = "CRASH_UPLOAD_URL";
+
+/// Where a minidump should end up.
+#[derive(Clone, Debug)]
+pub enum DumpDestination {
+    File(PathBuf),
+    Upload(String), // Full upload endpoint, including the event id
+}
+
+impl std::fmt::Display for DumpDestination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DumpDestination::File(path) => write!(f, "{}", path.display()),
+            DumpDestination::Upload(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// Builds the minidump upload endpoint for `event_id` on `server`.
+pub fn upload_endpoint(server: &str, event_id: &str) -> String {
+    format!("{}/crashes/{}/minidump", server.trim_end_matches('/'), event_id)
+}
+
+/// Whether a dump for `destination` is written to it as it is, rather than
+/// compressed, encrypted or uploaded, so it can be dumped straight into the
+/// file.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn stored_as_is(destination: &DumpDestination, encrypt_to: Option<&str>) -> bool {
+    let DumpDestination::File(path) = destination else {
+        return false;
+    };
+    let plain = path
+        .to_str()
+        .and_then(|p| p.strip_suffix(crate::encryption::EXTENSION))
+        .map(Path::new)
+        .unwrap_or(path);
+    Compression::from_path(plain) == Compression::None && encrypt_to.is_none()
+}
+
+// Writes the `.sum` of the dump at `path`. The dump is usable without it; a
+// missing `.sum` only leaves it unchecked.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn write_sum(path: &Path, digest: std::io::Result<Digest>) {
+    let mut sum_path = path.as_os_str().to_owned();
+    sum_path.push(crate::integrity::SUFFIX);
+    if let Err(e) = digest.and_then(|digest| std::fs::write(&sum_path, digest.to_json())) {
+        eprintln!("Failed to write the digest of minidump '{}': {}", path.display(), e);
+    }
+}
+
+/// Stores the finished minidump `data` at `destination` like `write_dump`.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn deliver_dump(data: &[u8], destination: &DumpDestination, encrypt_to: Option<&str>) -> Result<(), String> {
+    match destination {
+        DumpDestination::File(path) => {
+            let plain = path
+                .to_str()
+                .and_then(|p| p.strip_suffix(crate::encryption::EXTENSION))
+                .map(Path::new)
+                .unwrap_or(path);
+            let mut stored = Compression::from_path(plain).compress(data).map_err(|e| e.to_string())?;
+            if let Some(recipient) = encrypt_to {
+                stored = crate::encryption::encrypt(recipient, &stored).map_err(|e| e.to_string())?;
+            }
+            std::fs::write(path, stored).map_err(|e| e.to_string())?;
+            write_sum(path, Ok(Digest::new(data, None)));
+            Ok(())
+        }
+        DumpDestination::Upload(url) => upload_minidump(url, data, encrypt_to),
+    }
+}
+
+/// Writes the minidump produced by `writer` to `destination`. Files named
+/// `*.gz` or `*.zst` are compressed accordingly; with `encrypt_to` the dump
+/// is encrypted to that age recipient, after compression (`*.dmp.gz.age`).
+/// Files get their `.sum` next to them, see `crate::integrity`. `streams` are
+/// added to the dump before that, see `crate::minidump_streams`. Windows
+/// dumps are written by `crate::windows_dump` instead.
+#[cfg(not(any(windows, target_arch = "wasm32")))]
+pub fn write_dump(
+    writer: &mut MinidumpWriter,
+    destination: &DumpDestination,
+    encrypt_to: Option<&str>,
+    streams: &[(String, Vec<u8>)],
+) -> Result<(), String> {
+    match destination {
+        DumpDestination::File(path) if stored_as_is(destination, encrypt_to) => {
+            let mut file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+                .map_err(|e| e.to_string())?;
+            writer.dump(&mut file).map_err(|e| format!("{:?}", e))?;
+            crate::minidump_streams::append(&mut file, streams).map_err(|e| e.to_string())?;
+            // Read back rather than held in memory, like the dump itself.
+            write_sum(path, std::fs::File::open(path).and_then(Digest::of_reader));
+            Ok(())
+        }
+        _ => {
+            let mut buffer = Cursor::new(Vec::new());
+            writer.dump(&mut buffer).map_err(|e| format!("{:?}", e))?;
+            crate::minidump_streams::append(&mut buffer, streams).map_err(|e| e.to_string())?;
+            deliver_dump(buffer.get_ref(), destination, encrypt_to)
+        }
+    }
+}
+
+/// Sends a finished minidump to `url` with its SHA-256, encrypted to
+/// `encrypt_to` if given. No Content-Length is set, so the body goes out
+/// with chunked transfer encoding.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn upload_minidump(url: &str, data: &[u8], encrypt_to: Option<&str>) -> Result<(), String> {
+    let mut request = ureq::post(url)
+        .set("Content-Type", "application/octet-stream")
+        .set(crate::integrity::SHA256_HEADER, &crate::integrity::sha256_hex(data));
+    if let Ok(project) = std::env::var("CRASH_PROJECT") {
+        request = request.set("X-Crash-Project", &project);
+    }
+    match encrypt_to {
+        Some(recipient) => {
+            let data = crate::encryption::encrypt(recipient, data).map_err(|e| e.to_string())?;
+            request = request.set(crate::encryption::HEADER, "age");
+            request.send(data.as_slice()).map(|_| ()).map_err(|e| e.to_string())
+        }
+        None => request.send(data).map(|_| ()).map_err(|e| e.to_string()),
+    }
+}
@@ -0,0 +1,368 @@
+// Watchdog mode: a long-lived helper process that captures crashes from the
+// outside.
+//
+// The on-demand helper in `helper.rs` is fork'ed from the signal handler,
+// which itself can fail when the crashing process is in bad shape, and the
+// JSON report is still written in-process. In watchdog mode `crash::init`
+// starts `crash-helper --watchdog` right away, connected through a socket
+// pair. On a crash the process only sends a short notification and waits
+// for the acknowledgement; the watchdog writes both the report and the
+// minidump. When the application exits normally the socket closes and the
+// watchdog exits too.
+
+use std::ffi::{OsStr, OsString};
+use std::io::{Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use minidump_writer::minidump_writer::MinidumpWriter;
+
+use crate::compression::Compression;
+use crate::config::Config;
+use crate::encoding::Encoding;
+use crate::event::{Exception, ExceptionValue, SentryEvent};
+use crate::helper::HANDSHAKE_FD;
+use crate::upload::{self, DumpDestination};
+
+// Notification layout: pid u32, tid u32, signal u32 (0 for a panic, where the
+// report is written in-process; with `signals::STACK_OVERFLOW` set for a
+// stack overflow), fault address u64, event id (36 ASCII bytes), destination
+// kind u32 (see `DESTINATION_DEFAULT`), destination length u32, streams path
+// length u32. The destination and the path of the streams handed over, see
+// `crate::minidump_streams`, follow; a signal handler sends neither.
+const MESSAGE_LEN: usize = 68;
+const EVENT_ID_LEN: usize = 36;
+// Longest destination or streams path accepted.
+const MAX_PATH_LEN: usize = 64 * 1024;
+
+// Destination kinds: the watchdog's own for the event, a file, or an upload
+// endpoint.
+const DESTINATION_DEFAULT: u32 = 0;
+const DESTINATION_FILE: u32 = 1;
+const DESTINATION_UPLOAD: u32 = 2;
+
+// Our end of the socket pair, or -1 when no watchdog is running.
+static WATCHDOG_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Starts the watchdog helper at `helper_path`, which writes reports and
+/// minidumps as described by `config`.
+pub fn spawn(helper_path: &Path, config: &Config) -> std::io::Result<()> {
+    let mut fds = [0i32; 2];
+    // SAFETY: `fds` has room for the two descriptors.
+    if unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0, fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let (ours, theirs) = (fds[0], fds[1]);
+
+    let mut command = Command::new(helper_path);
+    command
+        .arg("--watchdog")
+        .arg("--output-dir")
+        .arg(&config.output_dir)
+        .arg("--filename-template")
+        .arg(&config.filename_template);
+    if let Some(app_name) = &config.app_name {
+        command.arg("--app-name").arg(app_name);
+    }
+    if let Some(app_version) = &config.app_version {
+        command.arg("--app-version").arg(app_version);
+    }
+    for (flag, value) in [
+        ("--release", &config.release),
+        ("--environment", &config.environment),
+        ("--dist", &config.dist),
+        ("--server-name", &config.server_name),
+    ] {
+        if let Some(value) = value {
+            command.arg(flag).arg(value);
+        }
+    }
+    if let Some(dsn) = &config.sentry_dsn {
+        command.arg("--sentry-dsn").arg(dsn);
+    }
+    if let Some(url) = &config.upload_url {
+        command.arg("--upload-url").arg(url);
+        if !config.upload_minidump {
+            command.arg("--no-minidump-upload");
+        }
+    }
+    if !config.write_local {
+        command.arg("--no-local");
+    }
+    if config.encoding != Encoding::Json {
+        command.arg("--encoding").arg(config.encoding.name());
+    }
+    if config.compression != Compression::None {
+        command.arg("--compression").arg(config.compression.name());
+    }
+    if let Some(recipient) = &config.encrypt_to {
+        command.arg("--encrypt-to").arg(recipient);
+    }
+    if config.scrub_pii {
+        command.arg("--scrub-pii");
+        for pattern in &config.scrub_rules {
+            command.arg("--scrub-rule").arg(pattern);
+        }
+    }
+    if config.capture_env {
+        command.arg("--capture-env");
+        for pattern in &config.env_allowlist {
+            command.arg("--env-allow").arg(pattern);
+        }
+        for pattern in &config.env_denylist {
+            command.arg("--env-deny").arg(pattern);
+        }
+    }
+    // SAFETY: only async-signal-safe calls between fork and exec.
+    unsafe {
+        command.pre_exec(move || {
+            // dup2 clears close-on-exec on the new descriptor, unless the
+            // socket already is HANDSHAKE_FD.
+            let result = if theirs == HANDSHAKE_FD {
+                libc::fcntl(theirs, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(theirs, HANDSHAKE_FD)
+            };
+            if result < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn();
+    // SAFETY: the child has its own copy; ours is no longer needed.
+    unsafe { libc::close(theirs) };
+    let child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            unsafe { libc::close(ours) };
+            return Err(e);
+        }
+    };
+
+    // Allow the watchdog to ptrace us (needed under Yama ptrace_scope=1).
+    // SAFETY: plain prctl call.
+    unsafe { libc::prctl(libc::PR_SET_PTRACER, child.id() as libc::c_ulong, 0, 0, 0) };
+
+    if WATCHDOG_FD
+        .compare_exchange(-1, ours, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        unsafe { libc::close(ours) };
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "crash watchdog already running",
+        ));
+    }
+    Ok(())
+}
+
+/// Asks the watchdog to capture the calling thread and waits until it is
+/// done. Returns false if there is no watchdog or it did not answer. Only
+/// async-signal-safe calls are used, so this can run in a signal handler.
+pub fn notify(signal: i32, fault_addr: usize, event_id: &str) -> bool {
+    request(signal, fault_addr, event_id, DESTINATION_DEFAULT, &[], &[])
+}
+
+/// Asks the watchdog to dump the calling thread to `destination`, adding the
+/// streams handed over in `streams`, and waits until it is done. Returns false
+/// if there is no watchdog or it did not answer; the streams are then still
+/// there for the helper.
+pub fn notify_dump(event_id: &str, destination: &DumpDestination, streams: Option<&Path>) -> bool {
+    let (kind, target) = match destination {
+        DumpDestination::File(path) => (DESTINATION_FILE, path.as_os_str().as_bytes()),
+        DumpDestination::Upload(url) => (DESTINATION_UPLOAD, url.as_bytes()),
+    };
+    let streams = streams.map(|path| path.as_os_str().as_bytes()).unwrap_or_default();
+    request(0, 0, event_id, kind, target, streams)
+}
+
+fn request(signal: i32, fault_addr: usize, event_id: &str, kind: u32, destination: &[u8], streams: &[u8]) -> bool {
+    let fd = WATCHDOG_FD.load(Ordering::SeqCst);
+    if fd < 0 || event_id.len() != EVENT_ID_LEN || destination.len() > MAX_PATH_LEN || streams.len() > MAX_PATH_LEN {
+        return false;
+    }
+
+    // SAFETY: getpid/gettid are async-signal-safe.
+    let (pid, tid) = unsafe { (libc::getpid(), libc::syscall(libc::SYS_gettid) as libc::pid_t) };
+    let mut message = [0u8; MESSAGE_LEN];
+    message[0..4].copy_from_slice(&(pid as u32).to_le_bytes());
+    message[4..8].copy_from_slice(&(tid as u32).to_le_bytes());
+    message[8..12].copy_from_slice(&(signal as u32).to_le_bytes());
+    message[12..20].copy_from_slice(&(fault_addr as u64).to_le_bytes());
+    message[20..56].copy_from_slice(event_id.as_bytes());
+    message[56..60].copy_from_slice(&kind.to_le_bytes());
+    message[60..64].copy_from_slice(&(destination.len() as u32).to_le_bytes());
+    message[64..68].copy_from_slice(&(streams.len() as u32).to_le_bytes());
+
+    // SAFETY: write/read on a socket are async-signal-safe.
+    unsafe {
+        for part in [&message[..], destination, streams] {
+            if !part.is_empty() && libc::write(fd, part.as_ptr() as *const libc::c_void, part.len()) != part.len() as isize {
+                return false;
+            }
+        }
+        let mut ack = 0u8;
+        libc::read(fd, &mut ack as *mut u8 as *mut libc::c_void, 1) == 1
+    }
+}
+
+/// Entry point of `crash-helper --watchdog --output-dir <dir>`. Serves crash
+/// notifications until the application closes the socket.
+pub fn run(args: &[String]) -> i32 {
+    let value_of = |flag: &str| {
+        let i = args.iter().position(|a| a == flag)?;
+        args.get(i + 1).cloned()
+    };
+    let values_of = |flag: &str| {
+        args.windows(2)
+            .filter(|pair| pair[0] == flag)
+            .map(|pair| pair[1].clone())
+            .collect::<Vec<_>>()
+    };
+    let config = Config {
+        output_dir: value_of("--output-dir").map(PathBuf::from).unwrap_or_else(|| PathBuf::from(".")),
+        filename_template: value_of("--filename-template")
+            .unwrap_or_else(|| crate::output::DEFAULT_FILENAME_TEMPLATE.to_string()),
+        app_name: value_of("--app-name"),
+        app_version: value_of("--app-version"),
+        release: value_of("--release"),
+        environment: value_of("--environment"),
+        dist: value_of("--dist"),
+        server_name: value_of("--server-name"),
+        compression: value_of("--compression")
+            .and_then(|name| Compression::from_name(&name))
+            .unwrap_or_default(),
+        encoding: value_of("--encoding")
+            .and_then(|name| Encoding::from_name(&name))
+            .unwrap_or_default(),
+        encrypt_to: value_of("--encrypt-to"),
+        upload_url: value_of("--upload-url"),
+        sentry_dsn: value_of("--sentry-dsn"),
+        upload_minidump: !args.iter().any(|a| a == "--no-minidump-upload"),
+        write_local: !args.iter().any(|a| a == "--no-local"),
+        capture_env: args.iter().any(|a| a == "--capture-env"),
+        env_allowlist: values_of("--env-allow"),
+        env_denylist: values_of("--env-deny"),
+        scrub_pii: args.iter().any(|a| a == "--scrub-pii"),
+        scrub_rules: values_of("--scrub-rule"),
+        ..Config::default()
+    };
+    // Same host as the application, so our own view of it is accurate. The
+    // environment is inherited from the application as well.
+    crate::contexts::init(&config);
+
+    // SAFETY: `spawn` placed our end of the socket pair on HANDSHAKE_FD.
+    let mut socket = unsafe { std::os::unix::net::UnixStream::from_raw_fd(HANDSHAKE_FD) };
+    let mut message = [0u8; MESSAGE_LEN];
+    loop {
+        if socket.read_exact(&mut message).is_err() {
+            // The application exited (or closed the socket); so do we.
+            return 0;
+        }
+        let field = |range: std::ops::Range<usize>| {
+            let mut bytes = [0u8; 8];
+            bytes[..range.len()].copy_from_slice(&message[range]);
+            u64::from_le_bytes(bytes)
+        };
+        let pid = field(0..4) as i32;
+        let tid = field(4..8) as i32;
+        let signal = field(8..12) as i32;
+        let fault_addr = field(12..20);
+        let event_id = String::from_utf8_lossy(&message[20..56]).into_owned();
+        let kind = field(56..60) as u32;
+        let (destination_len, streams_len) = (field(60..64) as usize, field(64..68) as usize);
+        if destination_len > MAX_PATH_LEN || streams_len > MAX_PATH_LEN {
+            eprintln!("crash-helper: malformed notification for {}", event_id);
+            return 1;
+        }
+        let mut destination = vec![0u8; destination_len];
+        let mut streams_path = vec![0u8; streams_len];
+        if socket.read_exact(&mut destination).is_err() || socket.read_exact(&mut streams_path).is_err() {
+            return 0;
+        }
+
+        if signal != 0 {
+            if let Err(e) = write_report(&config, &event_id, signal, fault_addr) {
+                eprintln!("crash-helper: failed to write crash report {}: {}", event_id, e);
+            }
+        }
+        let destination = match kind {
+            DESTINATION_FILE => DumpDestination::File(PathBuf::from(OsString::from_vec(destination))),
+            DESTINATION_UPLOAD => DumpDestination::Upload(String::from_utf8_lossy(&destination).into_owned()),
+            _ => config.dump_destination(&event_id),
+        };
+        // Only panics hand over application streams.
+        let streams = if streams_path.is_empty() {
+            Vec::new()
+        } else {
+            crate::minidump_streams::take_handoff(Path::new(OsStr::from_bytes(&streams_path)))
+        };
+        match upload::write_dump(&mut MinidumpWriter::new(pid, tid), &destination, config.encrypt_to.as_deref(), &streams) {
+            Ok(()) => eprintln!("crash-helper: minidump saved to {}", destination),
+            Err(e) => eprintln!("crash-helper: failed to write minidump '{}': {}", destination, e),
+        }
+
+        if socket.write_all(&[1]).is_err() {
+            return 0;
+        }
+    }
+}
+
+fn write_report(config: &Config, event_id: &str, signal: i32, fault_addr: u64) -> std::io::Result<()> {
+    let (name, description) = crate::signals::exception_name(signal);
+    // The application sends 0 for signals without a fault address.
+    let fault_addr = crate::signals::has_fault_address(signal).then_some(fault_addr);
+    let at = crate::signals::FaultAt(fault_addr.map(|addr| addr as usize));
+    let message = match crate::signals::signal_name(signal & !crate::signals::STACK_OVERFLOW) {
+        (signal_name, _) if signal & crate::signals::STACK_OVERFLOW != 0 => {
+            format!("Fatal stack overflow ({}){}", signal_name, at)
+        }
+        (signal_name, signal_description) => {
+            format!("Fatal signal {} ({}){}", signal_name, signal_description, at)
+        }
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0);
+    let mut event = SentryEvent {
+        event_id: event_id.to_string(),
+        schema_version: crate::event::SCHEMA_VERSION,
+        timestamp: timestamp.to_string(),
+        message: Some(message),
+        level: Some(config.signal_level.as_str().to_string()),
+        platform: Some("native".to_string()),
+        stacktrace: None, // The minidump carries the stacks
+        exception: Some(Exception {
+            values: vec![ExceptionValue {
+                exception_type: name.to_string(),
+                value: description.to_string(),
+                fault_address: fault_addr.map(|addr| format!("{:#x}", addr)),
+                ..Default::default() // And the registers
+            }],
+        }),
+        installation_id: crate::install_id::installation_id().map(|s| s.to_string()),
+        contexts: crate::contexts::get(),
+        ..Default::default()
+    };
+    config.apply_release(&mut event);
+    let json = serde_json::to_string_pretty(&event).map_err(std::io::Error::other)?;
+    let file_name = config.report_file_name(event_id, timestamp as u64);
+    if crate::transport::deliver_report(config, &file_name, json.as_bytes()).is_empty() {
+        return Err(std::io::Error::other("report could not be uploaded or written"));
+    }
+    let crash = crate::feedback::LastCrash {
+        event_id: event.event_id,
+        timestamp: event.timestamp,
+        level: event.level.unwrap_or_default(),
+        message: event.message,
+    };
+    crate::feedback::mark(config, &crash);
+    Ok(())
+}
@@ -0,0 +1,121 @@
+// Browser support (wasm32).
+//
+// On wasm32-unknown-unknown there are no files, threads, signals or blocking
+// sockets, so only the panic hook and the capture functions are available.
+// Reports go to the crash server (or Sentry) with `fetch`, and are kept in
+// `localStorage` under `crash/<file name>` when written locally, where the
+// page can pick them up again. Minidumps, native crash handling, the deferred
+// hook, the upload queue and retention are native only; their settings are
+// ignored here.
+//
+// A panic usually ends the wasm instance, and nothing can wait for a response
+// in the panic hook, so uploads are fire-and-forget: a report counts as
+// uploaded once the request has been started. Requests small enough for
+// `keepalive` (64 KiB) even survive the page being closed. Keep
+// `write_local` on to have a copy in `localStorage` in case the upload does
+// not make it. `localStorage` holds text only, so reports written there must
+// be uncompressed JSON.
+
+use std::path::{Path, PathBuf};
+
+use wasm_bindgen::{JsCast, JsValue};
+
+// Largest body browsers accept for a `keepalive` request.
+const KEEPALIVE_LIMIT: usize = 64 * 1024;
+
+const INSTALL_ID_KEY: &str = "crash/installation_id";
+
+fn js_error(value: JsValue) -> String {
+    value.as_string().unwrap_or_else(|| format!("{:?}", value))
+}
+
+fn local_storage() -> std::io::Result<web_sys::Storage> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Unsupported, "localStorage is not available"))
+}
+
+/// Starts a POST of `body` to `url` with `headers`. Works in windows and
+/// workers.
+pub fn post(url: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<(), String> {
+    let global = js_sys::global();
+    let fetch: js_sys::Function = js_sys::Reflect::get(&global, &JsValue::from_str("fetch"))
+        .ok()
+        .and_then(|fetch| fetch.dyn_into().ok())
+        .ok_or("fetch is not available")?;
+
+    let request_headers = web_sys::Headers::new().map_err(js_error)?;
+    for (name, value) in headers {
+        request_headers.set(name, value).map_err(js_error)?;
+    }
+    let init = web_sys::RequestInit::new();
+    init.set_method("POST");
+    init.set_headers(&request_headers);
+    init.set_body(&js_sys::Uint8Array::from(body));
+    // `RequestInit` has no setter for `keepalive`.
+    let keepalive = JsValue::from_bool(body.len() <= KEEPALIVE_LIMIT);
+    js_sys::Reflect::set(&init, &JsValue::from_str("keepalive"), &keepalive).map_err(js_error)?;
+    let request = web_sys::Request::new_with_str_and_init(url, &init).map_err(js_error)?;
+    fetch.call1(&global, &request).map(|_| ()).map_err(js_error)
+}
+
+/// The browser's user agent string, if there is a browser.
+pub fn user_agent() -> Option<String> {
+    web_sys::window()?.navigator().user_agent().ok()
+}
+
+/// The installation ID kept in `localStorage`, created on first use.
+pub(crate) fn installation_id() -> std::io::Result<String> {
+    let storage = local_storage()?;
+    if let Some(existing) = storage.get_item(INSTALL_ID_KEY).ok().flatten() {
+        if uuid::Uuid::parse_str(&existing).is_ok() {
+            return Ok(existing);
+        }
+    }
+    let id = uuid::Uuid::new_v4().to_string();
+    storage
+        .set_item(INSTALL_ID_KEY, &id)
+        .map_err(|e| std::io::Error::other(js_error(e)))?;
+    Ok(id)
+}
+
+/// Keeps reports and attachments in `localStorage`, under `prefix` followed
+/// by the file name.
+#[derive(Clone, Debug)]
+pub struct LocalStorage {
+    prefix: String,
+}
+
+impl LocalStorage {
+    pub fn new(prefix: impl Into<String>) -> Self {
+        LocalStorage { prefix: prefix.into() }
+    }
+}
+
+impl Default for LocalStorage {
+    fn default() -> Self {
+        LocalStorage::new("crash/")
+    }
+}
+
+impl crate::storage::Storage for LocalStorage {
+    fn write(&self, name: &str, contents: &[u8]) -> std::io::Result<PathBuf> {
+        let text = std::str::from_utf8(contents).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "localStorage only holds text; use uncompressed JSON reports",
+            )
+        })?;
+        let key = format!("{}{}", self.prefix, name);
+        local_storage()?
+            .set_item(&key, text)
+            .map_err(|e| std::io::Error::other(js_error(e)))?;
+        Ok(PathBuf::from(key))
+    }
+
+    fn remove(&self, location: &Path) -> std::io::Result<()> {
+        local_storage()?
+            .remove_item(&location.to_string_lossy())
+            .map_err(|e| std::io::Error::other(js_error(e)))
+    }
+}
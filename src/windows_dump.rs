@@ -0,0 +1,145 @@
+// Minidumps on Windows.
+//
+// minidump-writer only dumps Linux and macOS processes, so on Windows the
+// panic hook asks dbghelp's `MiniDumpWriteDump` for the dump of its own
+// process. `Builder::minidump_type` picks how much of it goes in: thread
+// stacks and modules (`normal`), plus the writable data sections of the
+// loaded modules (`with_data_segs`), or the whole address space
+// (`full_memory`). Application streams are appended afterwards and the dump
+// is stored, compressed, encrypted or uploaded like on the other platforms,
+// see `crate::upload`.
+
+/// What a Windows minidump contains, one of dbghelp's `MINIDUMP_TYPE`s.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MinidumpType {
+    #[default]
+    Normal,
+    WithDataSegs,
+    FullMemory,
+}
+
+impl MinidumpType {
+    /// Parses `normal`, `with_data_segs` or `full_memory`.
+    pub fn from_name(name: &str) -> Option<MinidumpType> {
+        match name {
+            "normal" => Some(MinidumpType::Normal),
+            "with_data_segs" => Some(MinidumpType::WithDataSegs),
+            "full_memory" => Some(MinidumpType::FullMemory),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MinidumpType::Normal => "normal",
+            MinidumpType::WithDataSegs => "with_data_segs",
+            MinidumpType::FullMemory => "full_memory",
+        }
+    }
+
+    /// The `MINIDUMP_TYPE` flags passed to dbghelp.
+    pub fn flags(self) -> u32 {
+        match self {
+            MinidumpType::Normal => 0x0,       // MiniDumpNormal
+            MinidumpType::WithDataSegs => 0x1, // MiniDumpWithDataSegs
+            MinidumpType::FullMemory => 0x2,   // MiniDumpWithFullMemory
+        }
+    }
+}
+
+#[cfg(windows)]
+pub use platform::write_dump;
+
+#[cfg(windows)]
+mod platform {
+    use std::ffi::c_void;
+    use std::os::windows::io::AsRawHandle;
+    use std::path::Path;
+
+    use super::MinidumpType;
+    use crate::integrity::Digest;
+    use crate::upload::DumpDestination;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentProcess() -> *mut c_void;
+        fn GetCurrentProcessId() -> u32;
+    }
+
+    #[link(name = "dbghelp")]
+    extern "system" {
+        fn MiniDumpWriteDump(
+            process: *mut c_void,
+            process_id: u32,
+            file: *mut c_void,
+            dump_type: u32,
+            exception_param: *const c_void,
+            user_stream_param: *const c_void,
+            callback_param: *const c_void,
+        ) -> i32;
+    }
+
+    fn dump_into(file: &std::fs::File, dump_type: MinidumpType) -> Result<(), String> {
+        // SAFETY: the pseudo handle of the current process and an open file
+        // handle; a panic is no SEH exception, so there is no exception
+        // record to pass.
+        let ok = unsafe {
+            MiniDumpWriteDump(
+                GetCurrentProcess(),
+                GetCurrentProcessId(),
+                file.as_raw_handle() as *mut c_void,
+                dump_type.flags(),
+                std::ptr::null(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        if ok == 0 {
+            return Err(format!("MiniDumpWriteDump failed: {}", std::io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    fn open(path: &Path) -> Result<std::fs::File, String> {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Dumps the current process to `destination`, see `upload::write_dump`
+    /// for the other platforms. dbghelp only writes to files, so dumps that
+    /// are compressed, encrypted or uploaded are taken in a temporary file
+    /// first.
+    pub fn write_dump(
+        dump_type: MinidumpType,
+        event_id: &str,
+        destination: &DumpDestination,
+        encrypt_to: Option<&str>,
+        streams: &[(String, Vec<u8>)],
+    ) -> Result<(), String> {
+        if let DumpDestination::File(path) = destination {
+            if crate::upload::stored_as_is(destination, encrypt_to) {
+                let mut file = open(path)?;
+                dump_into(&file, dump_type)?;
+                crate::minidump_streams::append(&mut file, streams).map_err(|e| e.to_string())?;
+                // Read back rather than held in memory, like the dump itself.
+                crate::upload::write_sum(path, std::fs::File::open(path).and_then(Digest::of_reader));
+                return Ok(());
+            }
+        }
+        let temp = std::env::temp_dir().join(format!("crash_dump_{}.dmp{}", event_id, crate::output::TEMP_SUFFIX));
+        let written = open(&temp).and_then(|mut file| {
+            dump_into(&file, dump_type)?;
+            crate::minidump_streams::append(&mut file, streams).map_err(|e| e.to_string())?;
+            drop(file);
+            let data = std::fs::read(&temp).map_err(|e| e.to_string())?;
+            crate::upload::deliver_dump(&data, destination, encrypt_to)
+        });
+        let _ = std::fs::remove_file(&temp);
+        written
+    }
+}
@@ -0,0 +1,104 @@
+// A panic that ends the process under `panic = "abort"` is followed by the
+// SIGABRT std raises after the panic hook. The crash is reported once, by the
+// panic hook, which also leaves the note for `crash::last_crash`; the signal
+// handler only re-raises the abort.
+//
+// Each case runs in a child process, this test binary run again for the
+// `child` test with CRASH_TEST_OUTPUT set, which panics and then aborts the
+// way std does, or only aborts.
+#![cfg(unix)]
+
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const OUTPUT_ENV: &str = "CRASH_TEST_OUTPUT";
+const MODE_ENV: &str = "CRASH_TEST_MODE";
+
+#[test]
+fn child() {
+    let Some(dir) = std::env::var_os(OUTPUT_ENV) else {
+        return;
+    };
+    let mode = std::env::var(MODE_ENV).unwrap_or_default();
+    crash::Builder::new()
+        .output_dir(PathBuf::from(dir))
+        .external_config(false)
+        .minidump(false)
+        .deferred(mode == "deferred")
+        .init()
+        .unwrap();
+    // Not `crash::catch_and_report`, so the hook takes the panic as fatal.
+    if mode != "abort" {
+        let _ = std::panic::catch_unwind(|| panic!("boom"));
+    }
+    std::process::abort();
+}
+
+// Runs `child` in `mode` and returns its output directory.
+fn run_child(mode: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("crash-panic-abort-{}-{}", mode, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let status = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "child", "--nocapture", "--test-threads=1"])
+        .env(OUTPUT_ENV, &dir)
+        .env(MODE_ENV, mode)
+        .status()
+        .unwrap();
+    assert_eq!(status.signal(), Some(6), "child did not abort: {}", status);
+    dir
+}
+
+// The files in `dir` named `<prefix>...<suffix>`.
+fn files(dir: &Path, prefix: &str, suffix: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            name.starts_with(prefix) && name.ends_with(suffix)
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+fn json(path: &Path) -> serde_json::Value {
+    serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+}
+
+#[test]
+fn panic_then_abort_is_reported_once() {
+    let dir = run_child("regular");
+    let reports = files(&dir, "crash_report_", ".json");
+    assert_eq!(reports.len(), 1, "{:?}", reports);
+    let report = json(&reports[0]);
+    assert_eq!(report["message"], "boom");
+    // `crash::last_crash` in the next run names the panic, not the abort.
+    let last_crash = json(&dir.join("crash_last.json"));
+    assert_eq!(last_crash["event_id"], report["event_id"]);
+    assert_eq!(last_crash["message"], "boom");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn deferred_panic_then_abort_is_recorded_once() {
+    let dir = run_child("deferred");
+    assert_eq!(files(&dir, "crash_raw_", ".txt").len(), 1);
+    assert!(files(&dir, "crash_report_", ".json").is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn abort_is_reported_without_fault_address() {
+    let dir = run_child("abort");
+    let reports = files(&dir, "crash_report_", ".json");
+    assert_eq!(reports.len(), 1, "{:?}", reports);
+    let report = json(&reports[0]);
+    assert_eq!(report["message"], "Fatal signal SIGABRT (abort)");
+    let exception = &report["exception"]["values"][0];
+    assert_eq!(exception["type"], "SIGABRT");
+    assert!(exception.get("fault_address").is_none(), "{}", exception);
+    std::fs::remove_dir_all(&dir).unwrap();
+}